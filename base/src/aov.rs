@@ -0,0 +1,95 @@
+use crate::color::RGB;
+use crate::vector::{Point2, Vec3d};
+
+// Extra per pixel channels recorded next to the beauty image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov
+{
+    // Distance from the camera along the ray
+    Depth,
+    // World space hit position
+    Position,
+    // World space shading normal
+    Normal,
+    Albedo,
+    UV,
+    PrimitiveId,
+    ShapeId,
+    // Raster space offset from the previous frame to this one
+    Motion,
+}
+
+impl Aov
+{
+    // Layer name in multi-layer output
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Aov::Depth => "depth",
+            Aov::Position => "P",
+            Aov::Normal => "N",
+            Aov::Albedo => "albedo",
+            Aov::UV => "uv",
+            Aov::PrimitiveId => "primId",
+            Aov::ShapeId => "shapeId",
+            Aov::Motion => "motion",
+        }
+    }
+    pub fn channels(self) -> &'static [&'static str]
+    {
+        match self
+        {
+            Aov::Depth => &["Z"],
+            Aov::Position | Aov::Normal => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::UV => &["U", "V"],
+            Aov::PrimitiveId | Aov::ShapeId => &["id"],
+            Aov::Motion => &["X", "Y"],
+        }
+    }
+    // Ids can't be averaged, pixels keep the one of the sample closest to
+    // their center
+    pub fn is_id(self) -> bool
+    {
+        self == Aov::PrimitiveId || self == Aov::ShapeId
+    }
+    pub fn values(self, s: &AovSample) -> [f64; 3]
+    {
+        match self
+        {
+            Aov::Depth => [s.depth, 0., 0.],
+            Aov::Position => [s.position.x, s.position.y, s.position.z],
+            Aov::Normal => [s.normal.x, s.normal.y, s.normal.z],
+            Aov::Albedo => [s.albedo.r, s.albedo.g, s.albedo.b],
+            Aov::UV => [s.uv.x, s.uv.y, 0.],
+            Aov::PrimitiveId => [s.prim_id as f64, 0., 0.],
+            Aov::ShapeId => [s.shape_id as f64, 0., 0.],
+            Aov::Motion => [s.motion.x, s.motion.y, 0.],
+        }
+    }
+}
+
+// Everything the AOVs are made from, for one camera sample
+#[derive(Clone, Copy, Debug)]
+pub struct AovSample
+{
+    pub depth: f64,
+    pub position: Vec3d,
+    pub normal: Vec3d,
+    pub albedo: RGB,
+    pub uv: Point2,
+    pub prim_id: u32,
+    pub shape_id: u32,
+    pub motion: Point2,
+}
+
+impl AovSample
+{
+    // Camera ray that hit nothing: infinite depth and zero everything else
+    pub fn miss() -> AovSample
+    {
+        AovSample{ depth: f64::INFINITY, position: Vec3d::zero(), normal: Vec3d::zero(), albedo: RGB::black(),
+                   uv: Point2::new(0., 0.), prim_id: 0, shape_id: 0, motion: Point2::new(0., 0.) }
+    }
+}
@@ -0,0 +1,82 @@
+use std::env;
+use std::path::Path;
+use std::process;
+use base::compare::{self, DEFAULT_PPD};
+use base::image::Image;
+use base::imageio::{self, Encoding};
+
+const USAGE: &str = "usage: imgcmp <test> <reference> [--heatmap <out.png>] [--error flip|se|relse] [--max <v>] [--ppd <v>]";
+
+fn fail(msg: &str) -> !
+{
+    eprintln!("imgcmp: {}", msg);
+    process::exit(2);
+}
+
+fn read(path: &str) -> Image
+{
+    imageio::read_image(Path::new(path), Encoding::SRGB).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+}
+
+// Prints the metrics of the test image against the reference and optionally
+// writes a false color map of one of the per pixel errors
+fn main()
+{
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut files = Vec::new();
+    let (mut heatmap, mut error, mut max, mut ppd) = (None, "flip".to_string(), None, DEFAULT_PPD);
+    let mut i = 0;
+    while i < args.len()
+    {
+        let value = || args.get(i + 1).cloned().unwrap_or_else(|| fail(USAGE));
+        let number = |v: String| v.parse::<f64>().unwrap_or_else(|_| fail(&format!("invalid number {}", v)));
+        match args[i].as_str()
+        {
+            "--heatmap" => heatmap = Some(value()),
+            "--error" => error = value(),
+            "--max" => max = Some(number(value())),
+            "--ppd" => ppd = number(value()),
+            "-h" | "--help" =>
+            {
+                println!("{}", USAGE);
+                return;
+            }
+            _ =>
+            {
+                files.push(args[i].clone());
+                i += 1;
+                continue;
+            }
+        }
+        i += 2;
+    }
+    if files.len() != 2
+    {
+        fail(USAGE);
+    }
+    let (test, reference) = (read(&files[0]), read(&files[1]));
+    if test.width != reference.width || test.height != reference.height
+    {
+        fail(&format!("resolutions differ, {}x{} and {}x{}", test.width, test.height, reference.width, reference.height));
+    }
+    let flip = compare::flip(&test, &reference, ppd);
+    println!("MSE     {:.6e}", compare::mse(&test, &reference));
+    println!("relMSE  {:.6e}", compare::rel_mse(&test, &reference));
+    println!("PSNR    {:.4} dB", compare::psnr(&test, &reference, 1.));
+    println!("SSIM    {:.6}", compare::ssim(&test, &reference));
+    println!("FLIP    {:.6}", compare::mean(&flip));
+    if let Some(path) = heatmap
+    {
+        let (err, default_max) = match error.as_str()
+        {
+            "flip" => (flip, Some(1.)),
+            "se" => (compare::squared_error(&test, &reference), None),
+            "relse" => (compare::relative_squared_error(&test, &reference), None),
+            e => fail(&format!("unknown error {}", e)),
+        };
+        // Squared errors are scaled to their largest value unless told
+        let max = max.or(default_max).unwrap_or_else(|| err.data.iter().cloned().fold(0., f64::max));
+        let img = compare::heatmap(&err, if max > 0. { max } else { 1. });
+        imageio::write_image(Path::new(&path), &img).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+    }
+}
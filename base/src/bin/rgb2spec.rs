@@ -0,0 +1,53 @@
+use std::env;
+use std::path::Path;
+use std::process;
+use base::colorspace::RGBColorSpace;
+use base::rgbspectrum::RGBToSpectrumTable;
+
+const USAGE: &str = "usage: rgb2spec <srgb|rec2020|dci-p3|aces-cg> <out> [--res <n>]";
+
+fn fail(msg: &str) -> !
+{
+    eprintln!("rgb2spec: {}", msg);
+    process::exit(2);
+}
+
+// Fits the RGB to spectrum table of a color space and writes it out for
+// RGBToSpectrumTable::load. The bundled tables in base/data/rgb2spec were
+// made with the default resolution of 32.
+fn main()
+{
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut positional = Vec::new();
+    let mut res = 32;
+    let mut i = 0;
+    while i < args.len()
+    {
+        match args[i].as_str()
+        {
+            "--res" =>
+            {
+                let v = args.get(i + 1).unwrap_or_else(|| fail(USAGE));
+                res = v.parse().ok().filter(|&r| r >= 2).unwrap_or_else(|| fail(&format!("invalid resolution {}", v)));
+                i += 2;
+            }
+            "-h" | "--help" =>
+            {
+                println!("{}", USAGE);
+                return;
+            }
+            _ =>
+            {
+                positional.push(args[i].clone());
+                i += 1;
+            }
+        }
+    }
+    if positional.len() != 2
+    {
+        fail(USAGE);
+    }
+    let space = RGBColorSpace::named(&positional[0]).unwrap_or_else(|| fail(&format!("unknown color space {}", positional[0])));
+    let table = RGBToSpectrumTable::compute(&space, res);
+    table.save(Path::new(&positional[1])).unwrap_or_else(|e| fail(&format!("{}: {}", positional[1], e)));
+}
@@ -1,21 +1,156 @@
 use crate::ray::Ray;
-use crate::transformation::Transform;
-use crate::vector::Vec3d;
+use crate::transformation::{Matrix4, Transform};
+use crate::vector::{Vec3d, Point2};
+use crate::intrinsics::Intrinsics;
+use std::f64::consts::PI;
+
+// Shutter interval of a camera. With a rolling shutter every scanline is
+// exposed for the same duration but starts later the further down the film
+// it is, the bottom row starting readout after the top one.
+#[derive(Clone, Debug)]
+pub struct Shutter
+{
+    pub open: f64,
+    pub close: f64,
+    pub readout: Option<f64>,
+}
+
+impl Shutter
+{
+    pub fn instant() -> Shutter
+    {
+        Shutter{ open: 0., close: 0., readout: None }
+    }
+    pub fn global(open: f64, close: f64) -> Shutter
+    {
+        Shutter{ open, close, readout: None }
+    }
+    pub fn rolling(open: f64, close: f64, readout: f64) -> Shutter
+    {
+        Shutter{ open, close, readout: Some(readout) }
+    }
+    // Time of a ray through film height pf_y, u is uniform in [0, 1)
+    pub fn time(&self, pf_y: f64, u: f64) -> f64
+    {
+        let t = self.open + u*(self.close - self.open);
+        match self.readout
+        {
+            Some(readout) => t + readout*(1. - pf_y)/2.,
+            None => t,
+        }
+    }
+}
+
+// Result of sampling a direction from a reference point towards the camera
+#[derive(Clone, Debug)]
+pub struct CameraWiSample
+{
+    pub wi: Vec3d,
+    pub we: f64,
+    pub pdf: f64,
+    pub p_raster: Point2,
+    pub p_lens: Vec3d,
+}
+
+// A generated ray along with the weight of its sample and, for light-field
+// cameras, its (s, t, u, v) coordinates
+#[derive(Clone, Debug)]
+pub struct CameraRay
+{
+    pub ray: Ray,
+    pub weight: f64,
+    pub light_field: Option<[f64; 4]>,
+}
+
+// Film coordinates are the ones taken by generate_ray, [-1, 1] on both axes
+// with +y at the top. Cameras that can't be connected to from the scene keep
+// the default importance functions and report zero importance.
 pub trait Camera
 {
-    fn generate_ray(self, pf: (f64, f64), pl: (f64, f64)) -> Ray;
+    fn generate_ray(&self, pf: (f64, f64), pl: (f64, f64), u_time: f64) -> Ray;
+    fn generate_camera_ray(&self, pf: (f64, f64), pl: (f64, f64), u_time: f64) -> CameraRay
+    {
+        CameraRay{ ray: self.generate_ray(pf, pl, u_time), weight: 1., light_field: None }
+    }
+    fn world_to_raster(&self, p: Vec3d) -> Option<Point2>;
+    fn we(&self, _r: &Ray) -> f64
+    {
+        0.
+    }
+    // Returns the positional and directional densities
+    fn pdf_we(&self, _r: &Ray) -> (f64, f64)
+    {
+        (0., 0.)
+    }
+    fn sample_wi(&self, _p: Vec3d, _u: (f64, f64)) -> Option<CameraWiSample>
+    {
+        None
+    }
+}
+
+fn point_to_camera(camera_to_world: &[Transform], p: Vec3d) -> Vec3d
+{
+    let mut p = p;
+    for t in camera_to_world.iter().rev()
+    {
+        p = t.inv().act_point(p);
+    }
+    p
+}
+
+fn vector_to_camera(camera_to_world: &[Transform], v: Vec3d) -> Vec3d
+{
+    let mut v = v;
+    for t in camera_to_world.iter().rev()
+    {
+        v = t.inv().act_vector(v);
+    }
+    v
+}
+
+fn point_to_world(camera_to_world: &[Transform], p: Vec3d) -> Vec3d
+{
+    let mut p = p;
+    for t in camera_to_world
+    {
+        p = t.act_point(p);
+    }
+    p
+}
+
+// Single matrix of a chain of transforms applied in order
+pub fn compose(camera_to_world: &[Transform]) -> Matrix4
+{
+    camera_to_world.iter().fold(Matrix4::i(), |m, t| Matrix4::mul(&t.m, &m))
+}
+
+fn inside_film(x: f64, y: f64) -> bool
+{
+    x.abs() <= 1. && y.abs() <= 1.
 }
 
 pub struct PerspectiveCamera
 {
     pub camera_to_world: Vec<Transform>,
+    pub shutter: Shutter,
     pub fov_x: f64,
     pub fov_y: f64,
 }
 
+impl PerspectiveCamera
+{
+    // Camera space to film coordinates after the divide by w = z, depth
+    // becomes 1/z
+    pub fn projection(&self) -> Matrix4
+    {
+        let (tx, ty) = ((self.fov_x/2.).tan(), (self.fov_y/2.).tan());
+        Matrix4::new(&[[1./tx, 0., 0., 0.], [0., 1./ty, 0., 0.], [0., 0., 0., 1.], [0., 0., 1., 0.]])
+    }
+}
+
 impl Camera for PerspectiveCamera
 {
-    fn generate_ray(self, pf: (f64, f64), _: (f64, f64)) -> Ray
+    fn generate_ray(&self, pf: (f64, f64), _: (f64, f64), u_time: f64) -> Ray
     {
         let x = (self.fov_x/2.).tan()*pf.0;
         let y = (self.fov_y/2.).tan()*pf.1;
@@ -26,20 +161,86 @@ impl Camera for PerspectiveCamera
             r = t.act_ray(&r);
         }
         r.d = r.d.norm();
+        r.time = self.shutter.time(pf.1, u_time);
         r
     }
+    fn world_to_raster(&self, p: Vec3d) -> Option<Point2>
+    {
+        let pc = point_to_camera(&self.camera_to_world, p);
+        if pc.z <= 0.
+        {
+            return None;
+        }
+        let x = pc.x/pc.z/(self.fov_x/2.).tan();
+        let y = pc.y/pc.z/(self.fov_y/2.).tan();
+        if inside_film(x, y) { Some(Point2::new(x, y)) } else { None }
+    }
+    fn we(&self, r: &Ray) -> f64
+    {
+        // A pinhole has unit lens area, the film plane sits at z = 1
+        let d = vector_to_camera(&self.camera_to_world, r.d).norm();
+        let cos = d.z;
+        if cos <= 0.
+        {
+            return 0.;
+        }
+        let (tx, ty) = ((self.fov_x/2.).tan(), (self.fov_y/2.).tan());
+        if !inside_film(d.x/d.z/tx, d.y/d.z/ty)
+        {
+            return 0.;
+        }
+        let area = 4.*tx*ty;
+        1./(area*cos*cos*cos*cos)
+    }
+    fn pdf_we(&self, r: &Ray) -> (f64, f64)
+    {
+        let d = vector_to_camera(&self.camera_to_world, r.d).norm();
+        let cos = d.z;
+        let (tx, ty) = ((self.fov_x/2.).tan(), (self.fov_y/2.).tan());
+        if cos <= 0. || !inside_film(d.x/d.z/tx, d.y/d.z/ty)
+        {
+            return (0., 0.);
+        }
+        let area = 4.*tx*ty;
+        (1., 1./(area*cos*cos*cos))
+    }
+    fn sample_wi(&self, p: Vec3d, _: (f64, f64)) -> Option<CameraWiSample>
+    {
+        let p_raster = self.world_to_raster(p)?;
+        let p_lens = point_to_world(&self.camera_to_world, Vec3d::zero());
+        let wi = p_lens - p;
+        let dist = wi.len();
+        let wi = wi/dist;
+        let r = Ray::new(p_lens, -wi);
+        let cos = vector_to_camera(&self.camera_to_world, r.d).norm().z;
+        let we = self.we(&r);
+        if we == 0.
+        {
+            return None;
+        }
+        Some(CameraWiSample{ wi, we, pdf: dist*dist/cos, p_raster, p_lens })
+    }
 }
 
 pub struct OrthographicCamera
 {
     pub camera_to_world: Vec<Transform>,
+    pub shutter: Shutter,
     pub wx: f64,
     pub wy: f64,
 }
 
+impl OrthographicCamera
+{
+    pub fn projection(&self) -> Matrix4
+    {
+        Matrix4::new(&[[1./self.wx, 0., 0., 0.], [0., 1./self.wy, 0., 0.], [0., 0., 1., 0.], [0., 0., 0., 1.]])
+    }
+}
+
 impl Camera for OrthographicCamera
 {
-    fn generate_ray(self, pf: (f64, f64), _: (f64, f64)) -> Ray
+    fn generate_ray(&self, pf: (f64, f64), _: (f64, f64), u_time: f64) -> Ray
     {
         let x = self.wx*pf.0;
         let y = self.wy*pf.1;
@@ -50,6 +251,365 @@ impl Camera for OrthographicCamera
         {
             r = t.act_ray(&r);
         }
+        r.time = self.shutter.time(pf.1, u_time);
         r
     }
-}
\ No newline at end of file
+    fn world_to_raster(&self, p: Vec3d) -> Option<Point2>
+    {
+        let pc = point_to_camera(&self.camera_to_world, p);
+        if pc.z < 0.
+        {
+            return None;
+        }
+        let (x, y) = (pc.x/self.wx, pc.y/self.wy);
+        if inside_film(x, y) { Some(Point2::new(x, y)) } else { None }
+    }
+    fn we(&self, r: &Ray) -> f64
+    {
+        // Importance is a delta in direction, spread evenly over the film
+        let o = point_to_camera(&self.camera_to_world, r.o);
+        let d = vector_to_camera(&self.camera_to_world, r.d).norm();
+        if d.z < 1. - 1e-9 || !inside_film(o.x/self.wx, o.y/self.wy)
+        {
+            return 0.;
+        }
+        1./(4.*self.wx*self.wy)
+    }
+    fn pdf_we(&self, r: &Ray) -> (f64, f64)
+    {
+        if self.we(r) == 0.
+        {
+            return (0., 0.);
+        }
+        (1./(4.*self.wx*self.wy), 1.)
+    }
+    fn sample_wi(&self, p: Vec3d, _: (f64, f64)) -> Option<CameraWiSample>
+    {
+        // Only the film point straight behind p along the view axis connects
+        let p_raster = self.world_to_raster(p)?;
+        let pc = point_to_camera(&self.camera_to_world, p);
+        let p_lens = point_to_world(&self.camera_to_world, Vec3d::new(pc.x, pc.y, 0.));
+        let wi = (p_lens - p).norm();
+        let we = 1./(4.*self.wx*self.wy);
+        Some(CameraWiSample{ wi, we, pdf: 1., p_raster, p_lens })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eye
+{
+    Left,
+    Right,
+}
+
+impl Eye
+{
+    // Offset of the eye along the camera x axis, the left eye sits on -x
+    pub fn offset(self, interocular: f64) -> f64
+    {
+        match self
+        {
+            Eye::Left => -0.5*interocular,
+            Eye::Right => 0.5*interocular,
+        }
+    }
+}
+
+// Stereo pairs are rendered over/under: the top half of the film is the
+// left eye and the bottom half is the right eye.
+pub fn split_over_under(pf: (f64, f64)) -> (Eye, (f64, f64))
+{
+    if pf.1 >= 0.
+    {
+        (Eye::Left, (pf.0, 2.*pf.1 - 1.))
+    }
+    else
+    {
+        (Eye::Right, (pf.0, 2.*pf.1 + 1.))
+    }
+}
+
+pub fn join_over_under(eye: Eye, pe: Point2) -> Point2
+{
+    match eye
+    {
+        Eye::Left => Point2::new(pe.x, (pe.y + 1.)/2.),
+        Eye::Right => Point2::new(pe.x, (pe.y - 1.)/2.),
+    }
+}
+
+pub struct StereoCamera
+{
+    pub camera_to_world: Vec<Transform>,
+    pub shutter: Shutter,
+    pub fov_x: f64,
+    pub fov_y: f64,
+    pub interocular: f64,
+    pub convergence: f64,
+}
+
+impl StereoCamera
+{
+    pub fn eye_ray(&self, eye: Eye, pf: (f64, f64)) -> Ray
+    {
+        let e = eye.offset(self.interocular);
+        let x = (self.fov_x/2.).tan()*pf.0;
+        let y = (self.fov_y/2.).tan()*pf.1;
+        // Off-axis frustum: both eyes share the same window on the
+        // convergence plane, an infinite convergence gives parallel eyes
+        let shift = if self.convergence.is_finite() { e/self.convergence } else { 0. };
+        let d = Vec3d{x: x - shift, y, z:1.};
+        let r = Ray::new(Vec3d::new(e, 0., 0.), d);
+        let mut r = Ray::apply_trans(&r, &self.camera_to_world);
+        r.d = r.d.norm();
+        r
+    }
+    pub fn world_to_raster_eye(&self, p: Vec3d, eye: Eye) -> Option<Point2>
+    {
+        let e = eye.offset(self.interocular);
+        let q = point_to_camera(&self.camera_to_world, p) - Vec3d::new(e, 0., 0.);
+        if q.z <= 0.
+        {
+            return None;
+        }
+        let shift = if self.convergence.is_finite() { e/self.convergence } else { 0. };
+        let x = (q.x/q.z + shift)/(self.fov_x/2.).tan();
+        let y = q.y/q.z/(self.fov_y/2.).tan();
+        if inside_film(x, y) { Some(join_over_under(eye, Point2::new(x, y))) } else { None }
+    }
+}
+
+impl Camera for StereoCamera
+{
+    fn generate_ray(&self, pf: (f64, f64), _: (f64, f64), u_time: f64) -> Ray
+    {
+        let (eye, pe) = split_over_under(pf);
+        let mut r = self.eye_ray(eye, pe);
+        r.time = self.shutter.time(pf.1, u_time);
+        r
+    }
+    // Projection into the left eye, use world_to_raster_eye for the right one
+    fn world_to_raster(&self, p: Vec3d) -> Option<Point2>
+    {
+        self.world_to_raster_eye(p, Eye::Left)
+    }
+}
+
+// Omni-directional stereo: every column is seen from a viewpoint on a circle
+// of diameter interocular, tangent to the viewing direction. The film spans
+// 360 degrees of azimuth horizontally and 180 degrees of latitude per eye.
+pub struct OdsCamera
+{
+    pub camera_to_world: Vec<Transform>,
+    pub shutter: Shutter,
+    pub interocular: f64,
+}
+
+impl OdsCamera
+{
+    pub fn eye_ray(&self, eye: Eye, pf: (f64, f64)) -> Ray
+    {
+        let theta = PI*pf.0;
+        let phi = 0.5*PI*pf.1;
+        let d = Vec3d::new(theta.sin()*phi.cos(), phi.sin(), theta.cos()*phi.cos());
+        let o = eye.offset(self.interocular)*Vec3d::new(theta.cos(), 0., -theta.sin());
+        let r = Ray::new(o, d);
+        let mut r = Ray::apply_trans(&r, &self.camera_to_world);
+        r.d = r.d.norm();
+        r
+    }
+    pub fn world_to_raster_eye(&self, p: Vec3d, eye: Eye) -> Option<Point2>
+    {
+        // Find the azimuth whose tangent ray on the viewing circle passes p
+        let pc = point_to_camera(&self.camera_to_world, p);
+        let rho = f64::sqrt(pc.x*pc.x + pc.z*pc.z);
+        let k = eye.offset(self.interocular);
+        if rho <= k.abs()
+        {
+            return None;
+        }
+        let alpha = pc.x.atan2(pc.z);
+        let mut theta = alpha - (k/rho).asin();
+        if theta > PI
+        {
+            theta -= 2.*PI;
+        }
+        else if theta < -PI
+        {
+            theta += 2.*PI;
+        }
+        let s = f64::sqrt(rho*rho - k*k);
+        let phi = pc.y.atan2(s);
+        Some(join_over_under(eye, Point2::new(theta/PI, phi/(0.5*PI))))
+    }
+}
+
+impl Camera for OdsCamera
+{
+    fn generate_ray(&self, pf: (f64, f64), _: (f64, f64), u_time: f64) -> Ray
+    {
+        let (eye, pe) = split_over_under(pf);
+        let mut r = self.eye_ray(eye, pe);
+        r.time = self.shutter.time(pf.1, u_time);
+        r
+    }
+    // Projection into the left eye, use world_to_raster_eye for the right one
+    fn world_to_raster(&self, p: Vec3d) -> Option<Point2>
+    {
+        self.world_to_raster_eye(p, Eye::Left)
+    }
+}
+
+// Pinhole camera described by OpenCV intrinsics, so renders line up pixel for
+// pixel with images from a calibrated physical camera
+pub struct CalibratedCamera
+{
+    pub camera_to_world: Vec<Transform>,
+    pub shutter: Shutter,
+    pub intrinsics: Intrinsics,
+}
+
+impl Camera for CalibratedCamera
+{
+    fn generate_ray(&self, pf: (f64, f64), _: (f64, f64), u_time: f64) -> Ray
+    {
+        let (u, v) = self.intrinsics.film_to_pixel(pf);
+        let d = self.intrinsics.unproject(u, v);
+        let r = Ray::new(Vec3d::zero(), Vec3d::new(d.x, -d.y, d.z));
+        let mut r = Ray::apply_trans(&r, &self.camera_to_world);
+        r.d = r.d.norm();
+        r.time = self.shutter.time(pf.1, u_time);
+        r
+    }
+    fn world_to_raster(&self, p: Vec3d) -> Option<Point2>
+    {
+        let pc = point_to_camera(&self.camera_to_world, p);
+        let (u, v) = self.intrinsics.project(Vec3d::new(pc.x, -pc.y, pc.z))?;
+        let (x, y) = self.intrinsics.pixel_to_film(u, v);
+        if inside_film(x, y) { Some(Point2::new(x, y)) } else { None }
+    }
+}
+
+// Plenoptic camera: a microlens array sits on the focal plane of the main lens
+// and the sensor behind it. Each lenslet covers lenslet_pixels^2 sensor pixels
+// that see the scene through different parts of the main lens aperture.
+pub struct LightFieldCamera
+{
+    pub camera_to_world: Vec<Transform>,
+    pub shutter: Shutter,
+    pub fov_x: f64,
+    pub fov_y: f64,
+    pub lens_radius: f64,
+    pub focal_distance: f64,
+    pub lenslets: (usize, usize),
+    pub lenslet_pixels: usize,
+}
+
+impl LightFieldCamera
+{
+    pub fn sensor_resolution(&self) -> (usize, usize)
+    {
+        (self.lenslets.0*self.lenslet_pixels, self.lenslets.1*self.lenslet_pixels)
+    }
+    // Decodes a raw lenslet image, stored row by row from the top, into
+    // sub-aperture views
+    pub fn decode<T: Copy>(&self, raw: &[T]) -> LightField<T>
+    {
+        let (w, h) = self.sensor_resolution();
+        assert_eq!(raw.len(), w*h, "raw image doesn't match the sensor resolution");
+        let n = self.lenslet_pixels;
+        let mut data = Vec::with_capacity(raw.len());
+        for vr in 0..n
+        {
+            for vc in 0..n
+            {
+                // Every lenslet images the aperture upside down
+                let (q, p) = (n - 1 - vr, n - 1 - vc);
+                for j in 0..self.lenslets.1
+                {
+                    for i in 0..self.lenslets.0
+                    {
+                        data.push(raw[(j*n + q)*w + i*n + p]);
+                    }
+                }
+            }
+        }
+        LightField{ lenslets: self.lenslets, views: n, data }
+    }
+}
+
+impl Camera for LightFieldCamera
+{
+    fn generate_ray(&self, pf: (f64, f64), pl: (f64, f64), u_time: f64) -> Ray
+    {
+        self.generate_camera_ray(pf, pl, u_time).ray
+    }
+    fn generate_camera_ray(&self, pf: (f64, f64), _: (f64, f64), u_time: f64) -> CameraRay
+    {
+        // Locate the lenslet and the position under it on the sensor
+        let (nx, ny) = (self.lenslets.0 as f64, self.lenslets.1 as f64);
+        let sx = f64::min((pf.0 + 1.)/2.*nx, nx - 1e-9).max(0.);
+        let sy = f64::min((1. - pf.1)/2.*ny, ny - 1e-9).max(0.);
+        let (i, j) = (sx.floor(), sy.floor());
+        let (a, b) = (sx - i, sy - j);
+        // Rays cross at the lenslet center, so the pixel position maps to
+        // the opposite side of the aperture
+        let u = 1. - 2.*a;
+        let v = 2.*b - 1.;
+        let o = Vec3d::new(u*self.lens_radius, v*self.lens_radius, 0.);
+        let cx = 2.*(i + 0.5)/nx - 1.;
+        let cy = 1. - 2.*(j + 0.5)/ny;
+        let p_focus = self.focal_distance*Vec3d::new((self.fov_x/2.).tan()*cx, (self.fov_y/2.).tan()*cy, 1.);
+        let r = Ray::new(o, p_focus - o);
+        let mut r = Ray::apply_trans(&r, &self.camera_to_world);
+        r.d = r.d.norm();
+        r.time = self.shutter.time(pf.1, u_time);
+        // Pixels whose sub-aperture falls outside the round main lens see nothing
+        let weight = if u*u + v*v <= 1. { 1. } else { 0. };
+        CameraRay{ ray: r, weight, light_field: Some([i, j, u, v]) }
+    }
+    // Projection through the center of the aperture onto the lenslet center
+    fn world_to_raster(&self, p: Vec3d) -> Option<Point2>
+    {
+        let pc = point_to_camera(&self.camera_to_world, p);
+        if pc.z <= 0.
+        {
+            return None;
+        }
+        let x = pc.x/pc.z/(self.fov_x/2.).tan();
+        let y = pc.y/pc.z/(self.fov_y/2.).tan();
+        if !inside_film(x, y)
+        {
+            return None;
+        }
+        let (nx, ny) = (self.lenslets.0 as f64, self.lenslets.1 as f64);
+        let i = f64::min(((x + 1.)/2.*nx).floor(), nx - 1.);
+        let j = f64::min(((1. - y)/2.*ny).floor(), ny - 1.);
+        Some(Point2::new(2.*(i + 0.5)/nx - 1., 1. - 2.*(j + 0.5)/ny))
+    }
+}
+
+// Decoded 4D light field: views x views sub-aperture images, each with one
+// pixel per lenslet. View (0, 0) looks through the top left of the aperture.
+#[derive(Clone, Debug)]
+pub struct LightField<T>
+{
+    pub lenslets: (usize, usize),
+    pub views: usize,
+    pub data: Vec<T>,
+}
+
+impl<T: Copy> LightField<T>
+{
+    pub fn get(&self, view_row: usize, view_col: usize, s: usize, t: usize) -> T
+    {
+        let (nx, ny) = self.lenslets;
+        self.data[((view_row*self.views + view_col)*ny + t)*nx + s]
+    }
+    pub fn view(&self, view_row: usize, view_col: usize) -> &[T]
+    {
+        let size = self.lenslets.0*self.lenslets.1;
+        let start = (view_row*self.views + view_col)*size;
+        &self.data[start..start + size]
+    }
+}
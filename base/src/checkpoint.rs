@@ -0,0 +1,179 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::film::Film;
+
+const MAGIC: &[u8; 8] = b"FILMCKPT";
+const VERSION: u32 = 3;
+
+fn invalid(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Little endian values from a checkpoint
+pub(crate) struct StateReader<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a>
+{
+    pub(crate) fn new(data: &'a [u8]) -> StateReader<'a>
+    {
+        StateReader{ data, pos: 0 }
+    }
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]>
+    {
+        let b = self.data.get(self.pos..self.pos + n).ok_or_else(|| invalid("truncated checkpoint"))?;
+        self.pos += n;
+        Ok(b)
+    }
+    pub(crate) fn u64(&mut self) -> io::Result<u64>
+    {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+    pub(crate) fn f64(&mut self) -> io::Result<f64>
+    {
+        Ok(f64::from_bits(self.u64()?))
+    }
+    pub(crate) fn expect(&mut self, v: u64, what: &str) -> io::Result<()>
+    {
+        if self.u64()? != v
+        {
+            return Err(invalid(&format!("checkpoint {} doesn't match the film", what)));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn put_u64(out: &mut Vec<u8>, v: u64)
+{
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+pub(crate) fn put_f64(out: &mut Vec<u8>, v: f64)
+{
+    out.extend_from_slice(&v.to_bits().to_le_bytes());
+}
+
+// Where a progressive render stands: every pixel has samples [0, samples)
+// taken with a sampler of the given seed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress
+{
+    pub seed: u64,
+    pub samples: u64,
+}
+
+pub fn encode(film: &Film, progress: Progress) -> Vec<u8>
+{
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    put_u64(&mut out, progress.seed);
+    put_u64(&mut out, progress.samples);
+    film.save_state(&mut out);
+    out
+}
+
+fn read_header(data: &[u8]) -> io::Result<(Progress, StateReader<'_>)>
+{
+    if !data.starts_with(MAGIC)
+    {
+        return Err(invalid("not a film checkpoint"));
+    }
+    let mut r = StateReader::new(&data[MAGIC.len()..]);
+    let version = r.bytes(4)?;
+    if version != VERSION.to_le_bytes()
+    {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "unsupported checkpoint version"));
+    }
+    let progress = Progress{ seed: r.u64()?, samples: r.u64()? };
+    Ok((progress, r))
+}
+
+// Restores the film's sums in place and returns the progress they are at
+pub fn decode(data: &[u8], film: &Film) -> io::Result<Progress>
+{
+    let (progress, mut r) = read_header(data)?;
+    film.restore_state(&mut r)?;
+    Ok(progress)
+}
+
+// Written to a temporary file next to path that is synced and renamed over
+// it, so a killed job leaves either the old checkpoint or the new one
+pub fn save(path: &Path, film: &Film, progress: Progress) -> io::Result<()>
+{
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(&encode(film, progress))?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)
+}
+
+pub fn load(path: &Path, film: &Film) -> io::Result<Progress>
+{
+    decode(&fs::read(path)?, film)
+}
+
+// Renders one sample index per pixel at a time, from wherever the checkpoint
+// left off. Sample passes run in order and the film is saved after the first
+// pass that ends more than interval after the last save, and at the end.
+// Resumed renders are bit-identical to uninterrupted ones as long as a pass
+// adds its samples to the film in the same order every time.
+pub struct Progressive
+{
+    pub seed: u64,
+    pub samples: u64,
+    pub checkpoint: Option<PathBuf>,
+    pub interval: Duration,
+}
+
+impl Progressive
+{
+    pub fn new(seed: u64, samples: u64) -> Progressive
+    {
+        Progressive{ seed, samples, checkpoint: None, interval: Duration::from_secs(300) }
+    }
+    // Calls pass with every sample index still to take, returns the number
+    // of samples per pixel the film started from
+    pub fn run<F: FnMut(&Film, u64)>(&self, film: &Film, mut pass: F) -> io::Result<u64>
+    {
+        let start = match &self.checkpoint
+        {
+            Some(path) if path.exists() =>
+            {
+                let data = fs::read(path)?;
+                let (progress, mut r) = read_header(&data)?;
+                if progress.seed != self.seed
+                {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "checkpoint was rendered with another seed"));
+                }
+                film.restore_state(&mut r)?;
+                progress.samples
+            }
+            _ => 0,
+        };
+        let mut last_save = Instant::now();
+        for index in start..self.samples
+        {
+            pass(film, index);
+            if let Some(path) = &self.checkpoint
+            {
+                if last_save.elapsed() >= self.interval || index + 1 == self.samples
+                {
+                    save(path, film, Progress{ seed: self.seed, samples: index + 1 })?;
+                    last_save = Instant::now();
+                }
+            }
+        }
+        Ok(start)
+    }
+}
@@ -1,4 +1,8 @@
-#[derive(Clone, Copy, Debug)]
+use std::ops;
+
+// Linear RGB value, unbounded so that it can carry radiance. Use clamp to
+// bound it explicitly and RGB8 for display-referred output.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct RGB
 {
     pub r: f64,
@@ -10,7 +14,11 @@ impl RGB
 {
     pub fn new(r: f64, g: f64, b: f64) -> RGB
     {
-        RGB { r: clamp(r, 0., 1.), g: clamp(g, 0., 1.), b: clamp(b, 0., 1.)}
+        RGB { r, g, b }
+    }
+    pub fn gray(v: f64) -> RGB
+    {
+        RGB::new(v, v, v)
     }
     pub fn black() -> RGB
     {
@@ -32,20 +40,193 @@ impl RGB
     {
         RGB::new(0., 0., 1.)
     }
+    // Relative luminance with the Rec. 709 primaries
+    pub fn luminance(&self) -> f64
+    {
+        0.2126*self.r + 0.7152*self.g + 0.0722*self.b
+    }
+    pub fn max_comp(&self) -> f64
+    {
+        f64::max(self.r, f64::max(self.g, self.b))
+    }
+    pub fn is_black(&self) -> bool
+    {
+        self.r == 0. && self.g == 0. && self.b == 0.
+    }
+    pub fn has_nan(&self) -> bool
+    {
+        self.r.is_nan() || self.g.is_nan() || self.b.is_nan()
+    }
+    pub fn has_inf(&self) -> bool
+    {
+        self.r.is_infinite() || self.g.is_infinite() || self.b.is_infinite()
+    }
+    pub fn is_finite(&self) -> bool
+    {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
+    pub fn clamp(&self, low: f64, high: f64) -> RGB
+    {
+        RGB::new(clamp(self.r, low, high), clamp(self.g, low, high), clamp(self.b, low, high))
+    }
+    pub fn map<F: Fn(f64) -> f64>(&self, f: F) -> RGB
+    {
+        RGB::new(f(self.r), f(self.g), f(self.b))
+    }
+}
+
+impl ops::Add for RGB
+{
+    type Output = RGB;
+    fn add(self, other: RGB) -> RGB
+    {
+        RGB::new(self.r+other.r, self.g+other.g, self.b+other.b)
+    }
+}
+
+impl ops::AddAssign for RGB
+{
+    fn add_assign(&mut self, other: RGB)
+    {
+        *self = *self + other;
+    }
+}
+
+impl ops::Sub for RGB
+{
+    type Output = RGB;
+    fn sub(self, other: RGB) -> RGB
+    {
+        RGB::new(self.r-other.r, self.g-other.g, self.b-other.b)
+    }
+}
+
+impl ops::Mul for RGB
+{
+    type Output = RGB;
+    fn mul(self, other: RGB) -> RGB
+    {
+        RGB::new(self.r*other.r, self.g*other.g, self.b*other.b)
+    }
+}
+
+impl ops::Mul<f64> for RGB
+{
+    type Output = RGB;
+    fn mul(self, s: f64) -> RGB
+    {
+        RGB::new(self.r*s, self.g*s, self.b*s)
+    }
+}
+
+impl ops::Mul<RGB> for f64
+{
+    type Output = RGB;
+    fn mul(self, c: RGB) -> RGB
+    {
+        RGB::new(c.r*self, c.g*self, c.b*self)
+    }
+}
+
+impl ops::MulAssign<f64> for RGB
+{
+    fn mul_assign(&mut self, s: f64)
+    {
+        *self = *self * s;
+    }
+}
+
+impl ops::Div for RGB
+{
+    type Output = RGB;
+    fn div(self, other: RGB) -> RGB
+    {
+        RGB::new(self.r/other.r, self.g/other.g, self.b/other.b)
+    }
+}
+
+impl ops::Div<f64> for RGB
+{
+    type Output = RGB;
+    fn div(self, s: f64) -> RGB
+    {
+        RGB::new(self.r/s, self.g/s, self.b/s)
+    }
+}
+
+// Display-referred 8-bit color, ready to be written out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RGB8
+{
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl RGB8
+{
+    pub fn new(r: u8, g: u8, b: u8) -> RGB8
+    {
+        RGB8 { r, g, b }
+    }
+    // Quantizes an already display encoded value, clamping it to [0, 1]
+    pub fn from_rgb(c: RGB) -> RGB8
+    {
+        let q = |v: f64| (clamp(v, 0., 1.)*255. + 0.5) as u8;
+        RGB8::new(q(c.r), q(c.g), q(c.b))
+    }
+    pub fn to_rgb(self) -> RGB
+    {
+        RGB::new(self.r as f64/255., self.g as f64/255., self.b as f64/255.)
+    }
+}
+
+// sRGB transfer function, from encoded values to linear ones
+pub fn srgb_to_linear(v: f64) -> f64
+{
+    if v <= 0.04045
+    {
+        v/12.92
+    }
+    else
+    {
+        ((v + 0.055)/1.055).powf(2.4)
+    }
 }
 
-fn clamp(v: f64, up: f64, down: f64) -> f64
+pub fn linear_to_srgb(v: f64) -> f64
 {
-    if v > up
+    if v <= 0.0031308
     {
-        return up;
+        12.92*v
     }
-    else if v < down
+    else
+    {
+        1.055*v.powf(1./2.4) - 0.055
+    }
+}
+
+// Row major 3x3 matrix times a color
+pub fn mat_mul(m: &[[f64; 3]; 3], c: RGB) -> RGB
+{
+    RGB::new(m[0][0]*c.r + m[0][1]*c.g + m[0][2]*c.b,
+             m[1][0]*c.r + m[1][1]*c.g + m[1][2]*c.b,
+             m[2][0]*c.r + m[2][1]*c.g + m[2][2]*c.b)
+}
+
+// NaN maps to low
+fn clamp(v: f64, low: f64, high: f64) -> f64
+{
+    if v > high
     {
-        return down;
+        high
+    }
+    else if v >= low
+    {
+        v
     }
     else
     {
-        return v;
+        low
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,136 @@
+use crate::color::RGB;
+use crate::image::Image;
+use crate::transformation::Matrix3;
+use crate::vector::{Point2, Vec3d};
+
+// CIE XYZ of a chromaticity, with a luminance of one
+pub fn xy_to_xyz(xy: Point2) -> Vec3d
+{
+    Vec3d::new(xy.x/xy.y, 1., (1. - xy.x - xy.y)/xy.y)
+}
+
+pub fn xyz_to_xy(xyz: Vec3d) -> Point2
+{
+    let sum = xyz.x + xyz.y + xyz.z;
+    Point2::new(xyz.x/sum, xyz.y/sum)
+}
+
+// Bradford chromatic adaptation of XYZ colors seen under the source white
+// to the ones seen under the destination white
+pub fn bradford(src_white: Vec3d, dst_white: Vec3d) -> Matrix3
+{
+    let m = Matrix3::new([[0.8951, 0.2664, -0.1614], [-0.7502, 1.7135, 0.0367], [0.0389, -0.0685, 1.0296]]);
+    let (s, d) = (m.act(src_white), m.act(dst_white));
+    let scale = Matrix3::diag(d.x/s.x, d.y/s.y, d.z/s.z);
+    Matrix3::mul(&m.inv(), &Matrix3::mul(&scale, &m))
+}
+
+pub fn apply_matrix(m: &Matrix3, c: RGB) -> RGB
+{
+    let v = m.act(Vec3d::new(c.r, c.g, c.b));
+    RGB::new(v.x, v.y, v.z)
+}
+
+// Linear RGB space given by the chromaticities of its primaries and white
+// point
+#[derive(Clone, Debug, PartialEq)]
+pub struct RGBColorSpace
+{
+    pub r: Point2,
+    pub g: Point2,
+    pub b: Point2,
+    pub white: Point2,
+    pub rgb_to_xyz: Matrix3,
+    pub xyz_to_rgb: Matrix3,
+}
+
+const D65: Point2 = Point2{ x: 0.3127, y: 0.3290 };
+
+impl RGBColorSpace
+{
+    pub fn new(r: Point2, g: Point2, b: Point2, white: Point2) -> RGBColorSpace
+    {
+        // Primaries scaled so that RGB (1, 1, 1) is the white point
+        let primaries = Matrix3::from_columns(xy_to_xyz(r), xy_to_xyz(g), xy_to_xyz(b));
+        let s = primaries.inv().act(xy_to_xyz(white));
+        let rgb_to_xyz = Matrix3::mul(&primaries, &Matrix3::diag(s.x, s.y, s.z));
+        RGBColorSpace{ r, g, b, white, rgb_to_xyz, xyz_to_rgb: rgb_to_xyz.inv() }
+    }
+    pub fn srgb() -> RGBColorSpace
+    {
+        RGBColorSpace::new(Point2::new(0.64, 0.33), Point2::new(0.30, 0.60), Point2::new(0.15, 0.06), D65)
+    }
+    pub fn rec2020() -> RGBColorSpace
+    {
+        RGBColorSpace::new(Point2::new(0.708, 0.292), Point2::new(0.170, 0.797), Point2::new(0.131, 0.046), D65)
+    }
+    // Theatrical P3 with the DCI white point
+    pub fn dci_p3() -> RGBColorSpace
+    {
+        RGBColorSpace::new(Point2::new(0.680, 0.320), Point2::new(0.265, 0.690), Point2::new(0.150, 0.060), Point2::new(0.314, 0.351))
+    }
+    // ACES AP1 primaries with the ACES white point
+    pub fn aces_cg() -> RGBColorSpace
+    {
+        RGBColorSpace::new(Point2::new(0.713, 0.293), Point2::new(0.165, 0.830), Point2::new(0.128, 0.044), Point2::new(0.32168, 0.33767))
+    }
+    // Spaces by the names tools and scenes use, which are also the names of
+    // the bundled RGB to spectrum tables
+    pub fn named(name: &str) -> Option<RGBColorSpace>
+    {
+        match name
+        {
+            "srgb" => Some(RGBColorSpace::srgb()),
+            "rec2020" => Some(RGBColorSpace::rec2020()),
+            "dci-p3" => Some(RGBColorSpace::dci_p3()),
+            "aces-cg" => Some(RGBColorSpace::aces_cg()),
+            _ => None,
+        }
+    }
+    pub fn to_xyz(&self, c: RGB) -> Vec3d
+    {
+        self.rgb_to_xyz.act(Vec3d::new(c.r, c.g, c.b))
+    }
+    pub fn from_xyz(&self, xyz: Vec3d) -> RGB
+    {
+        let v = self.xyz_to_rgb.act(xyz);
+        RGB::new(v.x, v.y, v.z)
+    }
+    pub fn luminance(&self, c: RGB) -> f64
+    {
+        self.to_xyz(c).y
+    }
+    // Matrix from RGB in this space to RGB in the other one, adapting
+    // between the white points so that white stays white
+    pub fn convert_matrix(&self, to: &RGBColorSpace) -> Matrix3
+    {
+        let mut m = self.rgb_to_xyz;
+        if self.white != to.white
+        {
+            m = Matrix3::mul(&bradford(xy_to_xyz(self.white), xy_to_xyz(to.white)), &m);
+        }
+        Matrix3::mul(&to.xyz_to_rgb, &m)
+    }
+    pub fn convert(&self, c: RGB, to: &RGBColorSpace) -> RGB
+    {
+        apply_matrix(&self.convert_matrix(to), c)
+    }
+    // Converts the color channels of an image, others like alpha are kept
+    pub fn convert_image(&self, img: &Image, to: &RGBColorSpace) -> Image
+    {
+        let m = self.convert_matrix(to);
+        let mut out = img.clone();
+        if img.channel_count() < 3
+        {
+            return out;
+        }
+        for y in 0..img.height
+        {
+            for x in 0..img.width
+            {
+                out.set_rgb(x, y, apply_matrix(&m, img.get_rgb(x, y)));
+            }
+        }
+        out
+    }
+}
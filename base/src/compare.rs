@@ -0,0 +1,342 @@
+use crate::color::RGB;
+use crate::image::Image;
+
+// Error metrics of a test image against a reference, over the RGB channels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics
+{
+    pub mse: f64,
+    pub rel_mse: f64,
+    pub psnr: f64,
+    pub ssim: f64,
+    pub flip: f64,
+}
+
+// Pixels per degree of a 0.7 m wide 4K monitor seen from 0.7 m
+pub const DEFAULT_PPD: f64 = 67.0206;
+
+pub fn compare(test: &Image, reference: &Image) -> Metrics
+{
+    let mse = mse(test, reference);
+    Metrics{ mse, rel_mse: rel_mse(test, reference), psnr: psnr(test, reference, 1.), ssim: ssim(test, reference),
+             flip: mean(&flip(test, reference, DEFAULT_PPD)) }
+}
+
+fn check_resolution(test: &Image, reference: &Image)
+{
+    assert!(test.width == reference.width && test.height == reference.height, "Images differ in resolution!");
+}
+
+// Mean of the first channel
+pub fn mean(img: &Image) -> f64
+{
+    let n = img.width*img.height;
+    (0..n).map(|i| img.data[i*img.channel_count()]).sum::<f64>()/n as f64
+}
+
+// Per pixel error, averaged over the channels
+fn channel_errors<F: Fn(f64, f64) -> f64>(test: &Image, reference: &Image, f: F) -> Image
+{
+    check_resolution(test, reference);
+    let mut out = Image::new(test.width, test.height, &["Y"]);
+    for y in 0..test.height
+    {
+        for x in 0..test.width
+        {
+            let (t, r) = (test.get_rgb(x, y), reference.get_rgb(x, y));
+            out.set(x, y, 0, (f(t.r, r.r) + f(t.g, r.g) + f(t.b, r.b))/3.);
+        }
+    }
+    out
+}
+
+pub fn squared_error(test: &Image, reference: &Image) -> Image
+{
+    channel_errors(test, reference, |t, r| (t - r)*(t - r))
+}
+
+// Squared error relative to the squared reference, offset so dark pixels
+// don't dominate
+pub fn relative_squared_error(test: &Image, reference: &Image) -> Image
+{
+    channel_errors(test, reference, |t, r| (t - r)*(t - r)/(r*r + 1e-2))
+}
+
+pub fn mse(test: &Image, reference: &Image) -> f64
+{
+    mean(&squared_error(test, reference))
+}
+
+pub fn rel_mse(test: &Image, reference: &Image) -> f64
+{
+    mean(&relative_squared_error(test, reference))
+}
+
+// In dB, infinite for identical images
+pub fn psnr(test: &Image, reference: &Image, peak: f64) -> f64
+{
+    10.*(peak*peak/mse(test, reference)).log10()
+}
+
+// Separable convolution with a symmetric kernel, weights are renormalized
+// where it reaches past the border
+fn convolve(v: &[f64], w: usize, h: usize, kernel: &[f64]) -> Vec<f64>
+{
+    let r = (kernel.len()/2) as isize;
+    let pass = |v: &[f64], horizontal: bool| -> Vec<f64>
+    {
+        let mut out = vec![0.; w*h];
+        for y in 0..h as isize
+        {
+            for x in 0..w as isize
+            {
+                let (mut sum, mut sum_w) = (0., 0.);
+                for (i, k) in kernel.iter().enumerate()
+                {
+                    let (u, t) = if horizontal { (x + i as isize - r, y) } else { (x, y + i as isize - r) };
+                    if u >= 0 && t >= 0 && u < w as isize && t < h as isize
+                    {
+                        sum += k*v[t as usize*w + u as usize];
+                        sum_w += k;
+                    }
+                }
+                out[y as usize*w + x as usize] = sum/sum_w;
+            }
+        }
+        out
+    };
+    pass(&pass(v, true), false)
+}
+
+// SSIM of the luminance with the usual 11x11 Gaussian window of standard
+// deviation 1.5, for values in [0, 1]
+pub fn ssim_map(test: &Image, reference: &Image) -> Image
+{
+    check_resolution(test, reference);
+    let (w, h) = (test.width, test.height);
+    let kernel: Vec<f64> = (-5..=5).map(|i: i32| (-(i*i) as f64/(2.*1.5*1.5)).exp()).collect();
+    let a: Vec<f64> = test.to_rgb().iter().map(|c| c.luminance()).collect();
+    let b: Vec<f64> = reference.to_rgb().iter().map(|c| c.luminance()).collect();
+    let product = |u: &[f64], v: &[f64]| u.iter().zip(v).map(|(x, y)| x*y).collect::<Vec<f64>>();
+    let (mu_a, mu_b) = (convolve(&a, w, h, &kernel), convolve(&b, w, h, &kernel));
+    let aa = convolve(&product(&a, &a), w, h, &kernel);
+    let bb = convolve(&product(&b, &b), w, h, &kernel);
+    let ab = convolve(&product(&a, &b), w, h, &kernel);
+    let (c1, c2) = (0.01f64.powi(2), 0.03f64.powi(2));
+    let mut out = Image::new(w, h, &["Y"]);
+    for i in 0..w*h
+    {
+        let (ma, mb) = (mu_a[i], mu_b[i]);
+        let (va, vb, cov) = (aa[i] - ma*ma, bb[i] - mb*mb, ab[i] - ma*mb);
+        out.data[i] = (2.*ma*mb + c1)*(2.*cov + c2)/((ma*ma + mb*mb + c1)*(va + vb + c2));
+    }
+    out
+}
+
+pub fn ssim(test: &Image, reference: &Image) -> f64
+{
+    mean(&ssim_map(test, reference))
+}
+
+// CIE XYZ of D65 white, which YCxCz and L*a*b* are relative to
+const D65: [f64; 3] = [0.950428545, 1., 1.088900371];
+
+fn linear_to_xyz(c: RGB) -> [f64; 3]
+{
+    [0.4124564*c.r + 0.3575761*c.g + 0.1804375*c.b,
+     0.2126729*c.r + 0.7151522*c.g + 0.0721750*c.b,
+     0.0193339*c.r + 0.1191920*c.g + 0.9503041*c.b]
+}
+
+fn xyz_to_linear(v: [f64; 3]) -> RGB
+{
+    RGB::new(3.2404542*v[0] - 1.5371385*v[1] - 0.4985314*v[2],
+             -0.9692660*v[0] + 1.8760108*v[1] + 0.0415560*v[2],
+             0.0556434*v[0] - 0.2040259*v[1] + 1.0572252*v[2])
+}
+
+fn xyz_to_ycxcz(v: [f64; 3]) -> [f64; 3]
+{
+    let (x, y, z) = (v[0]/D65[0], v[1]/D65[1], v[2]/D65[2]);
+    [116.*y - 16., 500.*(x - y), 200.*(y - z)]
+}
+
+fn ycxcz_to_xyz(v: [f64; 3]) -> [f64; 3]
+{
+    let y = (v[0] + 16.)/116.;
+    [(y + v[1]/500.)*D65[0], y*D65[1], (y - v[2]/200.)*D65[2]]
+}
+
+// L*a*b* with a and b scaled by L, the Hunt effect adjustment of FLIP
+fn hunt_lab(c: RGB) -> [f64; 3]
+{
+    let v = linear_to_xyz(c);
+    let f = |t: f64| if t > 0.00885 { t.cbrt() } else { t/(3.*(6./29.)*(6./29.)) + 4./29. };
+    let (x, y, z) = (f(v[0]/D65[0]), f(v[1]/D65[1]), f(v[2]/D65[2]));
+    let l = 116.*y - 16.;
+    [l, 0.01*l*500.*(x - y), 0.01*l*200.*(y - z)]
+}
+
+fn hyab(a: [f64; 3], b: [f64; 3]) -> f64
+{
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+// Spatial contrast sensitivity filters of the achromatic, red-green and
+// blue-yellow channels, all with the radius of the widest one
+fn csf_kernels(ppd: f64) -> (usize, [Vec<f64>; 3])
+{
+    const PARAMS: [[f64; 4]; 3] = [[1., 0.0047, 0., 1e-5], [1., 0.0053, 0., 1e-5], [34.1, 0.04, 13.5, 0.025]];
+    let r = (3.*(0.04/(2.*std::f64::consts::PI*std::f64::consts::PI)).sqrt()*ppd).ceil() as isize;
+    let kernel = |p: &[f64; 4]| -> Vec<f64>
+    {
+        let pi2 = std::f64::consts::PI*std::f64::consts::PI;
+        let mut k = Vec::new();
+        for y in -r..=r
+        {
+            for x in -r..=r
+            {
+                let d2 = ((x*x + y*y) as f64)/(ppd*ppd);
+                k.push(p[0]*(std::f64::consts::PI/p[1]).sqrt()*(-pi2*d2/p[1]).exp()
+                       + p[2]*(std::f64::consts::PI/p[3]).sqrt()*(-pi2*d2/p[3]).exp());
+            }
+        }
+        let sum: f64 = k.iter().sum();
+        k.iter().map(|v| v/sum).collect()
+    };
+    (r as usize, [kernel(&PARAMS[0]), kernel(&PARAMS[1]), kernel(&PARAMS[2])])
+}
+
+// 2D correlation with a (2r + 1)^2 kernel, edge pixels extended outwards
+fn correlate(v: &[f64], w: usize, h: usize, r: usize, kernel: &[f64]) -> Vec<f64>
+{
+    let (r, n) = (r as isize, 2*r + 1);
+    let mut out = vec![0.; w*h];
+    for y in 0..h as isize
+    {
+        for x in 0..w as isize
+        {
+            let mut sum = 0.;
+            for j in -r..=r
+            {
+                let t = (y + j).clamp(0, h as isize - 1) as usize;
+                for i in -r..=r
+                {
+                    let u = (x + i).clamp(0, w as isize - 1) as usize;
+                    sum += kernel[(j + r) as usize*n + (i + r) as usize]*v[t*w + u];
+                }
+            }
+            out[y as usize*w + x as usize] = sum;
+        }
+    }
+    out
+}
+
+// Magnitudes of the edge and point responses of normalized luminance
+fn features(y: &[f64], w: usize, h: usize, ppd: f64) -> (Vec<f64>, Vec<f64>)
+{
+    let sd = 0.5*0.082*ppd;
+    let r = (3.*sd).ceil() as isize;
+    let kernels = |point: bool| -> (Vec<f64>, Vec<f64>)
+    {
+        let mut gx = Vec::new();
+        for j in -r..=r
+        {
+            for i in -r..=r
+            {
+                let g = (-((i*i + j*j) as f64)/(2.*sd*sd)).exp();
+                gx.push(if point { ((i*i) as f64/(sd*sd) - 1.)*g } else { -i as f64*g });
+            }
+        }
+        // Positive and negative weights each sum to one
+        let neg: f64 = -gx.iter().filter(|&&v| v < 0.).sum::<f64>();
+        let pos: f64 = gx.iter().filter(|&&v| v > 0.).sum();
+        let gx: Vec<f64> = gx.iter().map(|&v| if v < 0. { v/neg } else { v/pos }).collect();
+        let n = (2*r + 1) as usize;
+        let gy = (0..n*n).map(|k| gx[(k%n)*n + k/n]).collect();
+        (gx, gy)
+    };
+    let magnitude = |point: bool| -> Vec<f64>
+    {
+        let (gx, gy) = kernels(point);
+        let fx = correlate(y, w, h, r as usize, &gx);
+        let fy = correlate(y, w, h, r as usize, &gy);
+        fx.iter().zip(&fy).map(|(a, b)| (a*a + b*b).sqrt()).collect()
+    };
+    (magnitude(false), magnitude(true))
+}
+
+// Per pixel LDR-FLIP error in [0, 1] at the given pixels per degree of
+// visual angle. Values are taken as linear sRGB and clamped to [0, 1], HDR
+// images should be tone mapped first.
+pub fn flip(test: &Image, reference: &Image, ppd: f64) -> Image
+{
+    check_resolution(test, reference);
+    const QC: f64 = 0.7;
+    const QF: f64 = 0.5;
+    const PC: f64 = 0.4;
+    const PT: f64 = 0.95;
+    let (w, h) = (test.width, test.height);
+    let opponent = |img: &Image| -> [Vec<f64>; 3]
+    {
+        let mut channels = [Vec::with_capacity(w*h), Vec::with_capacity(w*h), Vec::with_capacity(w*h)];
+        for c in img.to_rgb()
+        {
+            let v = xyz_to_ycxcz(linear_to_xyz(c.clamp(0., 1.)));
+            for k in 0..3
+            {
+                channels[k].push(v[k]);
+            }
+        }
+        channels
+    };
+    let (t, r) = (opponent(test), opponent(reference));
+    // Color differences after the contrast sensitivity filters
+    let (radius, kernels) = csf_kernels(ppd);
+    let filtered = |o: &[Vec<f64>; 3]| -> Vec<[f64; 3]>
+    {
+        let f: Vec<Vec<f64>> = (0..3).map(|k| correlate(&o[k], w, h, radius, &kernels[k])).collect();
+        (0..w*h).map(|i| hunt_lab(xyz_to_linear(ycxcz_to_xyz([f[0][i], f[1][i], f[2][i]])).clamp(0., 1.))).collect()
+    };
+    let (ft, fr) = (filtered(&t), filtered(&r));
+    let cmax = hyab(hunt_lab(RGB::green()), hunt_lab(RGB::blue())).powf(QC);
+    let pccmax = PC*cmax;
+    // Feature differences on luminance normalized to [0, 1]
+    let normalized = |o: &[Vec<f64>; 3]| o[0].iter().map(|v| (v + 16.)/116.).collect::<Vec<f64>>();
+    let (edges_t, points_t) = features(&normalized(&t), w, h, ppd);
+    let (edges_r, points_r) = features(&normalized(&r), w, h, ppd);
+    let mut out = Image::new(w, h, &["Y"]);
+    for i in 0..w*h
+    {
+        let e = hyab(ft[i], fr[i]).powf(QC);
+        let color = if e < pccmax { PT/pccmax*e } else { PT + (e - pccmax)/(cmax - pccmax)*(1. - PT) };
+        let f = f64::max((edges_t[i] - edges_r[i]).abs(), (points_t[i] - points_r[i]).abs());
+        let feature = (f/2f64.sqrt()).powf(QF);
+        out.data[i] = color.powf(1. - feature);
+    }
+    out
+}
+
+// Approximation of the magma colormap, evenly spaced samples
+const MAGMA: [[f64; 3]; 9] = [
+    [0.001, 0.000, 0.014], [0.113, 0.065, 0.277], [0.317, 0.072, 0.485], [0.513, 0.148, 0.507], [0.716, 0.215, 0.475],
+    [0.894, 0.310, 0.414], [0.987, 0.536, 0.382], [0.995, 0.767, 0.537], [0.987, 0.991, 0.750]];
+
+// False color display-referred RGB of a single channel error image, with
+// zero black and max white
+pub fn heatmap(err: &Image, max: f64) -> Image
+{
+    let mut out = Image::new(err.width, err.height, &["R", "G", "B"]);
+    for y in 0..err.height
+    {
+        for x in 0..err.width
+        {
+            let v = err.get(x, y, 0)/max;
+            let t = if v.is_nan() { 0. } else { v.clamp(0., 1.) }*(MAGMA.len() - 1) as f64;
+            let i = usize::min(t as usize, MAGMA.len() - 2);
+            let (a, b, f) = (MAGMA[i], MAGMA[i + 1], t - i as f64);
+            out.set_rgb(x, y, RGB::new(a[0] + f*(b[0] - a[0]), a[1] + f*(b[1] - a[1]), a[2] + f*(b[2] - a[2])));
+        }
+    }
+    out
+}
@@ -0,0 +1,189 @@
+use std::ops;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex
+{
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex
+{
+    pub fn new(re: f64, im: f64) -> Complex
+    {
+        Complex{ re, im }
+    }
+    pub fn real(re: f64) -> Complex
+    {
+        Complex{ re, im: 0. }
+    }
+    // e^(i theta)
+    pub fn from_polar(r: f64, theta: f64) -> Complex
+    {
+        Complex{ re: r*theta.cos(), im: r*theta.sin() }
+    }
+    pub fn conj(self) -> Complex
+    {
+        Complex{ re: self.re, im: -self.im }
+    }
+    // Squared magnitude
+    pub fn norm(self) -> f64
+    {
+        self.re*self.re + self.im*self.im
+    }
+    pub fn abs(self) -> f64
+    {
+        self.re.hypot(self.im)
+    }
+    pub fn arg(self) -> f64
+    {
+        self.im.atan2(self.re)
+    }
+    // Principal square root, with a non-negative real part
+    pub fn sqrt(self) -> Complex
+    {
+        let r = self.abs();
+        if r == 0.
+        {
+            return Complex::real(0.);
+        }
+        let re = ((r + self.re)/2.).sqrt();
+        let im = ((r - self.re)/2.).sqrt();
+        Complex{ re, im: if self.im < 0. { -im } else { im } }
+    }
+}
+
+impl ops::Add for Complex
+{
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex
+    {
+        Complex{ re: self.re+other.re, im: self.im+other.im }
+    }
+}
+
+impl ops::AddAssign for Complex
+{
+    fn add_assign(&mut self, other: Complex)
+    {
+        *self = *self + other;
+    }
+}
+
+impl ops::Sub for Complex
+{
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex
+    {
+        Complex{ re: self.re-other.re, im: self.im-other.im }
+    }
+}
+
+impl ops::Mul for Complex
+{
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex
+    {
+        Complex{ re: self.re*other.re - self.im*other.im, im: self.re*other.im + self.im*other.re }
+    }
+}
+
+impl ops::Mul<f64> for Complex
+{
+    type Output = Complex;
+    fn mul(self, s: f64) -> Complex
+    {
+        Complex{ re: self.re*s, im: self.im*s }
+    }
+}
+
+impl ops::Div for Complex
+{
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex
+    {
+        let d = other.norm();
+        Complex{ re: (self.re*other.re + self.im*other.im)/d, im: (self.im*other.re - self.re*other.im)/d }
+    }
+}
+
+impl ops::Div<f64> for Complex
+{
+    type Output = Complex;
+    fn div(self, s: f64) -> Complex
+    {
+        Complex{ re: self.re/s, im: self.im/s }
+    }
+}
+
+impl ops::Neg for Complex
+{
+    type Output = Complex;
+    fn neg(self) -> Complex
+    {
+        Complex{ re: -self.re, im: -self.im }
+    }
+}
+
+// In place radix-2 transform, the inverse one is unnormalized
+pub fn fft(data: &mut [Complex], inverse: bool)
+{
+    let n = data.len();
+    assert!(n.is_power_of_two(), "FFT size must be a power of two!");
+    let mut j = 0;
+    for i in 1..n
+    {
+        let mut bit = n >> 1;
+        while j & bit != 0
+        {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j
+        {
+            data.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1. } else { -1. };
+    let mut len = 2;
+    while len <= n
+    {
+        let w = Complex::from_polar(1., sign*2.*std::f64::consts::PI/len as f64);
+        for start in (0..n).step_by(len)
+        {
+            let mut wk = Complex::real(1.);
+            for k in 0..len/2
+            {
+                let a = data[start + k];
+                let b = data[start + k + len/2]*wk;
+                data[start + k] = a + b;
+                data[start + k + len/2] = a - b;
+                wk = wk*w;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Rows then columns of a width x height array stored row by row
+pub fn fft_2d(data: &mut [Complex], width: usize, height: usize, inverse: bool)
+{
+    for row in data.chunks_mut(width)
+    {
+        fft(row, inverse);
+    }
+    let mut column = vec![Complex::real(0.); height];
+    for x in 0..width
+    {
+        for (y, c) in column.iter_mut().enumerate()
+        {
+            *c = data[y*width + x];
+        }
+        fft(&mut column, inverse);
+        for (y, c) in column.iter().enumerate()
+        {
+            data[y*width + x] = *c;
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use crate::film::for_each_filtered;
+use crate::filter::Filter;
+use crate::image::Image;
+use crate::imageio::ExrAttribute;
+
+pub fn murmur3_32(data: &[u8], seed: u32) -> u32
+{
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    let mut h = seed;
+    let blocks = data.chunks_exact(4);
+    let tail = blocks.remainder();
+    for b in blocks
+    {
+        h ^= mix(u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    if !tail.is_empty()
+    {
+        let mut k = 0;
+        for (i, &b) in tail.iter().enumerate()
+        {
+            k ^= (b as u32) << (8*i);
+        }
+        h ^= mix(k);
+    }
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+// Hash of a name as stored in the id channels. Hashes that would make an
+// infinite, NaN or denormal float get one exponent bit flipped.
+pub fn name_to_id(name: &str) -> f32
+{
+    let mut h = murmur3_32(name.as_bytes(), 0);
+    let exp = (h >> 23) & 0xff;
+    if exp == 0 || exp == 255
+    {
+        h ^= 1 << 23;
+    }
+    f32::from_bits(h)
+}
+
+fn json_string(s: &str) -> String
+{
+    let mut out = String::from("\"");
+    for c in s.chars()
+    {
+        match c
+        {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Total filter weight of a pixel and the part of it each id got
+type Coverage = (f64, Vec<(f32, f64)>);
+
+// Cryptomatte id matte of one kind of name, like objects, materials or
+// assets. Coverage is accumulated with the film's reconstruction filter
+// and every pixel keeps the ids with the most of it.
+pub struct Cryptomatte
+{
+    // Type name, CryptoObject, CryptoMaterial or CryptoAsset by convention
+    pub name: String,
+    // Number of ranks written, two per layer
+    pub depth: usize,
+    pub resolution: (usize, usize),
+    pixels: Mutex<Vec<Coverage>>,
+    manifest: Mutex<BTreeMap<String, u32>>,
+}
+
+impl Cryptomatte
+{
+    pub fn new(name: &str, resolution: (usize, usize), depth: usize) -> Cryptomatte
+    {
+        let n = resolution.0*resolution.1;
+        Cryptomatte{ name: name.to_string(), depth, resolution, pixels: Mutex::new(vec![(0., Vec::new()); n]),
+                     manifest: Mutex::new(BTreeMap::new()) }
+    }
+    // Id of a name, adding it to the manifest
+    pub fn register(&self, name: &str) -> f32
+    {
+        let id = name_to_id(name);
+        self.manifest.lock().unwrap().insert(name.to_string(), id.to_bits());
+        id
+    }
+    // Sample at raster position p that saw the given id, or nothing when
+    // id is None
+    pub fn add_sample(&self, filter: &dyn Filter, p: (f64, f64), id: Option<f32>, weight: f64)
+    {
+        let width = self.resolution.0;
+        let mut pixels = self.pixels.lock().unwrap();
+        for_each_filtered(filter, p, (0, 0, self.resolution.0, self.resolution.1), |x, y, w|
+        {
+            let (total, ids) = &mut pixels[y*width + x];
+            *total += w*weight;
+            if let Some(id) = id
+            {
+                match ids.iter_mut().find(|e| e.0.to_bits() == id.to_bits())
+                {
+                    Some(e) => e.1 += w*weight,
+                    None => ids.push((id, w*weight)),
+                }
+            }
+        });
+    }
+    pub fn layer_name(&self, i: usize) -> String
+    {
+        format!("{}{:02}", self.name, i)
+    }
+    // RGBA layers holding the (id, coverage) pairs of two ranks each, most
+    // coverage first
+    pub fn layers(&self) -> Vec<Image>
+    {
+        let (width, height) = self.resolution;
+        let mut layers = vec![Image::new(width, height, &["R", "G", "B", "A"]); self.depth.div_ceil(2)];
+        let pixels = self.pixels.lock().unwrap();
+        for (i, (total, ids)) in pixels.iter().enumerate()
+        {
+            let mut ranked = ids.clone();
+            ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.to_bits().cmp(&b.0.to_bits())));
+            for (rank, &(id, w)) in ranked.iter().take(self.depth).enumerate()
+            {
+                let c = 2*(rank % 2);
+                let data = &mut layers[rank/2].data;
+                data[4*i + c] = id as f64;
+                data[4*i + c + 1] = w/total;
+            }
+        }
+        layers
+    }
+    // JSON object from names to their hashes in hex
+    pub fn manifest(&self) -> String
+    {
+        let entries: Vec<String> = self.manifest.lock().unwrap().iter()
+            .map(|(name, h)| format!("{}:\"{:08x}\"", json_string(name), h)).collect();
+        format!("{{{}}}", entries.join(","))
+    }
+    // Header metadata, keyed by the first 7 hex digits of the hashed name
+    pub fn attributes(&self) -> Vec<(String, ExrAttribute)>
+    {
+        let key = &format!("{:08x}", murmur3_32(self.name.as_bytes(), 0))[..7];
+        let attribute = |k: &str, v: &str| (format!("cryptomatte/{}/{}", key, k), ExrAttribute::String(v.to_string()));
+        vec![attribute("name", &self.name), attribute("hash", "MurmurHash3_32"),
+             attribute("conversion", "uint32_to_float32"), attribute("manifest", &self.manifest())]
+    }
+}
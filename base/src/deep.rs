@@ -0,0 +1,142 @@
+use crate::color::RGB;
+use crate::image::{DeepImage, Image};
+
+// Part of a camera ray from z_front to z_back with its color premultiplied
+// by alpha, surfaces have z_back equal to z_front
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeepSample
+{
+    pub rgb: RGB,
+    pub alpha: f64,
+    pub z_front: f64,
+    pub z_back: f64,
+}
+
+impl DeepSample
+{
+    pub fn new(rgb: RGB, alpha: f64, z_front: f64, z_back: f64) -> DeepSample
+    {
+        DeepSample{ rgb, alpha, z_front, z_back }
+    }
+    pub fn surface(rgb: RGB, alpha: f64, z: f64) -> DeepSample
+    {
+        DeepSample{ rgb, alpha, z_front: z, z_back: z }
+    }
+}
+
+// Weighted sums of the samples seen by a pixel, sorted by z_front. Their
+// alpha is the pixel coverage they're visible over, which adds up across
+// camera samples that see different surfaces. Samples whose depths both lie
+// within the merge tolerance of an existing one are added to it, so opaque
+// surfaces don't grow the list with every sample.
+#[derive(Clone, Debug)]
+pub struct DeepPixel
+{
+    pub samples: Vec<DeepSample>,
+    pub weight_sum: f64,
+}
+
+impl DeepPixel
+{
+    pub fn zero() -> DeepPixel
+    {
+        DeepPixel{ samples: Vec::new(), weight_sum: 0. }
+    }
+    pub fn add(&mut self, s: &DeepSample, w: f64, tolerance: f64)
+    {
+        let i = self.samples.partition_point(|t| t.z_front < s.z_front);
+        let close = |t: &DeepSample| (t.z_front - s.z_front).abs() <= tolerance && (t.z_back - s.z_back).abs() <= tolerance;
+        let target = if i > 0 && close(&self.samples[i - 1]) { Some(i - 1) }
+                     else if i < self.samples.len() && close(&self.samples[i]) { Some(i) }
+                     else { None };
+        match target
+        {
+            Some(j) =>
+            {
+                let t = &mut self.samples[j];
+                t.rgb += s.rgb*w;
+                t.alpha += s.alpha*w;
+                t.z_front = f64::min(t.z_front, s.z_front);
+                t.z_back = f64::max(t.z_back, s.z_back);
+            }
+            None => self.samples.insert(i, DeepSample::new(s.rgb*w, s.alpha*w, s.z_front, s.z_back)),
+        }
+    }
+    // Segments along the path of one camera sample, in any order, each
+    // attenuated by the ones in front of it on the path
+    pub fn add_path(&mut self, samples: &[DeepSample], w: f64, tolerance: f64)
+    {
+        let mut order: Vec<&DeepSample> = samples.iter().collect();
+        order.sort_by(|a, b| a.z_front.total_cmp(&b.z_front));
+        let mut t = 1.;
+        for s in order
+        {
+            self.add(s, w*t, tolerance);
+            t *= 1. - s.alpha;
+        }
+        self.weight_sum += w;
+    }
+    pub fn merge(&mut self, other: &DeepPixel, tolerance: f64)
+    {
+        for s in &other.samples
+        {
+            self.add(s, 1., tolerance);
+        }
+        self.weight_sum += other.weight_sum;
+    }
+}
+
+// Deep image with R, G, B, A, Z and ZBack channels from resolved pixels.
+// A sample covering c of the pixel behind samples covering c_front gets an
+// alpha of c/(1 - c_front), with its color scaled alike, so that compositing
+// front to back gives back the pixel's average.
+pub fn to_image(width: usize, height: usize, pixels: &[DeepPixel]) -> DeepImage
+{
+    let counts: Vec<usize> = pixels.iter().map(|p| p.samples.len()).collect();
+    let mut img = DeepImage::new(width, height, &["R", "G", "B", "A", "Z", "ZBack"], &counts);
+    for (i, p) in pixels.iter().enumerate()
+    {
+        let inv = if p.weight_sum != 0. { 1./p.weight_sum } else { 0. };
+        let mut covered = 0.;
+        for (k, s) in p.samples.iter().enumerate()
+        {
+            let f = if covered < 1. - 1e-9 { inv/(1. - covered) } else { 0. };
+            covered += s.alpha*inv;
+            let v = img.sample_mut(i%width, i/width, k);
+            v.copy_from_slice(&[s.rgb.r*f, s.rgb.g*f, s.rgb.b*f, (s.alpha*f).min(1.), s.z_front, s.z_back]);
+        }
+    }
+    img
+}
+
+// Composites the samples of every pixel front to back into an RGBA image,
+// None if one of the R, G, B, A and Z channels is missing
+pub fn flatten(img: &DeepImage) -> Option<Image>
+{
+    let index = |name: &str| img.channel_index(name);
+    let channels = [index("R")?, index("G")?, index("B")?, index("A")?];
+    let z = index("Z")?;
+    let mut out = Image::new(img.width, img.height, &["R", "G", "B", "A"]);
+    for y in 0..img.height
+    {
+        for x in 0..img.width
+        {
+            let mut samples: Vec<&[f64]> = (0..img.sample_count(x, y)).map(|i| img.sample(x, y, i)).collect();
+            samples.sort_by(|a, b| a[z].total_cmp(&b[z]));
+            let mut acc = [0.; 4];
+            for s in samples
+            {
+                let t = 1. - acc[3];
+                for (a, &c) in acc.iter_mut().zip(&channels)
+                {
+                    *a += t*s[c];
+                }
+            }
+            for (c, &v) in acc.iter().enumerate()
+            {
+                out.set(x, y, c, v);
+            }
+        }
+    }
+    Some(out)
+}
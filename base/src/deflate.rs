@@ -0,0 +1,475 @@
+// zlib streams (RFC 1950/1951) and the checksums used by PNG and OpenEXR
+use std::io;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                              257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                              7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+const WINDOW: usize = 32768;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: usize = 15;
+
+pub fn crc32(data: &[u8]) -> u32
+{
+    crc32_update(0, data)
+}
+
+// Continues a CRC over more data, starting from the CRC of what came before
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32
+{
+    let mut c = !crc;
+    for &b in data
+    {
+        c ^= b as u32;
+        for _ in 0..8
+        {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+    }
+    !c
+}
+
+pub fn adler32(data: &[u8]) -> u32
+{
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552)
+    {
+        for &x in chunk
+        {
+            a += x as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+struct BitWriter
+{
+    out: Vec<u8>,
+    acc: u64,
+    n: u32,
+}
+
+impl BitWriter
+{
+    fn write(&mut self, bits: u32, count: u32)
+    {
+        self.acc |= (bits as u64) << self.n;
+        self.n += count;
+        while self.n >= 8
+        {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n -= 8;
+        }
+    }
+    // Huffman codes go out most significant bit first
+    fn write_code(&mut self, code: u32, len: u32)
+    {
+        let mut rev = 0;
+        for i in 0..len
+        {
+            rev |= ((code >> i) & 1) << (len - 1 - i);
+        }
+        self.write(rev, len);
+    }
+    fn finish(mut self) -> Vec<u8>
+    {
+        if self.n > 0
+        {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(w: &mut BitWriter, sym: usize)
+{
+    let sym = sym as u32;
+    match sym
+    {
+        0..=143 => w.write_code(0x30 + sym, 8),
+        144..=255 => w.write_code(0x190 + sym - 144, 9),
+        256..=279 => w.write_code(sym - 256, 7),
+        _ => w.write_code(0xc0 + sym - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize)
+{
+    let li = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+    write_literal(w, 257 + li);
+    w.write((len - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li] as u32);
+    let di = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    w.write_code(di as u32, 5);
+    w.write((dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+fn hash3(data: &[u8], i: usize) -> usize
+{
+    let v = (data[i] as usize) << 16 | (data[i + 1] as usize) << 8 | data[i + 2] as usize;
+    (v.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+// Raw deflate with a single fixed Huffman block and greedy LZ77 matching
+pub fn deflate(data: &[u8]) -> Vec<u8>
+{
+    let mut w = BitWriter{ out: Vec::with_capacity(data.len()/2 + 16), acc: 0, n: 0 };
+    w.write(1, 1);
+    w.write(1, 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize|
+    {
+        if i + 2 < data.len()
+        {
+            let h = hash3(data, i);
+            prev[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+    };
+    let mut i = 0;
+    while i < data.len()
+    {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + 2 < data.len()
+        {
+            let mut cand = head[hash3(data, i)];
+            let max_len = usize::min(MAX_MATCH, data.len() - i);
+            let mut chain = 0;
+            while cand != usize::MAX && i - cand <= WINDOW && chain < MAX_CHAIN
+            {
+                let mut l = 0;
+                while l < max_len && data[cand + l] == data[i + l]
+                {
+                    l += 1;
+                }
+                if l > best_len
+                {
+                    best_len = l;
+                    best_dist = i - cand;
+                    if l == max_len
+                    {
+                        break;
+                    }
+                }
+                let next = prev[cand % WINDOW];
+                if next == usize::MAX || next >= cand
+                {
+                    break;
+                }
+                cand = next;
+                chain += 1;
+            }
+        }
+        if best_len >= 3
+        {
+            write_match(&mut w, best_len, best_dist);
+            for j in i..i + best_len
+            {
+                insert(&mut head, &mut prev, j);
+            }
+            i += best_len;
+        }
+        else
+        {
+            write_literal(&mut w, data[i] as usize);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+    write_literal(&mut w, 256);
+    w.finish()
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8>
+{
+    let mut out = vec![0x78, 0x9c];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn invalid(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+struct BitReader<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+    acc: u64,
+    n: u32,
+}
+
+impl<'a> BitReader<'a>
+{
+    // Past the end of the data the stream reads as zeros, overruns are
+    // caught in consume
+    fn refill(&mut self)
+    {
+        while self.n <= 56
+        {
+            let b = if self.pos < self.data.len() { self.data[self.pos] } else { 0 };
+            self.acc |= (b as u64) << self.n;
+            self.pos += 1;
+            self.n += 8;
+        }
+    }
+    fn peek(&mut self, count: u32) -> u32
+    {
+        self.refill();
+        (self.acc & ((1u64 << count) - 1)) as u32
+    }
+    fn consume(&mut self, count: u32) -> io::Result<()>
+    {
+        self.acc >>= count;
+        self.n -= count;
+        if self.pos - (self.n as usize/8) > self.data.len()
+        {
+            return Err(invalid("unexpected end of deflate stream"));
+        }
+        Ok(())
+    }
+    fn bits(&mut self, count: u32) -> io::Result<u32>
+    {
+        if count == 0
+        {
+            return Ok(0);
+        }
+        let v = self.peek(count);
+        self.consume(count)?;
+        Ok(v)
+    }
+    fn align(&mut self)
+    {
+        let r = self.n % 8;
+        self.acc >>= r;
+        self.n -= r;
+    }
+}
+
+// Canonical Huffman decoding table indexed by the next MAX_BITS input bits
+const MAX_BITS: u32 = 15;
+
+struct Huffman
+{
+    table: Vec<(u16, u8)>,
+}
+
+impl Huffman
+{
+    fn new(lengths: &[u8]) -> io::Result<Huffman>
+    {
+        let mut count = [0u32; 16];
+        for &l in lengths
+        {
+            count[l as usize] += 1;
+        }
+        count[0] = 0;
+        let mut next = [0u32; 16];
+        let mut code = 0;
+        for bits in 1..16
+        {
+            code = (code + count[bits - 1]) << 1;
+            next[bits] = code;
+        }
+        let mut table = vec![(0u16, 0u8); 1 << MAX_BITS];
+        for (sym, &l) in lengths.iter().enumerate()
+        {
+            if l == 0
+            {
+                continue;
+            }
+            let c = next[l as usize];
+            next[l as usize] += 1;
+            if c >= 1 << l
+            {
+                return Err(invalid("oversubscribed Huffman code"));
+            }
+            let mut rev = 0;
+            for i in 0..l
+            {
+                rev |= ((c >> i) & 1) << (l - 1 - i);
+            }
+            let mut idx = rev as usize;
+            while idx < table.len()
+            {
+                table[idx] = (sym as u16, l);
+                idx += 1 << l;
+            }
+        }
+        Ok(Huffman{ table })
+    }
+    fn decode(&self, br: &mut BitReader) -> io::Result<u16>
+    {
+        let (sym, len) = self.table[br.peek(MAX_BITS) as usize];
+        if len == 0
+        {
+            return Err(invalid("invalid Huffman code"));
+        }
+        br.consume(len as u32)?;
+        Ok(sym)
+    }
+}
+
+fn fixed_tables() -> io::Result<(Huffman, Huffman)>
+{
+    let mut lengths = [0u8; 288];
+    for (i, l) in lengths.iter_mut().enumerate()
+    {
+        *l = match i
+        {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_tables(br: &mut BitReader) -> io::Result<(Huffman, Huffman)>
+{
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let hlit = br.bits(5)? as usize + 257;
+    let hdist = br.bits(5)? as usize + 1;
+    let hclen = br.bits(4)? as usize + 4;
+    let mut cl = [0u8; 19];
+    for &o in ORDER.iter().take(hclen)
+    {
+        cl[o] = br.bits(3)? as u8;
+    }
+    let cl = Huffman::new(&cl)?;
+    let mut lengths = vec![0u8; hlit + hdist];
+    let mut i = 0;
+    while i < hlit + hdist
+    {
+        let sym = cl.decode(br)?;
+        let (value, repeat) = match sym
+        {
+            0..=15 => (sym as u8, 1),
+            16 =>
+            {
+                if i == 0
+                {
+                    return Err(invalid("repeat without a previous length"));
+                }
+                (lengths[i - 1], 3 + br.bits(2)? as usize)
+            }
+            17 => (0, 3 + br.bits(3)? as usize),
+            _ => (0, 11 + br.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len()
+        {
+            return Err(invalid("too many code lengths"));
+        }
+        for l in lengths.iter_mut().skip(i).take(repeat)
+        {
+            *l = value;
+        }
+        i += repeat;
+    }
+    Ok((Huffman::new(&lengths[..hlit])?, Huffman::new(&lengths[hlit..])?))
+}
+
+pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>>
+{
+    let mut br = BitReader{ data, pos: 0, acc: 0, n: 0 };
+    let mut out: Vec<u8> = Vec::with_capacity(data.len()*4);
+    loop
+    {
+        let last = br.bits(1)?;
+        match br.bits(2)?
+        {
+            0 =>
+            {
+                br.align();
+                let len = br.bits(16)? as usize;
+                let nlen = br.bits(16)? as usize;
+                if len != !nlen & 0xffff
+                {
+                    return Err(invalid("corrupt stored block"));
+                }
+                for _ in 0..len
+                {
+                    out.push(br.bits(8)? as u8);
+                }
+            }
+            kind @ 1..=2 =>
+            {
+                let (lit, dist) = if kind == 1 { fixed_tables()? } else { dynamic_tables(&mut br)? };
+                loop
+                {
+                    let sym = lit.decode(&mut br)? as usize;
+                    if sym < 256
+                    {
+                        out.push(sym as u8);
+                        continue;
+                    }
+                    if sym == 256
+                    {
+                        break;
+                    }
+                    if sym > 285
+                    {
+                        return Err(invalid("invalid length symbol"));
+                    }
+                    let li = sym - 257;
+                    let len = LENGTH_BASE[li] as usize + br.bits(LENGTH_EXTRA[li] as u32)? as usize;
+                    let di = dist.decode(&mut br)? as usize;
+                    if di >= 30
+                    {
+                        return Err(invalid("invalid distance symbol"));
+                    }
+                    let d = DIST_BASE[di] as usize + br.bits(DIST_EXTRA[di] as u32)? as usize;
+                    if d > out.len()
+                    {
+                        return Err(invalid("distance too far back"));
+                    }
+                    let start = out.len() - d;
+                    for k in 0..len
+                    {
+                        let b = out[start + k];
+                        out.push(b);
+                    }
+                }
+            }
+            _ => return Err(invalid("invalid block type")),
+        }
+        if last == 1
+        {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+pub fn zlib_decompress(data: &[u8]) -> io::Result<Vec<u8>>
+{
+    if data.len() < 6 || data[0] & 0x0f != 8 || ((data[0] as u32) << 8 | data[1] as u32) % 31 != 0
+    {
+        return Err(invalid("not a zlib stream"));
+    }
+    if data[1] & 0x20 != 0
+    {
+        return Err(invalid("zlib preset dictionaries are not supported"));
+    }
+    let out = inflate(&data[2..])?;
+    let n = data.len();
+    let expected = u32::from_be_bytes([data[n - 4], data[n - 3], data[n - 2], data[n - 1]]);
+    if adler32(&out) != expected
+    {
+        return Err(invalid("zlib checksum mismatch"));
+    }
+    Ok(out)
+}
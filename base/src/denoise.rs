@@ -0,0 +1,358 @@
+use crate::color::RGB;
+use crate::image::Image;
+use crate::vector::Vec3d;
+
+// Buffers the filters avoid blurring across, at the resolution of the color
+// image. Albedo is divided out before filtering and multiplied back after,
+// so texture detail survives. Variance is the per channel variance of the
+// pixel estimates, see Film::resolve_variance, without it the variance of
+// the 3x3 neighborhood is used.
+#[derive(Clone, Copy, Default)]
+pub struct Guides<'a>
+{
+    pub albedo: Option<&'a Image>,
+    pub normal: Option<&'a Image>,
+    pub depth: Option<&'a Image>,
+    pub variance: Option<&'a Image>,
+}
+
+// Gaussian falloffs of the feature weights on the distance between normals,
+// the relative depth difference and the distance between albedos
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeatureSigmas
+{
+    pub normal: f64,
+    pub depth: f64,
+    pub albedo: f64,
+}
+
+impl Default for FeatureSigmas
+{
+    fn default() -> FeatureSigmas
+    {
+        FeatureSigmas{ normal: 0.3, depth: 0.1, albedo: 0.1 }
+    }
+}
+
+// Edge avoiding a-trous wavelet filter, 5x5 B3 spline kernels spread 2^i
+// pixels apart at iteration i. Colors are compared by luminance relative to
+// the standard deviation of the estimate, which is filtered along.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ATrous
+{
+    pub iterations: usize,
+    pub sigma_color: f64,
+    pub features: FeatureSigmas,
+}
+
+impl Default for ATrous
+{
+    fn default() -> ATrous
+    {
+        ATrous{ iterations: 5, sigma_color: 4., features: FeatureSigmas::default() }
+    }
+}
+
+// Non-local means with the variance cancelling patch distance, pixels are
+// weighted by the smaller of the patch and feature weights
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NlMeans
+{
+    pub search_radius: usize,
+    pub patch_radius: usize,
+    // Filter strength and how much of the variance is cancelled
+    pub k: f64,
+    pub alpha: f64,
+    pub features: FeatureSigmas,
+}
+
+impl Default for NlMeans
+{
+    fn default() -> NlMeans
+    {
+        NlMeans{ search_radius: 7, patch_radius: 3, k: 0.45, alpha: 1., features: FeatureSigmas::default() }
+    }
+}
+
+const B3: [f64; 5] = [1./16., 1./4., 3./8., 1./4., 1./16.];
+
+// Guide values per pixel, row by row from the top
+struct Features
+{
+    normal: Option<Vec<Vec3d>>,
+    depth: Option<Vec<f64>>,
+    albedo: Option<Vec<RGB>>,
+    sigmas: FeatureSigmas,
+}
+
+impl Features
+{
+    fn new(guides: &Guides, sigmas: FeatureSigmas) -> Features
+    {
+        let normal = guides.normal.map(|n| (0..n.width*n.height).map(|i|
+        {
+            let (x, y) = (i%n.width, i/n.width);
+            Vec3d::new(n.get(x, y, 0), n.get(x, y, 1), n.get(x, y, 2))
+        }).collect());
+        let depth = guides.depth.map(|d| d.data.iter().step_by(d.channel_count()).cloned().collect());
+        let albedo = guides.albedo.map(|a| a.to_rgb());
+        Features{ normal, depth, albedo, sigmas }
+    }
+    fn weight(&self, p: usize, q: usize) -> f64
+    {
+        let mut e = 0.;
+        if let Some(n) = &self.normal
+        {
+            e += (n[p] - n[q]).lensq()/(self.sigmas.normal*self.sigmas.normal);
+        }
+        if let Some(z) = &self.depth
+        {
+            let d = depth_distance(z[p], z[q]);
+            e += d*d/(self.sigmas.depth*self.sigmas.depth);
+        }
+        if let Some(a) = &self.albedo
+        {
+            let d = a[p] - a[q];
+            e += (d.r*d.r + d.g*d.g + d.b*d.b)/(self.sigmas.albedo*self.sigmas.albedo);
+        }
+        (-e).exp()
+    }
+}
+
+// Relative difference, misses have infinite depth
+fn depth_distance(a: f64, b: f64) -> f64
+{
+    if a == b
+    {
+        0.
+    }
+    else if !a.is_finite() || !b.is_finite()
+    {
+        1.
+    }
+    else
+    {
+        (a - b).abs()/f64::max(a.abs(), b.abs())
+    }
+}
+
+// Albedo channels too dark to divide by are left alone
+fn albedo_factor(a: f64) -> f64
+{
+    if a > 1e-3 { a } else { 1. }
+}
+
+// Color and variance with the albedo divided out, and the albedo itself
+fn demodulate(color: &Image, guides: &Guides) -> (Vec<RGB>, Vec<RGB>, Vec<RGB>)
+{
+    let (w, h) = (color.width, color.height);
+    let albedo: Vec<RGB> = match guides.albedo
+    {
+        Some(a) => a.to_rgb().iter().map(|c| c.map(albedo_factor)).collect(),
+        None => vec![RGB::white(); w*h],
+    };
+    let c: Vec<RGB> = color.to_rgb().iter().zip(&albedo).map(|(&c, &a)| c/a).collect();
+    let var = match guides.variance
+    {
+        Some(v) => v.to_rgb().iter().zip(&albedo).map(|(&v, &a)| v/(a*a)).collect(),
+        None => local_variance(&c, w, h),
+    };
+    (c, var, albedo)
+}
+
+fn local_variance(c: &[RGB], w: usize, h: usize) -> Vec<RGB>
+{
+    let mut var = Vec::with_capacity(w*h);
+    for y in 0..h
+    {
+        for x in 0..w
+        {
+            let (mut sum, mut sum_sq, mut n) = (RGB::black(), RGB::black(), 0.);
+            for v in y.saturating_sub(1)..usize::min(y + 2, h)
+            {
+                for u in x.saturating_sub(1)..usize::min(x + 2, w)
+                {
+                    let s = c[v*w + u];
+                    sum += s;
+                    sum_sq += s*s;
+                    n += 1.;
+                }
+            }
+            let mean = sum/n;
+            var.push((sum_sq/n - mean*mean).map(|v| v.max(0.)));
+        }
+    }
+    var
+}
+
+// Color image with the filtered RGB, other channels are copied over
+fn remodulate(color: &Image, c: &[RGB], albedo: &[RGB]) -> Image
+{
+    let mut out = color.clone();
+    for (i, (&c, &a)) in c.iter().zip(albedo).enumerate()
+    {
+        out.set_rgb(i%color.width, i/color.width, c*a);
+    }
+    out
+}
+
+impl ATrous
+{
+    pub fn apply(&self, color: &Image, guides: &Guides) -> Image
+    {
+        let (w, h) = (color.width, color.height);
+        let features = Features::new(guides, self.features);
+        let (mut c, var, albedo) = demodulate(color, guides);
+        let mut var: Vec<f64> = var.iter().map(|v| v.luminance()).collect();
+        for i in 0..self.iterations
+        {
+            let step = 1isize << i;
+            let std_dev = blur3(&var, w, h).iter().map(|v| v.max(0.).sqrt()).collect::<Vec<f64>>();
+            let mut next_c = Vec::with_capacity(w*h);
+            let mut next_var = Vec::with_capacity(w*h);
+            for y in 0..h as isize
+            {
+                for x in 0..w as isize
+                {
+                    let p = y as usize*w + x as usize;
+                    let lp = c[p].luminance();
+                    let (mut sum, mut sum_var, mut sum_w) = (RGB::black(), 0., 0.);
+                    for (j, hy) in B3.iter().enumerate()
+                    {
+                        let qy = y + (j as isize - 2)*step;
+                        if qy < 0 || qy >= h as isize
+                        {
+                            continue;
+                        }
+                        for (k, hx) in B3.iter().enumerate()
+                        {
+                            let qx = x + (k as isize - 2)*step;
+                            if qx < 0 || qx >= w as isize
+                            {
+                                continue;
+                            }
+                            let q = qy as usize*w + qx as usize;
+                            let dl = (lp - c[q].luminance()).abs();
+                            let wc = (-dl/(self.sigma_color*std_dev[p] + 1e-10)).exp();
+                            let wq = hx*hy*wc*features.weight(p, q);
+                            sum += c[q]*wq;
+                            sum_var += wq*wq*var[q];
+                            sum_w += wq;
+                        }
+                    }
+                    next_c.push(sum/sum_w);
+                    next_var.push(sum_var/(sum_w*sum_w));
+                }
+            }
+            c = next_c;
+            var = next_var;
+        }
+        remodulate(color, &c, &albedo)
+    }
+}
+
+// 3x3 Gaussian, renormalized at the borders
+fn blur3(v: &[f64], w: usize, h: usize) -> Vec<f64>
+{
+    const K: [f64; 3] = [0.25, 0.5, 0.25];
+    let mut out = Vec::with_capacity(w*h);
+    for y in 0..h
+    {
+        for x in 0..w
+        {
+            let (mut sum, mut sum_w) = (0., 0.);
+            for (j, ky) in K.iter().enumerate()
+            {
+                for (i, kx) in K.iter().enumerate()
+                {
+                    let (u, t) = ((x + i).wrapping_sub(1), (y + j).wrapping_sub(1));
+                    if u < w && t < h
+                    {
+                        sum += kx*ky*v[t*w + u];
+                        sum_w += kx*ky;
+                    }
+                }
+            }
+            out.push(sum/sum_w);
+        }
+    }
+    out
+}
+
+// Mean over the (2r + 1)^2 window, clamped to the image
+fn box_filter(v: &[f64], w: usize, h: usize, r: usize) -> Vec<f64>
+{
+    let mut rows = vec![0.; w*h];
+    for y in 0..h
+    {
+        for x in 0..w
+        {
+            let (x0, x1) = (x.saturating_sub(r), usize::min(x + r + 1, w));
+            rows[y*w + x] = v[y*w + x0..y*w + x1].iter().sum::<f64>()/(x1 - x0) as f64;
+        }
+    }
+    let mut out = vec![0.; w*h];
+    for y in 0..h
+    {
+        let (y0, y1) = (y.saturating_sub(r), usize::min(y + r + 1, h));
+        for x in 0..w
+        {
+            out[y*w + x] = (y0..y1).map(|t| rows[t*w + x]).sum::<f64>()/(y1 - y0) as f64;
+        }
+    }
+    out
+}
+
+impl NlMeans
+{
+    pub fn apply(&self, color: &Image, guides: &Guides) -> Image
+    {
+        let (w, h) = (color.width, color.height);
+        let features = Features::new(guides, self.features);
+        let (c, var, albedo) = demodulate(color, guides);
+        let mut sum = vec![RGB::black(); w*h];
+        let mut sum_w = vec![0.; w*h];
+        let r = self.search_radius as isize;
+        let k2 = self.k*self.k;
+        let clamped = |x: isize, y: isize| y.clamp(0, h as isize - 1) as usize*w + x.clamp(0, w as isize - 1) as usize;
+        let mut d = vec![0.; w*h];
+        for dy in -r..=r
+        {
+            for dx in -r..=r
+            {
+                // Per pixel distance to the offset one, averaged over patches,
+                // with pixels past the border taken from the edge
+                for y in 0..h as isize
+                {
+                    for x in 0..w as isize
+                    {
+                        let p = y as usize*w + x as usize;
+                        let q = clamped(x + dx, y + dy);
+                        let channel = |up: f64, uq: f64, vp: f64, vq: f64|
+                            ((up - uq)*(up - uq) - self.alpha*(vp + f64::min(vp, vq)))/(1e-10 + k2*(vp + vq));
+                        d[p] = (channel(c[p].r, c[q].r, var[p].r, var[q].r) + channel(c[p].g, c[q].g, var[p].g, var[q].g)
+                                + channel(c[p].b, c[q].b, var[p].b, var[q].b))/3.;
+                    }
+                }
+                let patch = box_filter(&d, w, h, self.patch_radius);
+                for y in 0..h as isize
+                {
+                    for x in 0..w as isize
+                    {
+                        let (qx, qy) = (x + dx, y + dy);
+                        if qx < 0 || qy < 0 || qx >= w as isize || qy >= h as isize
+                        {
+                            continue;
+                        }
+                        let (p, q) = (y as usize*w + x as usize, qy as usize*w + qx as usize);
+                        let wq = f64::min((-patch[p].max(0.)).exp(), features.weight(p, q));
+                        sum[p] += c[q]*wq;
+                        sum_w[p] += wq;
+                    }
+                }
+            }
+        }
+        let c: Vec<RGB> = sum.iter().zip(&sum_w).map(|(&s, &w)| s/w).collect();
+        remodulate(color, &c, &albedo)
+    }
+}
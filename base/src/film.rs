@@ -0,0 +1,897 @@
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::aov::{Aov, AovSample};
+use crate::camera::Camera;
+use crate::checkpoint::{put_f64, put_u64, StateReader};
+use crate::color::RGB;
+use crate::cryptomatte::Cryptomatte;
+use crate::deep::{self, DeepPixel, DeepSample};
+use crate::filter::Filter;
+use crate::image::{DeepImage, Image};
+use crate::imageio::{self, ExrCompression, ExrLayer, ExrOptions, ExrPixelType};
+use crate::lpe::Lpe;
+use crate::metadata::RenderMetadata;
+use crate::polarization::RGBStokes;
+use crate::vector::{Point2, Vec3d};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Pixel
+{
+    pub rgb_sum: [f64; 3],
+    pub weight_sum: f64,
+}
+
+impl Pixel
+{
+    pub fn zero() -> Pixel
+    {
+        Pixel{ rgb_sum: [0.; 3], weight_sum: 0. }
+    }
+    fn add(&mut self, l: RGB, w: f64)
+    {
+        self.rgb_sum[0] += w*l.r;
+        self.rgb_sum[1] += w*l.g;
+        self.rgb_sum[2] += w*l.b;
+        self.weight_sum += w;
+    }
+    fn merge(&mut self, other: &Pixel)
+    {
+        for c in 0..3
+        {
+            self.rgb_sum[c] += other.rgb_sum[c];
+        }
+        self.weight_sum += other.weight_sum;
+    }
+}
+
+// Unfiltered sample count and moments of the samples inside a pixel
+#[derive(Clone, Copy, Debug)]
+struct Moments
+{
+    n: f64,
+    sum: RGB,
+    sum_sq: RGB,
+}
+
+impl Moments
+{
+    fn zero() -> Moments
+    {
+        Moments{ n: 0., sum: RGB::black(), sum_sq: RGB::black() }
+    }
+    fn add(&mut self, l: RGB)
+    {
+        self.n += 1.;
+        self.sum += l;
+        self.sum_sq += l*l;
+    }
+    fn merge(&mut self, other: &Moments)
+    {
+        self.n += other.n;
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+    }
+    // Variance of the mean, from the unbiased sample variance
+    fn variance(&self) -> RGB
+    {
+        if self.n < 2.
+        {
+            return RGB::black();
+        }
+        let mean = self.sum/self.n;
+        ((self.sum_sq - mean*mean*self.n)/(self.n*(self.n - 1.))).map(|v| v.max(0.))
+    }
+}
+
+// Filter weighted sum of the per channel Stokes vectors of a pixel's
+// samples
+#[derive(Clone, Copy, Debug)]
+struct StokesPixel
+{
+    sum: RGBStokes,
+    weight_sum: f64,
+}
+
+impl StokesPixel
+{
+    fn zero() -> StokesPixel
+    {
+        StokesPixel{ sum: RGBStokes::zero(), weight_sum: 0. }
+    }
+    fn merge(&mut self, other: &StokesPixel)
+    {
+        self.sum += other.sum;
+        self.weight_sum += other.weight_sum;
+    }
+    fn resolve(&self) -> RGBStokes
+    {
+        if self.weight_sum != 0. { self.sum*(1./self.weight_sum) } else { RGBStokes::zero() }
+    }
+}
+
+// Raster coordinates are in pixels with (0, 0) the top left corner of the
+// image, so pixel (x, y) is centered at (x + 0.5, y + 0.5). Tiles are the
+// way to render in parallel: every thread fills its own FilmTile without
+// locking and merges it back once. The add_* methods of Film take a lock
+// over the whole buffer for every sample, so threads calling them are
+// serialized, they are meant for single threaded callers and for sparse
+// samples. Splats from light tracing are the exception and are added
+// atomically, as they land anywhere on the image. AOVs aren't filtered,
+// each pixel only sees the samples inside of it, and neither are deep
+// samples or the variance estimates. Light path expression passes and
+// Stokes vectors are filtered like the beauty image, see lpe::LpeTracker.
+pub struct Film
+{
+    pub resolution: (usize, usize),
+    pub filter: Box<dyn Filter + Send + Sync>,
+    pixels: Mutex<Vec<Pixel>>,
+    splats: Vec<[AtomicU64; 3]>,
+    aovs: Vec<Aov>,
+    aov_data: Mutex<Vec<f64>>,
+    lpes: Vec<(String, Lpe)>,
+    lpe_pixels: Mutex<Vec<Pixel>>,
+    deep_tolerance: Option<f64>,
+    deep_pixels: Mutex<Vec<DeepPixel>>,
+    variance: bool,
+    moments: Mutex<Vec<Moments>>,
+    polarized: bool,
+    stokes: Mutex<Vec<StokesPixel>>,
+}
+
+impl Film
+{
+    pub fn new(resolution: (usize, usize), filter: Box<dyn Filter + Send + Sync>) -> Film
+    {
+        let n = resolution.0*resolution.1;
+        let splats = (0..n).map(|_| [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)]).collect();
+        Film{ resolution, filter, pixels: Mutex::new(vec![Pixel::zero(); n]), splats, aovs: Vec::new(), aov_data: Mutex::new(Vec::new()),
+              lpes: Vec::new(), lpe_pixels: Mutex::new(Vec::new()), deep_tolerance: None, deep_pixels: Mutex::new(Vec::new()),
+              variance: false, moments: Mutex::new(Vec::new()), polarized: false, stokes: Mutex::new(Vec::new()) }
+    }
+    // Starts recording the given AOVs, dropping anything recorded so far
+    pub fn set_aovs(&mut self, aovs: &[Aov])
+    {
+        self.aovs = aovs.to_vec();
+        self.aov_data = Mutex::new(aov_records(aovs, self.resolution.0*self.resolution.1));
+    }
+    pub fn aovs(&self) -> &[Aov]
+    {
+        &self.aovs
+    }
+    // Starts recording a pass for each named expression
+    pub fn set_lpes(&mut self, lpes: Vec<(String, Lpe)>)
+    {
+        let n = self.resolution.0*self.resolution.1*lpes.len();
+        self.lpes = lpes;
+        self.lpe_pixels = Mutex::new(vec![Pixel::zero(); n]);
+    }
+    pub fn lpes(&self) -> &[(String, Lpe)]
+    {
+        &self.lpes
+    }
+    // Starts recording deep samples, the ones of a pixel less than
+    // merge_tolerance apart in depth are combined
+    pub fn enable_deep(&mut self, merge_tolerance: f64)
+    {
+        self.deep_tolerance = Some(merge_tolerance);
+        self.deep_pixels = Mutex::new(vec![DeepPixel::zero(); self.resolution.0*self.resolution.1]);
+    }
+    pub fn is_deep(&self) -> bool
+    {
+        self.deep_tolerance.is_some()
+    }
+    // Starts tracking the per pixel variance the denoisers are guided by
+    pub fn enable_variance(&mut self)
+    {
+        self.variance = true;
+        self.moments = Mutex::new(vec![Moments::zero(); self.resolution.0*self.resolution.1]);
+    }
+    pub fn tracks_variance(&self) -> bool
+    {
+        self.variance
+    }
+    // Starts recording the Stokes vectors of camera samples, for the degree
+    // and angle of polarization
+    pub fn enable_polarization(&mut self)
+    {
+        self.polarized = true;
+        self.stokes = Mutex::new(vec![StokesPixel::zero(); self.resolution.0*self.resolution.1]);
+    }
+    pub fn is_polarized(&self) -> bool
+    {
+        self.polarized
+    }
+    // Film coordinates are the [-1, 1] ones taken by Camera::generate_ray
+    pub fn film_to_raster(&self, pf: (f64, f64)) -> (f64, f64)
+    {
+        ((pf.0 + 1.)/2.*self.resolution.0 as f64, (1. - pf.1)/2.*self.resolution.1 as f64)
+    }
+    pub fn raster_to_film(&self, p: (f64, f64)) -> (f64, f64)
+    {
+        (2.*p.0/self.resolution.0 as f64 - 1., 1. - 2.*p.1/self.resolution.1 as f64)
+    }
+    // Tile for the samples taken in pixels [x0, x1) x [y0, y1)
+    pub fn tile(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> FilmTile<'_>
+    {
+        let (rx, ry) = self.filter.radius();
+        let bx0 = (x0 as f64 - rx).floor().max(0.) as usize;
+        let by0 = (y0 as f64 - ry).floor().max(0.) as usize;
+        let bx1 = usize::min((x1 as f64 + rx).ceil() as usize, self.resolution.0);
+        let by1 = usize::min((y1 as f64 + ry).ceil() as usize, self.resolution.1);
+        let n = (bx1 - bx0)*(by1 - by0);
+        FilmTile{ film: self, bounds: (bx0, by0, bx1, by1), pixels: vec![Pixel::zero(); n], aov_data: aov_records(&self.aovs, n),
+                   lpe_pixels: vec![Pixel::zero(); n*self.lpes.len()],
+                   deep_pixels: vec![DeepPixel::zero(); if self.is_deep() { n } else { 0 }],
+                   moments: vec![Moments::zero(); if self.variance { n } else { 0 }],
+                   stokes: vec![StokesPixel::zero(); if self.polarized { n } else { 0 }] }
+    }
+    pub fn merge_tile(&self, tile: FilmTile)
+    {
+        let (x0, y0, x1, y1) = tile.bounds;
+        let mut pixels = self.pixels.lock().unwrap();
+        for y in y0..y1
+        {
+            for x in x0..x1
+            {
+                let p = &tile.pixels[(y - y0)*(x1 - x0) + x - x0];
+                pixels[y*self.resolution.0 + x].merge(p);
+            }
+        }
+        drop(pixels);
+        if self.variance
+        {
+            let mut moments = self.moments.lock().unwrap();
+            for y in y0..y1
+            {
+                for x in x0..x1
+                {
+                    moments[y*self.resolution.0 + x].merge(&tile.moments[(y - y0)*(x1 - x0) + x - x0]);
+                }
+            }
+        }
+        if self.polarized
+        {
+            let mut stokes = self.stokes.lock().unwrap();
+            for y in y0..y1
+            {
+                for x in x0..x1
+                {
+                    stokes[y*self.resolution.0 + x].merge(&tile.stokes[(y - y0)*(x1 - x0) + x - x0]);
+                }
+            }
+        }
+        let passes = self.lpes.len();
+        let mut lpe_pixels = self.lpe_pixels.lock().unwrap();
+        for y in y0..y1
+        {
+            for x in x0..x1
+            {
+                let i = ((y - y0)*(x1 - x0) + x - x0)*passes;
+                let j = (y*self.resolution.0 + x)*passes;
+                for k in 0..passes
+                {
+                    lpe_pixels[j + k].merge(&tile.lpe_pixels[i + k]);
+                }
+            }
+        }
+        drop(lpe_pixels);
+        if let Some(tolerance) = self.deep_tolerance
+        {
+            let mut deep_pixels = self.deep_pixels.lock().unwrap();
+            for y in y0..y1
+            {
+                for x in x0..x1
+                {
+                    deep_pixels[y*self.resolution.0 + x].merge(&tile.deep_pixels[(y - y0)*(x1 - x0) + x - x0], tolerance);
+                }
+            }
+        }
+        if self.aovs.is_empty()
+        {
+            return;
+        }
+        let stride = aov_stride(&self.aovs);
+        let mut data = self.aov_data.lock().unwrap();
+        for y in y0..y1
+        {
+            for x in x0..x1
+            {
+                let i = ((y - y0)*(x1 - x0) + x - x0)*stride;
+                let j = (y*self.resolution.0 + x)*stride;
+                merge_aov(&mut data[j..j + stride], &tile.aov_data[i..i + stride], &self.aovs);
+            }
+        }
+    }
+    // Locks the whole image, use a FilmTile from parallel renderers
+    pub fn add_sample(&self, p: (f64, f64), l: RGB, weight: f64)
+    {
+        let mut pixels = self.pixels.lock().unwrap();
+        let width = self.resolution.0;
+        let bounds = (0, 0, self.resolution.0, self.resolution.1);
+        for_each_filtered(self.filter.as_ref(), p, bounds, |x, y, w|
+        {
+            pixels[y*width + x].add(l, w*weight);
+        });
+        drop(pixels);
+        if self.variance
+        {
+            if let Some((x, y, _)) = containing_pixel(p, bounds)
+            {
+                self.moments.lock().unwrap()[y*width + x].add(l);
+            }
+        }
+    }
+    // Unfiltered contribution from light tracing, scaled at resolve time
+    pub fn add_splat(&self, p: (f64, f64), l: RGB)
+    {
+        let (x, y) = (p.0.floor(), p.1.floor());
+        if x < 0. || y < 0. || x >= self.resolution.0 as f64 || y >= self.resolution.1 as f64
+        {
+            return;
+        }
+        let s = &self.splats[y as usize*self.resolution.0 + x as usize];
+        atomic_add(&s[0], l.r);
+        atomic_add(&s[1], l.g);
+        atomic_add(&s[2], l.b);
+    }
+    // Radiance of a camera sample in every pass, zero for the ones its path
+    // didn't contribute to
+    pub fn add_lpe_sample(&self, p: (f64, f64), radiance: &[RGB], weight: f64)
+    {
+        let mut pixels = self.lpe_pixels.lock().unwrap();
+        let (width, passes) = (self.resolution.0, self.lpes.len());
+        let bounds = (0, 0, self.resolution.0, self.resolution.1);
+        for_each_filtered(self.filter.as_ref(), p, bounds, |x, y, w|
+        {
+            for (k, &l) in radiance.iter().take(passes).enumerate()
+            {
+                pixels[(y*width + x)*passes + k].add(l, w*weight);
+            }
+        });
+    }
+    // Stokes vector of a camera sample with its x axis along the film's
+    // +x and its direction towards the camera, see Stokes::to_rgb
+    pub fn add_stokes_sample(&self, p: (f64, f64), s: RGBStokes, weight: f64)
+    {
+        assert!(self.polarized, "Polarization isn't enabled!");
+        let mut stokes = self.stokes.lock().unwrap();
+        let width = self.resolution.0;
+        for_each_filtered(self.filter.as_ref(), p, (0, 0, self.resolution.0, self.resolution.1), |x, y, w|
+        {
+            let pixel = &mut stokes[y*width + x];
+            pixel.sum += s*(w*weight);
+            pixel.weight_sum += w*weight;
+        });
+    }
+    pub fn add_aov_sample(&self, p: (f64, f64), s: &AovSample)
+    {
+        if let Some((x, y, d2)) = containing_pixel(p, (0, 0, self.resolution.0, self.resolution.1))
+        {
+            let stride = aov_stride(&self.aovs);
+            let i = (y*self.resolution.0 + x)*stride;
+            add_aov(&mut self.aov_data.lock().unwrap()[i..i + stride], &self.aovs, d2, s);
+        }
+    }
+    // Segments along the path of a camera sample, empty for one that saw
+    // nothing still counts towards the pixel's coverage
+    pub fn add_deep_sample(&self, p: (f64, f64), samples: &[DeepSample], weight: f64)
+    {
+        let tolerance = self.deep_tolerance.expect("Deep samples aren't enabled!");
+        if let Some((x, y, _)) = containing_pixel(p, (0, 0, self.resolution.0, self.resolution.1))
+        {
+            self.deep_pixels.lock().unwrap()[y*self.resolution.0 + x].add_path(samples, weight, tolerance);
+        }
+    }
+    // Raster space motion of a point from where the previous frame's camera
+    // saw it at p_prev to raster position p
+    pub fn motion_vector(&self, p: (f64, f64), prev_camera: &dyn Camera, p_prev: Vec3d) -> Point2
+    {
+        match prev_camera.world_to_raster(p_prev)
+        {
+            Some(pf) =>
+            {
+                let q = self.film_to_raster((pf.x, pf.y));
+                Point2::new(p.0 - q.0, p.1 - q.1)
+            }
+            None => Point2::new(0., 0.),
+        }
+    }
+    pub fn pixel(&self, x: usize, y: usize) -> Pixel
+    {
+        self.pixels.lock().unwrap()[y*self.resolution.0 + x]
+    }
+    // Final image, row by row from the top
+    pub fn resolve(&self, splat_scale: f64) -> Vec<RGB>
+    {
+        let pixels = self.pixels.lock().unwrap();
+        pixels.iter().zip(self.splats.iter()).map(|(p, s)|
+        {
+            let inv = if p.weight_sum != 0. { 1./p.weight_sum } else { 0. };
+            let splat = |a: &AtomicU64| f64::from_bits(a.load(Ordering::Relaxed));
+            RGB::new(p.rgb_sum[0], p.rgb_sum[1], p.rgb_sum[2])*inv + RGB::new(splat(&s[0]), splat(&s[1]), splat(&s[2]))*splat_scale
+        }).collect()
+    }
+    // Variance of every pixel's estimate from the unfiltered samples inside
+    // it, zero where there are fewer than two
+    pub fn resolve_variance(&self) -> Image
+    {
+        let variance: Vec<RGB> = self.moments.lock().unwrap().iter().map(|m| m.variance()).collect();
+        if variance.is_empty()
+        {
+            return Image::new(self.resolution.0, self.resolution.1, &["R", "G", "B"]);
+        }
+        Image::from_rgb(self.resolution.0, self.resolution.1, &variance)
+    }
+    // Every pixel's filtered Stokes vector, S0 to S3 each with R, G and B
+    pub fn resolve_stokes(&self) -> Image
+    {
+        let channels = ["S0.R", "S0.G", "S0.B", "S1.R", "S1.G", "S1.B", "S2.R", "S2.G", "S2.B", "S3.R", "S3.G", "S3.B"];
+        let mut img = Image::new(self.resolution.0, self.resolution.1, &channels);
+        for (chunk, p) in img.data.chunks_mut(12).zip(self.stokes.lock().unwrap().iter())
+        {
+            for (c, v) in chunk.chunks_mut(3).zip(p.resolve().s)
+            {
+                c.copy_from_slice(&[v.r, v.g, v.b]);
+            }
+        }
+        img
+    }
+    // Degree of polarization and angle of linear polarization in radians
+    // from the film's +x axis, counterclockwise as seen on the image, for
+    // every channel
+    pub fn resolve_polarization(&self) -> Image
+    {
+        let mut img = Image::new(self.resolution.0, self.resolution.1, &["DoP.R", "DoP.G", "DoP.B", "AoP.R", "AoP.G", "AoP.B"]);
+        for (chunk, p) in img.data.chunks_mut(6).zip(self.stokes.lock().unwrap().iter())
+        {
+            let s = p.resolve();
+            let (dop, aop) = (s.dop(), s.aop());
+            chunk.copy_from_slice(&[dop.r, dop.g, dop.b, aop.r, aop.g, aop.b]);
+        }
+        img
+    }
+    // One image per pass, in the order they were set
+    pub fn resolve_lpes(&self) -> Vec<Image>
+    {
+        let (width, height) = self.resolution;
+        let passes = self.lpes.len();
+        let pixels = self.lpe_pixels.lock().unwrap();
+        (0..passes).map(|k|
+        {
+            let rgb: Vec<RGB> = (0..width*height).map(|i|
+            {
+                let p = &pixels[i*passes + k];
+                let inv = if p.weight_sum != 0. { 1./p.weight_sum } else { 0. };
+                RGB::new(p.rgb_sum[0], p.rgb_sum[1], p.rgb_sum[2])*inv
+            }).collect();
+            Image::from_rgb(width, height, &rgb)
+        }).collect()
+    }
+    // One image per AOV, in the order they were set
+    pub fn resolve_aovs(&self) -> Vec<Image>
+    {
+        let (width, height) = self.resolution;
+        let stride = aov_stride(&self.aovs);
+        let data = self.aov_data.lock().unwrap();
+        let mut offset = 2;
+        let mut images = Vec::new();
+        for a in &self.aovs
+        {
+            let n = a.channels().len();
+            let mut img = Image::new(width, height, a.channels());
+            for (i, rec) in data.chunks(stride).enumerate()
+            {
+                for c in 0..n
+                {
+                    let v = rec[offset + c];
+                    img.data[i*n + c] = if a.is_id() { v } else if rec[0] > 0. { v/rec[0] } else { 0. };
+                }
+            }
+            images.push(img);
+            offset += n;
+        }
+        images
+    }
+    // Depth sorted samples of every pixel, see deep::to_image
+    pub fn resolve_deep(&self) -> DeepImage
+    {
+        deep::to_image(self.resolution.0, self.resolution.1, &self.deep_pixels.lock().unwrap())
+    }
+    pub fn write_deep_exr(&self, path: &Path, metadata: Option<&RenderMetadata>) -> io::Result<()>
+    {
+        let attributes = metadata.map(|m| m.exr_attributes()).unwrap_or_default();
+        let options = ExrOptions{ compression: ExrCompression::Zips, attributes };
+        imageio::write_deep_exr(path, &self.resolve_deep(), &options)
+    }
+    // Everything accumulated so far, see checkpoint::save. Cryptomattes keep
+    // their own coverage and aren't included.
+    pub(crate) fn save_state(&self, out: &mut Vec<u8>)
+    {
+        put_u64(out, self.resolution.0 as u64);
+        put_u64(out, self.resolution.1 as u64);
+        put_u64(out, self.aov_data.lock().unwrap().len() as u64);
+        put_u64(out, self.lpes.len() as u64);
+        put_u64(out, self.is_deep() as u64);
+        put_u64(out, self.variance as u64);
+        put_u64(out, self.polarized as u64);
+        let put_pixel = |out: &mut Vec<u8>, p: &Pixel|
+        {
+            for &v in p.rgb_sum.iter().chain(std::iter::once(&p.weight_sum))
+            {
+                put_f64(out, v);
+            }
+        };
+        for p in self.pixels.lock().unwrap().iter()
+        {
+            put_pixel(out, p);
+        }
+        for s in self.splats.iter().flatten()
+        {
+            put_u64(out, s.load(Ordering::Relaxed));
+        }
+        for &v in self.aov_data.lock().unwrap().iter()
+        {
+            put_f64(out, v);
+        }
+        for p in self.lpe_pixels.lock().unwrap().iter()
+        {
+            put_pixel(out, p);
+        }
+        for p in self.deep_pixels.lock().unwrap().iter()
+        {
+            put_f64(out, p.weight_sum);
+            put_u64(out, p.samples.len() as u64);
+            for s in &p.samples
+            {
+                for v in [s.rgb.r, s.rgb.g, s.rgb.b, s.alpha, s.z_front, s.z_back]
+                {
+                    put_f64(out, v);
+                }
+            }
+        }
+        for m in self.moments.lock().unwrap().iter()
+        {
+            for v in [m.n, m.sum.r, m.sum.g, m.sum.b, m.sum_sq.r, m.sum_sq.g, m.sum_sq.b]
+            {
+                put_f64(out, v);
+            }
+        }
+        for p in self.stokes.lock().unwrap().iter()
+        {
+            for c in p.sum.s
+            {
+                for v in [c.r, c.g, c.b]
+                {
+                    put_f64(out, v);
+                }
+            }
+            put_f64(out, p.weight_sum);
+        }
+    }
+    // Replaces the sums with saved ones, the film has to be set up the same
+    // way it was when they were saved. Nothing changes on error.
+    pub(crate) fn restore_state(&self, r: &mut StateReader) -> io::Result<()>
+    {
+        let n = self.resolution.0*self.resolution.1;
+        r.expect(self.resolution.0 as u64, "resolution")?;
+        r.expect(self.resolution.1 as u64, "resolution")?;
+        let aov_len = self.aov_data.lock().unwrap().len();
+        r.expect(aov_len as u64, "AOV layout")?;
+        r.expect(self.lpes.len() as u64, "light path expressions")?;
+        r.expect(self.is_deep() as u64, "deep setting")?;
+        r.expect(self.variance as u64, "variance setting")?;
+        r.expect(self.polarized as u64, "polarization setting")?;
+        let get_pixel = |r: &mut StateReader| -> io::Result<Pixel>
+        {
+            Ok(Pixel{ rgb_sum: [r.f64()?, r.f64()?, r.f64()?], weight_sum: r.f64()? })
+        };
+        let pixels = (0..n).map(|_| get_pixel(r)).collect::<io::Result<Vec<Pixel>>>()?;
+        let splats = (0..3*n).map(|_| r.u64()).collect::<io::Result<Vec<u64>>>()?;
+        let aov_data = (0..aov_len).map(|_| r.f64()).collect::<io::Result<Vec<f64>>>()?;
+        let lpe_pixels = (0..n*self.lpes.len()).map(|_| get_pixel(r)).collect::<io::Result<Vec<Pixel>>>()?;
+        let mut deep_pixels = Vec::new();
+        for _ in 0..if self.is_deep() { n } else { 0 }
+        {
+            let mut p = DeepPixel::zero();
+            p.weight_sum = r.f64()?;
+            for _ in 0..r.u64()?
+            {
+                let rgb = RGB::new(r.f64()?, r.f64()?, r.f64()?);
+                p.samples.push(DeepSample::new(rgb, r.f64()?, r.f64()?, r.f64()?));
+            }
+            deep_pixels.push(p);
+        }
+        let mut moments = Vec::new();
+        for _ in 0..if self.variance { n } else { 0 }
+        {
+            let n = r.f64()?;
+            let sum = RGB::new(r.f64()?, r.f64()?, r.f64()?);
+            moments.push(Moments{ n, sum, sum_sq: RGB::new(r.f64()?, r.f64()?, r.f64()?) });
+        }
+        let mut stokes = Vec::new();
+        for _ in 0..if self.polarized { n } else { 0 }
+        {
+            let mut sum = RGBStokes::zero();
+            for c in sum.s.iter_mut()
+            {
+                *c = RGB::new(r.f64()?, r.f64()?, r.f64()?);
+            }
+            stokes.push(StokesPixel{ sum, weight_sum: r.f64()? });
+        }
+        *self.pixels.lock().unwrap() = pixels;
+        for (a, &v) in self.splats.iter().flatten().zip(&splats)
+        {
+            a.store(v, Ordering::Relaxed);
+        }
+        *self.aov_data.lock().unwrap() = aov_data;
+        *self.lpe_pixels.lock().unwrap() = lpe_pixels;
+        *self.deep_pixels.lock().unwrap() = deep_pixels;
+        *self.moments.lock().unwrap() = moments;
+        *self.stokes.lock().unwrap() = stokes;
+        Ok(())
+    }
+    // Beauty as the unnamed half float layer, every pass as a half float
+    // layer with its name, every AOV as a float layer named after it, the
+    // Stokes vectors and polarization as float layers when they are recorded
+    // and the ranked layers and manifest of each Cryptomatte, with the
+    // metadata as header attributes
+    pub fn write_exr(&self, path: &Path, exr: &FilmExrOptions) -> io::Result<()>
+    {
+        let beauty = Image::from_rgb(self.resolution.0, self.resolution.1, &self.resolve(exr.splat_scale));
+        let aovs = self.resolve_aovs();
+        let passes = self.resolve_lpes();
+        let mut layers = vec![ExrLayer::new("", &beauty, ExrPixelType::Half)];
+        for ((name, _), img) in self.lpes.iter().zip(&passes)
+        {
+            layers.push(ExrLayer::new(name, img, ExrPixelType::Half));
+        }
+        for (a, img) in self.aovs.iter().zip(&aovs)
+        {
+            layers.push(ExrLayer::new(a.name(), img, ExrPixelType::Float));
+        }
+        let polarization = if self.polarized { vec![self.resolve_stokes(), self.resolve_polarization()] } else { Vec::new() };
+        for (name, img) in ["stokes", "polarization"].iter().zip(&polarization)
+        {
+            layers.push(ExrLayer::new(name, img, ExrPixelType::Float));
+        }
+        let mattes: Vec<Vec<Image>> = exr.cryptomattes.iter().map(|c| c.layers()).collect();
+        let mut options = ExrOptions::default();
+        if let Some(m) = exr.metadata
+        {
+            options.attributes = m.exr_attributes();
+        }
+        for (c, images) in exr.cryptomattes.iter().zip(&mattes)
+        {
+            for (i, img) in images.iter().enumerate()
+            {
+                layers.push(ExrLayer::new(&c.layer_name(i), img, ExrPixelType::Float));
+            }
+            options.attributes.extend(c.attributes());
+        }
+        imageio::write_exr(path, &layers, &options)
+    }
+}
+
+// What Film::write_exr adds to the image: the scale of light tracing
+// splats, one for no scaling by default, Cryptomattes and render metadata
+#[derive(Clone, Copy)]
+pub struct FilmExrOptions<'a>
+{
+    pub splat_scale: f64,
+    pub cryptomattes: &'a [&'a Cryptomatte],
+    pub metadata: Option<&'a RenderMetadata>,
+}
+
+impl<'a> Default for FilmExrOptions<'a>
+{
+    fn default() -> FilmExrOptions<'a>
+    {
+        FilmExrOptions{ splat_scale: 1., cryptomattes: &[], metadata: None }
+    }
+}
+
+// Samples of one thread, for the pixels of its tile and the filter's reach
+// around them. Neighboring tiles overlap there and merge_tile adds them up.
+pub struct FilmTile<'a>
+{
+    film: &'a Film,
+    pub bounds: (usize, usize, usize, usize),
+    pixels: Vec<Pixel>,
+    aov_data: Vec<f64>,
+    lpe_pixels: Vec<Pixel>,
+    deep_pixels: Vec<DeepPixel>,
+    moments: Vec<Moments>,
+    stokes: Vec<StokesPixel>,
+}
+
+impl<'a> FilmTile<'a>
+{
+    pub fn add_sample(&mut self, p: (f64, f64), l: RGB, weight: f64)
+    {
+        let (x0, y0, x1, _) = self.bounds;
+        let pixels = &mut self.pixels;
+        for_each_filtered(self.film.filter.as_ref(), p, self.bounds, |x, y, w|
+        {
+            pixels[(y - y0)*(x1 - x0) + x - x0].add(l, w*weight);
+        });
+        if self.film.variance
+        {
+            if let Some((x, y, _)) = containing_pixel(p, self.bounds)
+            {
+                self.moments[(y - y0)*(x1 - x0) + x - x0].add(l);
+            }
+        }
+    }
+    pub fn add_lpe_sample(&mut self, p: (f64, f64), radiance: &[RGB], weight: f64)
+    {
+        let (x0, y0, x1, _) = self.bounds;
+        let passes = self.film.lpes.len();
+        let pixels = &mut self.lpe_pixels;
+        for_each_filtered(self.film.filter.as_ref(), p, self.bounds, |x, y, w|
+        {
+            for (k, &l) in radiance.iter().take(passes).enumerate()
+            {
+                pixels[((y - y0)*(x1 - x0) + x - x0)*passes + k].add(l, w*weight);
+            }
+        });
+    }
+    pub fn add_stokes_sample(&mut self, p: (f64, f64), s: RGBStokes, weight: f64)
+    {
+        assert!(self.film.polarized, "Polarization isn't enabled!");
+        let (x0, y0, x1, _) = self.bounds;
+        let stokes = &mut self.stokes;
+        for_each_filtered(self.film.filter.as_ref(), p, self.bounds, |x, y, w|
+        {
+            let pixel = &mut stokes[(y - y0)*(x1 - x0) + x - x0];
+            pixel.sum += s*(w*weight);
+            pixel.weight_sum += w*weight;
+        });
+    }
+    pub fn add_aov_sample(&mut self, p: (f64, f64), s: &AovSample)
+    {
+        let (x0, y0, x1, _) = self.bounds;
+        if let Some((x, y, d2)) = containing_pixel(p, self.bounds)
+        {
+            let stride = aov_stride(&self.film.aovs);
+            let i = ((y - y0)*(x1 - x0) + x - x0)*stride;
+            add_aov(&mut self.aov_data[i..i + stride], &self.film.aovs, d2, s);
+        }
+    }
+    pub fn add_deep_sample(&mut self, p: (f64, f64), samples: &[DeepSample], weight: f64)
+    {
+        let tolerance = self.film.deep_tolerance.expect("Deep samples aren't enabled!");
+        let (x0, y0, x1, _) = self.bounds;
+        if let Some((x, y, _)) = containing_pixel(p, self.bounds)
+        {
+            self.deep_pixels[(y - y0)*(x1 - x0) + x - x0].add_path(samples, weight, tolerance);
+        }
+    }
+}
+
+// AOV records hold the sample count, the squared distance of the sample the
+// ids come from to the pixel center and then the channels of every AOV
+fn aov_stride(aovs: &[Aov]) -> usize
+{
+    2 + aovs.iter().map(|a| a.channels().len()).sum::<usize>()
+}
+
+fn aov_records(aovs: &[Aov], n: usize) -> Vec<f64>
+{
+    if aovs.is_empty()
+    {
+        return Vec::new();
+    }
+    let stride = aov_stride(aovs);
+    let mut data = vec![0.; n*stride];
+    for rec in data.chunks_mut(stride)
+    {
+        rec[1] = f64::INFINITY;
+    }
+    data
+}
+
+// Pixel containing p if it's inside bounds, and the squared distance to its
+// center
+fn containing_pixel(p: (f64, f64), bounds: (usize, usize, usize, usize)) -> Option<(usize, usize, f64)>
+{
+    let (x, y) = (p.0.floor(), p.1.floor());
+    if x < bounds.0 as f64 || y < bounds.1 as f64 || x >= bounds.2 as f64 || y >= bounds.3 as f64
+    {
+        return None;
+    }
+    let (dx, dy) = (p.0 - x - 0.5, p.1 - y - 0.5);
+    Some((x as usize, y as usize, dx*dx + dy*dy))
+}
+
+fn add_aov(rec: &mut [f64], aovs: &[Aov], d2: f64, s: &AovSample)
+{
+    rec[0] += 1.;
+    let closer = d2 < rec[1];
+    if closer
+    {
+        rec[1] = d2;
+    }
+    let mut k = 2;
+    for a in aovs
+    {
+        let n = a.channels().len();
+        for (c, &v) in a.values(s).iter().take(n).enumerate()
+        {
+            if !a.is_id()
+            {
+                rec[k + c] += v;
+            }
+            else if closer
+            {
+                rec[k + c] = v;
+            }
+        }
+        k += n;
+    }
+}
+
+fn merge_aov(rec: &mut [f64], other: &[f64], aovs: &[Aov])
+{
+    let closer = other[1] < rec[1];
+    rec[0] += other[0];
+    rec[1] = f64::min(rec[1], other[1]);
+    let mut k = 2;
+    for a in aovs
+    {
+        for c in k..k + a.channels().len()
+        {
+            if !a.is_id()
+            {
+                rec[c] += other[c];
+            }
+            else if closer
+            {
+                rec[c] = other[c];
+            }
+        }
+        k += a.channels().len();
+    }
+}
+
+// Calls f with every pixel inside bounds that the filter centered at p
+// reaches, along with the filter weight
+pub(crate) fn for_each_filtered<F: FnMut(usize, usize, f64)>(filter: &dyn Filter, p: (f64, f64), bounds: (usize, usize, usize, usize), mut f: F)
+{
+    let (rx, ry) = filter.radius();
+    let (x0, y0, x1, y1) = bounds;
+    let xa = (p.0 - 0.5 - rx).ceil().max(x0 as f64);
+    let ya = (p.1 - 0.5 - ry).ceil().max(y0 as f64);
+    let xb = (p.0 - 0.5 + rx).floor().min(x1 as f64 - 1.);
+    let yb = (p.1 - 0.5 + ry).floor().min(y1 as f64 - 1.);
+    if xa > xb || ya > yb
+    {
+        return;
+    }
+    for y in ya as usize..=yb as usize
+    {
+        for x in xa as usize..=xb as usize
+        {
+            let w = filter.evaluate((x as f64 + 0.5 - p.0, y as f64 + 0.5 - p.1));
+            if w != 0.
+            {
+                f(x, y, w);
+            }
+        }
+    }
+}
+
+fn atomic_add(a: &AtomicU64, v: f64)
+{
+    let mut old = a.load(Ordering::Relaxed);
+    loop
+    {
+        let new = (f64::from_bits(old) + v).to_bits();
+        match a.compare_exchange_weak(old, new, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => return,
+            Err(x) => old = x,
+        }
+    }
+}
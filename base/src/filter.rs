@@ -0,0 +1,191 @@
+use std::f64::consts::PI;
+
+// Pixel reconstruction filter, evaluated at an offset from the pixel center
+// in pixels. The filter is zero outside of [-radius, radius].
+pub trait Filter
+{
+    fn radius(&self) -> (f64, f64);
+    fn evaluate(&self, p: (f64, f64)) -> f64;
+}
+
+pub struct BoxFilter
+{
+    pub radius: (f64, f64),
+}
+
+impl BoxFilter
+{
+    pub fn new(radius: (f64, f64)) -> BoxFilter
+    {
+        BoxFilter{ radius }
+    }
+}
+
+impl Filter for BoxFilter
+{
+    fn radius(&self) -> (f64, f64)
+    {
+        self.radius
+    }
+    fn evaluate(&self, p: (f64, f64)) -> f64
+    {
+        if p.0.abs() <= self.radius.0 && p.1.abs() <= self.radius.1 { 1. } else { 0. }
+    }
+}
+
+pub struct TriangleFilter
+{
+    pub radius: (f64, f64),
+}
+
+impl TriangleFilter
+{
+    pub fn new(radius: (f64, f64)) -> TriangleFilter
+    {
+        TriangleFilter{ radius }
+    }
+}
+
+impl Filter for TriangleFilter
+{
+    fn radius(&self) -> (f64, f64)
+    {
+        self.radius
+    }
+    fn evaluate(&self, p: (f64, f64)) -> f64
+    {
+        f64::max(0., self.radius.0 - p.0.abs())*f64::max(0., self.radius.1 - p.1.abs())
+    }
+}
+
+// Gaussian shifted down so that it reaches zero at the radius
+pub struct GaussianFilter
+{
+    pub radius: (f64, f64),
+    pub sigma: f64,
+    exp: (f64, f64),
+}
+
+impl GaussianFilter
+{
+    pub fn new(radius: (f64, f64), sigma: f64) -> GaussianFilter
+    {
+        let exp = (gaussian(radius.0, sigma), gaussian(radius.1, sigma));
+        GaussianFilter{ radius, sigma, exp }
+    }
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64
+{
+    f64::exp(-x*x/(2.*sigma*sigma))
+}
+
+impl Filter for GaussianFilter
+{
+    fn radius(&self) -> (f64, f64)
+    {
+        self.radius
+    }
+    fn evaluate(&self, p: (f64, f64)) -> f64
+    {
+        if p.0.abs() > self.radius.0 || p.1.abs() > self.radius.1
+        {
+            return 0.;
+        }
+        let gx = f64::max(0., gaussian(p.0, self.sigma) - self.exp.0);
+        let gy = f64::max(0., gaussian(p.1, self.sigma) - self.exp.1);
+        gx*gy
+    }
+}
+
+// Mitchell-Netravali cubic, b = c = 1/3 is the recommended setting
+pub struct MitchellFilter
+{
+    pub radius: (f64, f64),
+    pub b: f64,
+    pub c: f64,
+}
+
+impl MitchellFilter
+{
+    pub fn new(radius: (f64, f64), b: f64, c: f64) -> MitchellFilter
+    {
+        MitchellFilter{ radius, b, c }
+    }
+    // The cubic over [-2, 2]
+    fn mitchell_1d(&self, x: f64) -> f64
+    {
+        let x = x.abs();
+        let (b, c) = (self.b, self.c);
+        if x > 2.
+        {
+            0.
+        }
+        else if x > 1.
+        {
+            ((-b - 6.*c)*x*x*x + (6.*b + 30.*c)*x*x + (-12.*b - 48.*c)*x + (8.*b + 24.*c))/6.
+        }
+        else
+        {
+            ((12. - 9.*b - 6.*c)*x*x*x + (-18. + 12.*b + 6.*c)*x*x + (6. - 2.*b))/6.
+        }
+    }
+}
+
+impl Filter for MitchellFilter
+{
+    fn radius(&self) -> (f64, f64)
+    {
+        self.radius
+    }
+    fn evaluate(&self, p: (f64, f64)) -> f64
+    {
+        self.mitchell_1d(2.*p.0/self.radius.0)*self.mitchell_1d(2.*p.1/self.radius.1)
+    }
+}
+
+// Sinc windowed by a wider sinc, tau is the number of lobes in the radius
+pub struct LanczosFilter
+{
+    pub radius: (f64, f64),
+    pub tau: f64,
+}
+
+impl LanczosFilter
+{
+    pub fn new(radius: (f64, f64), tau: f64) -> LanczosFilter
+    {
+        LanczosFilter{ radius, tau }
+    }
+    fn windowed_sinc(&self, x: f64, radius: f64) -> f64
+    {
+        if x.abs() > radius
+        {
+            return 0.;
+        }
+        // Scale so that the window spans the radius
+        let x = x/radius*self.tau;
+        sinc(x)*sinc(x/self.tau)
+    }
+}
+
+fn sinc(x: f64) -> f64
+{
+    if x.abs() < 1e-5
+    {
+        return 1. - PI*PI*x*x/6.;
+    }
+    (PI*x).sin()/(PI*x)
+}
+
+impl Filter for LanczosFilter
+{
+    fn radius(&self) -> (f64, f64)
+    {
+        self.radius
+    }
+    fn evaluate(&self, p: (f64, f64)) -> f64
+    {
+        self.windowed_sinc(p.0, self.radius.0)*self.windowed_sinc(p.1, self.radius.1)
+    }
+}
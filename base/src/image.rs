@@ -0,0 +1,137 @@
+use crate::color::RGB;
+
+// Linear float image with named channels, stored row by row from the top
+// with the channels of a pixel next to each other
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image
+{
+    pub width: usize,
+    pub height: usize,
+    pub channels: Vec<String>,
+    pub data: Vec<f64>,
+}
+
+impl Image
+{
+    pub fn new(width: usize, height: usize, channels: &[&str]) -> Image
+    {
+        let channels: Vec<String> = channels.iter().map(|c| c.to_string()).collect();
+        let data = vec![0.; width*height*channels.len()];
+        Image{ width, height, channels, data }
+    }
+    pub fn from_rgb(width: usize, height: usize, pixels: &[RGB]) -> Image
+    {
+        assert_eq!(pixels.len(), width*height, "pixel count doesn't match the resolution");
+        let mut img = Image::new(width, height, &["R", "G", "B"]);
+        for (i, c) in pixels.iter().enumerate()
+        {
+            img.data[3*i] = c.r;
+            img.data[3*i + 1] = c.g;
+            img.data[3*i + 2] = c.b;
+        }
+        img
+    }
+    pub fn channel_count(&self) -> usize
+    {
+        self.channels.len()
+    }
+    pub fn channel_index(&self, name: &str) -> Option<usize>
+    {
+        self.channels.iter().position(|c| c == name)
+    }
+    pub fn get(&self, x: usize, y: usize, c: usize) -> f64
+    {
+        self.data[(y*self.width + x)*self.channels.len() + c]
+    }
+    pub fn set(&mut self, x: usize, y: usize, c: usize, v: f64)
+    {
+        let n = self.channels.len();
+        self.data[(y*self.width + x)*n + c] = v;
+    }
+    // RGB of a pixel, single channel images are read as gray
+    pub fn get_rgb(&self, x: usize, y: usize) -> RGB
+    {
+        if self.channels.len() < 3
+        {
+            return RGB::gray(self.get(x, y, 0));
+        }
+        RGB::new(self.get(x, y, 0), self.get(x, y, 1), self.get(x, y, 2))
+    }
+    pub fn set_rgb(&mut self, x: usize, y: usize, c: RGB)
+    {
+        self.set(x, y, 0, c.r);
+        self.set(x, y, 1, c.g);
+        self.set(x, y, 2, c.b);
+    }
+    pub fn to_rgb(&self) -> Vec<RGB>
+    {
+        let mut pixels = Vec::with_capacity(self.width*self.height);
+        for y in 0..self.height
+        {
+            for x in 0..self.width
+            {
+                pixels.push(self.get_rgb(x, y));
+            }
+        }
+        pixels
+    }
+}
+
+// Image with any number of samples per pixel. The samples of pixel i are
+// offsets[i]..offsets[i + 1], each with its channels next to each other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeepImage
+{
+    pub width: usize,
+    pub height: usize,
+    pub channels: Vec<String>,
+    pub offsets: Vec<usize>,
+    pub data: Vec<f64>,
+}
+
+impl DeepImage
+{
+    // Zeroed samples, counts are given per pixel row by row from the top
+    pub fn new(width: usize, height: usize, channels: &[&str], counts: &[usize]) -> DeepImage
+    {
+        assert_eq!(counts.len(), width*height, "sample counts don't match the resolution");
+        let channels: Vec<String> = channels.iter().map(|c| c.to_string()).collect();
+        let mut offsets = Vec::with_capacity(counts.len() + 1);
+        offsets.push(0);
+        for &n in counts
+        {
+            offsets.push(offsets[offsets.len() - 1] + n);
+        }
+        let data = vec![0.; offsets[counts.len()]*channels.len()];
+        DeepImage{ width, height, channels, offsets, data }
+    }
+    pub fn channel_count(&self) -> usize
+    {
+        self.channels.len()
+    }
+    pub fn channel_index(&self, name: &str) -> Option<usize>
+    {
+        self.channels.iter().position(|c| c == name)
+    }
+    pub fn sample_count(&self, x: usize, y: usize) -> usize
+    {
+        let i = y*self.width + x;
+        self.offsets[i + 1] - self.offsets[i]
+    }
+    pub fn max_samples(&self) -> usize
+    {
+        self.offsets.windows(2).map(|w| w[1] - w[0]).max().unwrap_or(0)
+    }
+    pub fn sample(&self, x: usize, y: usize, i: usize) -> &[f64]
+    {
+        let n = self.channels.len();
+        let s = self.offsets[y*self.width + x] + i;
+        &self.data[s*n..(s + 1)*n]
+    }
+    pub fn sample_mut(&mut self, x: usize, y: usize, i: usize) -> &mut [f64]
+    {
+        let n = self.channels.len();
+        let s = self.offsets[y*self.width + x] + i;
+        &mut self.data[s*n..(s + 1)*n]
+    }
+}
@@ -0,0 +1,2626 @@
+use std::fs;
+use std::convert::TryFrom;
+use std::io;
+use std::path::Path;
+use crate::color::srgb_to_linear;
+use crate::image::{DeepImage, Image};
+use crate::deflate;
+use crate::metadata::RenderMetadata;
+
+fn extension(path: &Path) -> String
+{
+    path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase()
+}
+
+// Writes an image in the format given by the extension of path. LDR formats
+// get 8 bits per channel and OpenEXR gets ZIP compressed half floats.
+pub fn write_image(path: &Path, img: &Image) -> io::Result<()>
+{
+    match extension(path).as_str()
+    {
+        "png" => write_png(path, img, 8),
+        "ppm" => write_ppm(path, img, 8),
+        "pfm" => write_pfm(path, img),
+        "hdr" => write_hdr(path, img),
+        "exr" => write_exr(path, &[ExrLayer::new("", img, ExrPixelType::Half)], &ExrOptions::default()),
+        e => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown image format .{}", e))),
+    }
+}
+
+// Same as write_image with the metadata in PNG text chunks or OpenEXR
+// header attributes. The other formats have no room for it and are written
+// without.
+pub fn write_image_metadata(path: &Path, img: &Image, metadata: &RenderMetadata) -> io::Result<()>
+{
+    match extension(path).as_str()
+    {
+        "png" => write_png_text(path, img, 8, &metadata.text()),
+        "exr" =>
+        {
+            let options = ExrOptions{ attributes: metadata.exr_attributes(), ..ExrOptions::default() };
+            write_exr(path, &[ExrLayer::new("", img, ExrPixelType::Half)], &options)
+        }
+        _ => write_image(path, img),
+    }
+}
+
+// Quantizes display-referred values, clamped to [0, 1]
+fn quantize(v: f64, max: f64) -> u32
+{
+    let v = if v.is_nan() { 0. } else { v.clamp(0., 1.) };
+    (v*max + 0.5) as u32
+}
+
+fn check_bit_depth(bit_depth: u8) -> io::Result<()>
+{
+    if bit_depth != 8 && bit_depth != 16
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "bit depth must be 8 or 16"));
+    }
+    Ok(())
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8])
+{
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = deflate::crc32_update(deflate::crc32(kind), data);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Gray, gray alpha, RGB or RGBA depending on the number of channels
+pub fn encode_png(img: &Image, bit_depth: u8) -> io::Result<Vec<u8>>
+{
+    encode_png_text(img, bit_depth, &[])
+}
+
+// Keyword and text pairs as tEXt chunks, or as iTXt ones for text that
+// isn't Latin-1
+fn png_text_chunk(out: &mut Vec<u8>, keyword: &str, text: &str) -> io::Result<()>
+{
+    let printable = |c: char| (' '..='~').contains(&c) || ('\u{a1}'..='\u{ff}').contains(&c);
+    if keyword.is_empty() || keyword.chars().count() > 79 || !keyword.chars().all(printable)
+        || keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ")
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid PNG text keyword {:?}", keyword)));
+    }
+    let mut data: Vec<u8> = keyword.chars().map(|c| c as u8).collect();
+    data.push(0);
+    if text.chars().all(|c| c == '\n' || printable(c))
+    {
+        data.extend(text.chars().map(|c| c as u8));
+        png_chunk(out, b"tEXt", &data);
+    }
+    else
+    {
+        // Uncompressed, with empty language tag and translated keyword
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+        png_chunk(out, b"iTXt", &data);
+    }
+    Ok(())
+}
+
+pub fn encode_png_text(img: &Image, bit_depth: u8, text: &[(String, String)]) -> io::Result<Vec<u8>>
+{
+    check_bit_depth(bit_depth)?;
+    let nc = img.channel_count();
+    let color_type = match nc
+    {
+        1 => 0,
+        2 => 4,
+        3 => 2,
+        4 => 6,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "PNG needs 1 to 4 channels")),
+    };
+    let mut out = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(img.width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(img.height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+    png_chunk(&mut out, b"IHDR", &ihdr);
+    for (keyword, value) in text
+    {
+        png_text_chunk(&mut out, keyword, value)?;
+    }
+    // Every scanline uses the Sub filter, which suits smooth renders
+    let bpp = nc*bit_depth as usize/8;
+    let stride = img.width*bpp;
+    let mut raw = Vec::with_capacity((stride + 1)*img.height);
+    let mut line = Vec::with_capacity(stride);
+    for y in 0..img.height
+    {
+        line.clear();
+        for x in 0..img.width
+        {
+            for c in 0..nc
+            {
+                let v = img.get(x, y, c);
+                if bit_depth == 8
+                {
+                    line.push(quantize(v, 255.) as u8);
+                }
+                else
+                {
+                    line.extend_from_slice(&(quantize(v, 65535.) as u16).to_be_bytes());
+                }
+            }
+        }
+        raw.push(1);
+        for i in 0..stride
+        {
+            let left = if i >= bpp { line[i - bpp] } else { 0 };
+            raw.push(line[i].wrapping_sub(left));
+        }
+    }
+    png_chunk(&mut out, b"IDAT", &deflate::zlib_compress(&raw));
+    png_chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+pub fn write_png(path: &Path, img: &Image, bit_depth: u8) -> io::Result<()>
+{
+    fs::write(path, encode_png(img, bit_depth)?)
+}
+
+pub fn write_png_text(path: &Path, img: &Image, bit_depth: u8, text: &[(String, String)]) -> io::Result<()>
+{
+    fs::write(path, encode_png_text(img, bit_depth, text)?)
+}
+
+// Binary PPM for RGB images and PGM for single channel ones
+pub fn encode_ppm(img: &Image, bit_depth: u8) -> io::Result<Vec<u8>>
+{
+    check_bit_depth(bit_depth)?;
+    let (magic, nc) = match img.channel_count()
+    {
+        1 => ("P5", 1),
+        n if n >= 3 => ("P6", 3),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "PPM needs 1 or 3 channels")),
+    };
+    let max = if bit_depth == 8 { 255 } else { 65535 };
+    let mut out = format!("{}\n{} {}\n{}\n", magic, img.width, img.height, max).into_bytes();
+    for y in 0..img.height
+    {
+        for x in 0..img.width
+        {
+            for c in 0..nc
+            {
+                let q = quantize(img.get(x, y, c), max as f64);
+                if bit_depth == 8
+                {
+                    out.push(q as u8);
+                }
+                else
+                {
+                    out.extend_from_slice(&(q as u16).to_be_bytes());
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub fn write_ppm(path: &Path, img: &Image, bit_depth: u8) -> io::Result<()>
+{
+    fs::write(path, encode_ppm(img, bit_depth)?)
+}
+
+// Little endian PFM, rows are stored from the bottom up
+pub fn encode_pfm(img: &Image) -> io::Result<Vec<u8>>
+{
+    let (magic, nc) = match img.channel_count()
+    {
+        1 => ("Pf", 1),
+        n if n >= 3 => ("PF", 3),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "PFM needs 1 or 3 channels")),
+    };
+    let mut out = format!("{}\n{} {}\n-1.0\n", magic, img.width, img.height).into_bytes();
+    for y in (0..img.height).rev()
+    {
+        for x in 0..img.width
+        {
+            for c in 0..nc
+            {
+                out.extend_from_slice(&(img.get(x, y, c) as f32).to_le_bytes());
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub fn write_pfm(path: &Path, img: &Image) -> io::Result<()>
+{
+    fs::write(path, encode_pfm(img)?)
+}
+
+fn to_rgbe(r: f64, g: f64, b: f64) -> [u8; 4]
+{
+    let v = r.max(g).max(b);
+    if v.is_nan() || v < 1e-32
+    {
+        return [0; 4];
+    }
+    // Values beyond the largest exponent, infinities included, saturate
+    let e = (v.log2().floor() + 1.).min(127.) as i32;
+    let scale = 256./2f64.powi(e);
+    let q = |c: f64| (c.max(0.)*scale).min(255.) as u8;
+    [q(r), q(g), q(b), (e + 128) as u8]
+}
+
+// Radiance RGBE with flat, uncompressed scanlines
+pub fn encode_hdr(img: &Image) -> io::Result<Vec<u8>>
+{
+    if img.channel_count() == 2 || img.channel_count() == 0
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "HDR needs 1 or 3 channels"));
+    }
+    let mut out = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", img.height, img.width).into_bytes();
+    for y in 0..img.height
+    {
+        for x in 0..img.width
+        {
+            let c = img.get_rgb(x, y);
+            out.extend_from_slice(&to_rgbe(c.r, c.g, c.b));
+        }
+    }
+    Ok(out)
+}
+
+pub fn write_hdr(path: &Path, img: &Image) -> io::Result<()>
+{
+    fs::write(path, encode_hdr(img)?)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrPixelType
+{
+    Half,
+    Float,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrCompression
+{
+    None,
+    Rle,
+    Zips,
+    Zip,
+}
+
+impl ExrCompression
+{
+    fn id(self) -> u8
+    {
+        match self
+        {
+            ExrCompression::None => 0,
+            ExrCompression::Rle => 1,
+            ExrCompression::Zips => 2,
+            ExrCompression::Zip => 3,
+        }
+    }
+    fn lines_per_chunk(self) -> usize
+    {
+        if self == ExrCompression::Zip { 16 } else { 1 }
+    }
+}
+
+// Extra header attributes
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExrAttribute
+{
+    String(String),
+    Int(i32),
+    Float(f32),
+    // Row major, OpenEXR multiplies row vectors from the left so transforms
+    // are the transpose of a Matrix4
+    M44f([[f32; 4]; 4]),
+}
+
+impl ExrAttribute
+{
+    fn kind(&self) -> &'static str
+    {
+        match self
+        {
+            ExrAttribute::String(_) => "string",
+            ExrAttribute::Int(_) => "int",
+            ExrAttribute::Float(_) => "float",
+            ExrAttribute::M44f(_) => "m44f",
+        }
+    }
+    fn bytes(&self) -> Vec<u8>
+    {
+        match self
+        {
+            ExrAttribute::String(s) => s.as_bytes().to_vec(),
+            ExrAttribute::Int(v) => v.to_le_bytes().to_vec(),
+            ExrAttribute::Float(v) => v.to_le_bytes().to_vec(),
+            ExrAttribute::M44f(m) => m.iter().flatten().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+    fn parse(kind: &str, value: &[u8]) -> Option<ExrAttribute>
+    {
+        match (kind, value.len())
+        {
+            ("string", _) => Some(ExrAttribute::String(String::from_utf8_lossy(value).into_owned())),
+            ("int", 4) => Some(ExrAttribute::Int(le_i32(value))),
+            ("float", 4) => Some(ExrAttribute::Float(f32::from_bits(le_u32(value)))),
+            ("m44f", 64) =>
+            {
+                let mut m = [[0.; 4]; 4];
+                for (i, v) in m.iter_mut().flatten().enumerate()
+                {
+                    *v = f32::from_bits(le_u32(&value[4*i..]));
+                }
+                Some(ExrAttribute::M44f(m))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExrOptions
+{
+    pub compression: ExrCompression,
+    pub attributes: Vec<(String, ExrAttribute)>,
+}
+
+impl Default for ExrOptions
+{
+    fn default() -> ExrOptions
+    {
+        ExrOptions{ compression: ExrCompression::Zip, attributes: Vec::new() }
+    }
+}
+
+// Channels of the image are written as layer.channel, or under their own
+// name for the unnamed layer
+pub struct ExrLayer<'a>
+{
+    pub name: String,
+    pub image: &'a Image,
+    pub pixel_type: ExrPixelType,
+}
+
+impl<'a> ExrLayer<'a>
+{
+    pub fn new(name: &str, image: &'a Image, pixel_type: ExrPixelType) -> ExrLayer<'a>
+    {
+        ExrLayer{ name: name.to_string(), image, pixel_type }
+    }
+}
+
+pub fn f32_to_half(f: f32) -> u16
+{
+    let x = f.to_bits();
+    let sign = ((x >> 16) & 0x8000) as u16;
+    let exp = ((x >> 23) & 0xff) as i32;
+    let mant = x & 0x7f_ffff;
+    if exp == 0xff
+    {
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f
+    {
+        return sign | 0x7c00;
+    }
+    if e <= 0
+    {
+        // Subnormal half, rounded to nearest even
+        if e < -10
+        {
+            return sign;
+        }
+        let m = mant | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let mut h = m >> shift;
+        let rem = m & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if rem > halfway || (rem == halfway && h & 1 == 1)
+        {
+            h += 1;
+        }
+        return sign | h as u16;
+    }
+    let mut h = ((e as u32) << 10) | (mant >> 13);
+    let rem = mant & 0x1fff;
+    if rem > 0x1000 || (rem == 0x1000 && h & 1 == 1)
+    {
+        h += 1;
+    }
+    sign | h as u16
+}
+
+struct ExrChannel<'a>
+{
+    name: String,
+    image: &'a Image,
+    index: usize,
+    pixel_type: ExrPixelType,
+}
+
+fn exr_attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8])
+{
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+fn box2i(width: usize, height: usize) -> Vec<u8>
+{
+    let mut v = Vec::with_capacity(16);
+    for x in &[0, 0, width as i32 - 1, height as i32 - 1]
+    {
+        v.extend_from_slice(&x.to_le_bytes());
+    }
+    v
+}
+
+// Splits even and odd bytes and delta encodes them, as OpenEXR does before
+// RLE and ZIP compression
+fn exr_predict(raw: &[u8]) -> Vec<u8>
+{
+    let mut t = Vec::with_capacity(raw.len());
+    t.extend(raw.iter().step_by(2));
+    t.extend(raw.iter().skip(1).step_by(2));
+    let mut p = t.first().cloned().unwrap_or(0);
+    for v in t.iter_mut().skip(1)
+    {
+        let d = (*v as i32 - p as i32 + 128 + 256) as u8;
+        p = *v;
+        *v = d;
+    }
+    t
+}
+
+fn exr_rle(data: &[u8]) -> Vec<u8>
+{
+    const MIN_RUN: usize = 3;
+    const MAX_RUN: usize = 127;
+    let mut out = Vec::new();
+    let mut start = 0;
+    let n = data.len();
+    while start < n
+    {
+        let mut end = start + 1;
+        while end < n && data[start] == data[end] && end - start - 1 < MAX_RUN
+        {
+            end += 1;
+        }
+        if end - start >= MIN_RUN
+        {
+            out.push((end - start - 1) as u8);
+            out.push(data[start]);
+            start = end;
+        }
+        else
+        {
+            while end < n && (end + 1 >= n || data[end] != data[end + 1] || end + 2 >= n || data[end + 1] != data[end + 2])
+                && end - start < MAX_RUN
+            {
+                end += 1;
+            }
+            out.push((start as isize - end as isize) as u8);
+            out.extend_from_slice(&data[start..end]);
+            start = end;
+        }
+    }
+    out
+}
+
+// Header of a single part scanline file, deep ones also give their most
+// samples in a pixel
+fn exr_header(channels: &[(&str, ExrPixelType)], width: usize, height: usize, compression: ExrCompression,
+              options: &ExrOptions, deep: Option<usize>) -> Vec<u8>
+{
+    let long_names = channels.iter().any(|c| c.0.len() > 31) || options.attributes.iter().any(|a| a.0.len() > 31);
+    let mut out = EXR_MAGIC.to_vec();
+    let version: u32 = 2 | if long_names { 0x400 } else { 0 } | if deep.is_some() { 0x800 } else { 0 };
+    out.extend_from_slice(&version.to_le_bytes());
+    let mut chlist = Vec::new();
+    for (name, pixel_type) in channels
+    {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        let t: i32 = if *pixel_type == ExrPixelType::Half { 1 } else { 2 };
+        chlist.extend_from_slice(&t.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    exr_attribute(&mut out, "channels", "chlist", &chlist);
+    exr_attribute(&mut out, "compression", "compression", &[compression.id()]);
+    exr_attribute(&mut out, "dataWindow", "box2i", &box2i(width, height));
+    exr_attribute(&mut out, "displayWindow", "box2i", &box2i(width, height));
+    exr_attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut out, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    let mut center = Vec::new();
+    center.extend_from_slice(&0f32.to_le_bytes());
+    center.extend_from_slice(&0f32.to_le_bytes());
+    exr_attribute(&mut out, "screenWindowCenter", "v2f", &center);
+    exr_attribute(&mut out, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    if let Some(max_samples) = deep
+    {
+        let chunks = height.div_ceil(compression.lines_per_chunk()) as i32;
+        exr_attribute(&mut out, "chunkCount", "int", &chunks.to_le_bytes());
+        exr_attribute(&mut out, "maxSamplesPerPixel", "int", &(max_samples as i32).to_le_bytes());
+        exr_attribute(&mut out, "type", "string", b"deepscanline");
+        exr_attribute(&mut out, "version", "int", &1i32.to_le_bytes());
+    }
+    for (name, value) in &options.attributes
+    {
+        exr_attribute(&mut out, name, value.kind(), &value.bytes());
+    }
+    out.push(0);
+    out
+}
+
+// Single part scanline OpenEXR with every layer at the same resolution
+pub fn encode_exr(layers: &[ExrLayer], options: &ExrOptions) -> io::Result<Vec<u8>>
+{
+    if layers.is_empty()
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no layers to write"));
+    }
+    let (width, height) = (layers[0].image.width, layers[0].image.height);
+    let mut channels = Vec::new();
+    for layer in layers
+    {
+        if layer.image.width != width || layer.image.height != height
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "layers differ in resolution"));
+        }
+        for (index, c) in layer.image.channels.iter().enumerate()
+        {
+            let name = if layer.name.is_empty() { c.clone() } else { format!("{}.{}", layer.name, c) };
+            channels.push(ExrChannel{ name, image: layer.image, index, pixel_type: layer.pixel_type });
+        }
+    }
+    channels.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+    let names: Vec<(&str, ExrPixelType)> = channels.iter().map(|c| (c.name.as_str(), c.pixel_type)).collect();
+    let mut out = exr_header(&names, width, height, options.compression, options, None);
+    // Chunks, preceded by their offset table
+    let lines = options.compression.lines_per_chunk();
+    let chunk_count = height.div_ceil(lines);
+    let table_pos = out.len();
+    out.resize(table_pos + 8*chunk_count, 0);
+    for chunk in 0..chunk_count
+    {
+        let offset = out.len() as u64;
+        out[table_pos + 8*chunk..table_pos + 8*chunk + 8].copy_from_slice(&offset.to_le_bytes());
+        let y0 = chunk*lines;
+        let y1 = usize::min(y0 + lines, height);
+        let mut raw = Vec::new();
+        for y in y0..y1
+        {
+            for c in &channels
+            {
+                for x in 0..width
+                {
+                    let v = c.image.get(x, y, c.index) as f32;
+                    match c.pixel_type
+                    {
+                        ExrPixelType::Half => raw.extend_from_slice(&f32_to_half(v).to_le_bytes()),
+                        ExrPixelType::Float => raw.extend_from_slice(&v.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        let packed = match options.compression
+        {
+            ExrCompression::None => raw.clone(),
+            ExrCompression::Rle => exr_rle(&exr_predict(&raw)),
+            ExrCompression::Zips | ExrCompression::Zip => deflate::zlib_compress(&exr_predict(&raw)),
+        };
+        // Chunks that don't shrink are stored uncompressed
+        let data = if packed.len() < raw.len() { packed } else { raw };
+        out.extend_from_slice(&(y0 as i32).to_le_bytes());
+        out.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        out.extend_from_slice(&data);
+    }
+    Ok(out)
+}
+
+pub fn write_exr(path: &Path, layers: &[ExrLayer], options: &ExrOptions) -> io::Result<()>
+{
+    fs::write(path, encode_exr(layers, options)?)
+}
+
+// Deep scanline OpenEXR with every channel stored as float, one line per
+// chunk. Deep data isn't written with ZIP, it falls back to ZIPS.
+pub fn encode_deep_exr(img: &DeepImage, options: &ExrOptions) -> io::Result<Vec<u8>>
+{
+    if img.channels.is_empty()
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no channels to write"));
+    }
+    let compression = if options.compression == ExrCompression::Zip { ExrCompression::Zips } else { options.compression };
+    let (width, height, n) = (img.width, img.height, img.channel_count());
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| img.channels[a].as_bytes().cmp(img.channels[b].as_bytes()));
+    let names: Vec<(&str, ExrPixelType)> = order.iter().map(|&c| (img.channels[c].as_str(), ExrPixelType::Float)).collect();
+    let mut out = exr_header(&names, width, height, compression, options, Some(img.max_samples()));
+    let pack = |raw: Vec<u8>| -> Vec<u8>
+    {
+        let packed = match compression
+        {
+            ExrCompression::None => return raw,
+            ExrCompression::Rle => exr_rle(&exr_predict(&raw)),
+            ExrCompression::Zips | ExrCompression::Zip => deflate::zlib_compress(&exr_predict(&raw)),
+        };
+        if packed.len() < raw.len() { packed } else { raw }
+    };
+    let table_pos = out.len();
+    out.resize(table_pos + 8*height, 0);
+    for y in 0..height
+    {
+        let offset = out.len() as u64;
+        out[table_pos + 8*y..table_pos + 8*y + 8].copy_from_slice(&offset.to_le_bytes());
+        // Sample counts are stored as running totals over the line
+        let (first, last) = (img.offsets[y*width], img.offsets[(y + 1)*width]);
+        let mut table = Vec::with_capacity(4*width);
+        for x in 0..width
+        {
+            table.extend_from_slice(&((img.offsets[y*width + x + 1] - first) as i32).to_le_bytes());
+        }
+        let mut raw = Vec::with_capacity(4*n*(last - first));
+        for &c in &order
+        {
+            for s in first..last
+            {
+                raw.extend_from_slice(&(img.data[s*n + c] as f32).to_le_bytes());
+            }
+        }
+        let raw_size = raw.len() as u64;
+        let (table, data) = (pack(table), pack(raw));
+        out.extend_from_slice(&(y as i32).to_le_bytes());
+        out.extend_from_slice(&(table.len() as u64).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        out.extend_from_slice(&raw_size.to_le_bytes());
+        out.extend_from_slice(&table);
+        out.extend_from_slice(&data);
+    }
+    Ok(out)
+}
+
+pub fn write_deep_exr(path: &Path, img: &DeepImage, options: &ExrOptions) -> io::Result<()>
+{
+    fs::write(path, encode_deep_exr(img, options)?)
+}
+
+// How integer formats store their values, alpha is always taken as linear
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding
+{
+    Linear,
+    SRGB,
+}
+
+fn invalid(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn unsupported(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::Unsupported, msg.to_string())
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+
+// Reads an image into linear floats. The format is detected from the
+// contents, except for TGA which has no signature and goes by extension.
+// Float formats are always linear, encoding only applies to integer ones.
+pub fn read_image(path: &Path, encoding: Encoding) -> io::Result<Image>
+{
+    let data = fs::read(path)?;
+    decode_image(&data, &extension(path), encoding)
+}
+
+pub fn decode_image(data: &[u8], extension: &str, encoding: Encoding) -> io::Result<Image>
+{
+    if data.starts_with(&PNG_SIGNATURE)
+    {
+        decode_png(data, encoding)
+    }
+    else if data.starts_with(&[0xff, 0xd8])
+    {
+        decode_jpeg(data, encoding)
+    }
+    else if data.starts_with(&EXR_MAGIC)
+    {
+        decode_exr(data)
+    }
+    else if data.starts_with(b"PF") || data.starts_with(b"Pf")
+    {
+        decode_pfm(data)
+    }
+    else if data.starts_with(b"#?")
+    {
+        decode_hdr(data)
+    }
+    else if data.starts_with(b"P5") || data.starts_with(b"P6")
+    {
+        decode_ppm(data, encoding)
+    }
+    else if extension == "tga"
+    {
+        decode_tga(data, encoding)
+    }
+    else
+    {
+        Err(unsupported("unknown image format"))
+    }
+}
+
+fn ldr_channels(n: usize) -> &'static [&'static str]
+{
+    match n
+    {
+        1 => &["Y"],
+        2 => &["Y", "A"],
+        3 => &["R", "G", "B"],
+        _ => &["R", "G", "B", "A"],
+    }
+}
+
+// Linearizes normalized integer values, leaving alpha alone
+fn decode_ldr(img: &mut Image, encoding: Encoding)
+{
+    if encoding == Encoding::Linear
+    {
+        return;
+    }
+    let n = img.channel_count();
+    let alpha = img.channel_index("A");
+    for (i, v) in img.data.iter_mut().enumerate()
+    {
+        if Some(i % n) != alpha
+        {
+            *v = srgb_to_linear(*v);
+        }
+    }
+}
+
+fn be_u32(b: &[u8]) -> u32
+{
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn le_u16(b: &[u8]) -> usize
+{
+    u16::from_le_bytes([b[0], b[1]]) as usize
+}
+
+fn le_u32(b: &[u8]) -> u32
+{
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn le_i32(b: &[u8]) -> i32
+{
+    le_u32(b) as i32
+}
+
+fn le_u64(b: &[u8]) -> u64
+{
+    le_u32(b) as u64 | (le_u32(&b[4..]) as u64) << 32
+}
+
+fn slice(data: &[u8], start: usize, len: usize) -> io::Result<&[u8]>
+{
+    start.checked_add(len).and_then(|end| data.get(start..end)).ok_or_else(|| invalid("unexpected end of file"))
+}
+
+// Pass origins and steps of Adam7 interlacing
+const ADAM7: [(usize, usize, usize, usize); 7] =
+    [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+fn png_unfilter(filter: u8, row: &mut [u8], prev: &[u8], bpp: usize) -> io::Result<()>
+{
+    for i in 0..row.len()
+    {
+        let a = if i >= bpp { row[i - bpp] as i32 } else { 0 };
+        let b = prev[i] as i32;
+        let c = if i >= bpp { prev[i - bpp] as i32 } else { 0 };
+        let p = match filter
+        {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => (a + b)/2,
+            4 =>
+            {
+                let (pa, pb, pc) = ((b - c).abs(), (a - c).abs(), (a + b - 2*c).abs());
+                if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+            }
+            _ => return Err(invalid("invalid PNG filter")),
+        };
+        row[i] = row[i].wrapping_add(p as u8);
+    }
+    Ok(())
+}
+
+// Keyword and text of every tEXt, zTXt and iTXt chunk, in file order
+pub fn decode_png_text(data: &[u8]) -> io::Result<Vec<(String, String)>>
+{
+    if !data.starts_with(&PNG_SIGNATURE)
+    {
+        return Err(invalid("not a PNG file"));
+    }
+    let latin1 = |b: &[u8]| b.iter().map(|&c| c as char).collect::<String>();
+    let mut text = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+    loop
+    {
+        let len = be_u32(slice(data, pos, 4)?) as usize;
+        let kind = slice(data, pos + 4, 4)?;
+        let body = slice(data, pos + 8, len)?;
+        pos += 12 + len;
+        if kind == b"IEND"
+        {
+            break;
+        }
+        if kind != b"tEXt" && kind != b"zTXt" && kind != b"iTXt"
+        {
+            continue;
+        }
+        let end = body.iter().position(|&b| b == 0).ok_or_else(|| invalid("invalid PNG text chunk"))?;
+        let (keyword, rest) = (latin1(&body[..end]), &body[end + 1..]);
+        let value = match kind
+        {
+            b"tEXt" => latin1(rest),
+            b"zTXt" => latin1(&deflate::zlib_decompress(slice(rest, 1, rest.len().saturating_sub(1))?)?),
+            _ =>
+            {
+                // Compression flag and method, then language tag and
+                // translated keyword
+                let flags = slice(rest, 0, 2)?;
+                let mut p = 2;
+                for _ in 0..2
+                {
+                    p += 1 + rest[p..].iter().position(|&b| b == 0).ok_or_else(|| invalid("invalid PNG text chunk"))?;
+                }
+                let bytes = if flags[0] == 1 { deflate::zlib_decompress(&rest[p..])? } else { rest[p..].to_vec() };
+                String::from_utf8(bytes).map_err(|_| invalid("invalid UTF-8 in PNG text"))?
+            }
+        };
+        text.push((keyword, value));
+    }
+    Ok(text)
+}
+
+// Any color type and bit depth, interlaced or not. Palette images become RGB
+// and transparency chunks become an alpha channel.
+pub fn decode_png(data: &[u8], encoding: Encoding) -> io::Result<Image>
+{
+    if !data.starts_with(&PNG_SIGNATURE)
+    {
+        return Err(invalid("not a PNG file"));
+    }
+    let mut header = None;
+    let (mut palette, mut trns, mut idat) = (Vec::new(), Vec::new(), Vec::new());
+    let mut pos = PNG_SIGNATURE.len();
+    loop
+    {
+        let len = be_u32(slice(data, pos, 4)?) as usize;
+        let kind = slice(data, pos + 4, 4)?;
+        let body = slice(data, pos + 8, len)?;
+        let crc = be_u32(slice(data, pos + 8 + len, 4)?);
+        if deflate::crc32_update(deflate::crc32(kind), body) != crc
+        {
+            return Err(invalid("PNG chunk checksum mismatch"));
+        }
+        match kind
+        {
+            b"IHDR" if len >= 13 => header = Some((be_u32(body) as usize, be_u32(&body[4..]) as usize, body[8] as usize, body[9], body[12])),
+            b"PLTE" => palette = body.to_vec(),
+            b"tRNS" => trns = body.to_vec(),
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+    let (width, height, depth, color_type, interlace) = header.ok_or_else(|| invalid("missing PNG header"))?;
+    let samples = match (color_type, depth)
+    {
+        (0, 1) | (0, 2) | (0, 4) | (0, 8) | (0, 16) => 1,
+        (3, 1) | (3, 2) | (3, 4) | (3, 8) => 1,
+        (4, 8) | (4, 16) => 2,
+        (2, 8) | (2, 16) => 3,
+        (6, 8) | (6, 16) => 4,
+        _ => return Err(invalid("invalid PNG color type or bit depth")),
+    };
+    if width == 0 || height == 0
+    {
+        return Err(invalid("empty PNG image"));
+    }
+    let channels = match color_type
+    {
+        0 | 2 if !trns.is_empty() => samples + 1,
+        3 if !trns.is_empty() => 4,
+        3 => 3,
+        _ => samples,
+    };
+    let mut img = Image::new(width, height, ldr_channels(channels));
+    let raw = deflate::zlib_decompress(&idat)?;
+    let bits = samples*depth;
+    let bpp = usize::max(1, bits/8);
+    let max = ((1u32 << depth) - 1) as f64;
+    let passes: &[(usize, usize, usize, usize)] = if interlace == 1 { &ADAM7 } else { &[(0, 0, 1, 1)] };
+    let mut pos = 0;
+    for &(x0, y0, dx, dy) in passes
+    {
+        if x0 >= width || y0 >= height
+        {
+            continue;
+        }
+        let (pw, ph) = ((width - x0).div_ceil(dx), (height - y0).div_ceil(dy));
+        let stride = (pw*bits).div_ceil(8);
+        let mut prev = vec![0; stride];
+        for py in 0..ph
+        {
+            let filter = *raw.get(pos).ok_or_else(|| invalid("truncated PNG image data"))?;
+            let mut row = slice(&raw, pos + 1, stride)?.to_vec();
+            pos += 1 + stride;
+            png_unfilter(filter, &mut row, &prev, bpp)?;
+            let sample = |i: usize| -> u32
+            {
+                match depth
+                {
+                    16 => ((row[2*i] as u32) << 8) | row[2*i + 1] as u32,
+                    8 => row[i] as u32,
+                    _ => (row[i*depth/8] as u32 >> (8 - depth - i*depth % 8)) & ((1 << depth) - 1),
+                }
+            };
+            for px in 0..pw
+            {
+                let (x, y) = (x0 + px*dx, y0 + py*dy);
+                if color_type == 3
+                {
+                    let index = sample(px) as usize;
+                    let c = palette.get(3*index..3*index + 3).ok_or_else(|| invalid("PNG palette index out of range"))?;
+                    for (k, &v) in c.iter().enumerate()
+                    {
+                        img.set(x, y, k, v as f64/255.);
+                    }
+                    if channels == 4
+                    {
+                        img.set(x, y, 3, *trns.get(index).unwrap_or(&255) as f64/255.);
+                    }
+                    continue;
+                }
+                let mut keyed = channels > samples;
+                for k in 0..samples
+                {
+                    let v = sample(px*samples + k);
+                    keyed = keyed && trns.len() >= 2*k + 2 && v == ((trns[2*k] as u32) << 8 | trns[2*k + 1] as u32);
+                    img.set(x, y, k, v as f64/max);
+                }
+                if channels > samples
+                {
+                    img.set(x, y, samples, if keyed { 0. } else { 1. });
+                }
+            }
+            prev = row;
+        }
+    }
+    decode_ldr(&mut img, encoding);
+    Ok(img)
+}
+
+// Natural order index of each coefficient in zigzag order
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63];
+
+// Entropy coded segment reader, removes stuffed zero bytes and stops at
+// markers
+struct JpegBits<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    n: u32,
+}
+
+impl<'a> JpegBits<'a>
+{
+    fn fill(&mut self)
+    {
+        while self.n <= 24
+        {
+            let mut b = 0;
+            if self.pos < self.data.len()
+            {
+                b = self.data[self.pos];
+                if b != 0xff
+                {
+                    self.pos += 1;
+                }
+                else if self.data.get(self.pos + 1) == Some(&0)
+                {
+                    self.pos += 2;
+                }
+                else
+                {
+                    b = 0;
+                }
+            }
+            self.acc |= (b as u32) << (24 - self.n);
+            self.n += 8;
+        }
+    }
+    fn peek16(&mut self) -> u32
+    {
+        self.fill();
+        self.acc >> 16
+    }
+    fn consume(&mut self, count: u32)
+    {
+        self.acc <<= count;
+        self.n -= count;
+    }
+    fn bits(&mut self, count: u32) -> i32
+    {
+        if count == 0
+        {
+            return 0;
+        }
+        self.fill();
+        let v = self.acc >> (32 - count);
+        self.consume(count);
+        v as i32
+    }
+    // Value of a coefficient with count magnitude bits
+    fn receive_extend(&mut self, count: u32) -> i32
+    {
+        let v = self.bits(count);
+        if count > 0 && v < 1 << (count - 1) { v - (1 << count) + 1 } else { v }
+    }
+    fn restart(&mut self) -> io::Result<()>
+    {
+        self.acc = 0;
+        self.n = 0;
+        match self.data.get(self.pos..self.pos + 2)
+        {
+            Some(&[0xff, m]) if (0xd0..=0xd7).contains(&m) =>
+            {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err(invalid("missing JPEG restart marker")),
+        }
+    }
+}
+
+// Huffman table indexed by the next 16 bits of input
+struct JpegHuffman
+{
+    table: Vec<(u8, u8)>,
+}
+
+impl JpegHuffman
+{
+    fn new(counts: &[u8], symbols: &[u8]) -> io::Result<JpegHuffman>
+    {
+        let mut table = vec![(0, 0); 1 << 16];
+        let (mut code, mut k) = (0usize, 0);
+        for len in 1..=16
+        {
+            for _ in 0..counts[len - 1]
+            {
+                let start = code << (16 - len);
+                let end = (code + 1) << (16 - len);
+                if end > table.len() || k >= symbols.len()
+                {
+                    return Err(invalid("invalid JPEG Huffman table"));
+                }
+                for t in &mut table[start..end]
+                {
+                    *t = (symbols[k], len as u8);
+                }
+                code += 1;
+                k += 1;
+            }
+            code <<= 1;
+        }
+        Ok(JpegHuffman{ table })
+    }
+    fn decode(&self, bits: &mut JpegBits) -> io::Result<u8>
+    {
+        let (sym, len) = self.table[bits.peek16() as usize];
+        if len == 0
+        {
+            return Err(invalid("invalid JPEG Huffman code"));
+        }
+        bits.consume(len as u32);
+        Ok(sym)
+    }
+}
+
+struct JpegComponent
+{
+    id: u8,
+    h: usize,
+    v: usize,
+    quant: usize,
+    dc: usize,
+    ac: usize,
+    stride: usize,
+    plane: Vec<u8>,
+    pred: i32,
+    // Quantized coefficients of every block in natural order, kept until
+    // the last scan of a progressive image
+    coef: Vec<i32>,
+}
+
+// Spectral selection and successive approximation of a progressive scan
+#[derive(Clone, Copy)]
+struct JpegBand
+{
+    start: usize,
+    end: usize,
+    high: u32,
+    low: u32,
+}
+
+struct Jpeg
+{
+    width: usize,
+    height: usize,
+    components: Vec<JpegComponent>,
+    quant: [[u16; 64]; 4],
+    dc: Vec<Option<JpegHuffman>>,
+    ac: Vec<Option<JpegHuffman>>,
+    restart_interval: usize,
+    idct: [[f32; 8]; 8],
+    progressive: bool,
+    eob_run: usize,
+}
+
+impl Jpeg
+{
+    fn decode_block(&mut self, bits: &mut JpegBits, c: usize, bx: usize, by: usize) -> io::Result<()>
+    {
+        let comp = &mut self.components[c];
+        let missing = || invalid("missing JPEG Huffman table");
+        let dc = self.dc[comp.dc].as_ref().ok_or_else(missing)?;
+        let ac = self.ac[comp.ac].as_ref().ok_or_else(missing)?;
+        let q = &self.quant[comp.quant];
+        let mut coef = [0f32; 64];
+        let t = dc.decode(bits)? as u32;
+        comp.pred += bits.receive_extend(t);
+        coef[0] = (comp.pred*q[0] as i32) as f32;
+        let mut k = 1;
+        while k < 64
+        {
+            let rs = ac.decode(bits)?;
+            let (r, s) = ((rs >> 4) as usize, (rs & 15) as u32);
+            if s == 0
+            {
+                if r != 15
+                {
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += r;
+            if k > 63
+            {
+                return Err(invalid("JPEG coefficient out of range"));
+            }
+            coef[ZIGZAG[k]] = (bits.receive_extend(s)*q[k] as i32) as f32;
+            k += 1;
+        }
+        self.inverse_dct(c, bx, by, &coef);
+        Ok(())
+    }
+    // Separable inverse DCT, rows then columns
+    fn inverse_dct(&mut self, c: usize, bx: usize, by: usize, coef: &[f32; 64])
+    {
+        let comp = &mut self.components[c];
+        let m = &self.idct;
+        let mut tmp = [0f32; 64];
+        for v in 0..8
+        {
+            for x in 0..8
+            {
+                tmp[v*8 + x] = (0..8).map(|u| m[x][u]*coef[v*8 + u]).sum();
+            }
+        }
+        for (y, my) in m.iter().enumerate()
+        {
+            for x in 0..8
+            {
+                let s: f32 = (0..8).map(|v| my[v]*tmp[v*8 + x]).sum();
+                comp.plane[(by*8 + y)*comp.stride + bx*8 + x] = (s + 128.).round().clamp(0., 255.) as u8;
+            }
+        }
+    }
+    // One band of a block in a progressive scan, the first scan of a band
+    // sends the high bits of the coefficients and later ones refine them a
+    // bit at a time
+    fn decode_band(&mut self, bits: &mut JpegBits, c: usize, bx: usize, by: usize, band: JpegBand) -> io::Result<()>
+    {
+        let comp = &mut self.components[c];
+        let missing = || invalid("missing JPEG Huffman table");
+        let at = (by*comp.stride/8 + bx)*64;
+        let coef = &mut comp.coef[at..at + 64];
+        if band.start == 0
+        {
+            if band.high == 0
+            {
+                let t = self.dc[comp.dc].as_ref().ok_or_else(missing)?.decode(bits)? as u32;
+                comp.pred += bits.receive_extend(t);
+                coef[0] = comp.pred << band.low;
+            }
+            else if bits.bits(1) == 1
+            {
+                coef[0] |= 1 << band.low;
+            }
+            return Ok(());
+        }
+        let ac = self.ac[comp.ac].as_ref().ok_or_else(missing)?;
+        let mut k = band.start;
+        if band.high == 0
+        {
+            if self.eob_run > 0
+            {
+                self.eob_run -= 1;
+                return Ok(());
+            }
+            while k <= band.end
+            {
+                let rs = ac.decode(bits)?;
+                let (r, s) = ((rs >> 4) as usize, (rs & 15) as u32);
+                if s == 0
+                {
+                    if r != 15
+                    {
+                        // The rest of this band is zero here and in the next
+                        // eob_run blocks
+                        self.eob_run = (1 << r) - 1 + bits.bits(r as u32) as usize;
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += r;
+                if k > band.end
+                {
+                    return Err(invalid("JPEG coefficient out of range"));
+                }
+                coef[ZIGZAG[k]] = bits.receive_extend(s) << band.low;
+                k += 1;
+            }
+            return Ok(());
+        }
+        // Refinement, new coefficients are +-1 at this bit and ones that are
+        // already nonzero get a correction bit each as they are skipped over
+        let bit = 1 << band.low;
+        let refine = |bits: &mut JpegBits, v: &mut i32|
+        {
+            if bits.bits(1) == 1 && *v & bit == 0
+            {
+                *v += if *v >= 0 { bit } else { -bit };
+            }
+        };
+        if self.eob_run == 0
+        {
+            while k <= band.end
+            {
+                let rs = ac.decode(bits)?;
+                let (mut r, s) = ((rs >> 4) as i32, rs & 15);
+                let value = match s
+                {
+                    0 if r != 15 =>
+                    {
+                        self.eob_run = (1 << r) + bits.bits(r as u32) as usize;
+                        break;
+                    }
+                    0 => 0,
+                    1 => if bits.bits(1) == 1 { bit } else { -bit },
+                    _ => return Err(invalid("invalid JPEG refinement")),
+                };
+                // Skip r zero coefficients and stop at the next zero one
+                while k <= band.end
+                {
+                    let v = &mut coef[ZIGZAG[k]];
+                    if *v != 0
+                    {
+                        refine(bits, v);
+                    }
+                    else if r == 0
+                    {
+                        break;
+                    }
+                    else
+                    {
+                        r -= 1;
+                    }
+                    k += 1;
+                }
+                if value != 0
+                {
+                    if k > band.end
+                    {
+                        return Err(invalid("JPEG coefficient out of range"));
+                    }
+                    coef[ZIGZAG[k]] = value;
+                }
+                k += 1;
+            }
+        }
+        if self.eob_run > 0
+        {
+            for k in k..=band.end
+            {
+                let v = &mut coef[ZIGZAG[k]];
+                if *v != 0
+                {
+                    refine(bits, v);
+                }
+            }
+            self.eob_run -= 1;
+        }
+        Ok(())
+    }
+    fn decode_unit(&mut self, bits: &mut JpegBits, c: usize, bx: usize, by: usize, band: JpegBand) -> io::Result<()>
+    {
+        if self.progressive { self.decode_band(bits, c, bx, by, band) } else { self.decode_block(bits, c, bx, by) }
+    }
+    fn decode_scan(&mut self, data: &[u8], pos: usize, scan: &[usize], band: JpegBand) -> io::Result<usize>
+    {
+        let hmax = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        let vmax = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        let mut bits = JpegBits{ data, pos, acc: 0, n: 0 };
+        for &c in scan
+        {
+            self.components[c].pred = 0;
+        }
+        self.eob_run = 0;
+        // A single component scan isn't interleaved and covers only the
+        // blocks inside the image, one at a time
+        let (units_x, units_y) = if scan.len() == 1
+        {
+            let c = &self.components[scan[0]];
+            ((self.width*c.h).div_ceil(hmax).div_ceil(8), (self.height*c.v).div_ceil(vmax).div_ceil(8))
+        }
+        else
+        {
+            (self.width.div_ceil(8*hmax), self.height.div_ceil(8*vmax))
+        };
+        for unit in 0..units_x*units_y
+        {
+            if self.restart_interval > 0 && unit > 0 && unit % self.restart_interval == 0
+            {
+                bits.restart()?;
+                for &c in scan
+                {
+                    self.components[c].pred = 0;
+                }
+                self.eob_run = 0;
+            }
+            let (ux, uy) = (unit % units_x, unit/units_x);
+            if scan.len() == 1
+            {
+                self.decode_unit(&mut bits, scan[0], ux, uy, band)?;
+                continue;
+            }
+            for &c in scan
+            {
+                let (h, v) = (self.components[c].h, self.components[c].v);
+                for by in 0..v
+                {
+                    for bx in 0..h
+                    {
+                        self.decode_unit(&mut bits, c, ux*h + bx, uy*v + by, band)?;
+                    }
+                }
+            }
+        }
+        Ok(bits.pos)
+    }
+    // Dequantizes and transforms the coefficients gathered by the scans of
+    // a progressive image
+    fn finish_progressive(&mut self)
+    {
+        for c in 0..self.components.len()
+        {
+            let comp = &mut self.components[c];
+            let (q, blocks) = (self.quant[comp.quant], std::mem::take(&mut comp.coef));
+            let per_row = comp.stride/8;
+            for (b, block) in blocks.chunks(64).enumerate()
+            {
+                let mut coef = [0f32; 64];
+                for k in 0..64
+                {
+                    coef[ZIGZAG[k]] = (block[ZIGZAG[k]]*q[k] as i32) as f32;
+                }
+                self.inverse_dct(c, b % per_row, b/per_row, &coef);
+            }
+        }
+    }
+}
+
+// Baseline, extended sequential and progressive Huffman coded JPEG, gray
+// or YCbCr
+pub fn decode_jpeg(data: &[u8], encoding: Encoding) -> io::Result<Image>
+{
+    let mut idct = [[0f32; 8]; 8];
+    for (x, row) in idct.iter_mut().enumerate()
+    {
+        for (u, m) in row.iter_mut().enumerate()
+        {
+            let cu = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1. };
+            *m = cu/2.*((2*x + 1) as f32*u as f32*std::f32::consts::PI/16.).cos();
+        }
+    }
+    let mut jpeg = Jpeg{ width: 0, height: 0, components: Vec::new(), quant: [[0; 64]; 4],
+                         dc: (0..4).map(|_| None).collect(), ac: (0..4).map(|_| None).collect(), restart_interval: 0, idct,
+                         progressive: false, eob_run: 0 };
+    let mut adobe_transform = None;
+    let mut pos = 2;
+    loop
+    {
+        // Markers may be padded with any number of 0xff bytes
+        while data.get(pos) == Some(&0xff) && data.get(pos + 1) == Some(&0xff)
+        {
+            pos += 1;
+        }
+        let marker = slice(data, pos, 2)?;
+        if marker[0] != 0xff
+        {
+            return Err(invalid("expected a JPEG marker"));
+        }
+        let marker = marker[1];
+        pos += 2;
+        if marker == 0xd9
+        {
+            break;
+        }
+        if (0xd0..=0xd8).contains(&marker) || marker == 0x01
+        {
+            continue;
+        }
+        let len = slice(data, pos, 2)?;
+        let len = ((len[0] as usize) << 8 | len[1] as usize).max(2);
+        let body = slice(data, pos + 2, len - 2)?;
+        pos += len;
+        match marker
+        {
+            0xc0..=0xc2 =>
+            {
+                jpeg.progressive = marker == 0xc2;
+                if body.len() < 6 || body[0] != 8
+                {
+                    return Err(unsupported("only 8-bit JPEG is supported"));
+                }
+                jpeg.height = (body[1] as usize) << 8 | body[2] as usize;
+                jpeg.width = (body[3] as usize) << 8 | body[4] as usize;
+                let n = body[5] as usize;
+                if jpeg.width == 0 || jpeg.height == 0 || (n != 1 && n != 3)
+                {
+                    return Err(unsupported("JPEG must be gray or three component and of known size"));
+                }
+                let c = slice(body, 6, 3*n)?;
+                for i in 0..n
+                {
+                    let (h, v) = ((c[3*i + 1] >> 4) as usize, (c[3*i + 1] & 15) as usize);
+                    if !(1..=4).contains(&h) || !(1..=4).contains(&v) || c[3*i + 2] > 3
+                    {
+                        return Err(invalid("invalid JPEG component"));
+                    }
+                    jpeg.components.push(JpegComponent{ id: c[3*i], h, v, quant: c[3*i + 2] as usize, dc: 0, ac: 0,
+                                                        stride: 0, plane: Vec::new(), pred: 0, coef: Vec::new() });
+                }
+                let hmax = jpeg.components.iter().map(|c| c.h).max().unwrap();
+                let vmax = jpeg.components.iter().map(|c| c.v).max().unwrap();
+                let (mx, my) = (jpeg.width.div_ceil(8*hmax), jpeg.height.div_ceil(8*vmax));
+                for c in &mut jpeg.components
+                {
+                    c.stride = mx*c.h*8;
+                    c.plane = vec![0; c.stride*my*c.v*8];
+                    if jpeg.progressive
+                    {
+                        c.coef = vec![0; c.plane.len()];
+                    }
+                }
+            }
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf =>
+                return Err(unsupported("lossless, hierarchical and arithmetic coded JPEG are not supported")),
+            0xc4 =>
+            {
+                let mut p = 0;
+                while p < body.len()
+                {
+                    let (class, id) = (body[p] >> 4, (body[p] & 15) as usize);
+                    let counts = slice(body, p + 1, 16)?;
+                    let total = counts.iter().map(|&c| c as usize).sum();
+                    let table = JpegHuffman::new(counts, slice(body, p + 17, total)?)?;
+                    if id > 3 || class > 1
+                    {
+                        return Err(invalid("invalid JPEG Huffman table"));
+                    }
+                    if class == 0 { jpeg.dc[id] = Some(table) } else { jpeg.ac[id] = Some(table) }
+                    p += 17 + total;
+                }
+            }
+            0xdb =>
+            {
+                let mut p = 0;
+                while p < body.len()
+                {
+                    let (wide, id) = (body[p] >> 4 != 0, (body[p] & 15) as usize);
+                    if id > 3
+                    {
+                        return Err(invalid("invalid JPEG quantization table"));
+                    }
+                    let size = if wide { 2 } else { 1 };
+                    let q = slice(body, p + 1, 64*size)?;
+                    for k in 0..64
+                    {
+                        jpeg.quant[id][k] = if wide { (q[2*k] as u16) << 8 | q[2*k + 1] as u16 } else { q[k] as u16 };
+                    }
+                    p += 1 + 64*size;
+                }
+            }
+            0xdd if body.len() >= 2 => jpeg.restart_interval = (body[0] as usize) << 8 | body[1] as usize,
+            0xee if body.len() >= 12 && body.starts_with(b"Adobe") => adobe_transform = Some(body[11]),
+            0xda =>
+            {
+                if jpeg.components.is_empty()
+                {
+                    return Err(invalid("JPEG scan before frame header"));
+                }
+                let n = *body.first().ok_or_else(|| invalid("invalid JPEG scan"))? as usize;
+                let s = slice(body, 1, 2*n)?;
+                let mut scan = Vec::new();
+                for i in 0..n
+                {
+                    let c = jpeg.components.iter().position(|c| c.id == s[2*i]).ok_or_else(|| invalid("unknown JPEG scan component"))?;
+                    jpeg.components[c].dc = (s[2*i + 1] >> 4) as usize & 3;
+                    jpeg.components[c].ac = (s[2*i + 1] & 15) as usize & 3;
+                    scan.push(c);
+                }
+                let b = slice(body, 1 + 2*n, 3)?;
+                let band = JpegBand{ start: b[0] as usize, end: b[1] as usize, high: (b[2] >> 4) as u32, low: (b[2] & 15) as u32 };
+                if jpeg.progressive && (band.end > 63 || band.start > band.end || (band.start == 0) != (band.end == 0)
+                                        || (band.start > 0 && n != 1) || band.low > 13)
+                {
+                    return Err(invalid("invalid JPEG progressive scan"));
+                }
+                pos = jpeg.decode_scan(data, pos, &scan, band)?;
+                // Skip anything up to the next marker
+                while pos + 1 < data.len() && !(data[pos] == 0xff && data[pos + 1] != 0 && !(0xd0..=0xd7).contains(&data[pos + 1]))
+                {
+                    pos += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    if jpeg.components.is_empty()
+    {
+        return Err(invalid("JPEG without a frame"));
+    }
+    if jpeg.progressive
+    {
+        jpeg.finish_progressive();
+    }
+    let (width, height) = (jpeg.width, jpeg.height);
+    let hmax = jpeg.components.iter().map(|c| c.h).max().unwrap();
+    let vmax = jpeg.components.iter().map(|c| c.v).max().unwrap();
+    let ycc = jpeg.components.len() == 3 && adobe_transform != Some(0);
+    let mut img = Image::new(width, height, ldr_channels(jpeg.components.len()));
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            // Subsampled components are upsampled by replication
+            let s: Vec<f64> = jpeg.components.iter().map(|c| c.plane[y*c.v/vmax*c.stride + x*c.h/hmax] as f64).collect();
+            if ycc
+            {
+                let (cb, cr) = (s[1] - 128., s[2] - 128.);
+                let rgb = [s[0] + 1.402*cr, s[0] - 0.344136*cb - 0.714136*cr, s[0] + 1.772*cb];
+                for (k, v) in rgb.iter().enumerate()
+                {
+                    img.set(x, y, k, v.clamp(0., 255.)/255.);
+                }
+            }
+            else
+            {
+                for (k, v) in s.iter().enumerate()
+                {
+                    img.set(x, y, k, v/255.);
+                }
+            }
+        }
+    }
+    decode_ldr(&mut img, encoding);
+    Ok(img)
+}
+
+// Color mapped, true color and gray images, raw or run length encoded
+pub fn decode_tga(data: &[u8], encoding: Encoding) -> io::Result<Image>
+{
+    let h = slice(data, 0, 18)?;
+    let (kind, depth, descriptor) = (h[2], h[16] as usize, h[17]);
+    let (map_first, map_len, map_depth) = (le_u16(&h[3..]), le_u16(&h[5..]), h[7] as usize);
+    let (width, height) = (le_u16(&h[12..]), le_u16(&h[14..]));
+    let mut pos = 18 + h[0] as usize;
+    let palette = if h[1] == 1 { slice(data, pos, map_len*map_depth.div_ceil(8))? } else { &[] };
+    pos += palette.len();
+    let (base, rle) = (kind & !8, kind & 8 != 0);
+    let color_depth = match (base, depth)
+    {
+        (1, 8) if !palette.is_empty() => map_depth,
+        (2, 15) | (2, 16) | (2, 24) | (2, 32) | (3, 8) | (3, 16) => depth,
+        _ => return Err(unsupported("unsupported TGA image type or depth")),
+    };
+    if base == 1 && ![15, 16, 24, 32].contains(&map_depth)
+    {
+        return Err(unsupported("unsupported TGA color map depth"));
+    }
+    let alpha = descriptor & 15 != 0 && (color_depth == 16 || color_depth == 32);
+    let channels = if base == 3 { depth/8 } else if alpha { 4 } else { 3 };
+    let bpp = depth.div_ceil(8);
+    let n = width*height;
+    let raw = if rle
+    {
+        let mut raw = Vec::with_capacity(n*bpp);
+        while raw.len() < n*bpp
+        {
+            let packet = *data.get(pos).ok_or_else(|| invalid("unexpected end of file"))?;
+            let count = (packet & 0x7f) as usize + 1;
+            pos += 1;
+            if packet & 0x80 != 0
+            {
+                let px = slice(data, pos, bpp)?;
+                for _ in 0..count
+                {
+                    raw.extend_from_slice(px);
+                }
+                pos += bpp;
+            }
+            else
+            {
+                raw.extend_from_slice(slice(data, pos, count*bpp)?);
+                pos += count*bpp;
+            }
+        }
+        raw.truncate(n*bpp);
+        raw
+    }
+    else
+    {
+        slice(data, pos, n*bpp)?.to_vec()
+    };
+    let mut img = Image::new(width, height, ldr_channels(channels));
+    let (top_down, right_to_left) = (descriptor & 0x20 != 0, descriptor & 0x10 != 0);
+    for i in 0..n
+    {
+        let (sx, sy) = (i % width, i/width);
+        let x = if right_to_left { width - 1 - sx } else { sx };
+        let y = if top_down { sy } else { height - 1 - sy };
+        let p = &raw[i*bpp..(i + 1)*bpp];
+        if base == 3
+        {
+            for (k, &v) in p.iter().enumerate()
+            {
+                img.set(x, y, k, v as f64/255.);
+            }
+            continue;
+        }
+        let c = if base == 1
+        {
+            let index = (p[0] as usize).checked_sub(map_first).ok_or_else(|| invalid("TGA color map index out of range"))?;
+            let size = map_depth.div_ceil(8);
+            palette.get(index*size..(index + 1)*size).ok_or_else(|| invalid("TGA color map index out of range"))?
+        }
+        else
+        {
+            p
+        };
+        // Colors are stored as BGR(A), or 5 bits per channel with one
+        // attribute bit
+        let rgba = if color_depth <= 16
+        {
+            let v = le_u16(c);
+            [((v >> 10) & 31) as f64/31., ((v >> 5) & 31) as f64/31., (v & 31) as f64/31., (v >> 15) as f64]
+        }
+        else
+        {
+            [c[2] as f64/255., c[1] as f64/255., c[0] as f64/255., if color_depth == 32 { c[3] as f64/255. } else { 1. }]
+        };
+        for (k, &v) in rgba.iter().take(channels).enumerate()
+        {
+            img.set(x, y, k, v);
+        }
+    }
+    decode_ldr(&mut img, encoding);
+    Ok(img)
+}
+
+// Whitespace separated header fields of the Netpbm formats and PFM, with
+// the position just past the single whitespace that ends the header
+fn header_fields(data: &[u8], count: usize) -> io::Result<(Vec<String>, usize)>
+{
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < count
+    {
+        match data.get(pos)
+        {
+            None => return Err(invalid("truncated image header")),
+            Some(b'#') =>
+            {
+                while pos < data.len() && data[pos] != b'\n'
+                {
+                    pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => pos += 1,
+            Some(_) =>
+            {
+                let start = pos;
+                while pos < data.len() && !data[pos].is_ascii_whitespace()
+                {
+                    pos += 1;
+                }
+                fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+            }
+        }
+    }
+    Ok((fields, pos + 1))
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> io::Result<T>
+{
+    s.parse().map_err(|_| invalid("invalid number in image header"))
+}
+
+// Binary PGM and PPM, with 8 or 16 bits per channel
+pub fn decode_ppm(data: &[u8], encoding: Encoding) -> io::Result<Image>
+{
+    let (fields, pos) = header_fields(data, 4)?;
+    let channels = match fields[0].as_str()
+    {
+        "P5" => 1,
+        "P6" => 3,
+        _ => return Err(unsupported("only binary PGM and PPM are supported")),
+    };
+    let (width, height, max): (usize, usize, u32) = (parse(&fields[1])?, parse(&fields[2])?, parse(&fields[3])?);
+    if max == 0 || max > 65535
+    {
+        return Err(invalid("invalid PPM maximum value"));
+    }
+    let size = if max < 256 { 1 } else { 2 };
+    let raw = slice(data, pos, width*height*channels*size)?;
+    let mut img = Image::new(width, height, ldr_channels(channels));
+    for (i, v) in img.data.iter_mut().enumerate()
+    {
+        let q = if size == 1 { raw[i] as u32 } else { (raw[2*i] as u32) << 8 | raw[2*i + 1] as u32 };
+        *v = q as f64/max as f64;
+    }
+    decode_ldr(&mut img, encoding);
+    Ok(img)
+}
+
+// The scale in the header only gives the byte order, rows are bottom up
+pub fn decode_pfm(data: &[u8]) -> io::Result<Image>
+{
+    let (fields, pos) = header_fields(data, 4)?;
+    let channels = match fields[0].as_str()
+    {
+        "Pf" => 1,
+        "PF" => 3,
+        _ => return Err(invalid("not a PFM file")),
+    };
+    let (width, height, scale): (usize, usize, f64) = (parse(&fields[1])?, parse(&fields[2])?, parse(&fields[3])?);
+    let raw = slice(data, pos, width*height*channels*4)?;
+    let mut img = Image::new(width, height, ldr_channels(channels));
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            for c in 0..channels
+            {
+                let i = 4*(((height - 1 - y)*width + x)*channels + c);
+                let b = [raw[i], raw[i + 1], raw[i + 2], raw[i + 3]];
+                let v = if scale < 0. { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) };
+                img.set(x, y, c, v as f64);
+            }
+        }
+    }
+    Ok(img)
+}
+
+fn hdr_scanline(data: &[u8], pos: &mut usize, width: usize) -> io::Result<Vec<[u8; 4]>>
+{
+    let mut line = vec![[0u8; 4]; width];
+    let head = slice(data, *pos, 4)?;
+    if (8..0x8000).contains(&width) && head[0] == 2 && head[1] == 2 && head[2] & 0x80 == 0
+    {
+        // Adaptive run length encoding, one component after the other
+        if ((head[2] as usize) << 8 | head[3] as usize) != width
+        {
+            return Err(invalid("HDR scanline width mismatch"));
+        }
+        *pos += 4;
+        for c in 0..4
+        {
+            let mut x = 0;
+            while x < width
+            {
+                let count = *data.get(*pos).ok_or_else(|| invalid("unexpected end of file"))? as usize;
+                *pos += 1;
+                if count > 128
+                {
+                    let v = *data.get(*pos).ok_or_else(|| invalid("unexpected end of file"))?;
+                    *pos += 1;
+                    if x + count - 128 > width
+                    {
+                        return Err(invalid("HDR run overflows the scanline"));
+                    }
+                    for p in &mut line[x..x + count - 128]
+                    {
+                        p[c] = v;
+                    }
+                    x += count - 128;
+                }
+                else
+                {
+                    if count == 0 || x + count > width
+                    {
+                        return Err(invalid("HDR run overflows the scanline"));
+                    }
+                    for (p, &v) in line[x..x + count].iter_mut().zip(slice(data, *pos, count)?)
+                    {
+                        p[c] = v;
+                    }
+                    *pos += count;
+                    x += count;
+                }
+            }
+        }
+        return Ok(line);
+    }
+    // Flat pixels, where a (1, 1, 1, n) pixel repeats the previous one
+    let (mut x, mut shift) = (0, 0);
+    while x < width
+    {
+        let p = slice(data, *pos, 4)?;
+        *pos += 4;
+        if p[0] == 1 && p[1] == 1 && p[2] == 1 && x > 0
+        {
+            let count = (p[3] as usize) << shift;
+            if x + count > width
+            {
+                return Err(invalid("HDR run overflows the scanline"));
+            }
+            let prev = line[x - 1];
+            for q in &mut line[x..x + count]
+            {
+                *q = prev;
+            }
+            x += count;
+            shift += 8;
+        }
+        else
+        {
+            line[x] = [p[0], p[1], p[2], p[3]];
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(line)
+}
+
+// Radiance RGBE, flat or run length encoded, stored top down or bottom up
+pub fn decode_hdr(data: &[u8]) -> io::Result<Image>
+{
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| -> io::Result<String>
+    {
+        let end = data[*pos..].iter().position(|&b| b == b'\n').ok_or_else(|| invalid("truncated HDR header"))?;
+        let line = String::from_utf8_lossy(&data[*pos..*pos + end]).into_owned();
+        *pos += end + 1;
+        Ok(line)
+    };
+    loop
+    {
+        let line = next_line(&mut pos)?;
+        if line.is_empty()
+        {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=")
+        {
+            if format.trim() != "32-bit_rle_rgbe"
+            {
+                return Err(unsupported("only RGBE Radiance files are supported"));
+            }
+        }
+    }
+    let resolution = next_line(&mut pos)?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    if fields.len() != 4 || (fields[0] != "-Y" && fields[0] != "+Y") || fields[2] != "+X"
+    {
+        return Err(unsupported("unsupported HDR orientation"));
+    }
+    let (height, width): (usize, usize) = (parse(fields[1])?, parse(fields[3])?);
+    let mut img = Image::new(width, height, &["R", "G", "B"]);
+    for row in 0..height
+    {
+        let y = if fields[0] == "-Y" { row } else { height - 1 - row };
+        for (x, p) in hdr_scanline(data, &mut pos, width)?.iter().enumerate()
+        {
+            if p[3] == 0
+            {
+                continue;
+            }
+            let f = 2f64.powi(p[3] as i32 - 136);
+            for (c, &v) in p.iter().take(3).enumerate()
+            {
+                img.set(x, y, c, (v as f64 + 0.5)*f);
+            }
+        }
+    }
+    Ok(img)
+}
+
+pub fn half_to_f32(h: u16) -> f32
+{
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let mant = (h & 0x3ff) as u32;
+    let bits = if exp == 0x1f
+    {
+        sign | 0x7f80_0000 | (mant << 13)
+    }
+    else if exp != 0
+    {
+        sign | ((exp + 127 - 15) << 23) | (mant << 13)
+    }
+    else if mant == 0
+    {
+        sign
+    }
+    else
+    {
+        // Subnormal half, renormalized
+        let (mut e, mut m) = (127 - 15 + 1, mant);
+        while m & 0x400 == 0
+        {
+            m <<= 1;
+            e -= 1;
+        }
+        sign | (e << 23) | ((m & 0x3ff) << 13)
+    };
+    f32::from_bits(bits)
+}
+
+fn exr_unpredict(data: &[u8]) -> Vec<u8>
+{
+    let mut t = data.to_vec();
+    for i in 1..t.len()
+    {
+        t[i] = (t[i - 1] as i32 + t[i] as i32 - 128) as u8;
+    }
+    let half = t.len().div_ceil(2);
+    let mut out = Vec::with_capacity(t.len());
+    for i in 0..half
+    {
+        out.push(t[i]);
+        if half + i < t.len()
+        {
+            out.push(t[half + i]);
+        }
+    }
+    out
+}
+
+fn exr_unrle(data: &[u8]) -> io::Result<Vec<u8>>
+{
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len()
+    {
+        let count = data[pos] as i8;
+        pos += 1;
+        if count < 0
+        {
+            out.extend_from_slice(slice(data, pos, -(count as isize) as usize)?);
+            pos += -(count as isize) as usize;
+        }
+        else
+        {
+            let v = *data.get(pos).ok_or_else(|| invalid("truncated EXR run"))?;
+            out.extend(std::iter::repeat(v).take(count as usize + 1));
+            pos += 1;
+        }
+    }
+    Ok(out)
+}
+
+// Most significant bit first reader of PIZ Huffman data, up to end bits
+struct PizBits<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> PizBits<'a>
+{
+    fn read(&mut self, count: usize) -> io::Result<u64>
+    {
+        if self.pos + count > self.end
+        {
+            return Err(invalid("truncated EXR PIZ data"));
+        }
+        let mut v = 0;
+        for i in self.pos..self.pos + count
+        {
+            v = v << 1 | ((self.data[i >> 3] >> (7 - (i & 7))) & 1) as u64;
+        }
+        self.pos += count;
+        Ok(v)
+    }
+}
+
+// Huffman stage of PIZ: a header with the symbol range and bit count, the
+// code lengths in 6 bits each with runs of unused symbols folded into the
+// lengths 59 to 63, then the codes. The last symbol in the range isn't a
+// value but repeats the previous one as often as the next 8 bits say.
+fn piz_huffman(data: &[u8], n: usize) -> io::Result<Vec<u16>>
+{
+    if n == 0
+    {
+        return Ok(Vec::new());
+    }
+    let (im, run_symbol) = (le_u32(slice(data, 0, 4)?) as usize, le_u32(slice(data, 4, 4)?) as usize);
+    let code_bits = le_u32(slice(data, 12, 4)?) as usize;
+    if im > run_symbol || run_symbol > 0x1_0000
+    {
+        return Err(invalid("invalid EXR PIZ code table"));
+    }
+    let mut bits = PizBits{ data, pos: 160, end: 8*data.len() };
+    let mut lengths = vec![0; 0x1_0001];
+    let mut i = im;
+    while i <= run_symbol
+    {
+        let l = bits.read(6)? as usize;
+        if l < 59
+        {
+            lengths[i] = l;
+            i += 1;
+            continue;
+        }
+        i += if l == 63 { bits.read(8)? as usize + 6 } else { l - 57 };
+        if i > run_symbol + 1
+        {
+            return Err(invalid("invalid EXR PIZ code table"));
+        }
+    }
+    // Canonical codes, numbered from the longest ones up
+    let mut count = [0; 59];
+    let mut symbols = vec![Vec::new(); 59];
+    for (s, &l) in lengths.iter().enumerate().filter(|&(_, &l)| l > 0)
+    {
+        count[l] += 1;
+        symbols[l].push(s);
+    }
+    let (mut first, mut c) = ([0u64; 59], 0);
+    for l in (1..59).rev()
+    {
+        first[l] = c;
+        c = (c + count[l]) >> 1;
+    }
+    bits.pos = bits.pos.div_ceil(8)*8;
+    if bits.pos + code_bits > bits.end
+    {
+        return Err(invalid("truncated EXR PIZ data"));
+    }
+    bits.end = bits.pos + code_bits;
+    let mut out: Vec<u16> = Vec::with_capacity(n);
+    while bits.pos < bits.end
+    {
+        let (mut code, mut l) = (0, 0);
+        let symbol = loop
+        {
+            code = code << 1 | bits.read(1)?;
+            l += 1;
+            if l > 58
+            {
+                return Err(invalid("invalid EXR PIZ code"));
+            }
+            if code >= first[l] && code - first[l] < count[l]
+            {
+                break symbols[l][(code - first[l]) as usize];
+            }
+        };
+        let repeat = if symbol == run_symbol { bits.read(8)? as usize } else { 1 };
+        let v = if symbol == run_symbol { *out.last().ok_or_else(|| invalid("invalid EXR PIZ run"))? } else { symbol as u16 };
+        if out.len() + repeat > n
+        {
+            return Err(invalid("EXR chunk has the wrong size"));
+        }
+        out.extend(std::iter::repeat(v).take(repeat));
+    }
+    if out.len() != n
+    {
+        return Err(invalid("EXR chunk has the wrong size"));
+    }
+    Ok(out)
+}
+
+// Inverse of one step of the PIZ wavelet, on 14 bit values without
+// overflow or modulo 2^16 otherwise
+fn piz_wdec(l: u16, h: u16, w14: bool) -> (u16, u16)
+{
+    if w14
+    {
+        let (l, h) = (l as i16 as i32, h as i16 as i32);
+        let a = l + (h & 1) + (h >> 1);
+        (a as i16 as u16, (a - h) as i16 as u16)
+    }
+    else
+    {
+        let (m, d) = (l as i32, h as i32);
+        let b = (m - (d >> 1)) & 0xffff;
+        (((d + b - 0x8000) & 0xffff) as u16, b as u16)
+    }
+}
+
+// Inverse 2D Haar wavelet of PIZ over nx by ny values with strides ox and
+// oy, coarsest level first
+fn piz_wavelet(buf: &mut [u16], (nx, ox): (usize, usize), (ny, oy): (usize, usize), max: u16)
+{
+    let w14 = max < 1 << 14;
+    let mut p = 1;
+    while p <= nx.min(ny)
+    {
+        p <<= 1;
+    }
+    p >>= 1;
+    let mut p2 = p;
+    p >>= 1;
+    while p >= 1
+    {
+        let (ox1, oy1, ox2, oy2) = (ox*p, oy*p, ox*p2, oy*p2);
+        let mut py = 0;
+        while py <= oy*(ny - p2)
+        {
+            let mut px = py;
+            while px <= py + ox*(nx - p2)
+            {
+                let (p01, p10, p11) = (px + ox1, px + oy1, px + oy1 + ox1);
+                let (i00, i10) = piz_wdec(buf[px], buf[p10], w14);
+                let (i01, i11) = piz_wdec(buf[p01], buf[p11], w14);
+                let (a, b) = piz_wdec(i00, i01, w14);
+                buf[px] = a;
+                buf[p01] = b;
+                let (a, b) = piz_wdec(i10, i11, w14);
+                buf[p10] = a;
+                buf[p11] = b;
+                px += ox2;
+            }
+            // Odd column
+            if nx & p != 0
+            {
+                let (a, b) = piz_wdec(buf[px], buf[px + oy1], w14);
+                buf[px] = a;
+                buf[px + oy1] = b;
+            }
+            py += oy2;
+        }
+        // Odd line
+        if ny & p != 0
+        {
+            let mut px = py;
+            while px <= py + ox*(nx - p2)
+            {
+                let (a, b) = piz_wdec(buf[px], buf[px + ox1], w14);
+                buf[px] = a;
+                buf[px + ox1] = b;
+                px += ox2;
+            }
+        }
+        p2 = p;
+        p >>= 1;
+    }
+}
+
+// PIZ chunk: a bitmap of the 16 bit values that occur, Huffman coded
+// indices into them and a wavelet transform of every channel, half values
+// taking one 16 bit word and 32 bit ones two
+fn exr_unpiz(packed: &[u8], channels: &[(String, i32)], width: usize, lines: usize, expected: usize) -> io::Result<Vec<u8>>
+{
+    let (min, max) = (le_u16(slice(packed, 0, 2)?), le_u16(slice(packed, 2, 2)?));
+    let mut bitmap = vec![0; 8192];
+    let mut pos = 4;
+    if max >= bitmap.len()
+    {
+        return Err(invalid("invalid EXR PIZ bitmap"));
+    }
+    if min <= max
+    {
+        bitmap[min..=max].copy_from_slice(slice(packed, pos, max - min + 1)?);
+        pos += max - min + 1;
+    }
+    let lut: Vec<u16> = (0..=u16::MAX).filter(|&i| i == 0 || bitmap[i as usize >> 3] & (1 << (i & 7)) != 0).collect();
+    let length = le_i32(slice(packed, pos, 4)?) as usize;
+    let mut data = piz_huffman(slice(packed, pos + 4, length)?, expected/2)?;
+    let mut start = 0;
+    let mut ranges = Vec::new();
+    for c in channels
+    {
+        let size = if c.1 == 1 { 1 } else { 2 };
+        let n = width*lines*size;
+        for j in 0..size
+        {
+            piz_wavelet(&mut data[start + j..start + n], (width, size), (lines, width*size), (lut.len() - 1) as u16);
+        }
+        ranges.push((start, width*size));
+        start += n;
+    }
+    let mut raw = Vec::with_capacity(expected);
+    for y in 0..lines
+    {
+        for &(start, n) in &ranges
+        {
+            for &v in &data[start + y*n..start + (y + 1)*n]
+            {
+                raw.extend_from_slice(&lut.get(v as usize).cloned().unwrap_or(0).to_le_bytes());
+            }
+        }
+    }
+    Ok(raw)
+}
+
+// PXR24 chunk: for every line and channel the differences between
+// neighboring values, split into byte planes from the most significant one
+// and deflated. Floats are rounded to 24 bits and lose their lowest byte.
+fn exr_unpxr24(packed: &[u8], channels: &[(String, i32)], width: usize, lines: usize, expected: usize) -> io::Result<Vec<u8>>
+{
+    let planes = |c: &(String, i32)| match c.1 { 0 => 4, 1 => 2, _ => 3 };
+    let t = deflate::zlib_decompress(packed)?;
+    if t.len() != channels.iter().map(planes).sum::<usize>()*width*lines
+    {
+        return Err(invalid("EXR chunk has the wrong size"));
+    }
+    let mut raw = Vec::with_capacity(expected);
+    let mut p = 0;
+    for _ in 0..lines
+    {
+        for c in channels
+        {
+            let n = planes(c);
+            let mut v: u32 = 0;
+            for x in 0..width
+            {
+                let diff = (0..n).fold(0, |d, k| d << 8 | t[p + k*width + x] as u32);
+                v = v.wrapping_add(if c.1 == 2 { diff << 8 } else { diff });
+                match c.1
+                {
+                    1 => raw.extend_from_slice(&(v as u16).to_le_bytes()),
+                    _ => raw.extend_from_slice(&v.to_le_bytes()),
+                }
+            }
+            p += n*width;
+        }
+    }
+    Ok(raw)
+}
+
+// Chunk data that is as large as its unpacked size is stored uncompressed
+fn exr_unpack(compression: u8, packed: &[u8], expected: usize) -> io::Result<Vec<u8>>
+{
+    let raw = if packed.len() == expected
+    {
+        packed.to_vec()
+    }
+    else
+    {
+        match compression
+        {
+            1 => exr_unpredict(&exr_unrle(packed)?),
+            2 | 3 => exr_unpredict(&deflate::zlib_decompress(packed)?),
+            _ => Vec::new(),
+        }
+    };
+    if raw.len() != expected
+    {
+        return Err(invalid("EXR chunk has the wrong size"));
+    }
+    Ok(raw)
+}
+
+struct ExrHeader
+{
+    // Name and pixel type of each channel, sorted by name
+    channels: Vec<(String, i32)>,
+    compression: u8,
+    window: (i32, i32, i32, i32),
+    // Every other attribute of a supported type
+    attributes: Vec<(String, ExrAttribute)>,
+    // Start of the chunk offset table
+    end: usize,
+}
+
+fn exr_parse_header(data: &[u8]) -> io::Result<ExrHeader>
+{
+    let mut pos = 8;
+    let read_name = |pos: &mut usize| -> io::Result<String>
+    {
+        let end = data[*pos..].iter().position(|&b| b == 0).ok_or_else(|| invalid("truncated EXR header"))?;
+        let name = String::from_utf8_lossy(&data[*pos..*pos + end]).into_owned();
+        *pos += end + 1;
+        Ok(name)
+    };
+    let (mut channels, mut compression, mut window, mut attributes) = (Vec::new(), None, None, Vec::new());
+    loop
+    {
+        let name = read_name(&mut pos)?;
+        if name.is_empty()
+        {
+            break;
+        }
+        let kind = read_name(&mut pos)?;
+        let size = le_i32(slice(data, pos, 4)?) as usize;
+        let value = slice(data, pos + 4, size)?;
+        pos += 4 + size;
+        match (name.as_str(), kind.as_str())
+        {
+            ("channels", "chlist") =>
+            {
+                let mut p = 0;
+                while p < value.len() && value[p] != 0
+                {
+                    let end = p + value[p..].iter().position(|&b| b == 0).ok_or_else(|| invalid("invalid EXR channel list"))?;
+                    let name = String::from_utf8_lossy(&value[p..end]).into_owned();
+                    let c = slice(value, end + 1, 16)?;
+                    if le_i32(&c[8..]) != 1 || le_i32(&c[12..]) != 1
+                    {
+                        return Err(unsupported("subsampled EXR channels are not supported"));
+                    }
+                    channels.push((name, le_i32(c)));
+                    p = end + 17;
+                }
+            }
+            ("compression", _) => compression = value.first().cloned(),
+            ("dataWindow", "box2i") if size == 16 =>
+                window = Some((le_i32(value), le_i32(&value[4..]), le_i32(&value[8..]), le_i32(&value[12..]))),
+            (_, kind) => attributes.extend(ExrAttribute::parse(kind, value).map(|a| (name.clone(), a))),
+        }
+    }
+    let (x0, y0, x1, y1) = window.ok_or_else(|| invalid("missing EXR data window"))?;
+    if x1 < x0 || y1 < y0 || channels.is_empty() || channels.iter().any(|c| c.1 < 0 || c.1 > 2)
+    {
+        return Err(invalid("invalid EXR header"));
+    }
+    let compression = compression.ok_or_else(|| invalid("missing EXR compression"))?;
+    Ok(ExrHeader{ channels, compression, window: (x0, y0, x1, y1), attributes, end: pos })
+}
+
+// String, int, float and m44f header attributes of a scanline or deep
+// OpenEXR, in file order
+pub fn decode_exr_attributes(data: &[u8]) -> io::Result<Vec<(String, ExrAttribute)>>
+{
+    if !data.starts_with(&EXR_MAGIC)
+    {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    Ok(exr_parse_header(data)?.attributes)
+}
+
+// Deflate expands data by at most about 1032 to 1, so a file can't hold
+// more values than this per byte
+const EXR_MAX_VALUES_PER_BYTE: usize = 1032;
+
+// Width, height and chunk count of the data window, checked against the
+// size of the file before anything is allocated for it
+fn exr_window(data: &[u8], header: &ExrHeader, lines: usize) -> io::Result<(usize, usize, usize)>
+{
+    let (x0, y0, x1, y1) = header.window;
+    let size = |a: i32, b: i32| usize::try_from(b as i64 - a as i64 + 1).map_err(|_| invalid("invalid EXR data window"));
+    let (width, height) = (size(x0, x1)?, size(y0, y1)?);
+    let chunks = height.div_ceil(lines);
+    if chunks.checked_mul(8).and_then(|n| n.checked_add(header.end)).unwrap_or(usize::MAX) > data.len()
+    {
+        return Err(invalid("EXR offset table runs past the end of the file"));
+    }
+    let values = width.checked_mul(height).and_then(|n| n.checked_mul(header.channels.len()));
+    if values.unwrap_or(usize::MAX)/EXR_MAX_VALUES_PER_BYTE > data.len()
+    {
+        return Err(invalid("EXR data window is too large for the file"));
+    }
+    Ok((width, height, chunks))
+}
+
+// Single part scanline OpenEXR with NONE, RLE, ZIPS, ZIP, PIZ or PXR24
+// compression. Channels keep their names, with R, G, B and A moved to the
+// front.
+pub fn decode_exr(data: &[u8]) -> io::Result<Image>
+{
+    if !data.starts_with(&EXR_MAGIC)
+    {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    let version = le_i32(slice(data, 4, 4)?);
+    if version & 0x1a00 != 0
+    {
+        return Err(unsupported("tiled, deep and multi-part OpenEXR are not supported"));
+    }
+    let header = exr_parse_header(data)?;
+    let compression = header.compression;
+    let lines = match compression
+    {
+        0..=2 => 1,
+        3 | 5 => 16,
+        4 => 32,
+        _ => return Err(unsupported("B44 and DWA compressed OpenEXR are not supported")),
+    };
+    let (width, height, chunks) = exr_window(data, &header, lines)?;
+    let (channels, (_, y0, _, y1), pos) = (header.channels, header.window, header.end);
+    // Output order of the channels, stored sorted by name in the file
+    let mut order: Vec<usize> = (0..channels.len()).collect();
+    let rank = |name: &str| ["R", "G", "B", "A"].iter().position(|&c| c == name).unwrap_or(4);
+    order.sort_by_key(|&i| rank(&channels[i].0));
+    let names: Vec<&str> = order.iter().map(|&i| channels[i].0.as_str()).collect();
+    let mut img = Image::new(width, height, &names);
+    let mut target = vec![0; channels.len()];
+    for (k, &i) in order.iter().enumerate()
+    {
+        target[i] = k;
+    }
+    let line_size: usize = channels.iter().map(|c| if c.1 == 1 { 2 } else { 4 }).sum::<usize>()*width;
+    for chunk in 0..chunks
+    {
+        let offset = usize::try_from(le_u64(slice(data, pos + 8*chunk, 8)?)).unwrap_or(usize::MAX).min(data.len());
+        let y = le_i32(slice(data, offset, 4)?);
+        let size = le_i32(slice(data, offset + 4, 4)?) as usize;
+        let packed = slice(data, offset + 8, size)?;
+        if y < y0 || y > y1
+        {
+            return Err(invalid("EXR chunk outside of the data window"));
+        }
+        let ys = (y - y0) as usize;
+        let count = usize::min(lines, height - ys);
+        let expected = line_size*count;
+        let raw = match compression
+        {
+            4 if packed.len() != expected => exr_unpiz(packed, &channels, width, count, expected)?,
+            5 if packed.len() != expected => exr_unpxr24(packed, &channels, width, count, expected)?,
+            _ => exr_unpack(compression, packed, expected)?,
+        };
+        if raw.len() != expected
+        {
+            return Err(invalid("EXR chunk has the wrong size"));
+        }
+        let mut p = 0;
+        for row in ys..ys + count
+        {
+            for (i, c) in channels.iter().enumerate()
+            {
+                for x in 0..width
+                {
+                    let v = match c.1
+                    {
+                        0 => le_u32(&raw[p..]) as f64,
+                        1 => half_to_f32(u16::from_le_bytes([raw[p], raw[p + 1]])) as f64,
+                        _ => f32::from_bits(le_u32(&raw[p..])) as f64,
+                    };
+                    p += if c.1 == 1 { 2 } else { 4 };
+                    img.set(x, row, target[i], v);
+                }
+            }
+        }
+    }
+    Ok(img)
+}
+
+// Single part deep scanline OpenEXR, channels are kept in file order
+pub fn decode_deep_exr(data: &[u8]) -> io::Result<DeepImage>
+{
+    if !data.starts_with(&EXR_MAGIC)
+    {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    let version = le_i32(slice(data, 4, 4)?);
+    if version & 0x800 == 0
+    {
+        return Err(invalid("not a deep OpenEXR file"));
+    }
+    if version & 0x1200 != 0
+    {
+        return Err(unsupported("tiled and multi-part OpenEXR are not supported"));
+    }
+    let header = exr_parse_header(data)?;
+    let lines = match header.compression
+    {
+        0..=2 => 1,
+        3 => 16,
+        _ => return Err(unsupported("unsupported EXR compression")),
+    };
+    let (width, height, chunk_count) = exr_window(data, &header, lines)?;
+    let (channels, (_, y0, _, y1), pos) = (header.channels, header.window, header.end);
+    // Sample counts have to be known before the image can be allocated, so
+    // the unpacked sample data of every chunk is kept until then
+    let mut counts = vec![0; width*height];
+    let mut chunks = Vec::new();
+    for chunk in 0..chunk_count
+    {
+        let offset = usize::try_from(le_u64(slice(data, pos + 8*chunk, 8)?)).unwrap_or(usize::MAX).min(data.len());
+        let y = le_i32(slice(data, offset, 4)?);
+        let table_size = le_u64(slice(data, offset + 4, 8)?) as usize;
+        let data_size = le_u64(slice(data, offset + 12, 8)?) as usize;
+        let raw_size = le_u64(slice(data, offset + 20, 8)?) as usize;
+        if y < y0 || y > y1
+        {
+            return Err(invalid("EXR chunk outside of the data window"));
+        }
+        let ys = (y - y0) as usize;
+        let count = usize::min(lines, height - ys);
+        let table = exr_unpack(header.compression, slice(data, offset + 28, table_size)?, 4*width*count)?;
+        let mut previous = 0;
+        for (i, t) in table.chunks(4).enumerate()
+        {
+            let total = le_i32(t);
+            if total < previous
+            {
+                return Err(invalid("invalid EXR sample count table"));
+            }
+            counts[ys*width + i] = (total - previous) as usize;
+            previous = total;
+        }
+        let expected = previous as usize*channels.iter().map(|c| if c.1 == 1 { 2 } else { 4 }).sum::<usize>();
+        if raw_size != expected
+        {
+            return Err(invalid("EXR chunk has the wrong size"));
+        }
+        let raw = exr_unpack(header.compression, slice(data, offset + 28 + table_size, data_size)?, expected)?;
+        chunks.push((ys, count, raw));
+    }
+    let names: Vec<&str> = channels.iter().map(|c| c.0.as_str()).collect();
+    let mut img = DeepImage::new(width, height, &names, &counts);
+    let n = channels.len();
+    for (ys, count, raw) in chunks
+    {
+        // Line by line, with all the samples of a channel next to each other
+        let mut p = 0;
+        for row in ys..ys + count
+        {
+            let (first, last) = (img.offsets[row*width], img.offsets[(row + 1)*width]);
+            for (c, channel) in channels.iter().enumerate()
+            {
+                for s in first..last
+                {
+                    img.data[s*n + c] = match channel.1
+                    {
+                        0 => le_u32(&raw[p..]) as f64,
+                        1 => half_to_f32(u16::from_le_bytes([raw[p], raw[p + 1]])) as f64,
+                        _ => f32::from_bits(le_u32(&raw[p..])) as f64,
+                    };
+                    p += if channel.1 == 1 { 2 } else { 4 };
+                }
+            }
+        }
+    }
+    Ok(img)
+}
@@ -0,0 +1,313 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::vector::Vec3d;
+use crate::transformation::{Matrix4, Transform};
+
+// Lens distortion in OpenCV conventions. Brown-Conrady is the default
+// cv::calibrateCamera model (k1, k2, p1, p2, k3), Kannala-Brandt is the
+// cv::fisheye equidistant model (k1, k2, k3, k4).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distortion
+{
+    None,
+    BrownConrady{ k1: f64, k2: f64, p1: f64, p2: f64, k3: f64 },
+    KannalaBrandt{ k1: f64, k2: f64, k3: f64, k4: f64 },
+}
+
+impl Distortion
+{
+    // Direction in the OpenCV camera frame (x right, y down, z forward) to
+    // distorted normalized image coordinates
+    pub fn project(&self, d: Vec3d) -> Option<(f64, f64)>
+    {
+        match *self
+        {
+            Distortion::None =>
+            {
+                if d.z <= 0.
+                {
+                    return None;
+                }
+                Some((d.x/d.z, d.y/d.z))
+            }
+            Distortion::BrownConrady{ k1, k2, p1, p2, k3 } =>
+            {
+                if d.z <= 0.
+                {
+                    return None;
+                }
+                let (x, y) = (d.x/d.z, d.y/d.z);
+                let r2 = x*x + y*y;
+                let radial = 1. + r2*(k1 + r2*(k2 + r2*k3));
+                let xd = x*radial + 2.*p1*x*y + p2*(r2 + 2.*x*x);
+                let yd = y*radial + p1*(r2 + 2.*y*y) + 2.*p2*x*y;
+                Some((xd, yd))
+            }
+            Distortion::KannalaBrandt{ k1, k2, k3, k4 } =>
+            {
+                let r = f64::sqrt(d.x*d.x + d.y*d.y);
+                if r == 0.
+                {
+                    return if d.z > 0. { Some((0., 0.)) } else { None };
+                }
+                let theta = r.atan2(d.z);
+                let t2 = theta*theta;
+                let theta_d = theta*(1. + t2*(k1 + t2*(k2 + t2*(k3 + t2*k4))));
+                Some((theta_d*d.x/r, theta_d*d.y/r))
+            }
+        }
+    }
+    // Inverse of project, returns a unit direction in the OpenCV camera frame
+    pub fn unproject(&self, xd: f64, yd: f64) -> Vec3d
+    {
+        match *self
+        {
+            Distortion::None => Vec3d::new(xd, yd, 1.).norm(),
+            Distortion::BrownConrady{ k1, k2, p1, p2, k3 } =>
+            {
+                // Newton iterations starting from the distorted point
+                let (mut x, mut y) = (xd, yd);
+                for _ in 0..20
+                {
+                    let r2 = x*x + y*y;
+                    let radial = 1. + r2*(k1 + r2*(k2 + r2*k3));
+                    let dradial = k1 + r2*(2.*k2 + 3.*k3*r2);
+                    let fx = x*radial + 2.*p1*x*y + p2*(r2 + 2.*x*x) - xd;
+                    let fy = y*radial + p1*(r2 + 2.*y*y) + 2.*p2*x*y - yd;
+                    if fx.abs() < 1e-15 && fy.abs() < 1e-15
+                    {
+                        break;
+                    }
+                    let j00 = radial + 2.*x*x*dradial + 2.*p1*y + 6.*p2*x;
+                    let j01 = 2.*x*y*dradial + 2.*p1*x + 2.*p2*y;
+                    let j10 = j01;
+                    let j11 = radial + 2.*y*y*dradial + 6.*p1*y + 2.*p2*x;
+                    let det = j00*j11 - j01*j10;
+                    if det == 0.
+                    {
+                        break;
+                    }
+                    x -= (j11*fx - j01*fy)/det;
+                    y -= (j00*fy - j10*fx)/det;
+                }
+                Vec3d::new(x, y, 1.).norm()
+            }
+            Distortion::KannalaBrandt{ k1, k2, k3, k4 } =>
+            {
+                let theta_d = f64::sqrt(xd*xd + yd*yd);
+                if theta_d == 0.
+                {
+                    return Vec3d::new(0., 0., 1.);
+                }
+                let mut theta = theta_d;
+                for _ in 0..20
+                {
+                    let t2 = theta*theta;
+                    let f = theta*(1. + t2*(k1 + t2*(k2 + t2*(k3 + t2*k4)))) - theta_d;
+                    if f.abs() < 1e-15
+                    {
+                        break;
+                    }
+                    let df = 1. + t2*(3.*k1 + t2*(5.*k2 + t2*(7.*k3 + t2*9.*k4)));
+                    theta -= f/df;
+                }
+                let s = theta.sin()/theta_d;
+                Vec3d::new(s*xd, s*yd, theta.cos())
+            }
+        }
+    }
+    pub fn coefficients(&self) -> Vec<f64>
+    {
+        match *self
+        {
+            Distortion::None => Vec::new(),
+            Distortion::BrownConrady{ k1, k2, p1, p2, k3 } => vec![k1, k2, p1, p2, k3],
+            Distortion::KannalaBrandt{ k1, k2, k3, k4 } => vec![k1, k2, k3, k4],
+        }
+    }
+}
+
+// Pinhole intrinsics in pixels. As in OpenCV the center of the top left
+// pixel is (0, 0) and v grows downwards.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Intrinsics
+{
+    pub width: usize,
+    pub height: usize,
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    pub distortion: Distortion,
+}
+
+impl Intrinsics
+{
+    pub fn project(&self, d: Vec3d) -> Option<(f64, f64)>
+    {
+        let (x, y) = self.distortion.project(d)?;
+        Some((self.fx*x + self.cx, self.fy*y + self.cy))
+    }
+    pub fn unproject(&self, u: f64, v: f64) -> Vec3d
+    {
+        self.distortion.unproject((u - self.cx)/self.fx, (v - self.cy)/self.fy)
+    }
+    // Conversions between OpenCV pixel coordinates and film coordinates
+    pub fn film_to_pixel(&self, pf: (f64, f64)) -> (f64, f64)
+    {
+        let u = (pf.0 + 1.)/2.*self.width as f64 - 0.5;
+        let v = (1. - pf.1)/2.*self.height as f64 - 0.5;
+        (u, v)
+    }
+    pub fn pixel_to_film(&self, u: f64, v: f64) -> (f64, f64)
+    {
+        let x = 2.*(u + 0.5)/self.width as f64 - 1.;
+        let y = 1. - 2.*(v + 0.5)/self.height as f64;
+        (x, y)
+    }
+    // Writes the intrinsics the way cv::FileStorage stores a calibration
+    pub fn to_opencv_yaml(&self) -> String
+    {
+        let model = match self.distortion
+        {
+            Distortion::KannalaBrandt{ .. } => "fisheye",
+            _ => "plumb_bob",
+        };
+        let mut s = String::from("%YAML:1.0\n---\n");
+        s += &format!("image_width: {}\n", self.width);
+        s += &format!("image_height: {}\n", self.height);
+        let k = [self.fx, 0., self.cx, 0., self.fy, self.cy, 0., 0., 1.];
+        s += &opencv_matrix("camera_matrix", 3, 3, &k);
+        s += &format!("distortion_model: {}\n", model);
+        let mut dist = self.distortion.coefficients();
+        if dist.is_empty()
+        {
+            dist = vec![0.; 5];
+        }
+        s += &opencv_matrix("distortion_coefficients", 1, dist.len(), &dist);
+        s
+    }
+    pub fn from_opencv_yaml(s: &str) -> io::Result<Intrinsics>
+    {
+        let width = yaml_scalar(s, "image_width")?.parse::<usize>().map_err(invalid)?;
+        let height = yaml_scalar(s, "image_height")?.parse::<usize>().map_err(invalid)?;
+        let k = yaml_matrix_data(s, "camera_matrix")?;
+        if k.len() != 9
+        {
+            return Err(invalid("camera_matrix must be 3x3"));
+        }
+        let dist = yaml_matrix_data(s, "distortion_coefficients")?;
+        let fisheye = match yaml_scalar(s, "distortion_model")
+        {
+            Ok(model) => model == "fisheye" || model == "equidistant",
+            Err(_) => false,
+        };
+        let distortion = if fisheye
+        {
+            if dist.len() != 4
+            {
+                return Err(invalid("fisheye model needs 4 distortion coefficients"));
+            }
+            Distortion::KannalaBrandt{ k1: dist[0], k2: dist[1], k3: dist[2], k4: dist[3] }
+        }
+        else
+        {
+            match dist.len()
+            {
+                0 => Distortion::None,
+                4 => Distortion::BrownConrady{ k1: dist[0], k2: dist[1], p1: dist[2], p2: dist[3], k3: 0. },
+                5 => Distortion::BrownConrady{ k1: dist[0], k2: dist[1], p1: dist[2], p2: dist[3], k3: dist[4] },
+                _ => return Err(invalid("unsupported distortion model")),
+            }
+        };
+        // All zero Brown-Conrady coefficients are a pinhole, a fisheye with
+        // zero coefficients is still equidistant
+        let distortion = match distortion
+        {
+            Distortion::BrownConrady{ .. } if dist.iter().all(|&c| c == 0.) => Distortion::None,
+            d => d,
+        };
+        Ok(Intrinsics{ width, height, fx: k[0], fy: k[4], cx: k[2], cy: k[5], distortion })
+    }
+    pub fn read(path: &Path) -> io::Result<Intrinsics>
+    {
+        Intrinsics::from_opencv_yaml(&fs::read_to_string(path)?)
+    }
+    pub fn write(&self, path: &Path) -> io::Result<()>
+    {
+        fs::write(path, self.to_opencv_yaml())
+    }
+}
+
+// OpenCV camera space has y pointing down, ours has y pointing up
+fn flip_y() -> Matrix4
+{
+    Matrix4::new(&[[1., 0., 0., 0.],
+                   [0., -1., 0., 0.],
+                   [0., 0., 1., 0.],
+                   [0., 0., 0., 1.]])
+}
+
+// Builds camera_to_world from OpenCV extrinsics, x_cam = r * x_world + t
+pub fn from_opencv_extrinsics(r: &[[f64; 3]; 3], t: Vec3d) -> Transform
+{
+    let mut m = Matrix4::i();
+    for i in 0..3
+    {
+        m.mat[i][..3].copy_from_slice(&r[i]);
+        m.mat[i][3] = t[i];
+    }
+    let world_to_camera = Matrix4::mul(&flip_y(), &m);
+    Transform::new(&world_to_camera).inv()
+}
+
+// Inverse of from_opencv_extrinsics, returns (r, t)
+pub fn to_opencv_extrinsics(camera_to_world: &Transform) -> ([[f64; 3]; 3], Vec3d)
+{
+    let m = Matrix4::mul(&flip_y(), &camera_to_world.m_inv);
+    let mut r = [[0.; 3]; 3];
+    for (i, row) in r.iter_mut().enumerate()
+    {
+        row.copy_from_slice(&m.mat[i][..3]);
+    }
+    (r, Vec3d::new(m[(0,3)], m[(1,3)], m[(2,3)]))
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn opencv_matrix(name: &str, rows: usize, cols: usize, data: &[f64]) -> String
+{
+    let values: Vec<String> = data.iter().map(|v| format!("{:e}", v)).collect();
+    format!("{}: !!opencv-matrix\n   rows: {}\n   cols: {}\n   dt: d\n   data: [ {} ]\n", name, rows, cols, values.join(", "))
+}
+
+fn yaml_scalar<'a>(s: &'a str, key: &str) -> io::Result<&'a str>
+{
+    let prefix = format!("{}:", key);
+    for line in s.lines()
+    {
+        if let Some(rest) = line.trim_start().strip_prefix(prefix.as_str())
+        {
+            return Ok(rest.trim().trim_matches('"'));
+        }
+    }
+    Err(invalid(format!("missing {}", key)))
+}
+
+fn yaml_matrix_data(s: &str, key: &str) -> io::Result<Vec<f64>>
+{
+    let start = s.find(&format!("{}:", key)).ok_or_else(|| invalid(format!("missing {}", key)))?;
+    let rest = &s[start..];
+    let open = rest.find("data:").and_then(|i| rest[i..].find('[').map(|j| i + j))
+        .ok_or_else(|| invalid(format!("missing data of {}", key)))?;
+    let close = rest[open..].find(']').ok_or_else(|| invalid(format!("unterminated data of {}", key)))?;
+    rest[open + 1..open + close]
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<f64>().map_err(invalid))
+        .collect()
+}
@@ -3,8 +3,32 @@ pub mod ray;
 pub mod bounding;
 pub mod transformation;
 pub mod solver;
+pub mod sampler;
 pub mod color;
+pub mod colorspace;
+pub mod spectrum;
+pub mod rgbspectrum;
+pub mod spectra;
 pub mod camera;
+pub mod checkpoint;
+pub mod intrinsics;
+pub mod sensor;
+pub mod filter;
+pub mod aov;
+pub mod lpe;
+pub mod metadata;
+pub mod film;
+pub mod cryptomatte;
+pub mod compare;
+pub mod complex;
+pub mod deep;
+pub mod denoise;
+pub mod image;
+pub mod deflate;
+pub mod imageio;
+pub mod tonemap;
+pub mod post;
+pub mod polarization;
 
 #[cfg(test)]
 mod aabb_tests {
@@ -23,7 +47,7 @@ mod aabb_tests {
         assert_eq!(aabb.hit(&r2), false);
         assert_eq!(aabb.hit(&r3), true);
         assert_eq!(aabb.hit(&r4), false);
-        let r5 = Ray{ o:o, d:Vec3d::new(-1., 0., 0.), t:0., tmax:0.5 };
+        let r5 = Ray{ o:o, d:Vec3d::new(-1., 0., 0.), t:0., tmax:0.5, time:0. };
         assert_eq!(aabb.hit(&r5), false);
     }
 }
@@ -68,4 +92,1403 @@ mod matrix_tests{
             assert_eq!(Matrix4::mul(&m, &m_inv), i);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod camera_tests {
+    use crate::vector::Vec3d;
+    use crate::ray::Ray;
+    use crate::transformation::Transform;
+    use crate::camera::{Camera, Eye, Shutter, StereoCamera, OdsCamera, PerspectiveCamera, LightFieldCamera};
+    #[test]
+    fn stereo_convergence_test_0() {
+        let cam = StereoCamera{ camera_to_world: Vec::new(), shutter: Shutter::instant(), fov_x: 1., fov_y: 1., interocular: 0.064, convergence: 2. };
+        let pf = (0.3, -0.2);
+        let l = cam.eye_ray(Eye::Left, pf);
+        let r = cam.eye_ray(Eye::Right, pf);
+        let pl = l.pos(2./l.d.z);
+        let pr = r.pos(2./r.d.z);
+        assert!((pl - pr).len() < 1e-9);
+        assert!(f64::abs(l.o.x + 0.032) < 1e-12);
+        let top = cam.generate_ray((0.3, 0.5), (0., 0.), 0.);
+        assert!(f64::abs(top.o.x + 0.032) < 1e-12);
+    }
+    #[test]
+    fn ods_test_0() {
+        let cam = OdsCamera{ camera_to_world: Vec::new(), shutter: Shutter::instant(), interocular: 0.064 };
+        for &x in &[-0.9, -0.25, 0., 0.4, 0.75] {
+            for &eye in &[Eye::Left, Eye::Right] {
+                let r = cam.eye_ray(eye, (x, 0.3));
+                assert!(f64::abs(r.o.len() - 0.032) < 1e-12);
+                assert!(f64::abs(Vec3d::dot(r.o, r.d)) < 1e-12);
+            }
+        }
+        let r = cam.eye_ray(Eye::Left, (0., 0.));
+        assert!((r.d - Vec3d::new(0., 0., 1.)).len() < 1e-12);
+        assert!((r.o - Vec3d::new(-0.032, 0., 0.)).len() < 1e-12);
+    }
+    #[test]
+    fn rolling_shutter_test_0() {
+        let cam = PerspectiveCamera{ camera_to_world: Vec::new(), shutter: Shutter::rolling(0., 0.01, 0.03), fov_x: 1., fov_y: 1. };
+        let top = cam.generate_ray((0., 1.), (0., 0.), 0.5);
+        let mid = cam.generate_ray((0., 0.), (0., 0.), 0.5);
+        let bottom = cam.generate_ray((0., -1.), (0., 0.), 0.);
+        assert!(f64::abs(top.time - 0.005) < 1e-12);
+        assert!(f64::abs(mid.time - 0.02) < 1e-12);
+        assert!(f64::abs(bottom.time - 0.03) < 1e-12);
+        let global = PerspectiveCamera{ shutter: Shutter::global(1., 2.), ..cam };
+        assert!(f64::abs(global.generate_ray((0.3, -1.), (0., 0.), 0.25).time - 1.25) < 1e-12);
+    }
+    #[test]
+    fn world_to_raster_test_0() {
+        let c2w = vec![Transform::look_at(Vec3d::new(1., 2., -3.), Vec3d::new(0., 0., 1.), Vec3d::new(0., 1., 0.)).inv()];
+        let persp = PerspectiveCamera{ camera_to_world: c2w.clone(), shutter: Shutter::instant(), fov_x: 1.2, fov_y: 0.9 };
+        let stereo = StereoCamera{ camera_to_world: c2w.clone(), shutter: Shutter::instant(), fov_x: 1.2, fov_y: 0.9, interocular: 0.064, convergence: 3. };
+        let ods = OdsCamera{ camera_to_world: c2w, shutter: Shutter::instant(), interocular: 0.064 };
+        let cams: [&dyn Camera; 3] = [&persp, &stereo, &ods];
+        for cam in cams.iter() {
+            // Points on the top half belong to the left eye, which is what world_to_raster projects to
+            for &pf in &[(0.2, 0.7), (-0.6, 0.1), (0.9, 0.3), (-0.1, 0.8)] {
+                let r = cam.generate_ray(pf, (0., 0.), 0.);
+                let p = cam.world_to_raster(r.pos(5.)).unwrap();
+                assert!(f64::abs(p.x - pf.0) < 1e-9 && f64::abs(p.y - pf.1) < 1e-9);
+            }
+        }
+        let p = persp.world_to_raster(persp.generate_ray((0.4, -0.5), (0., 0.), 0.).pos(2.)).unwrap();
+        assert!(f64::abs(p.x - 0.4) < 1e-9 && f64::abs(p.y + 0.5) < 1e-9);
+        let p = ods.world_to_raster_eye(ods.generate_ray((-0.7, -0.2), (0., 0.), 0.).pos(2.), Eye::Right).unwrap();
+        assert!(f64::abs(p.x + 0.7) < 1e-9 && f64::abs(p.y + 0.2) < 1e-9);
+        let p = stereo.world_to_raster_eye(stereo.generate_ray((0.4, -0.5), (0., 0.), 0.).pos(2.), Eye::Right).unwrap();
+        assert!(f64::abs(p.x - 0.4) < 1e-9 && f64::abs(p.y + 0.5) < 1e-9);
+    }
+    #[test]
+    fn importance_test_0() {
+        let cam = PerspectiveCamera{ camera_to_world: Vec::new(), shutter: Shutter::instant(), fov_x: 1.2, fov_y: 0.9 };
+        let area = 4.*(0.6f64).tan()*(0.45f64).tan();
+        let r = cam.generate_ray((0.5, -0.4), (0., 0.), 0.);
+        let cos = r.d.z;
+        assert!(f64::abs(cam.we(&r)*area*cos.powi(4) - 1.) < 1e-9);
+        assert!(f64::abs(cam.pdf_we(&r).1*area*cos.powi(3) - 1.) < 1e-9);
+        let s = cam.sample_wi(r.pos(4.), (0.5, 0.5)).unwrap();
+        assert!((s.wi + r.d).len() < 1e-9);
+        assert!(f64::abs(s.pdf - 16./cos) < 1e-9);
+        assert!(f64::abs(s.p_raster.x - 0.5) < 1e-9 && f64::abs(s.p_raster.y + 0.4) < 1e-9);
+        let out = Ray::new(Vec3d::zero(), Vec3d::new(0., 0., -1.));
+        assert_eq!(cam.we(&out), 0.);
+    }
+    #[test]
+    fn light_field_test_0() {
+        let cam = LightFieldCamera{ camera_to_world: Vec::new(), shutter: Shutter::instant(), fov_x: 1., fov_y: 0.8,
+                                    lens_radius: 0.05, focal_distance: 3., lenslets: (8, 6), lenslet_pixels: 5 };
+        assert_eq!(cam.sensor_resolution(), (40, 30));
+        // All pixels under one lenslet focus on the same point of the focal plane
+        let (w, h) = (40., 30.);
+        let mut focus = Vec::new();
+        for &(px, py) in &[(10.5, 13.5), (12.5, 11.5), (14.5, 14.5)] {
+            let c = cam.generate_camera_ray((2.*px/w - 1., 1. - 2.*py/h), (0., 0.), 0.);
+            let lf = c.light_field.unwrap();
+            assert_eq!((lf[0], lf[1]), (2., 2.));
+            focus.push(c.ray.pos((3. - c.ray.o.z)/c.ray.d.z));
+        }
+        assert!((focus[0] - focus[1]).len() < 1e-9 && (focus[0] - focus[2]).len() < 1e-9);
+        let corner = cam.generate_camera_ray((2.*10.1/w - 1., 1. - 2.*10.1/h), (0., 0.), 0.);
+        assert_eq!(corner.weight, 0.);
+        // Decoding picks one pixel per lenslet for every view
+        let raw: Vec<usize> = (0..40*30).collect();
+        let lf = cam.decode(&raw);
+        assert_eq!(lf.view(0, 0).len(), 48);
+        assert_eq!(lf.get(4, 4, 0, 0), 0);
+        assert_eq!(lf.get(0, 0, 2, 1), (5 + 4)*40 + 10 + 4);
+        assert_eq!(lf.get(2, 1, 3, 2), (10 + 2)*40 + 15 + 3);
+    }
+}
+
+#[cfg(test)]
+mod intrinsics_tests {
+    use crate::vector::Vec3d;
+    use crate::camera::{Camera, CalibratedCamera, Shutter};
+    use crate::intrinsics::{Distortion, Intrinsics, from_opencv_extrinsics, to_opencv_extrinsics};
+    fn intrinsics(distortion: Distortion) -> Intrinsics {
+        Intrinsics{ width: 640, height: 480, fx: 500., fy: 510., cx: 321.5, cy: 238.25, distortion }
+    }
+    #[test]
+    fn distortion_roundtrip_test_0() {
+        let models = [Distortion::None,
+                      Distortion::BrownConrady{ k1: -0.28, k2: 0.07, p1: 0.001, p2: -0.0015, k3: 0.01 },
+                      Distortion::KannalaBrandt{ k1: 0.05, k2: -0.01, k3: 0.002, k4: -0.0003 }];
+        for model in models.iter() {
+            let k = intrinsics(model.clone());
+            for &(u, v) in &[(10., 12.), (320., 240.), (600., 50.), (100., 470.)] {
+                let d = k.unproject(u, v);
+                let (u2, v2) = k.project(d).unwrap();
+                assert!(f64::abs(u - u2) < 1e-8 && f64::abs(v - v2) < 1e-8);
+            }
+        }
+        // cv::projectPoints of (0.1, -0.2, 1) with the Brown-Conrady model above
+        let (u, v) = intrinsics(models[1].clone()).project(Vec3d::new(0.1, -0.2, 1.)).unwrap();
+        let (x, y) = (0.1, -0.2);
+        let r2: f64 = x*x + y*y;
+        let radial = 1. - 0.28*r2 + 0.07*r2*r2 + 0.01*r2*r2*r2;
+        let xd = x*radial + 2.*0.001*x*y - 0.0015*(r2 + 2.*x*x);
+        let yd = y*radial + 0.001*(r2 + 2.*y*y) - 2.*0.0015*x*y;
+        assert!(f64::abs(u - (500.*xd + 321.5)) < 1e-9 && f64::abs(v - (510.*yd + 238.25)) < 1e-9);
+    }
+    #[test]
+    fn opencv_yaml_test_0() {
+        for model in [Distortion::BrownConrady{ k1: -0.28, k2: 0.07, p1: 0.001, p2: -0.0015, k3: 0.01 },
+                      Distortion::KannalaBrandt{ k1: 0.05, k2: -0.01, k3: 0.002, k4: -0.0003 }].iter() {
+            let k = intrinsics(model.clone());
+            assert_eq!(Intrinsics::from_opencv_yaml(&k.to_opencv_yaml()).unwrap(), k);
+        }
+        let yaml = "%YAML:1.0\n---\nimage_width: 640\nimage_height: 480\ncamera_matrix: !!opencv-matrix\n   rows: 3\n   cols: 3\n   dt: d\n   data: [ 5.0e+02, 0., 3.2e+02, 0.,\n       5.0e+02, 2.4e+02, 0., 0., 1. ]\ndistortion_coefficients: !!opencv-matrix\n   rows: 5\n   cols: 1\n   dt: d\n   data: [ -0.1, 0.01, 0., 0., 0. ]\n";
+        let k = Intrinsics::from_opencv_yaml(yaml).unwrap();
+        assert_eq!(k.cx, 320.);
+        assert_eq!(k.distortion, Distortion::BrownConrady{ k1: -0.1, k2: 0.01, p1: 0., p2: 0., k3: 0. });
+        // Zero coefficients are a pinhole for Brown-Conrady only
+        let zero = yaml.replace("-0.1, 0.01", "0., 0.");
+        assert_eq!(Intrinsics::from_opencv_yaml(&zero).unwrap().distortion, Distortion::None);
+        let fisheye = intrinsics(Distortion::KannalaBrandt{ k1: 0., k2: 0., k3: 0., k4: 0. });
+        assert_eq!(Intrinsics::from_opencv_yaml(&fisheye.to_opencv_yaml()).unwrap(), fisheye);
+    }
+    #[test]
+    fn calibrated_camera_test_0() {
+        let r = [[0.8, 0., -0.6], [0., 1., 0.], [0.6, 0., 0.8]];
+        let t = Vec3d::new(0.1, -0.3, 2.);
+        let c2w = from_opencv_extrinsics(&r, t);
+        let (r2, t2) = to_opencv_extrinsics(&c2w);
+        assert!((t2 - t).len() < 1e-9 && f64::abs(r2[2][0] - 0.6) < 1e-9);
+        let k = intrinsics(Distortion::BrownConrady{ k1: -0.28, k2: 0.07, p1: 0.001, p2: -0.0015, k3: 0.01 });
+        let cam = CalibratedCamera{ camera_to_world: vec![c2w], shutter: Shutter::instant(), intrinsics: k.clone() };
+        // A world point projects to the same pixel as OpenCV with these extrinsics
+        let pw = Vec3d::new(0.3, 0.2, 1.5);
+        let pc = Vec3d::new(Vec3d::dot(Vec3d::new(0.8, 0., -0.6), pw), pw.y, Vec3d::dot(Vec3d::new(0.6, 0., 0.8), pw)) + t;
+        let (u, v) = k.project(pc).unwrap();
+        let p = cam.world_to_raster(pw).unwrap();
+        let (u2, v2) = k.film_to_pixel((p.x, p.y));
+        assert!(f64::abs(u - u2) < 1e-8 && f64::abs(v - v2) < 1e-8);
+        let ray = cam.generate_ray((p.x, p.y), (0., 0.), 0.);
+        let along = pw - ray.o;
+        assert!((along.norm() - ray.d).len() < 1e-8);
+    }
+}
+#[cfg(test)]
+mod sensor_tests {
+    use crate::color::RGB;
+    use crate::sensor::{Sensor, RgbResponse, SpectralResponse};
+    use crate::spectrum::ResponseCurve;
+    #[test]
+    fn exposure_test_0() {
+        let mut s = Sensor::ideal();
+        let c = s.expose(&RGB{ r: 0.5, g: 2., b: 4. });
+        assert!(f64::abs(c.r - 0.5) < 1e-12 && f64::abs(c.g - 2.) < 1e-12 && f64::abs(c.b - 4.) < 1e-12);
+        // One stop more shutter, two stops more ISO and one stop less aperture
+        s.shutter_time = 2.;
+        s.iso = 400.;
+        s.f_number = std::f64::consts::SQRT_2;
+        assert!(f64::abs(s.exposure() - 4.) < 1e-12);
+    }
+    #[test]
+    fn white_balance_test_0() {
+        let s = Sensor::new(RgbResponse{ matrix: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]] }, Some(&RGB{ r: 1.2, g: 1., b: 0.6 }));
+        let c = s.expose(&RGB{ r: 0.6, g: 0.5, b: 0.3 });
+        assert!(f64::abs(c.r - 0.5) < 1e-12 && f64::abs(c.g - 0.5) < 1e-12 && f64::abs(c.b - 0.5) < 1e-12);
+        let band = |lo: f64, hi: f64| ResponseCurve::new(vec![lo, lo + 1., hi - 1., hi], vec![0., 1., 1., 0.]);
+        let warm = ResponseCurve::new(vec![360., 830.], vec![0.2, 1.8]);
+        let mut s = Sensor::new(SpectralResponse{ r: band(580., 700.), g: band(490., 580.), b: band(400., 490.) }, Some(&warm));
+        s.shutter_time = 0.5;
+        let bright = ResponseCurve::new(warm.lambda.clone(), warm.value.iter().map(|v| 3.*v).collect());
+        let c = s.expose(&bright);
+        assert!(f64::abs(c.r - c.g) < 1e-9 && f64::abs(c.b - c.g) < 1e-9);
+        assert!(f64::abs(c.g - 1.5*warm.eval(535.)) < 1e-2);
+        // No response to the illuminant in some channels, or outside of the
+        // visible range entirely, gives finite gains
+        let s = Sensor::new(SpectralResponse{ r: band(580., 700.), g: band(490., 580.), b: band(400., 490.) }, Some(&band(600., 650.)));
+        let g = s.white_balance_gains();
+        assert!(g.r == 1. && g.g == 1. && g.b == 1.);
+        let s = Sensor::new(SpectralResponse{ r: band(900., 950.), g: band(900., 950.), b: band(900., 950.) }, Some(&warm));
+        let (g, c) = (s.white_balance_gains(), s.expose(&warm));
+        assert!(g.r.is_finite() && g.b.is_finite() && c.r.is_finite() && c.g.is_finite() && c.b.is_finite());
+    }
+}
+#[cfg(test)]
+mod film_tests {
+    use crate::color::RGB;
+    use crate::film::Film;
+    use crate::filter::{Filter, BoxFilter, TriangleFilter, GaussianFilter, MitchellFilter, LanczosFilter};
+    #[test]
+    fn filter_test_0() {
+        let filters: Vec<Box<dyn Filter>> = vec![Box::new(BoxFilter::new((0.5, 0.5))),
+                                                 Box::new(TriangleFilter::new((2., 2.))),
+                                                 Box::new(GaussianFilter::new((1.5, 1.5), 0.5)),
+                                                 Box::new(MitchellFilter::new((2., 2.), 1./3., 1./3.)),
+                                                 Box::new(LanczosFilter::new((3., 3.), 3.))];
+        for f in filters.iter() {
+            let (rx, ry) = f.radius();
+            assert!(f.evaluate((0., 0.)) > 0.);
+            assert_eq!(f.evaluate((rx + 0.01, 0.)), 0.);
+            assert_eq!(f.evaluate((0., -ry - 0.01)), 0.);
+            assert!(f64::abs(f.evaluate((0.3, -0.2)) - f.evaluate((-0.3, 0.2))) < 1e-12);
+        }
+    }
+    #[test]
+    fn film_test_0() {
+        // A constant signal comes out unchanged whatever the filter
+        let film = Film::new((8, 6), Box::new(MitchellFilter::new((2., 2.), 1./3., 1./3.)));
+        let mut tile = film.tile(0, 0, 4, 6);
+        for y in 0..24 {
+            for x in 0..16 {
+                let p = ((x as f64 + 0.5)/2., (y as f64 + 0.5)/4.);
+                if p.0 < 4. {
+                    tile.add_sample(p, RGB{ r: 2., g: 0.5, b: 3. }, 1.);
+                } else {
+                    film.add_sample(p, RGB{ r: 2., g: 0.5, b: 3. }, 1.);
+                }
+            }
+        }
+        film.merge_tile(tile);
+        film.add_splat((3.5, 2.5), RGB{ r: 4., g: 0., b: 0. });
+        let img = film.resolve(0.25);
+        for (i, c) in img.iter().enumerate() {
+            let r = if i == 2*8 + 3 { 3. } else { 2. };
+            assert!(f64::abs(c.r - r) < 1e-9 && f64::abs(c.g - 0.5) < 1e-9 && f64::abs(c.b - 3.) < 1e-9);
+        }
+        assert_eq!(film.film_to_raster((0., 0.)), (4., 3.));
+        assert_eq!(film.raster_to_film((8., 0.)), (1., 1.));
+    }
+    #[test]
+    fn film_test_1() {
+        // Threads rendering their own tiles match a single threaded render
+        let sample = |x: usize, y: usize| ((x as f64*0.37 + y as f64*0.11).fract()*8., (x as f64*0.07 + y as f64*0.53).fract()*6.);
+        let color = |x: usize, y: usize| RGB{ r: x as f64, g: y as f64, b: 1. };
+        let serial = Film::new((8, 6), Box::new(GaussianFilter::new((1.5, 1.5), 0.5)));
+        for y in 0..40 {
+            for x in 0..40 {
+                serial.add_sample(sample(x, y), color(x, y), 1.);
+            }
+        }
+        let parallel = Film::new((8, 6), Box::new(GaussianFilter::new((1.5, 1.5), 0.5)));
+        std::thread::scope(|scope| {
+            for &(x0, y0) in &[(0, 0), (4, 0), (0, 3), (4, 3)] {
+                let film = &parallel;
+                scope.spawn(move || {
+                    let mut tile = film.tile(x0, y0, x0 + 4, y0 + 3);
+                    for y in 0..40 {
+                        for x in 0..40 {
+                            let p = sample(x, y);
+                            if p.0 >= x0 as f64 && p.0 < (x0 + 4) as f64 && p.1 >= y0 as f64 && p.1 < (y0 + 3) as f64 {
+                                tile.add_sample(p, color(x, y), 1.);
+                            }
+                        }
+                    }
+                    film.merge_tile(tile);
+                });
+            }
+        });
+        for (a, b) in serial.resolve(0.).iter().zip(parallel.resolve(0.).iter()) {
+            assert!((a.r - b.r).abs() < 1e-9 && (a.g - b.g).abs() < 1e-9 && (a.b - b.b).abs() < 1e-9);
+        }
+    }
+}
+#[cfg(test)]
+mod color_tests {
+    use crate::color::{RGB, RGB8};
+    #[test]
+    fn rgb_test_0() {
+        let a = RGB::new(2.5, -0.5, 100.);
+        assert_eq!((a.r, a.g, a.b), (2.5, -0.5, 100.));
+        let b = RGB::new(0.5, 2., 4.);
+        assert_eq!(a + b, RGB::new(3., 1.5, 104.));
+        assert_eq!(a*b, RGB::new(1.25, -1., 400.));
+        assert_eq!(a/b, RGB::new(5., -0.25, 25.));
+        assert_eq!(2.*b, b*2.);
+        assert_eq!(b/2., RGB::new(0.25, 1., 2.));
+        assert_eq!(a.clamp(0., 1.), RGB::new(1., 0., 1.));
+        assert!(f64::abs(RGB::white().luminance() - 1.) < 1e-12);
+        assert!(RGB::new(f64::NAN, 0., 0.).has_nan());
+        assert!(!RGB::new(f64::INFINITY, 0., 0.).is_finite());
+        assert!(a.is_finite() && !a.has_nan() && !a.has_inf());
+    }
+    #[test]
+    fn rgb8_test_0() {
+        assert_eq!(RGB8::from_rgb(RGB::new(1.5, 0.5, -1.)), RGB8::new(255, 128, 0));
+        assert_eq!(RGB8::from_rgb(RGB::new(f64::NAN, 0.2, 1.)), RGB8::new(0, 51, 255));
+        assert_eq!(RGB8::new(255, 0, 51).to_rgb(), RGB::new(1., 0., 0.2));
+    }
+}
+#[cfg(test)]
+mod imageio_tests {
+    use crate::image::Image;
+    use crate::imageio::*;
+    use crate::deflate::{crc32, adler32, zlib_compress, zlib_decompress};
+    use crate::color::{RGB, srgb_to_linear};
+    #[test]
+    fn half_test_0() {
+        assert_eq!(f32_to_half(0.), 0x0000);
+        assert_eq!(f32_to_half(-0.), 0x8000);
+        assert_eq!(f32_to_half(1.), 0x3c00);
+        assert_eq!(f32_to_half(-2.), 0xc000);
+        assert_eq!(f32_to_half(65504.), 0x7bff);
+        assert_eq!(f32_to_half(1e6), 0x7c00);
+        assert_eq!(f32_to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(0.333333), 0x3555);
+        assert_eq!(f32_to_half(f32::NAN) & 0x7c00, 0x7c00);
+    }
+    #[test]
+    fn checksum_test_0() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+    #[test]
+    fn encode_test_0() {
+        let mut img = Image::new(2, 2, &["Y"]);
+        img.set(0, 0, 0, 1.);
+        img.set(1, 1, 0, 0.5);
+        let pfm = encode_pfm(&img).unwrap();
+        let header = b"Pf\n2 2\n-1.0\n";
+        assert_eq!(&pfm[..header.len()], &header[..]);
+        // Bottom row comes first
+        let first: Vec<f32> = pfm[header.len()..].chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        assert_eq!(first, vec![0., 0.5, 1., 0.]);
+        let png = encode_png(&img, 16).unwrap();
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // Out of range values saturate the RGBE exponent instead of wrapping
+        let mut big = Image::new(3, 1, &["R", "G", "B"]);
+        for (x, &v) in [f64::INFINITY, 1e300, 1e38].iter().enumerate() {
+            big.set(x, 0, 0, v);
+        }
+        let hdr = encode_hdr(&big).unwrap();
+        assert_eq!(&hdr[hdr.len() - 12..], &[255, 0, 0, 255, 255, 0, 0, 255, 150, 0, 0, 255]);
+    }
+    #[test]
+    fn inflate_test_0() {
+        // Stored block
+        let z = [0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, 0x61, 0x62, 0x63, 0x02, 0x4d, 0x01, 0x27];
+        assert_eq!(zlib_decompress(&z).unwrap(), b"abc".to_vec());
+        let data: Vec<u8> = (0..5000u32).map(|i| (i*i % 251 % 7) as u8).collect();
+        assert_eq!(zlib_decompress(&zlib_compress(&data)).unwrap(), data);
+        assert!(zlib_decompress(&z[..10]).is_err());
+        for h in &[0u16, 1, 0x3555, 0x7bff, 0xc000, 0x8001]
+        {
+            assert_eq!(f32_to_half(half_to_f32(*h)), *h);
+        }
+    }
+    #[test]
+    fn decode_test_0() {
+        let mut img = Image::new(5, 3, &["R", "G", "B", "A"]);
+        for (i, v) in img.data.iter_mut().enumerate()
+        {
+            *v = (i % 17) as f64/16.;
+        }
+        let png = decode_image(&encode_png(&img, 16).unwrap(), "", Encoding::Linear).unwrap();
+        assert_eq!(png.channels, img.channels);
+        assert!(png.data.iter().zip(&img.data).all(|(a, b)| (a - b).abs() < 1e-4));
+        // Alpha isn't sRGB decoded
+        let png = decode_png(&encode_png(&img, 8).unwrap(), Encoding::SRGB).unwrap();
+        assert!((png.get(1, 0, 0) - srgb_to_linear(4./16.)).abs() < 1e-2);
+        assert!((png.get(0, 0, 3) - 3./16.).abs() < 1e-2);
+        let ppm = decode_ppm(&encode_ppm(&img, 8).unwrap(), Encoding::Linear).unwrap();
+        assert_eq!(ppm.channels, vec!["R", "G", "B"]);
+        assert!((ppm.get(4, 2, 2) - img.get(4, 2, 2)).abs() < 1e-2);
+        let pfm = decode_pfm(&encode_pfm(&img).unwrap()).unwrap();
+        assert_eq!(pfm.get(3, 1, 1), img.get(3, 1, 1));
+        let hdr = decode_hdr(&encode_hdr(&img).unwrap()).unwrap();
+        assert!((hdr.get(2, 2, 0) - img.get(2, 2, 0)).abs() < 1e-2);
+        for &compression in &[ExrCompression::None, ExrCompression::Rle, ExrCompression::Zips, ExrCompression::Zip]
+        {
+            let layers = [ExrLayer::new("", &img, ExrPixelType::Float)];
+            let exr = decode_exr(&encode_exr(&layers, &ExrOptions{ compression, ..ExrOptions::default() }).unwrap()).unwrap();
+            assert_eq!(exr, img);
+        }
+        let layers = [ExrLayer::new("", &img, ExrPixelType::Half), ExrLayer::new("diffuse", &img, ExrPixelType::Half)];
+        let exr = decode_exr(&encode_exr(&layers, &ExrOptions::default()).unwrap()).unwrap();
+        assert_eq!(&exr.channels[..5], &["R", "G", "B", "A", "diffuse.A"]);
+        assert_eq!(exr.get(4, 2, 1), img.get(4, 2, 1));
+    }
+    #[test]
+    fn decode_test_1() {
+        // Run length encoded bottom up TGA, a run of two red pixels then two
+        // raw ones
+        let mut tga = vec![0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 24, 0];
+        tga.extend_from_slice(&[0x81, 0, 0, 255, 0x01, 0, 255, 0, 255, 0, 0]);
+        let img = decode_image(&tga, "tga", Encoding::Linear).unwrap();
+        assert_eq!(img.get_rgb(0, 1), RGB::red());
+        assert_eq!(img.get_rgb(1, 1), RGB::red());
+        assert_eq!(img.get_rgb(0, 0), RGB::green());
+        assert_eq!(img.get_rgb(1, 0), RGB::blue());
+        // Run length encoded HDR scanline
+        let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        hdr.extend_from_slice(&[2, 2, 0, 8, 136, 128, 136, 0, 136, 64, 136, 128]);
+        let img = decode_hdr(&hdr).unwrap();
+        assert_eq!(img.get_rgb(7, 0), RGB::new(0.501953125, 0.001953125, 0.251953125));
+    }
+    #[test]
+    fn jpeg_test_0() {
+        // 8x8 gray image with a DC coefficient of 80 and nothing else
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xdb, 0x00, 0x43, 0x00];
+        jpeg.extend_from_slice(&[1; 64]);
+        jpeg.extend_from_slice(&[0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00]);
+        for &(class, symbol) in &[(0x00, 7), (0x10, 0)]
+        {
+            jpeg.extend_from_slice(&[0xff, 0xc4, 0x00, 0x14, class, 1]);
+            jpeg.extend_from_slice(&[0; 15]);
+            jpeg.push(symbol);
+        }
+        jpeg.extend_from_slice(&[0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, 0x50, 0x7f, 0xff, 0xd9]);
+        let img = decode_image(&jpeg, "", Encoding::Linear).unwrap();
+        assert_eq!(img.channels, vec!["Y"]);
+        assert!(img.data.iter().all(|&v| v == 138./255.));
+        let sof = jpeg.iter().position(|&b| b == 0xc0).unwrap();
+        jpeg[sof] = 0xc3;
+        assert_eq!(decode_jpeg(&jpeg, Encoding::Linear).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+        // A progressive scan must hold either the DC or a band of AC
+        jpeg[sof] = 0xc2;
+        assert_eq!(decode_jpeg(&jpeg, Encoding::Linear).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+    #[test]
+    fn jpeg_test_1() {
+        // A 32x23 progressive image with spectral selection and successive
+        // approximation scans, and the same image saved as baseline by
+        // another encoder, checked against pixels from another decoder
+        let progressive = decode_jpeg(include_bytes!("../testdata/progressive.jpg"), Encoding::Linear).unwrap();
+        let baseline = decode_jpeg(include_bytes!("../testdata/baseline.jpg"), Encoding::Linear).unwrap();
+        let close = |img: &Image, x: usize, y: usize, rgb: [f64; 3]|
+            (0..3).all(|c| (img.get(x, y, c)*255. - rgb[c]).abs() < 2.5);
+        assert!(close(&progressive, 5, 5, [72., 72., 72.]));
+        assert!(close(&progressive, 16, 11, [110., 20., 0.]));
+        assert!(close(&progressive, 20, 3, [32., 0., 0.]));
+        assert!(close(&baseline, 16, 11, [114., 20., 12.]));
+        assert!(close(&baseline, 20, 3, [42., 3., 8.]));
+        let diff = progressive.data.iter().zip(&baseline.data).map(|(a, b)| (a - b).abs()).sum::<f64>()/baseline.data.len() as f64;
+        assert!(diff < 3./255.);
+    }
+    #[test]
+    fn exr_compression_test_0() {
+        // The same 40x53 image with half, float and uint channels, written by
+        // another OpenEXR implementation with ZIP, PIZ and PXR24
+        let zip = decode_exr(include_bytes!("../testdata/zip.exr")).unwrap();
+        assert_eq!(zip.channels, vec!["R", "G", "B", "Z", "id"]);
+        assert_eq!((zip.get(5, 5, 1), zip.get(9, 50, 3), zip.get(3, 0, 4)), (-2.5, 28.25, 70000.));
+        assert_eq!(decode_exr(include_bytes!("../testdata/piz.exr")).unwrap(), zip);
+        // Lossless here as the floats fit in 24 bits
+        assert_eq!(decode_exr(include_bytes!("../testdata/pxr24.exr")).unwrap(), zip);
+        let mut piz = include_bytes!("../testdata/piz.exr").to_vec();
+        let at = piz.len() - 100;
+        piz.truncate(at);
+        assert!(decode_exr(&piz).is_err());
+    }
+    #[test]
+    fn exr_corrupt_test_0() {
+        // Damaged headers are rejected before the image is allocated
+        let deep = crate::image::DeepImage::new(2, 2, &["A", "Z"], &[1, 0, 2, 1]);
+        let deep = encode_deep_exr(&deep, &ExrOptions::default()).unwrap();
+        let files: [(&[u8], bool); 2] = [(include_bytes!("../testdata/zip.exr"), false), (&deep, true)];
+        for &(file, is_deep) in &files
+        {
+            let decode = |data: &[u8]| if is_deep { decode_deep_exr(data).map(|_| ()) } else { decode_exr(data).map(|_| ()) };
+            assert!(decode(file).is_ok());
+            let at = file.windows(10).position(|w| w == b"dataWindow").unwrap() + 17;
+            let patch = |edits: &[(usize, i32)]|
+            {
+                let mut data = file.to_vec();
+                for &(p, v) in edits
+                {
+                    data[p..p + 4].copy_from_slice(&v.to_le_bytes());
+                }
+                data
+            };
+            // A negative attribute size, a window wider than an i32, windows
+            // too large for the file and a truncated offset table
+            assert!(decode(&patch(&[(at - 4, -16)])).is_err());
+            assert!(decode(&patch(&[(at, i32::MIN), (at + 8, i32::MAX)])).is_err());
+            assert!(decode(&patch(&[(at + 8, 0x0100_0000)])).is_err());
+            assert!(decode(&patch(&[(at + 12, 0x7000_0000)])).is_err());
+            assert!(decode(&file[..at + 60]).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tonemap_tests {
+    use crate::color::{RGB, linear_to_srgb, srgb_to_linear};
+    use crate::image::Image;
+    use crate::tonemap::*;
+    #[test]
+    fn curve_test_0() {
+        let r = ToneCurve::Reinhard{ white: None }.eval(RGB::white());
+        assert!((r.g - 0.5).abs() < 1e-12);
+        let r = ToneCurve::Reinhard{ white: Some(4.) }.eval(RGB::gray(4.));
+        assert!((r.r - 1.).abs() < 1e-12);
+        for &curve in &[ToneCurve::Aces, ToneCurve::AgX]
+        {
+            let mut prev = -1.;
+            for i in 0..100
+            {
+                let v = curve.eval(RGB::gray(2f64.powf(i as f64/5. - 10.))).g;
+                assert!(v >= prev && v <= 1.);
+                prev = v;
+            }
+            assert!(prev > 0.9);
+            assert!(curve.eval(RGB::black()).max_comp() < 1e-3);
+        }
+        for i in 0..=10
+        {
+            let v = i as f64/10.;
+            assert!((srgb_to_linear(linear_to_srgb(v)) - v).abs() < 1e-12);
+        }
+        assert!((Oetf::Gamma(2.).encode(0.25) - 0.5).abs() < 1e-12);
+    }
+    #[test]
+    fn exposure_test_0() {
+        let mut img = Image::new(10, 10, &["R", "G", "B"]);
+        for v in img.data.iter_mut()
+        {
+            *v = 0.045;
+        }
+        // A few very bright pixels are ignored
+        img.set_rgb(0, 0, RGB::gray(1000.));
+        let ev = AutoExposure::default().exposure(&img);
+        assert!((ev - 2.).abs() < 0.1);
+        let t = DisplayTransform{ auto_exposure: Some(AutoExposure::default()), oetf: Oetf::Linear, ..DisplayTransform::default() };
+        assert!((t.apply(&img).get(5, 5, 1) - 0.18).abs() < 0.01);
+    }
+    #[test]
+    fn lut_test_0() {
+        let mut cube = String::from("# comment\nTITLE \"invert\"\nLUT_3D_SIZE 2\n");
+        for i in 0..8
+        {
+            cube += &format!("{} {} {}\n", 1 - (i & 1), 1 - ((i >> 1) & 1), 1 - (i >> 2));
+        }
+        let lut = Lut3D::parse_cube(&cube).unwrap();
+        let c = lut.apply(RGB::new(0.25, 0.5, 1.5));
+        assert!((c.r - 0.75).abs() < 1e-12 && (c.g - 0.5).abs() < 1e-12 && c.b.abs() < 1e-12);
+        let c = RGB::new(0.1, 0.7, 0.3);
+        assert!((Lut3D::identity(5).apply(c) - c).max_comp().abs() < 1e-12);
+        assert!(Lut3D::parse_cube("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        // Unknown keywords are skipped and the input range sets the domain
+        let ranged = Lut3D::parse_cube(&cube.replace("TITLE", "LUT_3D_INPUT_RANGE -1 3\nLUT_IN_VIDEO_RANGE\nTITLE")).unwrap();
+        assert_eq!((ranged.domain_min, ranged.domain_max), ([-1.; 3], [3.; 3]));
+        let c = ranged.apply(RGB::new(0., 1., 3.));
+        assert!((c.r - 0.75).abs() < 1e-12 && (c.g - 0.5).abs() < 1e-12 && c.b.abs() < 1e-12);
+    }
+}
+
+#[cfg(test)]
+mod colorspace_tests {
+    use crate::color::RGB;
+    use crate::colorspace::*;
+    use crate::transformation::Matrix3;
+    use crate::vector::{Point2, Vec3d};
+    fn close(a: RGB, b: RGB) -> bool {
+        (a - b).map(f64::abs).max_comp() < 1e-4
+    }
+    #[test]
+    fn matrix3_test_0() {
+        let m = Matrix3::new([[2., 1., 0.], [0., 3., 1.], [1., 0., 4.]]);
+        assert_eq!(Matrix3::mul(&m, &m.inv()), Matrix3::i());
+        assert_eq!(m.det(), 25.);
+    }
+    #[test]
+    fn colorspace_test_0() {
+        let srgb = RGBColorSpace::srgb();
+        let m = srgb.rgb_to_xyz;
+        assert!((m[(0,0)] - 0.4124).abs() < 1e-3 && (m[(1,1)] - 0.7152).abs() < 1e-3 && (m[(2,2)] - 0.9505).abs() < 1e-3);
+        assert!((srgb.luminance(RGB::new(0.2, 0.5, 0.9)) - RGB::new(0.2, 0.5, 0.9).luminance()).abs() < 1e-3);
+        let p = xyz_to_xy(srgb.to_xyz(RGB::red()));
+        assert!((p.x - 0.64).abs() < 1e-9 && (p.y - 0.33).abs() < 1e-9);
+        // White stays white, across white points too
+        for space in &[RGBColorSpace::rec2020(), RGBColorSpace::dci_p3(), RGBColorSpace::aces_cg()]
+        {
+            assert!(close(space.convert(RGB::white(), &srgb), RGB::white()));
+            let c = RGB::new(0.3, 0.6, 0.1);
+            assert!(close(srgb.convert(space.convert(c, &srgb), space), c));
+        }
+        // ACEScg to sRGB, as in the ACES reference implementation
+        let c = RGBColorSpace::aces_cg().convert(RGB::red(), &srgb);
+        assert!(close(c, RGB::new(1.7051, -0.1302, -0.0240)));
+    }
+    #[test]
+    fn bradford_test_0() {
+        let d65 = xy_to_xyz(Point2::new(0.3127, 0.3290));
+        let d50 = Vec3d::new(0.9642, 1., 0.8251);
+        let m = bradford(d65, d50);
+        let w = m.act(d65);
+        assert!((w - d50).len() < 1e-9);
+        assert!((m[(0,0)] - 1.0478).abs() < 1e-3 && (m[(0,1)] - 0.0229).abs() < 1e-3);
+    }
+}
+
+#[cfg(test)]
+mod aov_tests {
+    use crate::aov::{Aov, AovSample};
+    use crate::color::RGB;
+    use crate::film::{Film, FilmExrOptions};
+    use crate::filter::BoxFilter;
+    use crate::imageio::decode_exr;
+    use crate::vector::Point2;
+    #[test]
+    fn film_aov_test_0() {
+        let mut film = Film::new((4, 2), Box::new(BoxFilter::new((0.5, 0.5))));
+        film.set_aovs(&[Aov::Depth, Aov::ShapeId, Aov::UV]);
+        let mut s = AovSample::miss();
+        s.depth = 2.;
+        s.shape_id = 3;
+        s.uv = Point2::new(0.5, 1.);
+        film.add_aov_sample((1.1, 0.1), &s);
+        // Closer to the center of the pixel, so its id wins
+        let mut tile = film.tile(0, 0, 2, 2);
+        s.depth = 4.;
+        s.shape_id = 5;
+        tile.add_aov_sample((1.4, 0.6), &s);
+        tile.add_aov_sample((3.5, 0.5), &s);
+        film.merge_tile(tile);
+        let aovs = film.resolve_aovs();
+        assert_eq!(aovs.len(), 3);
+        assert_eq!(aovs[0].get(1, 0, 0), 3.);
+        assert_eq!(aovs[1].get(1, 0, 0), 5.);
+        assert_eq!(aovs[2].channels, vec!["U", "V"]);
+        assert_eq!(aovs[2].get(1, 0, 1), 1.);
+        // Outside of the tile
+        assert_eq!(aovs[0].get(3, 0, 0), 0.);
+        film.add_sample((0.5, 0.5), RGB::white(), 1.);
+        let path = std::env::temp_dir().join("aov_test_0.exr");
+        film.write_exr(&path, &FilmExrOptions::default()).unwrap();
+        let exr = decode_exr(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(exr.channels, vec!["R", "G", "B", "depth.Z", "shapeId.id", "uv.U", "uv.V"]);
+        assert_eq!(exr.get(1, 0, 4), 5.);
+        assert_eq!(exr.get(0, 0, 0), 1.);
+    }
+}
+
+#[cfg(test)]
+mod cryptomatte_tests {
+    use crate::cryptomatte::*;
+    use crate::film::{Film, FilmExrOptions};
+    use crate::filter::BoxFilter;
+    use crate::imageio::{ExrAttribute, decode_exr};
+    #[test]
+    fn hash_test_0() {
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"hello", 0), 0x248bfa47);
+        assert_eq!(murmur3_32(b"The quick brown fox jumps over the lazy dog", 0), 0x2e4ff723);
+        for name in &["hello", "bunny", "/root/geo/teapot", ""]
+        {
+            let id = name_to_id(name);
+            assert!(id.is_normal() || id == 0.);
+        }
+    }
+    #[test]
+    fn coverage_test_0() {
+        let filter = BoxFilter::new((0.5, 0.5));
+        let matte = Cryptomatte::new("CryptoObject", (2, 1), 4);
+        let (a, b) = (matte.register("a"), matte.register("b\"q"));
+        matte.add_sample(&filter, (0.5, 0.5), Some(a), 1.);
+        matte.add_sample(&filter, (0.5, 0.5), Some(b), 1.);
+        matte.add_sample(&filter, (0.5, 0.5), Some(b), 1.);
+        matte.add_sample(&filter, (0.5, 0.5), None, 1.);
+        let layers = matte.layers();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].get(0, 0, 0), b as f64);
+        assert_eq!(layers[0].get(0, 0, 1), 0.5);
+        assert_eq!(layers[0].get(0, 0, 2), a as f64);
+        assert_eq!(layers[0].get(0, 0, 3), 0.25);
+        assert_eq!(layers[1].get(0, 0, 1), 0.);
+        assert_eq!(matte.manifest(), format!("{{\"a\":\"{:08x}\",\"b\\\"q\":\"{:08x}\"}}", a.to_bits(), b.to_bits()));
+        let attributes = matte.attributes();
+        let key = &format!("{:08x}", murmur3_32(b"CryptoObject", 0))[..7];
+        assert_eq!(attributes[0], (format!("cryptomatte/{}/name", key), ExrAttribute::String("CryptoObject".to_string())));
+        let film = Film::new((2, 1), Box::new(filter));
+        let path = std::env::temp_dir().join("coverage_test_0.exr");
+        film.write_exr(&path, &FilmExrOptions{ cryptomattes: &[&matte], ..FilmExrOptions::default() }).unwrap();
+        let exr = decode_exr(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let r = exr.channel_index("CryptoObject00.R").unwrap();
+        assert_eq!(exr.get(0, 0, r) as f32, b);
+    }
+}
+
+#[cfg(test)]
+mod lpe_tests {
+    use crate::color::RGB;
+    use crate::film::Film;
+    use crate::filter::BoxFilter;
+    use crate::lpe::*;
+    fn ev(kind: EventType, scatter: Scatter) -> LpeEvent<'static> {
+        LpeEvent::new(kind, scatter)
+    }
+    #[test]
+    fn match_test_0() {
+        let (c, l) = (LpeEvent::camera(), LpeEvent::light());
+        let rd = ev(EventType::Reflect, Scatter::Diffuse);
+        let rs = ev(EventType::Reflect, Scatter::Singular);
+        let tg = ev(EventType::Transmit, Scatter::Glossy);
+        let direct_diffuse = Lpe::parse("C<RD>L").unwrap();
+        assert!(direct_diffuse.matches(&[c, rd, l]));
+        assert!(!direct_diffuse.matches(&[c, rd, rd, l]));
+        assert!(!direct_diffuse.matches(&[c, rs, l]));
+        let specular = Lpe::parse("C<RS>.*L").unwrap();
+        assert!(specular.matches(&[c, rs, l]));
+        assert!(specular.matches(&[c, rs, rd, tg, l]));
+        assert!(!specular.matches(&[c, rd, rs, l]));
+        let indirect = Lpe::parse("C . .+ [LO]").unwrap();
+        assert!(indirect.matches(&[c, rd, tg, ev(EventType::Emission, Scatter::None)]));
+        assert!(!indirect.matches(&[c, rd, l]));
+        let counted = Lpe::parse("C(D|G){2,3}[^O]").unwrap();
+        assert!(!counted.matches(&[c, rd, l]));
+        assert!(counted.matches(&[c, rd, tg, l]));
+        assert!(counted.matches(&[c, rd, tg, rd, l]));
+        assert!(!counted.matches(&[c, rd, tg, rd, rd, l]));
+        assert!(!counted.matches(&[c, rd, tg, ev(EventType::Emission, Scatter::None)]));
+        let labeled = Lpe::parse("C<R.'coat'>L").unwrap();
+        let coat = LpeEvent{ label: Some("coat"), ..rs };
+        assert!(labeled.matches(&[c, coat, l]));
+        assert!(!labeled.matches(&[c, rs, l]));
+        for bad in &["C<RX>L", "C(DL", "CD)L", "C{3,2}", "C'label", "C[DL"]
+        {
+            assert!(Lpe::parse(bad).is_err());
+        }
+    }
+    #[test]
+    fn tracker_test_0() {
+        let mut film = Film::new((2, 2), Box::new(BoxFilter::new((0.5, 0.5))));
+        film.set_lpes(vec![("direct".to_string(), Lpe::parse("C.L").unwrap()),
+                           ("indirect".to_string(), Lpe::parse("C..+L").unwrap())]);
+        let rd = ev(EventType::Reflect, Scatter::Diffuse);
+        let mut tracker = LpeTracker::new(film.lpes());
+        // Next event estimation at the first and second vertex
+        tracker.add(&[rd, LpeEvent::light()], RGB::gray(1.));
+        tracker.scatter(&rd);
+        tracker.add(&[rd, LpeEvent::light()], RGB::gray(0.5));
+        let radiance = tracker.radiance.clone();
+        assert_eq!(radiance, vec![RGB::gray(1.), RGB::gray(0.5)]);
+        film.add_lpe_sample((0.5, 0.5), &radiance, 1.);
+        let mut tile = film.tile(0, 0, 1, 1);
+        tile.add_lpe_sample((0.5, 0.5), &[RGB::black(), RGB::gray(1.5)], 1.);
+        film.merge_tile(tile);
+        let passes = film.resolve_lpes();
+        assert_eq!(passes[0].get_rgb(0, 0), RGB::gray(0.5));
+        assert_eq!(passes[1].get_rgb(0, 0), RGB::gray(1.));
+    }
+}
+
+#[cfg(test)]
+mod deep_tests {
+    use crate::color::RGB;
+    use crate::deep::{flatten, DeepSample};
+    use crate::film::Film;
+    use crate::filter::BoxFilter;
+    use crate::imageio::{decode_deep_exr, encode_deep_exr, ExrCompression, ExrOptions};
+    #[test]
+    fn film_deep_test_0() {
+        let mut film = Film::new((3, 2), Box::new(BoxFilter::new((0.5, 0.5))));
+        film.enable_deep(0.01);
+        // Fog in front of an opaque surface, then a miss in the same pixel
+        let fog = DeepSample::new(RGB::gray(0.1), 0.25, 1., 2.);
+        let wall = DeepSample::surface(RGB::new(0.6, 0.3, 0.), 0.75, 5.);
+        film.add_deep_sample((0.5, 0.5), &[wall, fog], 1.);
+        let mut tile = film.tile(0, 0, 2, 2);
+        tile.add_deep_sample((0.2, 0.7), &[DeepSample{ z_back: 5.005, ..wall }], 1.);
+        tile.add_deep_sample((0.4, 0.4), &[], 2.);
+        tile.add_deep_sample((1.5, 1.5), &[fog], 1.);
+        film.merge_tile(tile);
+        let img = film.resolve_deep();
+        assert_eq!(img.channels, vec!["R", "G", "B", "A", "Z", "ZBack"]);
+        assert_eq!(img.sample_count(0, 0), 2);
+        assert_eq!(img.sample_count(1, 0), 0);
+        assert_eq!(img.max_samples(), 2);
+        let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-12);
+        assert!(close(img.sample(0, 0, 0), &[0.025, 0.025, 0.025, 0.0625, 1., 2.]));
+        // The wall is seen through the fog by one sample, so it covers 0.328
+        // of the pixel, which is 0.35 of what the fog leaves
+        assert!(close(img.sample(0, 0, 1), &[0.28, 0.14, 0., 0.35, 5., 5.005]));
+        let flat = flatten(&img).unwrap();
+        assert!((flat.get(0, 0, 3) - (0.25 + 0.75*0.75 + 0.75)/4.).abs() < 1e-12);
+        assert!((flat.get(0, 0, 0) - (0.1 + 0.75*0.6 + 0.6)/4.).abs() < 1e-12);
+        for compression in &[ExrCompression::None, ExrCompression::Rle, ExrCompression::Zip]
+        {
+            let options = ExrOptions{ compression: *compression, ..ExrOptions::default() };
+            let exr = decode_deep_exr(&encode_deep_exr(&img, &options).unwrap()).unwrap();
+            assert_eq!(exr.channels, vec!["A", "B", "G", "R", "Z", "ZBack"]);
+            assert_eq!(exr.offsets, img.offsets);
+            assert_eq!(exr.sample(0, 0, 1)[3], img.sample(0, 0, 1)[0] as f32 as f64);
+            assert_eq!(exr.sample(1, 1, 0)[4], 1.);
+        }
+    }
+    #[test]
+    fn film_deep_test_1() {
+        // Opaque red and blue walls over either half of one pixel
+        let mut film = Film::new((1, 1), Box::new(BoxFilter::new((0.5, 0.5))));
+        film.enable_deep(0.01);
+        film.add_deep_sample((0.25, 0.5), &[DeepSample::surface(RGB::red(), 1., 1.)], 1.);
+        film.add_deep_sample((0.75, 0.5), &[DeepSample::surface(RGB::blue(), 1., 5.)], 1.);
+        let flat = flatten(&film.resolve_deep()).unwrap();
+        assert_eq!(flat.data, vec![0.5, 0., 0.5, 1.]);
+        // Samples can't be ordered without depth
+        assert!(flatten(&crate::image::DeepImage::new(1, 1, &["R", "G", "B", "A"], &[1])).is_none());
+    }
+}
+
+#[cfg(test)]
+mod denoise_tests {
+    use crate::color::RGB;
+    use crate::denoise::{ATrous, Guides, NlMeans};
+    use crate::film::Film;
+    use crate::filter::BoxFilter;
+    use crate::image::Image;
+    // Left half dark, right half bright, with noise of known variance
+    fn noisy() -> (Image, Image, Image, Image) {
+        let (w, h) = (24, 16);
+        let mut seed = 7u32;
+        let clean: Vec<RGB> = (0..w*h).map(|i| if i%w < w/2 { RGB::gray(0.2) } else { RGB::new(0.9, 0.6, 0.3) }).collect();
+        let mut noise = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            0.2*((seed >> 8) as f64/(1 << 24) as f64 - 0.5)
+        };
+        let color: Vec<RGB> = clean.iter().map(|&c| RGB::new(c.r + noise(), c.g + noise(), c.b + noise())).collect();
+        let normal: Vec<RGB> = (0..w*h).map(|i| if i%w < w/2 { RGB::new(0., 0., 1.) } else { RGB::new(1., 0., 0.) }).collect();
+        let variance = vec![RGB::gray(0.04/12.); w*h];
+        (Image::from_rgb(w, h, &clean), Image::from_rgb(w, h, &color), Image::from_rgb(w, h, &normal), Image::from_rgb(w, h, &variance))
+    }
+    fn mse(a: &Image, b: &Image) -> f64 {
+        a.data.iter().zip(&b.data).map(|(x, y)| (x - y)*(x - y)).sum::<f64>()/a.data.len() as f64
+    }
+    #[test]
+    fn denoise_test_0() {
+        let (clean, color, normal, variance) = noisy();
+        let guides = Guides{ normal: Some(&normal), variance: Some(&variance), ..Guides::default() };
+        let before = mse(&clean, &color);
+        for out in &[ATrous::default().apply(&color, &guides), NlMeans::default().apply(&color, &guides),
+                     ATrous::default().apply(&color, &Guides::default())] {
+            assert!(mse(&clean, out) < 0.25*before);
+        }
+        // The normals keep the edge sharp
+        let out = ATrous::default().apply(&color, &guides);
+        assert!((out.get(11, 8, 0) - 0.2).abs() < 0.05);
+        assert!((out.get(12, 8, 0) - 0.9).abs() < 0.05);
+        // Dividing out the albedo leaves nothing to filter
+        let albedo = clean.clone();
+        let guides = Guides{ albedo: Some(&albedo), ..guides };
+        assert!(mse(&clean, &NlMeans::default().apply(&clean, &guides)) < 1e-20);
+    }
+    #[test]
+    fn variance_test_0() {
+        let mut film = Film::new((2, 1), Box::new(BoxFilter::new((0.5, 0.5))));
+        film.enable_variance();
+        film.add_sample((0.5, 0.5), RGB::gray(1.), 1.);
+        let mut tile = film.tile(0, 0, 2, 1);
+        tile.add_sample((0.2, 0.2), RGB::gray(3.), 1.);
+        tile.add_sample((1.5, 0.5), RGB::gray(3.), 1.);
+        film.merge_tile(tile);
+        let variance = film.resolve_variance();
+        // Sample variance 2 over two samples
+        assert_eq!(variance.get_rgb(0, 0), RGB::gray(1.));
+        assert_eq!(variance.get_rgb(1, 0), RGB::black());
+    }
+}
+
+#[cfg(test)]
+mod compare_tests {
+    use crate::color::RGB;
+    use crate::compare::*;
+    use crate::image::Image;
+    fn checker(w: usize, h: usize, a: RGB, b: RGB) -> Image {
+        let pixels: Vec<RGB> = (0..w*h).map(|i| if (i%w/4 + i/w/4)%2 != 1 { a } else { b }).collect();
+        Image::from_rgb(w, h, &pixels)
+    }
+    #[test]
+    fn metric_test_0() {
+        let reference = checker(16, 16, RGB::gray(0.2), RGB::gray(0.8));
+        let same = compare(&reference, &reference);
+        assert_eq!(same.mse, 0.);
+        assert_eq!(same.psnr, f64::INFINITY);
+        assert!((same.ssim - 1.).abs() < 1e-9);
+        assert!(same.flip < 1e-9);
+        let offset = Image::from_rgb(16, 16, &reference.to_rgb().iter().map(|&c| c + RGB::gray(0.1)).collect::<Vec<RGB>>());
+        let m = compare(&offset, &reference);
+        assert!((m.mse - 0.01).abs() < 1e-12);
+        assert!((m.psnr - 20.).abs() < 1e-9);
+        assert!((m.rel_mse - 0.5*(0.01/0.05 + 0.01/0.65)).abs() < 1e-12);
+        assert!(m.ssim < 1. && m.ssim > 0.9);
+        // Losing the pattern is worse than a small offset
+        let flat = Image::from_rgb(16, 16, &vec![RGB::gray(0.5); 256]);
+        let lost = compare(&flat, &reference);
+        assert!(lost.flip > 2.*m.flip && lost.ssim < 0.1);
+        // Black against white is the largest color error
+        let black = Image::from_rgb(8, 8, &vec![RGB::black(); 64]);
+        let white = Image::from_rgb(8, 8, &vec![RGB::white(); 64]);
+        assert!(mean(&flip(&black, &white, DEFAULT_PPD)) > 0.9);
+        let map = heatmap(&squared_error(&offset, &reference), 0.01);
+        assert!((map.get_rgb(3, 3) - RGB::new(0.987, 0.991, 0.750)).max_comp().abs() < 1e-12);
+    }
+}
+
+#[cfg(test)]
+mod post_tests {
+    use crate::color::RGB;
+    use crate::complex::{fft, Complex};
+    use crate::image::Image;
+    use crate::post::*;
+    #[test]
+    fn fft_test_0() {
+        let x: Vec<Complex> = (0..8).map(|i| Complex::new(i as f64, (i*i%5) as f64)).collect();
+        let mut y = x.clone();
+        fft(&mut y, false);
+        // Constant term and the first bin against the direct sums
+        let sum = x.iter().fold(Complex::real(0.), |s, &v| s + v);
+        assert!((y[0] - sum).abs() < 1e-12);
+        let first = x.iter().enumerate().fold(Complex::real(0.), |s, (k, &v)| s + v*Complex::from_polar(1., -std::f64::consts::PI*k as f64/4.));
+        assert!((y[1] - first).abs() < 1e-12);
+        fft(&mut y, true);
+        for (a, b) in x.iter().zip(&y) {
+            assert!((*a - *b/8.).abs() < 1e-12);
+        }
+        assert!((Complex::new(-3., 4.).sqrt() - Complex::new(1., 2.)).abs() < 1e-12);
+    }
+    #[test]
+    fn effects_test_0() {
+        let (w, h) = (32, 24);
+        let mut img = Image::from_rgb(w, h, &vec![RGB::gray(0.5); w*h]);
+        // Nothing above the threshold
+        let bloom = Bloom::default();
+        assert_eq!(bloom.apply(&img), img);
+        img.set_rgb(16, 12, RGB::gray(100.));
+        let bloomed = bloom.apply(&img);
+        assert!(bloomed.get(18, 12, 0) > 0.5 && bloomed.get(16, 12, 0) > 100.);
+        // Glare moves light around without adding any
+        let glare = Glare{ vanes: 3, obstruction: 0.2, threshold: 0.5, ..Glare::default() };
+        let psf = glare.psf(64);
+        assert!((psf[1].iter().sum::<f64>() - 1.).abs() < 1e-9);
+        // Longer wavelengths spread further
+        assert!(psf[0][0] < psf[1][0] && psf[1][0] < psf[2][0]);
+        let glared = glare.apply(&img);
+        let total = |img: &Image| img.data.iter().sum::<f64>();
+        assert!(glared.get(16, 12, 1) < 100. && glared.get(17, 12, 1) > 0.5);
+        assert!(total(&glared) < total(&img) && total(&glared) > 0.95*total(&img));
+        assert!(glare.transmits(0.5, 0.1) && !glare.transmits(0.1, 0.5) && !glare.transmits(0.5, 0.));
+        // A fully obstructed aperture leaves the image alone
+        assert_eq!(Glare{ obstruction: 2., threshold: 0.5, ..Glare::default() }.apply(&img), img);
+        let vignette = Vignette{ fov: 60., optical: 1. };
+        assert!((vignette.factor(0., 0., 0.75) - 1.).abs() < 1e-12);
+        // Cosine fourth law at the edge of the field of view
+        assert!((Vignette{ fov: 60., optical: 0. }.factor(1., 0., 0.75) - 0.75f64.powi(2)).abs() < 1e-12);
+        let out = PostEffects{ vignette: Some(vignette), ..PostEffects::default() }.apply(&img);
+        assert!(out.get(0, 0, 0) < out.get(8, 8, 0) && out.get(8, 8, 0) < 0.5);
+    }
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use crate::aov::{Aov, AovSample};
+    use crate::checkpoint::{self, Progress, Progressive};
+    use crate::color::RGB;
+    use crate::film::Film;
+    use crate::filter::GaussianFilter;
+    use crate::sampler::{IndependentSampler, Pcg32};
+    #[test]
+    fn pcg_test_0() {
+        // Output of the reference pcg32-demo
+        let mut rng = Pcg32::new(42, 54);
+        let v: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+        assert_eq!(v, vec![0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e]);
+        let mut a = Pcg32::new(7, 3);
+        let mut b = a;
+        for _ in 0..1000 {
+            a.next_u32();
+        }
+        b.advance(1000);
+        assert_eq!(a, b);
+        let mut s = IndependentSampler::new(1);
+        s.start_pixel_sample(3, 4, 9);
+        let u = s.get_2d();
+        s.start_pixel_sample(5, 4, 9);
+        s.start_pixel_sample(3, 4, 9);
+        assert_eq!(s.get_2d(), u);
+    }
+    fn film() -> Film {
+        let mut film = Film::new((5, 4), Box::new(GaussianFilter::new((1.5, 1.5), 0.5)));
+        film.set_aovs(&[Aov::Depth]);
+        film.enable_variance();
+        film
+    }
+    // Stand-in for an integrator, a noisy image from the sampler
+    fn pass(film: &Film, index: u64) {
+        let mut sampler = IndependentSampler::new(11);
+        for y in 0..4 {
+            for x in 0..5 {
+                sampler.start_pixel_sample(x, y, index);
+                let (u, v) = sampler.get_2d();
+                let p = (x as f64 + u, y as f64 + v);
+                film.add_sample(p, RGB::new(sampler.get_1d(), u*v, 0.5), 1.);
+                film.add_aov_sample(p, &AovSample{ depth: sampler.get_1d(), ..AovSample::miss() });
+                film.add_splat(p, RGB::gray(0.01));
+            }
+        }
+    }
+    #[test]
+    fn resume_test_0() {
+        let path = std::env::temp_dir().join("resume_test_0.ckpt");
+        let _ = std::fs::remove_file(&path);
+        let uninterrupted = film();
+        Progressive::new(11, 16).run(&uninterrupted, pass).unwrap();
+        // Killed after 6 samples, then resumed by a new job
+        let first = film();
+        let mut job = Progressive::new(11, 6);
+        job.checkpoint = Some(path.clone());
+        job.run(&first, pass).unwrap();
+        let resumed = film();
+        job.samples = 16;
+        assert_eq!(job.run(&resumed, pass).unwrap(), 6);
+        let bits = |f: &Film| checkpoint::encode(f, Progress{ seed: 11, samples: 16 });
+        assert_eq!(bits(&resumed), bits(&uninterrupted));
+        assert_eq!(checkpoint::load(&path, &film()).unwrap(), Progress{ seed: 11, samples: 16 });
+        // Another seed or film setup is refused
+        assert!(Progressive{ seed: 12, ..job }.run(&film(), pass).is_err());
+        let other = Film::new((5, 4), Box::new(GaussianFilter::new((1.5, 1.5), 0.5)));
+        assert!(checkpoint::load(&path, &other).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod metadata_tests {
+    use crate::camera::{self, Camera, PerspectiveCamera, Shutter};
+    use crate::image::Image;
+    use crate::imageio::{self, ExrAttribute};
+    use crate::metadata::*;
+    use crate::transformation::Transform;
+    use crate::vector::Vec3d;
+    use std::time::Duration;
+    #[test]
+    fn metadata_test_0() {
+        let cam = PerspectiveCamera{ camera_to_world: vec![Transform::rotate_y(0.3), Transform::translate(Vec3d::new(1., 2., -3.))],
+                                     shutter: Shutter::instant(), fov_x: 1.2, fov_y: 0.9 };
+        let mut meta = RenderMetadata::new();
+        meta.scene_hash = Some(scene_hash(b"sphere 1"));
+        meta.samples = Some(64);
+        meta.render_time = Some(Duration::from_millis(1500));
+        meta.camera_to_world = Some(camera::compose(&cam.camera_to_world));
+        meta.projection = Some(cam.projection());
+        meta.command_line = "render scène.txt".to_string();
+        assert_eq!(scene_hash(b""), "cbf29ce484222325");
+        let img = Image::new(2, 2, &["R", "G", "B"]);
+        let png = imageio::encode_png_text(&img, 8, &meta.text()).unwrap();
+        let text = imageio::decode_png_text(&png).unwrap();
+        assert_eq!(text, meta.text());
+        assert!(text.contains(&("Render Time".to_string(), "1.500".to_string())));
+        assert!(imageio::encode_png_text(&img, 8, &[(" bad".to_string(), String::new())]).is_err());
+        let path = std::env::temp_dir().join("metadata_test_0.exr");
+        imageio::write_image_metadata(&path, &img, &meta).unwrap();
+        let attributes = imageio::decode_exr_attributes(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let get = |name: &str| attributes.iter().find(|a| a.0 == name).map(|a| a.1.clone());
+        assert_eq!(get("samples"), Some(ExrAttribute::Int(64)));
+        assert_eq!(get("commandLine"), Some(ExrAttribute::String("render scène.txt".to_string())));
+        // World to NDC of a visible point, row vector times matrix
+        let p = Vec3d::new(1.5, 2.2, 1.);
+        let film = cam.world_to_raster(p).unwrap();
+        let m = match get("worldToNDC") { Some(ExrAttribute::M44f(m)) => m, _ => panic!() };
+        let v: Vec<f64> = (0..4).map(|j| p.x*m[0][j] as f64 + p.y*m[1][j] as f64 + p.z*m[2][j] as f64 + m[3][j] as f64).collect();
+        assert!((v[0]/v[3] - (film.x + 1.)/2.).abs() < 1e-5);
+        assert!((v[1]/v[3] - (1. - film.y)/2.).abs() < 1e-5);
+        // The projection is kept without a camera placement
+        meta.camera_to_world = None;
+        let attributes = meta.exr_attributes();
+        assert!(attributes.iter().all(|a| a.0 != "worldToNDC" && a.0 != "worldToCamera"));
+        assert!(attributes.iter().any(|a| a.0 == "cameraToNDC"));
+    }
+}
+
+#[cfg(test)]
+mod spectrum_tests {
+    use crate::colorspace::{xyz_to_xy, RGBColorSpace};
+    use crate::spectrum::*;
+    #[test]
+    fn spectrum_test_0() {
+        let (_, y, _) = cie_xyz(555.);
+        assert_eq!(y, 1.);
+        let (x, y, z) = cie_xyz(447.5);
+        assert!((x - 0.343_02).abs() < 1e-5 && (y - 0.033_70).abs() < 1e-5 && (z - 1.780_36).abs() < 1e-5);
+        assert_eq!(cie_xyz(300.), (0., 0., 0.));
+        assert!((cie_y_integral() - 106.857).abs() < 1e-3);
+        let e = spectrum_to_xyz(&ConstantSpectrum{ c: 1. });
+        assert!((e.y - 1.).abs() < 1e-3);
+        let xy = xyz_to_xy(e);
+        assert!((xy.x - 1./3.).abs() < 2e-3 && (xy.y - 1./3.).abs() < 2e-3);
+        let mut sum = 0.;
+        let mut lambda = LAMBDA_MIN;
+        while lambda < LAMBDA_MAX {
+            sum += visible_wavelength_pdf(lambda + 0.5);
+            lambda += 1.;
+        }
+        assert!((sum - 1.).abs() < 1e-3);
+        let w = SampledWavelengths::sample_uniform(0.9, 400., 800.);
+        assert_eq!(w.lambda, [760., 460., 560., 660.]);
+        // Stratified estimates of a constant spectrum
+        let n = 256;
+        let (mut y, mut rgb) = (0., crate::color::RGB::black());
+        let srgb = RGBColorSpace::srgb();
+        for i in 0..n {
+            let w = SampledWavelengths::sample_visible((i as f64 + 0.5)/n as f64);
+            assert!(w.lambda.iter().all(|l| (LAMBDA_MIN..=LAMBDA_MAX).contains(l)));
+            let s = ConstantSpectrum{ c: 2. }.sample(&w);
+            y += s.y(&w)/n as f64;
+            rgb += s.to_rgb(&w, &srgb)/n as f64;
+        }
+        assert!((y - 2.).abs() < 0.01);
+        assert!((rgb - srgb.from_xyz(e*2.)).max_comp().abs() < 0.02);
+        let mut w = SampledWavelengths::sample_visible(0.3);
+        let pdf = w.pdf[0];
+        w.terminate_secondary();
+        assert!(w.secondary_terminated() && w.pdf[0] == pdf/4.);
+        let s = SampledSpectrum::new([1., 2., 3., 4.]);
+        assert_eq!((s*s/s).values, s.values);
+        assert_eq!(s.safe_div(&SampledSpectrum::new([1., 0., 1., 0.])).values, [1., 0., 3., 0.]);
+    }
+}
+
+#[cfg(test)]
+mod rgbspectrum_tests {
+    use crate::color::RGB;
+    use crate::colorspace::{xyz_to_xy, RGBColorSpace};
+    use crate::rgbspectrum::*;
+    use crate::spectrum::{self, Spectrum};
+    struct Lit<'a>(&'a dyn Spectrum, &'a dyn Spectrum);
+    impl<'a> Spectrum for Lit<'a> {
+        fn eval(&self, lambda: f64) -> f64 {
+            self.0.eval(lambda)*self.1.eval(lambda)
+        }
+    }
+    #[test]
+    fn uplift_test_0() {
+        let srgb = RGBColorSpace::srgb();
+        let d65 = spectrum::daylight(srgb.white);
+        let xy = xyz_to_xy(spectrum::spectrum_to_xyz(&d65));
+        assert!((xy.x - 0.3127).abs() < 3e-3 && (xy.y - 0.3290).abs() < 3e-3);
+        let table = RGBToSpectrumTable::decode(&RGBToSpectrumTable::compute(&srgb, 8).encode()).unwrap();
+        assert_eq!(table.res, 8);
+        // Reflectances seen under the table's illuminant keep their color, up
+        // to interpolation in this coarse table
+        for &c in &[RGB::new(0.8, 0.3, 0.1), RGB::new(0.1, 0.5, 0.4), RGB::new(0.2, 0.2, 0.9), RGB::gray(0.5), RGB::new(0.6, 0.9, 0.7)] {
+            let albedo = RGBAlbedoSpectrum::new(&table, c);
+            let rgb = srgb.from_xyz(spectrum::spectrum_to_xyz(&Lit(&albedo, &table.illuminant)));
+            assert!((rgb - c).map(f64::abs).max_comp() < 0.03, "{:?} {:?}", c, rgb);
+            let mut l = spectrum::LAMBDA_MIN;
+            while l <= spectrum::LAMBDA_MAX {
+                let v = albedo.eval(l);
+                assert!((0. ..=1.).contains(&v));
+                l += 10.;
+            }
+        }
+        // White lights are exact, colored ones scale the interpolation error
+        for &(c, tolerance) in &[(RGB::gray(2.), 1e-2), (RGB::new(3., 2.4, 1.8), 0.1)] {
+            let rgb = srgb.from_xyz(spectrum::spectrum_to_xyz(&RGBIlluminantSpectrum::new(&table, c)));
+            assert!((rgb - c).map(f64::abs).max_comp() < tolerance, "{:?}", rgb);
+        }
+        assert_eq!(RGBUnboundedSpectrum::new(&table, RGB::black()).eval(500.), 0.);
+        assert!(RGBToSpectrumTable::decode(b"RGB2SPEC").is_err());
+        // A resolution whose table size overflows
+        let mut data = b"RGB2SPEC".to_vec();
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&0x4000_0000u32.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        assert_eq!(RGBToSpectrumTable::decode(&data).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+    #[test]
+    fn uplift_test_1() {
+        for name in &["srgb", "rec2020", "dci-p3", "aces-cg"] {
+            let space = RGBColorSpace::named(name).unwrap();
+            let table = RGBToSpectrumTable::for_space(&space).unwrap();
+            assert!(std::ptr::eq(table, RGBToSpectrumTable::named(name).unwrap()));
+            assert_eq!((table.res, table.white), (32, space.white));
+            for &c in &[RGB::new(0.8, 0.3, 0.1), RGB::new(0.1, 0.5, 0.4), RGB::new(0.2, 0.2, 0.9), RGB::new(0.6, 0.9, 0.7)] {
+                let albedo = RGBAlbedoSpectrum::new(table, c);
+                let rgb = space.from_xyz(spectrum::spectrum_to_xyz(&Lit(&albedo, &table.illuminant)));
+                assert!((rgb - c).map(f64::abs).max_comp() < 0.01, "{} {:?} {:?}", name, c, rgb);
+            }
+        }
+        assert!(RGBToSpectrumTable::named("adobe").is_none());
+        let srgb = RGBColorSpace::srgb();
+        let other = RGBColorSpace::new(srgb.r, srgb.g, srgb.b, crate::vector::Point2::new(0.3457, 0.3585));
+        assert!(RGBToSpectrumTable::for_space(&other).is_none());
+    }
+}
+
+#[cfg(test)]
+mod spectra_tests {
+    use crate::colorspace::xyz_to_xy;
+    use crate::spectra::*;
+    use crate::spectrum::{spectrum_to_xyz, BlackbodySpectrum, Spectrum};
+    #[test]
+    fn named_test_0() {
+        // Catalog indices at the helium d line
+        for &(glass, nd) in &[(GLASS_BK7, 1.5168), (GLASS_BAF10, 1.6700), (GLASS_SF11, 1.7847), (GLASS_FUSED_SILICA, 1.4585)] {
+            assert!((glass.eval(587.56) - nd).abs() < 2e-4, "{}", glass.eval(587.56));
+            assert!(glass.eval(450.) > glass.eval(650.));
+        }
+        for &(name, x, y) in &[("stdillum-A", 0.44757, 0.40745), ("stdillum-D50", 0.34567, 0.35850), ("stdillum-D65", 0.31271, 0.32902)] {
+            let xyz = spectrum_to_xyz(named(name).unwrap().as_ref());
+            let xy = xyz_to_xy(xyz);
+            assert!((xyz.y - 1.).abs() < 1e-9 && (xy.x - x).abs() < 3e-3 && (xy.y - y).abs() < 3e-3, "{} {:?}", name, xy);
+        }
+        let b = BlackbodySpectrum{ t: 5800., normalized: false };
+        // Sun-like radiance peaks near 500 nm at about 27 kW/(sr m^2 nm)
+        assert!(b.eval(500.) > b.eval(400.) && b.eval(500.) > b.eval(600.));
+        assert!((b.eval(500.) - 26_880.).abs() < 20., "{}", b.eval(500.));
+        let lamp = named("blackbody-2700").unwrap();
+        assert!((lamp.eval(2.897_771_955e6/2700.) - 1.).abs() < 1e-12);
+        assert!(lamp.eval(450.) < lamp.eval(650.));
+        // Published chromaticities of the fluorescent illuminants
+        let f_xy = [(0.3131, 0.3371), (0.3721, 0.3751), (0.4091, 0.3941), (0.4402, 0.4031), (0.3138, 0.3452), (0.3779, 0.3882),
+                    (0.3129, 0.3292), (0.3458, 0.3586), (0.3741, 0.3727), (0.3458, 0.3588), (0.3805, 0.3769), (0.4370, 0.4042)];
+        for (i, &(x, y)) in f_xy.iter().enumerate() {
+            let xyz = spectrum_to_xyz(named(&format!("stdillum-F{}", i + 1)).unwrap().as_ref());
+            let xy = xyz_to_xy(xyz);
+            assert!((xyz.y - 1.).abs() < 1e-9 && (xy.x - x).abs() < 5e-4 && (xy.y - y).abs() < 5e-4, "F{} {:?}", i + 1, xy);
+        }
+        assert!(illuminant_f(0).is_none() && illuminant_f(13).is_none() && named("stdillum-F13").is_none());
+        // Normal incidence reflectance: gold is yellow, silver and aluminium
+        // close to flat
+        let reflectance = |name: &str, lambda: f64| {
+            let ior = metal(name).unwrap();
+            let (n, k) = (ior.eta.eval(lambda), ior.k.eval(lambda));
+            ((n - 1.)*(n - 1.) + k*k)/((n + 1.)*(n + 1.) + k*k)
+        };
+        assert!(reflectance("Au", 650.) > 0.9 && reflectance("Au", 450.) < 0.45);
+        assert!(reflectance("Cu", 650.) > 0.9 && reflectance("Cu", 450.) < 0.6);
+        for &name in &["Ag", "Al"] {
+            assert!((reflectance(name, 450.) - reflectance(name, 650.)).abs() < 0.08, "{}", name);
+        }
+        assert!((named("metal-Cu-eta").unwrap().eval(1239.84/1.9) - 0.214).abs() < 1e-9);
+        assert_eq!(named("metal-Al-k").unwrap().eval(830.), 8.45);
+        assert!(named("blackbody-x").is_none() && named("metal-Pb-eta").is_none());
+        let ior = parse_ior_csv("wl,n\n0.4,1.5\n0.6,1.4\n\nwl,k\n0.4,2\n0.6,3\n").unwrap();
+        assert!((ior.eta.eval(500.) - 1.45).abs() < 1e-12 && (ior.k.eval(500.) - 2.5).abs() < 1e-12);
+        assert!(parse_ior_csv("0.4,1.5").is_err() && parse_ior_csv("wl,n\n0.6,1\n0.4,1").is_err());
+    }
+}
+
+#[cfg(test)]
+mod polarization_tests {
+    use crate::checkpoint::{self, Progress};
+    use crate::color::RGB;
+    use crate::colorspace::RGBColorSpace;
+    use crate::complex::Complex;
+    use crate::film::Film;
+    use crate::filter::BoxFilter;
+    use crate::polarization::*;
+    use crate::spectra;
+    use crate::spectrum::{SampledSpectrum, SampledWavelengths, N_SPECTRUM_SAMPLES};
+    use crate::transformation::Matrix4;
+    use crate::vector::Vec3d;
+    use std::f64::consts::PI;
+    #[test]
+    fn fresnel_test_0() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+        let c = SampledSpectrum::constant;
+        // Unpolarized light reflects fully s polarized at Brewster's angle
+        let brewster = 1.5f64.atan().cos();
+        let s = Stokes::unpolarized(c(1.)).apply(&fresnel_reflection(brewster, Complex::real(1.5)));
+        assert!(close(s.dop()[0], 1.) && close(s.aop()[0], 0.) && s.intensity()[0] > 0.);
+        let s = Stokes::unpolarized(c(1.)).apply(&fresnel_reflection(1., Complex::real(1.5)));
+        assert!(close(s.intensity()[0], 0.04) && close(s.dop()[0], 0.));
+        // Conductors at normal incidence, and the phase shift turning linear
+        // polarization elliptical away from it
+        let (n, k) = (0.18299, 3.4242);
+        let r = fresnel_reflection(1., Complex::new(n, k))[(0, 0)];
+        assert!(close(r, ((n - 1.)*(n - 1.) + k*k)/((n + 1.)*(n + 1.) + k*k)));
+        let s = Stokes::linear(c(1.), PI/4.).apply(&fresnel_reflection(0.5, Complex::new(n, k)));
+        assert!(s.s[3][0].abs() > 0.1 && close(s.dop()[0], 1.));
+        // Energy is conserved for both linear polarizations
+        for &cos_i in &[1., 0.8, 0.3, 0.05] {
+            for &angle in &[0., PI/2.] {
+                let light = Stokes::linear(c(1.), angle);
+                let rt = light.apply(&fresnel_reflection(cos_i, Complex::real(1.33))).intensity() + light.apply(&fresnel_transmission(cos_i, 1.33)).intensity();
+                assert!(rt.values.iter().all(|&v| close(v, 1.)), "{} {:?}", cos_i, rt);
+            }
+        }
+        assert!(close(Stokes::unpolarized(c(1.)).apply(&fresnel_reflection(0.3, Complex::real(1./1.5))).intensity()[0], 1.));
+        assert_eq!(fresnel_transmission(0.3, 1./1.5)[(0, 0)], 0.);
+    }
+    #[test]
+    fn fresnel_test_1() {
+        // Gold seen at an angle in white light: every wavelength gets its own
+        // Fresnel matrix, and the reflection keeps its color per channel
+        let gold = spectra::metal("Au").unwrap();
+        let srgb = RGBColorSpace::srgb();
+        let (mut reflected, n) = (RGBStokes::zero(), 64);
+        for i in 0..n {
+            let w = SampledWavelengths::sample_visible((i as f64 + 0.5)/n as f64);
+            let m: [Matrix4; N_SPECTRUM_SAMPLES] = std::array::from_fn(|j| {
+                fresnel_reflection(0.4, Complex::new(gold.eta.eval(w.lambda[j]), gold.k.eval(w.lambda[j])))
+            });
+            let s = Stokes::unpolarized(SampledSpectrum::constant(1.)).apply_spectral(&m);
+            assert!(s.intensity()[0] != s.intensity()[1] || w.lambda[0] == w.lambda[1]);
+            reflected += s.to_rgb(&w, &srgb)*(1./n as f64);
+        }
+        let (i, dop) = (reflected.intensity(), reflected.dop());
+        assert!(i.r > i.g && i.g > i.b && i.b > 0.2, "{:?}", i);
+        // Where gold absorbs it polarizes more
+        assert!(dop.b > dop.g && dop.g > dop.r && dop.r > 0., "{:?}", dop);
+        assert!(reflected.aop().max_comp().abs() < 1e-9);
+        // Conversion is linear, so unpolarized light stays unpolarized
+        let w = SampledWavelengths::sample_visible(0.3);
+        let s = Stokes::unpolarized(SampledSpectrum::new([1., 2., 3., 4.])).to_rgb(&w, &srgb);
+        assert_eq!(s.dop(), RGB::black());
+        assert_eq!(s.s[0], SampledSpectrum::new([1., 2., 3., 4.]).to_rgb(&w, &srgb));
+    }
+    #[test]
+    fn frame_test_0() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+        let c = SampledSpectrum::constant;
+        // Malus's law
+        for &angle in &[0., 0.4, 1.1, PI/2.] {
+            let s = Stokes::linear(c(2.), 0.2).apply(&linear_polarizer(angle));
+            assert!(close(s.intensity()[0], 2.*(angle - 0.2).cos().powi(2)));
+        }
+        // Rotating the reference axis changes the angle but not the light
+        let (x, dir) = (Vec3d::new(1., 0., 0.), Vec3d::new(0., 0., 1.));
+        let new_x = Vec3d::new(0.6f64.cos(), 0.6f64.sin(), 0.);
+        assert!(close(frame_angle(x, new_x, dir), 0.6));
+        let s = Stokes::linear(c(1.), 0.7).rotate_frame(x, new_x, dir);
+        assert!(close(s.aop()[0], 0.1) && close(s.dop()[0], 1.));
+        let back = s.rotate_frame(new_x, x, dir);
+        assert!(close(back.aop()[0], 0.7));
+        let n = Vec3d::new(0., 1., 0.);
+        assert!(close(Vec3d::dot(s_axis(Vec3d::new(1., -1., 0.).norm(), n), Vec3d::new(0., 0., 1.)).abs(), 1.));
+        assert!(close(Vec3d::dot(s_axis(Vec3d::new(0., -1., 0.), n), n), 0.));
+    }
+    #[test]
+    fn film_test_0() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+        let film = || {
+            let mut film = Film::new((2, 1), Box::new(BoxFilter::new((0.5, 0.5))));
+            film.enable_polarization();
+            film
+        };
+        let f = film();
+        assert!(f.is_polarized());
+        f.add_stokes_sample((0.5, 0.5), RGBStokes::linear(RGB::gray(1.), 0.3), 1.);
+        f.add_stokes_sample((0.5, 0.5), RGBStokes::unpolarized(RGB::gray(1.)), 1.);
+        let mut tile = f.tile(1, 0, 2, 1);
+        // Red polarized and blue unpolarized, like behind a color filter array
+        let red = RGBStokes::linear(RGB::new(4., 0., 0.), -0.5);
+        tile.add_stokes_sample((1.5, 0.5), red + RGBStokes::unpolarized(RGB::new(0., 0., 2.)), 2.);
+        f.merge_tile(tile);
+        let p = f.resolve_polarization();
+        let names: Vec<&str> = p.channels.iter().map(String::as_str).collect();
+        assert_eq!(names, ["DoP.R", "DoP.G", "DoP.B", "AoP.R", "AoP.G", "AoP.B"]);
+        assert!((0..3).all(|c| close(p.data[c], 0.5) && close(p.data[3 + c], 0.3)));
+        assert!(close(p.data[6], 1.) && close(p.data[7], 0.) && close(p.data[8], 0.) && close(p.data[9], -0.5));
+        let stokes = f.resolve_stokes();
+        assert_eq!((stokes.channels.len(), stokes.channels[3].as_str()), (12, "S1.R"));
+        assert!(close(stokes.data[12], 4.) && close(stokes.data[14], 2.));
+        // Stokes data survives checkpoints, which check the setting
+        let restored = film();
+        let bits = checkpoint::encode(&f, Progress{ seed: 1, samples: 2 });
+        checkpoint::decode(&bits, &restored).unwrap();
+        assert_eq!(checkpoint::encode(&restored, Progress{ seed: 1, samples: 2 }), bits);
+        assert!(checkpoint::decode(&bits, &Film::new((2, 1), Box::new(BoxFilter::new((0.5, 0.5))))).is_err());
+    }
+}
@@ -0,0 +1,521 @@
+use std::io;
+use crate::color::RGB;
+
+// Light path expressions in the OpenShadingLanguage syntax. A path is the
+// sequence of events from the camera to the light, and every event has a
+// type, a scattering kind and an optional custom label:
+//
+//   C camera, R reflection, T transmission, V volume, L light,
+//   O emissive object, B background
+//   D diffuse, G glossy, S singular (specular)
+//
+// <RD> is a diffuse reflection and '.' matches anything, in or out of <>.
+// A single scattering letter like D matches any type, a quoted 'name'
+// matches the label. [...] and [^...] are sets of events, and (), |, *, +,
+// ? and {n,m} work as usual. C<RD>L is direct diffuse, C<RS>.*L is
+// everything seen through a specular reflection.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventType
+{
+    Camera,
+    Reflect,
+    Transmit,
+    Volume,
+    Light,
+    Emission,
+    Background,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scatter
+{
+    None,
+    Diffuse,
+    Glossy,
+    Singular,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LpeEvent<'a>
+{
+    pub kind: EventType,
+    pub scatter: Scatter,
+    pub label: Option<&'a str>,
+}
+
+impl<'a> LpeEvent<'a>
+{
+    pub fn new(kind: EventType, scatter: Scatter) -> LpeEvent<'a>
+    {
+        LpeEvent{ kind, scatter, label: None }
+    }
+    pub fn camera() -> LpeEvent<'a>
+    {
+        LpeEvent::new(EventType::Camera, Scatter::None)
+    }
+    pub fn light() -> LpeEvent<'a>
+    {
+        LpeEvent::new(EventType::Light, Scatter::None)
+    }
+}
+
+// One event of an expression, None fields match anything
+#[derive(Clone, Debug, PartialEq)]
+struct EventPattern
+{
+    kind: Option<EventType>,
+    scatter: Option<Scatter>,
+    label: Option<String>,
+}
+
+impl EventPattern
+{
+    fn any() -> EventPattern
+    {
+        EventPattern{ kind: None, scatter: None, label: None }
+    }
+    fn matches(&self, e: &LpeEvent) -> bool
+    {
+        self.kind.map_or(true, |k| k == e.kind) && self.scatter.map_or(true, |s| s == e.scatter)
+            && self.label.as_ref().map_or(true, |l| e.label == Some(l.as_str()))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Atom
+{
+    patterns: Vec<EventPattern>,
+    negate: bool,
+}
+
+impl Atom
+{
+    fn matches(&self, e: &LpeEvent) -> bool
+    {
+        self.patterns.iter().any(|p| p.matches(e)) != self.negate
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node
+{
+    Atom(Atom),
+    Seq(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+fn error(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_string())
+}
+
+fn type_of(c: char) -> Option<EventType>
+{
+    match c
+    {
+        'C' => Some(EventType::Camera),
+        'R' => Some(EventType::Reflect),
+        'T' => Some(EventType::Transmit),
+        'V' => Some(EventType::Volume),
+        'L' => Some(EventType::Light),
+        'O' => Some(EventType::Emission),
+        'B' => Some(EventType::Background),
+        _ => None,
+    }
+}
+
+fn scatter_of(c: char) -> Option<Scatter>
+{
+    match c
+    {
+        'D' => Some(Scatter::Diffuse),
+        'G' => Some(Scatter::Glossy),
+        'S' => Some(Scatter::Singular),
+        _ => None,
+    }
+}
+
+struct Parser
+{
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser
+{
+    fn peek(&mut self) -> Option<char>
+    {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace()
+        {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).cloned()
+    }
+    fn next(&mut self) -> Option<char>
+    {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+    fn expect(&mut self, c: char) -> io::Result<()>
+    {
+        if self.next() != Some(c)
+        {
+            return Err(error(&format!("expected '{}' in light path expression", c)));
+        }
+        Ok(())
+    }
+    fn alt(&mut self) -> io::Result<Node>
+    {
+        let mut options = vec![self.seq()?];
+        while self.peek() == Some('|')
+        {
+            self.pos += 1;
+            options.push(self.seq()?);
+        }
+        Ok(if options.len() == 1 { options.pop().unwrap() } else { Node::Alt(options) })
+    }
+    fn seq(&mut self) -> io::Result<Node>
+    {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek()
+        {
+            if c == '|' || c == ')'
+            {
+                break;
+            }
+            nodes.push(self.repeat()?);
+        }
+        Ok(Node::Seq(nodes))
+    }
+    fn number(&mut self) -> Option<usize>
+    {
+        let start = self.pos;
+        while self.pos < self.chars.len() && self.chars[self.pos].is_ascii_digit()
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+    fn repeat(&mut self) -> io::Result<Node>
+    {
+        let mut node = self.primary()?;
+        loop
+        {
+            let (min, max) = match self.peek()
+            {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') =>
+                {
+                    self.pos += 1;
+                    self.peek();
+                    let min = self.number().ok_or_else(|| error("expected a count in light path expression"))?;
+                    let max = if self.peek() == Some(',')
+                    {
+                        self.pos += 1;
+                        self.peek();
+                        self.number()
+                    }
+                    else
+                    {
+                        Some(min)
+                    };
+                    if self.peek() != Some('}') || max.is_some_and(|m| m < min)
+                    {
+                        return Err(error("invalid repeat count in light path expression"));
+                    }
+                    (min, max)
+                }
+                _ => return Ok(node),
+            };
+            self.pos += 1;
+            node = Node::Repeat(Box::new(node), min, max);
+        }
+    }
+    fn label(&mut self) -> io::Result<String>
+    {
+        let mut label = String::new();
+        self.pos += 1;
+        loop
+        {
+            match self.chars.get(self.pos)
+            {
+                Some('\'') => break,
+                Some(&c) => label.push(c),
+                None => return Err(error("unterminated label in light path expression")),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Ok(label)
+    }
+    // Single event outside of a set
+    fn event(&mut self) -> io::Result<EventPattern>
+    {
+        match self.peek()
+        {
+            Some('<') =>
+            {
+                self.pos += 1;
+                let mut p = EventPattern::any();
+                let c = self.next().ok_or_else(|| error("unterminated event in light path expression"))?;
+                if c != '.'
+                {
+                    p.kind = Some(type_of(c).ok_or_else(|| error(&format!("unknown event type '{}'", c)))?);
+                }
+                if self.peek() != Some('>')
+                {
+                    let c = self.next().ok_or_else(|| error("unterminated event in light path expression"))?;
+                    if c != '.'
+                    {
+                        p.scatter = Some(scatter_of(c).ok_or_else(|| error(&format!("unknown scattering '{}'", c)))?);
+                    }
+                }
+                match self.peek()
+                {
+                    Some('\'') => p.label = Some(self.label()?),
+                    Some('.') => self.pos += 1,
+                    _ => {}
+                }
+                self.expect('>')?;
+                Ok(p)
+            }
+            Some('\'') => Ok(EventPattern{ label: Some(self.label()?), ..EventPattern::any() }),
+            Some('.') =>
+            {
+                self.pos += 1;
+                Ok(EventPattern::any())
+            }
+            Some(c) =>
+            {
+                self.pos += 1;
+                if let Some(kind) = type_of(c)
+                {
+                    Ok(EventPattern{ kind: Some(kind), ..EventPattern::any() })
+                }
+                else if let Some(scatter) = scatter_of(c)
+                {
+                    Ok(EventPattern{ scatter: Some(scatter), ..EventPattern::any() })
+                }
+                else
+                {
+                    Err(error(&format!("unexpected '{}' in light path expression", c)))
+                }
+            }
+            None => Err(error("unexpected end of light path expression")),
+        }
+    }
+    fn primary(&mut self) -> io::Result<Node>
+    {
+        match self.peek()
+        {
+            Some('(') =>
+            {
+                self.pos += 1;
+                let node = self.alt()?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            Some('[') =>
+            {
+                self.pos += 1;
+                let negate = self.peek() == Some('^');
+                if negate
+                {
+                    self.pos += 1;
+                }
+                let mut patterns = Vec::new();
+                while self.peek() != Some(']')
+                {
+                    patterns.push(self.event()?);
+                }
+                self.pos += 1;
+                Ok(Node::Atom(Atom{ patterns, negate }))
+            }
+            _ => Ok(Node::Atom(Atom{ patterns: vec![self.event()?], negate: false })),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum NfaState
+{
+    Atom(Atom, usize),
+    Split(Vec<usize>),
+    Accept,
+}
+
+// Expression compiled to a nondeterministic automaton
+#[derive(Clone, Debug)]
+pub struct Lpe
+{
+    states: Vec<NfaState>,
+    start: usize,
+}
+
+// Set of automaton states a path prefix can be in
+#[derive(Clone, Debug, PartialEq)]
+pub struct LpeState
+{
+    active: Vec<usize>,
+}
+
+impl Lpe
+{
+    pub fn parse(expr: &str) -> io::Result<Lpe>
+    {
+        let mut parser = Parser{ chars: expr.chars().collect(), pos: 0 };
+        let node = parser.alt()?;
+        if parser.peek().is_some()
+        {
+            return Err(error("unbalanced ')' in light path expression"));
+        }
+        let mut lpe = Lpe{ states: vec![NfaState::Accept], start: 0 };
+        lpe.start = lpe.compile(&node, 0);
+        Ok(lpe)
+    }
+    // Builds the states for node, continuing at next, and returns the
+    // first one
+    fn compile(&mut self, node: &Node, next: usize) -> usize
+    {
+        match node
+        {
+            Node::Atom(a) =>
+            {
+                self.states.push(NfaState::Atom(a.clone(), next));
+                self.states.len() - 1
+            }
+            Node::Seq(nodes) => nodes.iter().rev().fold(next, |next, n| self.compile(n, next)),
+            Node::Alt(options) =>
+            {
+                let starts = options.iter().map(|n| self.compile(n, next)).collect();
+                self.states.push(NfaState::Split(starts));
+                self.states.len() - 1
+            }
+            Node::Repeat(n, min, max) =>
+            {
+                let mut next = next;
+                match max
+                {
+                    None =>
+                    {
+                        self.states.push(NfaState::Split(Vec::new()));
+                        let s = self.states.len() - 1;
+                        let body = self.compile(n, s);
+                        self.states[s] = NfaState::Split(vec![body, next]);
+                        next = s;
+                    }
+                    Some(max) =>
+                    {
+                        for _ in *min..*max
+                        {
+                            let body = self.compile(n, next);
+                            self.states.push(NfaState::Split(vec![body, next]));
+                            next = self.states.len() - 1;
+                        }
+                    }
+                }
+                for _ in 0..*min
+                {
+                    next = self.compile(n, next);
+                }
+                next
+            }
+        }
+    }
+    fn closure(&self, states: &[usize]) -> LpeState
+    {
+        let mut seen = vec![false; self.states.len()];
+        let mut stack = states.to_vec();
+        let mut active = Vec::new();
+        while let Some(s) = stack.pop()
+        {
+            if seen[s]
+            {
+                continue;
+            }
+            seen[s] = true;
+            match &self.states[s]
+            {
+                NfaState::Split(next) => stack.extend(next),
+                _ => active.push(s),
+            }
+        }
+        active.sort_unstable();
+        LpeState{ active }
+    }
+    // State before the camera event
+    pub fn start(&self) -> LpeState
+    {
+        self.closure(&[self.start])
+    }
+    pub fn advance(&self, state: &LpeState, e: &LpeEvent) -> LpeState
+    {
+        let next: Vec<usize> = state.active.iter().filter_map(|&s| match &self.states[s]
+        {
+            NfaState::Atom(a, next) if a.matches(e) => Some(*next),
+            _ => None,
+        }).collect();
+        self.closure(&next)
+    }
+    pub fn accepts(&self, state: &LpeState) -> bool
+    {
+        state.active.iter().any(|&s| matches!(self.states[s], NfaState::Accept))
+    }
+    // No continuation of the path can match anymore
+    pub fn is_dead(&self, state: &LpeState) -> bool
+    {
+        state.active.is_empty()
+    }
+    pub fn matches(&self, path: &[LpeEvent]) -> bool
+    {
+        let state = path.iter().fold(self.start(), |s, e| self.advance(&s, e));
+        self.accepts(&state)
+    }
+}
+
+// Where one camera path is in each pass's expression and what it has
+// contributed to them. Integrators call scatter for every vertex and add
+// for every light contribution, with the events leading to the light.
+pub struct LpeTracker<'a>
+{
+    lpes: &'a [(String, Lpe)],
+    states: Vec<LpeState>,
+    pub radiance: Vec<RGB>,
+}
+
+impl<'a> LpeTracker<'a>
+{
+    // Starts a path at the camera
+    pub fn new(lpes: &'a [(String, Lpe)]) -> LpeTracker<'a>
+    {
+        let camera = LpeEvent::camera();
+        let states = lpes.iter().map(|(_, l)| l.advance(&l.start(), &camera)).collect();
+        LpeTracker{ lpes, states, radiance: vec![RGB::black(); lpes.len()] }
+    }
+    pub fn scatter(&mut self, e: &LpeEvent)
+    {
+        for ((_, lpe), state) in self.lpes.iter().zip(self.states.iter_mut())
+        {
+            if !lpe.is_dead(state)
+            {
+                *state = lpe.advance(state, e);
+            }
+        }
+    }
+    pub fn add(&mut self, events: &[LpeEvent], l: RGB)
+    {
+        for (i, (_, lpe)) in self.lpes.iter().enumerate()
+        {
+            let state = events.iter().fold(self.states[i].clone(), |s, e| lpe.advance(&s, e));
+            if lpe.accepts(&state)
+            {
+                self.radiance[i] += l;
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::env;
+use std::time::Duration;
+use crate::imageio::ExrAttribute;
+use crate::transformation::Matrix4;
+
+// What produced an image, embedded in the files it is written to. Matrices
+// follow the column vector convention of Matrix4: camera_to_world takes
+// camera space points to world space and projection takes them to film
+// coordinates after the homogeneous divide.
+#[derive(Clone, Debug)]
+pub struct RenderMetadata
+{
+    pub software: String,
+    pub scene_hash: Option<String>,
+    pub samples: Option<u64>,
+    pub render_time: Option<Duration>,
+    pub camera_to_world: Option<Matrix4>,
+    pub projection: Option<Matrix4>,
+    pub command_line: String,
+}
+
+impl Default for RenderMetadata
+{
+    // This crate's version and the command line of the running process
+    fn default() -> RenderMetadata
+    {
+        RenderMetadata{ software: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")), scene_hash: None, samples: None, render_time: None,
+                        camera_to_world: None, projection: None, command_line: env::args().collect::<Vec<String>>().join(" ") }
+    }
+}
+
+// 64 bit FNV-1a of the scene description, in hex
+pub fn scene_hash(data: &[u8]) -> String
+{
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in data
+    {
+        h = (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", h)
+}
+
+fn matrix_text(m: &Matrix4) -> String
+{
+    m.mat.iter().flatten().map(|v| v.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+// OpenEXR matrices transform row vectors
+fn m44f(m: &Matrix4) -> ExrAttribute
+{
+    let mut t = [[0.; 4]; 4];
+    for (i, row) in t.iter_mut().enumerate()
+    {
+        for (j, v) in row.iter_mut().enumerate()
+        {
+            *v = m[(j, i)] as f32;
+        }
+    }
+    ExrAttribute::M44f(t)
+}
+
+impl RenderMetadata
+{
+    pub fn new() -> RenderMetadata
+    {
+        RenderMetadata::default()
+    }
+    // Keywords and values for PNG text chunks, matrices as 16 numbers row by
+    // row and the render time in seconds
+    pub fn text(&self) -> Vec<(String, String)>
+    {
+        let mut text = vec![("Software".to_string(), self.software.clone())];
+        let mut push = |k: &str, v: Option<String>| text.extend(v.map(|v| (k.to_string(), v)));
+        push("Scene Hash", self.scene_hash.clone());
+        push("Samples", self.samples.map(|s| s.to_string()));
+        push("Render Time", self.render_time.map(|t| format!("{:.3}", t.as_secs_f64())));
+        push("Camera To World", self.camera_to_world.as_ref().map(matrix_text));
+        push("Projection", self.projection.as_ref().map(matrix_text));
+        push("Command Line", Some(self.command_line.clone()));
+        text
+    }
+    // Header attributes for OpenEXR. The camera goes into the standard
+    // worldToCamera and worldToNDC attributes, NDC being [0, 1] on both axes
+    // from the top left corner of the film. A projection without a camera
+    // placement is written as cameraToNDC.
+    pub fn exr_attributes(&self) -> Vec<(String, ExrAttribute)>
+    {
+        let mut attributes = vec![("software".to_string(), ExrAttribute::String(self.software.clone()))];
+        let mut push = |k: &str, v: Option<ExrAttribute>| attributes.extend(v.map(|v| (k.to_string(), v)));
+        push("sceneHash", self.scene_hash.clone().map(ExrAttribute::String));
+        push("samples", self.samples.map(|s| ExrAttribute::Int(s.min(i32::MAX as u64) as i32)));
+        push("renderTime", self.render_time.map(|t| ExrAttribute::Float(t.as_secs_f32())));
+        let film_to_ndc = Matrix4::new(&[[0.5, 0., 0., 0.5], [0., -0.5, 0., 0.5], [0., 0., 1., 0.], [0., 0., 0., 1.]]);
+        let camera_to_ndc = self.projection.as_ref().map(|p| Matrix4::mul(&film_to_ndc, p));
+        match &self.camera_to_world
+        {
+            Some(c2w) =>
+            {
+                let w2c = c2w.inv();
+                push("worldToCamera", Some(m44f(&w2c)));
+                push("worldToNDC", camera_to_ndc.map(|ndc| m44f(&Matrix4::mul(&ndc, &w2c))));
+            }
+            None => push("cameraToNDC", camera_to_ndc.as_ref().map(m44f)),
+        }
+        push("commandLine", Some(ExrAttribute::String(self.command_line.clone())));
+        attributes
+    }
+}
@@ -0,0 +1,331 @@
+use std::ops;
+use crate::color::RGB;
+use crate::colorspace::RGBColorSpace;
+use crate::complex::Complex;
+use crate::spectrum::{SampledSpectrum, SampledWavelengths, N_SPECTRUM_SAMPLES};
+use crate::transformation::Matrix4;
+use crate::vector::Vec3d;
+
+fn mueller(m: &Matrix4, s: [f64; 4]) -> [f64; 4]
+{
+    let mut out = [0.; 4];
+    for (i, v) in out.iter_mut().enumerate()
+    {
+        *v = (0..4).map(|j| m[(i, j)]*s[j]).sum();
+    }
+    out
+}
+
+// Degree of polarization in [0, 1], zero for no light
+fn dop(s: [f64; 4]) -> f64
+{
+    if s[0] <= 0.
+    {
+        return 0.;
+    }
+    ((s[1]*s[1] + s[2]*s[2] + s[3]*s[3]).sqrt()/s[0]).min(1.)
+}
+
+fn dolp(s: [f64; 4]) -> f64
+{
+    if s[0] <= 0.
+    {
+        return 0.;
+    }
+    (s[1].hypot(s[2])/s[0]).min(1.)
+}
+
+// Angle of linear polarization from the x axis, in (-pi/2, pi/2]
+fn aop(s: [f64; 4]) -> f64
+{
+    0.5*s[2].atan2(s[1])
+}
+
+// Stokes vector of light travelling along some direction, relative to a
+// reference x axis perpendicular to it with y = dir x x. s1 is linear
+// polarization along x minus along y, s2 at +45 degrees minus at -45 and
+// s3 right minus left circular. Every component holds the sampled
+// wavelengths of the path, as Mueller matrices depend on them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stokes
+{
+    pub s: [SampledSpectrum; 4],
+}
+
+impl Stokes
+{
+    pub fn new(s0: SampledSpectrum, s1: SampledSpectrum, s2: SampledSpectrum, s3: SampledSpectrum) -> Stokes
+    {
+        Stokes{ s: [s0, s1, s2, s3] }
+    }
+    pub fn zero() -> Stokes
+    {
+        Stokes{ s: [SampledSpectrum::zero(); 4] }
+    }
+    pub fn unpolarized(intensity: SampledSpectrum) -> Stokes
+    {
+        Stokes::new(intensity, SampledSpectrum::zero(), SampledSpectrum::zero(), SampledSpectrum::zero())
+    }
+    // Fully linearly polarized at angle radians from the x axis
+    pub fn linear(intensity: SampledSpectrum, angle: f64) -> Stokes
+    {
+        Stokes::new(intensity, intensity*(2.*angle).cos(), intensity*(2.*angle).sin(), SampledSpectrum::zero())
+    }
+    pub fn intensity(&self) -> SampledSpectrum
+    {
+        self.s[0]
+    }
+    // Stokes vector at the i-th wavelength
+    pub fn at(&self, i: usize) -> [f64; 4]
+    {
+        self.s.map(|c| c[i])
+    }
+    fn per_wavelength(&self, f: fn([f64; 4]) -> f64) -> SampledSpectrum
+    {
+        let mut out = SampledSpectrum::zero();
+        for i in 0..N_SPECTRUM_SAMPLES
+        {
+            out[i] = f(self.at(i));
+        }
+        out
+    }
+    pub fn dop(&self) -> SampledSpectrum
+    {
+        self.per_wavelength(dop)
+    }
+    pub fn dolp(&self) -> SampledSpectrum
+    {
+        self.per_wavelength(dolp)
+    }
+    pub fn aop(&self) -> SampledSpectrum
+    {
+        self.per_wavelength(aop)
+    }
+    // The same Mueller matrix at every wavelength, like a rotation or an
+    // ideal polarizer
+    pub fn apply(&self, m: &Matrix4) -> Stokes
+    {
+        self.apply_each(|_| m)
+    }
+    // One Mueller matrix per wavelength, like Fresnel terms with a
+    // dispersive index
+    pub fn apply_spectral(&self, m: &[Matrix4; N_SPECTRUM_SAMPLES]) -> Stokes
+    {
+        self.apply_each(|i| &m[i])
+    }
+    fn apply_each<'a, F: Fn(usize) -> &'a Matrix4>(&self, m: F) -> Stokes
+    {
+        let mut out = Stokes::zero();
+        for i in 0..N_SPECTRUM_SAMPLES
+        {
+            for (c, v) in out.s.iter_mut().zip(mueller(m(i), self.at(i)))
+            {
+                c[i] = v;
+            }
+        }
+        out
+    }
+    // Same light relative to another x axis, both perpendicular to dir
+    pub fn rotate_frame(&self, x: Vec3d, new_x: Vec3d, dir: Vec3d) -> Stokes
+    {
+        self.apply(&rotator(frame_angle(x, new_x, dir)))
+    }
+    // Every component to RGB, which is linear so the result is the Stokes
+    // vector of each channel
+    pub fn to_rgb(&self, w: &SampledWavelengths, space: &RGBColorSpace) -> RGBStokes
+    {
+        RGBStokes{ s: self.s.map(|c| c.to_rgb(w, space)) }
+    }
+}
+
+impl ops::Add for Stokes
+{
+    type Output = Stokes;
+    fn add(self, other: Stokes) -> Stokes
+    {
+        let mut s = self.s;
+        for (v, &o) in s.iter_mut().zip(&other.s)
+        {
+            *v += o;
+        }
+        Stokes{ s }
+    }
+}
+
+impl ops::AddAssign for Stokes
+{
+    fn add_assign(&mut self, other: Stokes)
+    {
+        *self = *self + other;
+    }
+}
+
+impl ops::Mul<f64> for Stokes
+{
+    type Output = Stokes;
+    fn mul(self, k: f64) -> Stokes
+    {
+        Stokes{ s: self.s.map(|v| v*k) }
+    }
+}
+
+// Stokes vector of every RGB channel, what a film records and what a
+// polarization camera with a color filter array measures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RGBStokes
+{
+    pub s: [RGB; 4],
+}
+
+impl RGBStokes
+{
+    pub fn zero() -> RGBStokes
+    {
+        RGBStokes{ s: [RGB::black(); 4] }
+    }
+    pub fn unpolarized(intensity: RGB) -> RGBStokes
+    {
+        RGBStokes{ s: [intensity, RGB::black(), RGB::black(), RGB::black()] }
+    }
+    pub fn linear(intensity: RGB, angle: f64) -> RGBStokes
+    {
+        RGBStokes{ s: [intensity, intensity*(2.*angle).cos(), intensity*(2.*angle).sin(), RGB::black()] }
+    }
+    pub fn intensity(&self) -> RGB
+    {
+        self.s[0]
+    }
+    // Stokes vector of channel 0, 1 or 2 for R, G or B
+    pub fn channel(&self, c: usize) -> [f64; 4]
+    {
+        self.s.map(|v| [v.r, v.g, v.b][c])
+    }
+    fn per_channel(&self, f: fn([f64; 4]) -> f64) -> RGB
+    {
+        RGB::new(f(self.channel(0)), f(self.channel(1)), f(self.channel(2)))
+    }
+    pub fn dop(&self) -> RGB
+    {
+        self.per_channel(dop)
+    }
+    pub fn dolp(&self) -> RGB
+    {
+        self.per_channel(dolp)
+    }
+    pub fn aop(&self) -> RGB
+    {
+        self.per_channel(aop)
+    }
+}
+
+impl ops::Add for RGBStokes
+{
+    type Output = RGBStokes;
+    fn add(self, other: RGBStokes) -> RGBStokes
+    {
+        let mut s = self.s;
+        for (v, &o) in s.iter_mut().zip(&other.s)
+        {
+            *v += o;
+        }
+        RGBStokes{ s }
+    }
+}
+
+impl ops::AddAssign for RGBStokes
+{
+    fn add_assign(&mut self, other: RGBStokes)
+    {
+        *self = *self + other;
+    }
+}
+
+impl ops::Mul<f64> for RGBStokes
+{
+    type Output = RGBStokes;
+    fn mul(self, k: f64) -> RGBStokes
+    {
+        RGBStokes{ s: self.s.map(|v| v*k) }
+    }
+}
+
+// Angle from x to new_x around dir
+pub fn frame_angle(x: Vec3d, new_x: Vec3d, dir: Vec3d) -> f64
+{
+    let y = Vec3d::cross(dir, x);
+    Vec3d::dot(new_x, y).atan2(Vec3d::dot(new_x, x))
+}
+
+// Mueller matrix taking Stokes vectors to a reference frame rotated by
+// theta around the direction of travel
+pub fn rotator(theta: f64) -> Matrix4
+{
+    let (s, c) = (2.*theta).sin_cos();
+    Matrix4::new(&[[1., 0., 0., 0.], [0., c, s, 0.], [0., -s, c, 0.], [0., 0., 0., 1.]])
+}
+
+// Ideal linear polarizer transmitting along angle radians from the x axis
+pub fn linear_polarizer(angle: f64) -> Matrix4
+{
+    let p = Matrix4::new(&[[0.5, 0.5, 0., 0.], [0.5, 0.5, 0., 0.], [0., 0., 0., 0.], [0., 0., 0., 0.]]);
+    Matrix4::mul(&rotator(-angle), &Matrix4::mul(&p, &rotator(angle)))
+}
+
+// Perpendicular to the plane of incidence of a direction on a surface with
+// the given normal, the x axis the Fresnel matrices work in. Any
+// perpendicular axis will do at normal incidence.
+pub fn s_axis(dir: Vec3d, n: Vec3d) -> Vec3d
+{
+    let s = Vec3d::cross(n, dir);
+    if s.lensq() > 1e-18
+    {
+        return s.norm();
+    }
+    let a = if dir.x.abs() < 0.9 { Vec3d::new(1., 0., 0.) } else { Vec3d::new(0., 1., 0.) };
+    Vec3d::cross(dir, a).norm()
+}
+
+// Amplitude coefficients rs and rp for light at cos_i from a medium of
+// index one onto one of complex index eta, conductors have a positive
+// imaginary part
+pub fn fresnel_amplitudes(cos_i: f64, eta: Complex) -> (Complex, Complex)
+{
+    let cos_i = cos_i.clamp(0., 1.);
+    let ci = Complex::real(cos_i);
+    let sin2_t = Complex::real(1. - cos_i*cos_i)/(eta*eta);
+    let cos_t = (Complex::real(1.) - sin2_t).sqrt();
+    let rs = (ci - eta*cos_t)/(ci + eta*cos_t);
+    let rp = (eta*ci - cos_t)/(eta*ci + cos_t);
+    (rs, rp)
+}
+
+// Reflection by a smooth dielectric or conductor, in the s and p frame of
+// s_axis on both sides. The phase difference between rs and rp turns linear
+// polarization elliptical, which happens on metals and under total internal
+// reflection.
+pub fn fresnel_reflection(cos_i: f64, eta: Complex) -> Matrix4
+{
+    let (rs, rp) = fresnel_amplitudes(cos_i, eta);
+    let (a, b) = ((rs.norm() + rp.norm())/2., (rs.norm() - rp.norm())/2.);
+    let x = rs*rp.conj();
+    let (c, d) = (x.re, x.im);
+    Matrix4::new(&[[a, b, 0., 0.], [b, a, 0., 0.], [0., 0., c, d], [0., 0., -d, c]])
+}
+
+// Transmission into a real index eta, including the change in beam cross
+// section, zero under total internal reflection
+pub fn fresnel_transmission(cos_i: f64, eta: f64) -> Matrix4
+{
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin2_t = (1. - cos_i*cos_i)/(eta*eta);
+    if sin2_t >= 1. || cos_i == 0.
+    {
+        return Matrix4::new(&[[0.; 4]; 4]);
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let ts = 2.*cos_i/(cos_i + eta*cos_t);
+    let tp = 2.*cos_i/(eta*cos_i + cos_t);
+    let k = eta*cos_t/cos_i;
+    let (a, b, c) = (k*(ts*ts + tp*tp)/2., k*(ts*ts - tp*tp)/2., k*ts*tp);
+    Matrix4::new(&[[a, b, 0., 0.], [b, a, 0., 0.], [0., 0., c, 0.], [0., 0., 0., c]])
+}
@@ -0,0 +1,336 @@
+use std::f64::consts::PI;
+use crate::color::RGB;
+use crate::complex::{self, Complex};
+use crate::image::Image;
+
+// Effects on the scene-linear film image, applied before tone mapping.
+// Only the RGB channels are changed, the others are copied over.
+#[derive(Clone, Debug, Default)]
+pub struct PostEffects
+{
+    pub vignette: Option<Vignette>,
+    pub glare: Option<Glare>,
+    pub bloom: Option<Bloom>,
+}
+
+impl PostEffects
+{
+    pub fn apply(&self, img: &Image) -> Image
+    {
+        let mut out = img.clone();
+        if let Some(v) = &self.vignette
+        {
+            out = v.apply(&out);
+        }
+        if let Some(g) = &self.glare
+        {
+            out = g.apply(&out);
+        }
+        if let Some(b) = &self.bloom
+        {
+            out = b.apply(&out);
+        }
+        out
+    }
+}
+
+// Part of every channel above the threshold
+fn highlights(img: &Image, threshold: f64) -> Vec<RGB>
+{
+    img.to_rgb().iter().map(|c| c.map(|v| (v - threshold).max(0.))).collect()
+}
+
+// Gray images keep the luminance
+fn set_color(img: &mut Image, x: usize, y: usize, c: RGB)
+{
+    if img.channel_count() >= 3
+    {
+        img.set_rgb(x, y, c);
+    }
+    else
+    {
+        img.set(x, y, 0, c.luminance());
+    }
+}
+
+fn add_rgb(img: &Image, add: &[RGB]) -> Image
+{
+    let mut out = img.clone();
+    for (i, &c) in add.iter().enumerate()
+    {
+        let (x, y) = (i%img.width, i/img.width);
+        set_color(&mut out, x, y, img.get_rgb(x, y) + c);
+    }
+    out
+}
+
+// Highlights blurred at several scales, each octave twice as wide as the
+// last, and added back on top of the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom
+{
+    pub threshold: f64,
+    pub intensity: f64,
+    // Standard deviation in pixels of the narrowest blur
+    pub radius: f64,
+    pub octaves: usize,
+}
+
+impl Default for Bloom
+{
+    fn default() -> Bloom
+    {
+        Bloom{ threshold: 1., intensity: 0.05, radius: 2., octaves: 5 }
+    }
+}
+
+impl Bloom
+{
+    pub fn apply(&self, img: &Image) -> Image
+    {
+        let (w, h) = (img.width, img.height);
+        let bright = highlights(img, self.threshold);
+        let mut sum = vec![RGB::black(); w*h];
+        for octave in 0..self.octaves
+        {
+            let blurred = gaussian_blur(&bright, w, h, self.radius*2f64.powi(octave as i32));
+            for (s, b) in sum.iter_mut().zip(blurred)
+            {
+                *s += b;
+            }
+        }
+        let scale = self.intensity/self.octaves.max(1) as f64;
+        add_rgb(img, &sum.iter().map(|&c| c*scale).collect::<Vec<RGB>>())
+    }
+}
+
+// Separable Gaussian, renormalized where it reaches past the border
+fn gaussian_blur(v: &[RGB], w: usize, h: usize, sigma: f64) -> Vec<RGB>
+{
+    let r = (3.*sigma).ceil() as isize;
+    let kernel: Vec<f64> = (-r..=r).map(|i| (-((i*i) as f64)/(2.*sigma*sigma)).exp()).collect();
+    let pass = |v: &[RGB], horizontal: bool| -> Vec<RGB>
+    {
+        let mut out = vec![RGB::black(); w*h];
+        for y in 0..h as isize
+        {
+            for x in 0..w as isize
+            {
+                let (mut sum, mut sum_w) = (RGB::black(), 0.);
+                for (i, &k) in kernel.iter().enumerate()
+                {
+                    let (u, t) = if horizontal { (x + i as isize - r, y) } else { (x, y + i as isize - r) };
+                    if u >= 0 && t >= 0 && u < w as isize && t < h as isize
+                    {
+                        sum += v[t as usize*w + u as usize]*k;
+                        sum_w += k;
+                    }
+                }
+                out[y as usize*w + x as usize] = sum/sum_w;
+            }
+        }
+        out
+    };
+    pass(&pass(v, true), false)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aperture
+{
+    Circle,
+    // Regular polygon with straight blades, rotation in radians
+    Polygon{ blades: usize, rotation: f64 },
+}
+
+// Fraunhofer diffraction of the aperture: the point spread function is the
+// squared magnitude of the Fourier transform of the pupil, and it scales with
+// the wavelength, so each channel gets its own. Vanes holding a central
+// obstruction, and the edges of polygonal apertures, cause spikes.
+// Highlights above the threshold are convolved with it, at intensity 1 and
+// threshold 0 the whole image is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glare
+{
+    pub aperture: Aperture,
+    // Central obstruction and vane width relative to the aperture radius
+    pub obstruction: f64,
+    pub vanes: usize,
+    pub vane_width: f64,
+    // Radius in pixels of the first dark ring of a circular aperture at 550 nm
+    pub airy_radius: f64,
+    // Wavelengths in nm the channels are taken at
+    pub wavelengths: [f64; 3],
+    pub threshold: f64,
+    pub intensity: f64,
+}
+
+impl Default for Glare
+{
+    fn default() -> Glare
+    {
+        Glare{ aperture: Aperture::Polygon{ blades: 6, rotation: 0. }, obstruction: 0., vanes: 0, vane_width: 0.02, airy_radius: 3.,
+               wavelengths: [610., 550., 465.], threshold: 1., intensity: 1. }
+    }
+}
+
+impl Glare
+{
+    // Pupil point at distance r and angle phi from the center, in units of
+    // the aperture radius
+    pub fn transmits(&self, r: f64, phi: f64) -> bool
+    {
+        if r < self.obstruction
+        {
+            return false;
+        }
+        let inside = match self.aperture
+        {
+            Aperture::Circle => r <= 1.,
+            Aperture::Polygon{ blades, rotation } =>
+            {
+                let sector = 2.*PI/blades as f64;
+                let a = (phi - rotation).rem_euclid(sector) - sector/2.;
+                r*a.cos() <= (sector/2.).cos()
+            }
+        };
+        if !inside
+        {
+            return false;
+        }
+        // Vanes run from the center out at even angles
+        (0..self.vanes).all(|k|
+        {
+            let theta = 2.*PI*k as f64/self.vanes as f64;
+            let (along, across) = (r*(phi - theta).cos(), r*(phi - theta).sin());
+            along < 0. || across.abs() > self.vane_width/2.
+        })
+    }
+    // Point spread function of each channel on an n x n grid with its origin
+    // at index 0, normalized to sum to one
+    pub fn psf(&self, n: usize) -> [Vec<f64>; 3]
+    {
+        let mut buffer = vec![Complex::real(0.); n*n];
+        let mut out = [Vec::new(), Vec::new(), Vec::new()];
+        for (c, psf) in out.iter_mut().enumerate()
+        {
+            let lit = self.channel_psf(n, c, &mut buffer);
+            *psf = buffer.iter().map(|p| if lit { p.re } else { 0. }).collect();
+        }
+        out
+    }
+    // Writes the PSF of channel c to psf, false if the aperture is closed
+    fn channel_psf(&self, n: usize, c: usize, psf: &mut [Complex]) -> bool
+    {
+        if let Aperture::Polygon{ blades, .. } = self.aperture
+        {
+            assert!(blades >= 3, "a polygonal aperture needs at least three blades");
+        }
+        // The first Airy ring is at 1.22 n/d frequency bins for a pupil d
+        // samples across, and the PSF grows with the wavelength. Pupils
+        // wider than half the grid would alias, which limits the radius
+        // to about 2.5 pixels.
+        let d = f64::min(1.22*n as f64/self.airy_radius*550./self.wavelengths[c], n as f64/2.);
+        for (i, p) in psf.iter_mut().enumerate()
+        {
+            let (x, y) = ((i%n) as f64 - n as f64/2. + 0.5, (i/n) as f64 - n as f64/2. + 0.5);
+            let r = (x*x + y*y).sqrt()/(d/2.);
+            *p = Complex::real(if self.transmits(r, y.atan2(x)) { 1. } else { 0. });
+        }
+        complex::fft_2d(psf, n, n, false);
+        let sum: f64 = psf.iter().map(|p| p.norm()).sum();
+        if sum <= 0.
+        {
+            return false;
+        }
+        for p in psf.iter_mut()
+        {
+            *p = Complex::real(p.norm()/sum);
+        }
+        true
+    }
+    pub fn apply(&self, img: &Image) -> Image
+    {
+        let (w, h) = (img.width, img.height);
+        // Twice the image keeps the circular convolution from wrapping around
+        let n = (2*usize::max(w, h)).next_power_of_two();
+        let bright = highlights(img, self.threshold);
+        let mut glare = vec![RGB::black(); w*h];
+        // One channel at a time, reusing the buffers
+        let (mut a, mut k) = (vec![Complex::real(0.); n*n], vec![Complex::real(0.); n*n]);
+        for c in 0..3
+        {
+            // A closed aperture passes nothing, the image is left as is
+            if !self.channel_psf(n, c, &mut k)
+            {
+                continue;
+            }
+            let channel = |v: RGB| [v.r, v.g, v.b][c];
+            a.iter_mut().for_each(|a| *a = Complex::real(0.));
+            for (i, &b) in bright.iter().enumerate()
+            {
+                a[(i/w)*n + i%w] = Complex::real(channel(b));
+            }
+            complex::fft_2d(&mut a, n, n, false);
+            complex::fft_2d(&mut k, n, n, false);
+            for (a, k) in a.iter_mut().zip(&k)
+            {
+                *a = *a**k;
+            }
+            complex::fft_2d(&mut a, n, n, true);
+            for (i, g) in glare.iter_mut().enumerate()
+            {
+                let v = a[(i/w)*n + i%w].re/(n*n) as f64 - channel(bright[i]);
+                match c
+                {
+                    0 => g.r = v,
+                    1 => g.g = v,
+                    _ => g.b = v,
+                }
+            }
+        }
+        add_rgb(img, &glare.iter().map(|&c| c*self.intensity).collect::<Vec<RGB>>())
+    }
+}
+
+// Natural vignetting by the cosine fourth law for a lens with the given
+// horizontal field of view in degrees, and optical vignetting from the
+// aperture being cut by the lens barrel. The barrel's image of the pupil is
+// shifted by `optical` aperture radii at the image corners, less towards
+// the center, and only the overlap of the two discs lets light through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vignette
+{
+    pub fov: f64,
+    pub optical: f64,
+}
+
+impl Vignette
+{
+    // Factor at film coordinates in [-1, 1] horizontally and scaled by the
+    // height over width vertically
+    pub fn factor(&self, fx: f64, fy: f64, aspect: f64) -> f64
+    {
+        let r = (fx*fx + fy*fy).sqrt();
+        let t = r*(self.fov.to_radians()/2.).tan();
+        let cos2 = 1./(1. + t*t);
+        let d = f64::min(self.optical*r/(1. + aspect*aspect).sqrt(), 2.);
+        let overlap = (2.*(d/2.).acos() - d/2.*(4. - d*d).sqrt())/PI;
+        cos2*cos2*overlap
+    }
+    pub fn apply(&self, img: &Image) -> Image
+    {
+        let (w, h) = (img.width as f64, img.height as f64);
+        let aspect = h/w;
+        let mut out = img.clone();
+        for y in 0..img.height
+        {
+            for x in 0..img.width
+            {
+                let fx = 2.*(x as f64 + 0.5)/w - 1.;
+                let fy = (1. - 2.*(y as f64 + 0.5)/h)*aspect;
+                set_color(&mut out, x, y, img.get_rgb(x, y)*self.factor(fx, fy, aspect));
+            }
+        }
+        out
+    }
+}
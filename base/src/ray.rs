@@ -8,13 +8,14 @@ pub struct Ray
     pub d: Vec3d,
     pub t: f64,
     pub tmax: f64,
+    pub time: f64,
 }
 
 impl Ray
 {
     pub fn new(o: Vec3d, d: Vec3d) -> Ray
     {
-        Ray{ o, d, t: 0., tmax: f64::INFINITY }
+        Ray{ o, d, t: 0., tmax: f64::INFINITY, time: 0. }
     }
     pub fn apply_trans(r: &Ray, trans: &Vec<Transform>) -> Ray
     {
@@ -25,7 +26,7 @@ impl Ray
             o = tran.act_point(o);
             d = tran.act_vector(d);
         }
-        Ray { o:o, d:d, t:r.t, tmax:r.tmax}
+        Ray { o:o, d:d, t:r.t, tmax:r.tmax, time:r.time}
     }
     pub fn pos(&self, t:f64) -> Vec3d
     {
@@ -0,0 +1,418 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::thread;
+use crate::color::RGB;
+use crate::colorspace::RGBColorSpace;
+use crate::spectrum::{self, ResponseCurve, Spectrum, LAMBDA_MAX, LAMBDA_MIN};
+use crate::transformation::Matrix3;
+use crate::vector::{Point2, Vec3d};
+
+// Smooth spectrum s(c0 l^2 + c1 l + c2) with the sigmoid s(x) =
+// 1/2 + x/(2 sqrt(1 + x^2)), from Jakob and Hanika, "A Low-Dimensional
+// Function Space for Efficient Spectral Upsampling" (2019)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RGBSigmoidPolynomial
+{
+    pub c0: f64,
+    pub c1: f64,
+    pub c2: f64,
+}
+
+fn sigmoid(x: f64) -> f64
+{
+    if x.is_infinite()
+    {
+        return if x > 0. { 1. } else { 0. };
+    }
+    0.5 + x/(2.*(1. + x*x).sqrt())
+}
+
+impl RGBSigmoidPolynomial
+{
+    pub fn eval_at(&self, lambda: f64) -> f64
+    {
+        sigmoid((self.c0*lambda + self.c1)*lambda + self.c2)
+    }
+}
+
+impl Spectrum for RGBSigmoidPolynomial
+{
+    fn eval(&self, lambda: f64) -> f64
+    {
+        self.eval_at(lambda)
+    }
+}
+
+const MAGIC: &[u8; 8] = b"RGB2SPEC";
+
+// Tables made by rgb2spec at a resolution of 32, which round trips
+// reflectances to within 1% where 64 would take eight times the space
+const BUNDLED: [(&str, &[u8]); 4] = [("srgb", include_bytes!("../data/rgb2spec/srgb.bin")),
+                                     ("rec2020", include_bytes!("../data/rgb2spec/rec2020.bin")),
+                                     ("dci-p3", include_bytes!("../data/rgb2spec/dci-p3.bin")),
+                                     ("aces-cg", include_bytes!("../data/rgb2spec/aces-cg.bin"))];
+
+fn invalid(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Sigmoid polynomial coefficients of a color space on a grid over RGB in
+// [0, 1]. The largest channel picks one of three blocks and its value the
+// z node, the other two channels divided by it are spread evenly over x and
+// y. The z nodes are denser towards black and white.
+#[derive(Clone, Debug)]
+pub struct RGBToSpectrumTable
+{
+    pub white: Point2,
+    pub res: usize,
+    pub z_nodes: Vec<f32>,
+    // [block][z][y][x][coefficient]
+    pub coeffs: Vec<f32>,
+    // Daylight at the white point that reflectances are fitted under
+    pub illuminant: ResponseCurve,
+}
+
+fn smoothstep(x: f64) -> f64
+{
+    x*x*(3. - 2.*x)
+}
+
+fn z_nodes(res: usize) -> Vec<f32>
+{
+    (0..res).map(|k| smoothstep(smoothstep(k as f64/(res - 1) as f64)) as f32).collect()
+}
+
+fn lab(xyz: Vec3d, white: Vec3d) -> Vec3d
+{
+    let f = |t: f64|
+    {
+        let delta: f64 = 6./29.;
+        if t > delta.powi(3) { t.cbrt() } else { t/(3.*delta*delta) + 4./29. }
+    };
+    let (fx, fy, fz) = (f(xyz.x/white.x), f(xyz.y/white.y), f(xyz.z/white.z));
+    Vec3d::new(116.*fy - 16., 500.*(fx - fy), 200.*(fy - fz))
+}
+
+// Reflectance to RGB under the illuminant at 1 nm steps over the visible
+// range, rescaled so that a unit reflectance is exactly white
+struct Fit
+{
+    lambda: Vec<f64>,
+    weights: Vec<Vec3d>,
+    rgb_to_xyz: Matrix3,
+    white_xyz: Vec3d,
+}
+
+impl Fit
+{
+    fn new(space: &RGBColorSpace, illuminant: &ResponseCurve) -> Fit
+    {
+        let lambda: Vec<f64> = (0..=(LAMBDA_MAX - LAMBDA_MIN) as usize).map(|i| LAMBDA_MIN + i as f64).collect();
+        let mut weights: Vec<Vec3d> = lambda.iter().map(|&l|
+        {
+            let (x, y, z) = spectrum::cie_xyz(l);
+            space.xyz_to_rgb.act(Vec3d::new(x, y, z))*illuminant.eval(l)
+        }).collect();
+        let sum = weights.iter().fold(Vec3d::zero(), |a, &w| a + w);
+        for w in weights.iter_mut()
+        {
+            *w = Vec3d::new(w.x/sum.x, w.y/sum.y, w.z/sum.z);
+        }
+        Fit{ lambda, weights, rgb_to_xyz: space.rgb_to_xyz, white_xyz: space.rgb_to_xyz.act(Vec3d::new(1., 1., 1.)) }
+    }
+    // Coefficients over wavelengths mapped to [0, 1]
+    fn rgb(&self, c: [f64; 3]) -> Vec3d
+    {
+        let mut rgb = Vec3d::zero();
+        for (&l, &w) in self.lambda.iter().zip(&self.weights)
+        {
+            let x = (l - LAMBDA_MIN)/(LAMBDA_MAX - LAMBDA_MIN);
+            rgb = rgb + w*sigmoid((c[0]*x + c[1])*x + c[2]);
+        }
+        rgb
+    }
+    fn residual(&self, c: [f64; 3], target: Vec3d) -> Vec3d
+    {
+        let lab_of = |rgb: Vec3d| lab(self.rgb_to_xyz.act(rgb), self.white_xyz);
+        lab_of(target) - lab_of(self.rgb(c))
+    }
+    // Gauss-Newton on the CIELAB difference with a finite difference
+    // Jacobian, starting from c
+    fn solve(&self, target: Vec3d, c: &mut [f64; 3])
+    {
+        for _ in 0..15
+        {
+            let r = self.residual(*c, target);
+            if r.len() < 1e-6
+            {
+                break;
+            }
+            let mut jacobian = [[0.; 3]; 3];
+            for j in 0..3
+            {
+                let eps = 1e-5;
+                let (mut lo, mut hi) = (*c, *c);
+                lo[j] -= eps;
+                hi[j] += eps;
+                let d = (self.residual(hi, target) - self.residual(lo, target))/(2.*eps);
+                jacobian[0][j] = d.x;
+                jacobian[1][j] = d.y;
+                jacobian[2][j] = d.z;
+            }
+            let jacobian = Matrix3::new(jacobian);
+            if jacobian.det().abs() < 1e-15
+            {
+                break;
+            }
+            // Halves the step until it helps, far from the solution the full
+            // one can overshoot into the flat ends of the sigmoid
+            let step = jacobian.inv().act(r);
+            let mut t = 1.;
+            let next = loop
+            {
+                let mut next = *c;
+                for (v, s) in next.iter_mut().zip(&[step.x, step.y, step.z])
+                {
+                    *v -= t*s;
+                }
+                // Keeps the sigmoid from getting too steep to integrate
+                let max = next.iter().fold(0f64, |m, v| m.max(v.abs()));
+                if max > 200.
+                {
+                    for v in next.iter_mut()
+                    {
+                        *v *= 200./max;
+                    }
+                }
+                if self.residual(next, target).len() < r.len() || t < 1e-3
+                {
+                    break next;
+                }
+                t /= 2.;
+            };
+            *c = next;
+        }
+    }
+}
+
+impl RGBToSpectrumTable
+{
+    // Fits every grid point, which is slow at the usual resolution of 64 so
+    // tables are computed ahead of time by the rgb2spec tool, or bundled
+    // for the named spaces. Fits start in
+    // the middle of each z column and reuse their neighbor's solution.
+    pub fn compute(space: &RGBColorSpace, res: usize) -> RGBToSpectrumTable
+    {
+        assert!(res >= 2, "table resolution must be at least 2");
+        let illuminant = spectrum::daylight(space.white);
+        let fit = Fit::new(space, &illuminant);
+        let nodes = z_nodes(res);
+        let mut coeffs = vec![0f32; 3*res*res*res*3];
+        let columns: Vec<(usize, usize, usize)> = (0..3).flat_map(|l| (0..res).flat_map(move |j| (0..res).map(move |i| (l, j, i)))).collect();
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let results: Vec<Vec<(usize, [f64; 3])>> = thread::scope(|scope|
+        {
+            let handles: Vec<_> = columns.chunks(columns.len().div_ceil(threads)).map(|chunk|
+            {
+                let (fit, nodes) = (&fit, &nodes);
+                scope.spawn(move ||
+                {
+                    let mut out = Vec::new();
+                    for &(l, j, i) in chunk
+                    {
+                        let (x, y) = (i as f64/(res - 1) as f64, j as f64/(res - 1) as f64);
+                        let start = res/5;
+                        for ks in [(start..res).collect::<Vec<usize>>(), (0..start).rev().collect()]
+                        {
+                            let mut c = [0.; 3];
+                            for k in ks
+                            {
+                                let z = nodes[k] as f64;
+                                let mut rgb = [0.; 3];
+                                rgb[l] = z;
+                                rgb[(l + 1)%3] = x*z;
+                                rgb[(l + 2)%3] = y*z;
+                                fit.solve(Vec3d::new(rgb[0], rgb[1], rgb[2]), &mut c);
+                                out.push((((l*res + k)*res + j)*res + i, c));
+                            }
+                        }
+                    }
+                    out
+                })
+            }).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        // Back from wavelengths in [0, 1] to nm
+        let (c0, c1) = (LAMBDA_MIN, 1./(LAMBDA_MAX - LAMBDA_MIN));
+        for (index, [a, b, c]) in results.into_iter().flatten()
+        {
+            coeffs[3*index] = (a*c1*c1) as f32;
+            coeffs[3*index + 1] = (b*c1 - 2.*a*c0*c1*c1) as f32;
+            coeffs[3*index + 2] = (c - b*c0*c1 + a*c0*c0*c1*c1) as f32;
+        }
+        RGBToSpectrumTable{ white: space.white, res, z_nodes: nodes, coeffs, illuminant }
+    }
+    // RGB in [0, 1]
+    pub fn lookup(&self, rgb: RGB) -> RGBSigmoidPolynomial
+    {
+        let rgb = rgb.clamp(0., 1.);
+        let v = [rgb.r, rgb.g, rgb.b];
+        if v[0] == v[1] && v[1] == v[2]
+        {
+            // Constant at the gray level
+            let c2 = if v[0] == 0. || v[0] == 1. { (v[0] - 0.5)*f64::INFINITY } else { (v[0] - 0.5)/(v[0]*(1. - v[0])).sqrt() };
+            return RGBSigmoidPolynomial{ c0: 0., c1: 0., c2 };
+        }
+        let l = if v[0] >= v[1] && v[0] >= v[2] { 0 } else if v[1] >= v[2] { 1 } else { 2 };
+        let res = self.res;
+        let z = v[l];
+        let x = v[(l + 1)%3]*(res - 1) as f64/z;
+        let y = v[(l + 2)%3]*(res - 1) as f64/z;
+        let (xi, yi) = ((x as usize).min(res - 2), (y as usize).min(res - 2));
+        let zi = self.z_nodes[1..res - 1].iter().take_while(|&&n| n as f64 <= z).count();
+        let (dx, dy) = (x - xi as f64, y - yi as f64);
+        let dz = (z - self.z_nodes[zi] as f64)/(self.z_nodes[zi + 1] - self.z_nodes[zi]) as f64;
+        let mut c = [0.; 3];
+        for (n, c) in c.iter_mut().enumerate()
+        {
+            let at = |k: usize, j: usize, i: usize| self.coeffs[3*(((l*res + k)*res + j)*res + i) + n] as f64;
+            let lerp = |t: f64, a: f64, b: f64| (1. - t)*a + t*b;
+            *c = lerp(dz, lerp(dy, lerp(dx, at(zi, yi, xi), at(zi, yi, xi + 1)), lerp(dx, at(zi, yi + 1, xi), at(zi, yi + 1, xi + 1))),
+                          lerp(dy, lerp(dx, at(zi + 1, yi, xi), at(zi + 1, yi, xi + 1)), lerp(dx, at(zi + 1, yi + 1, xi), at(zi + 1, yi + 1, xi + 1))));
+        }
+        RGBSigmoidPolynomial{ c0: c[0], c1: c[1], c2: c[2] }
+    }
+    // Magic, white point, resolution, z nodes and coefficients, little
+    // endian with 32 bit floats
+    pub fn encode(&self) -> Vec<u8>
+    {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&self.white.x.to_le_bytes());
+        out.extend_from_slice(&self.white.y.to_le_bytes());
+        out.extend_from_slice(&(self.res as u32).to_le_bytes());
+        for v in self.z_nodes.iter().chain(&self.coeffs)
+        {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out
+    }
+    pub fn decode(data: &[u8]) -> io::Result<RGBToSpectrumTable>
+    {
+        if !data.starts_with(MAGIC) || data.len() < 28
+        {
+            return Err(invalid("not an RGB to spectrum table"));
+        }
+        let f64_at = |p: usize| f64::from_le_bytes([data[p], data[p + 1], data[p + 2], data[p + 3], data[p + 4], data[p + 5], data[p + 6], data[p + 7]]);
+        let white = Point2::new(f64_at(8), f64_at(16));
+        let res = u32::from_le_bytes([data[24], data[25], data[26], data[27]]) as usize;
+        // Checked, the resolution comes straight from the file
+        let len = res.checked_mul(res).and_then(|n| n.checked_mul(res)).and_then(|n| n.checked_mul(9)).and_then(|n| n.checked_add(res));
+        if res < 2 || len != Some((data.len() - 28)/4) || data.len() % 4 != 0
+        {
+            return Err(invalid("truncated RGB to spectrum table"));
+        }
+        let floats: Vec<f32> = data[28..].chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        let (z_nodes, coeffs) = floats.split_at(res);
+        Ok(RGBToSpectrumTable{ white, res, z_nodes: z_nodes.to_vec(), coeffs: coeffs.to_vec(), illuminant: spectrum::daylight(white) })
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()>
+    {
+        fs::write(path, self.encode())
+    }
+    pub fn load(path: &Path) -> io::Result<RGBToSpectrumTable>
+    {
+        RGBToSpectrumTable::decode(&fs::read(path)?)
+    }
+    // Bundled table of a color space by RGBColorSpace::named, decoded on
+    // first use
+    pub fn named(name: &str) -> Option<&'static RGBToSpectrumTable>
+    {
+        static TABLES: [OnceLock<RGBToSpectrumTable>; 4] = [OnceLock::new(), OnceLock::new(), OnceLock::new(), OnceLock::new()];
+        let i = BUNDLED.iter().position(|b| b.0 == name)?;
+        Some(TABLES[i].get_or_init(|| RGBToSpectrumTable::decode(BUNDLED[i].1).expect("bundled RGB to spectrum table is valid")))
+    }
+    // Bundled table of a space with the primaries and white point of one of
+    // the named ones, so that RGB assets in them need no setup
+    pub fn for_space(space: &RGBColorSpace) -> Option<&'static RGBToSpectrumTable>
+    {
+        let name = BUNDLED.iter().map(|b| b.0).find(|n| RGBColorSpace::named(n).as_ref() == Some(space))?;
+        RGBToSpectrumTable::named(name)
+    }
+}
+
+// Reflectance with the given RGB in [0, 1]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RGBAlbedoSpectrum
+{
+    pub rsp: RGBSigmoidPolynomial,
+}
+
+impl RGBAlbedoSpectrum
+{
+    pub fn new(table: &RGBToSpectrumTable, rgb: RGB) -> RGBAlbedoSpectrum
+    {
+        RGBAlbedoSpectrum{ rsp: table.lookup(rgb) }
+    }
+}
+
+impl Spectrum for RGBAlbedoSpectrum
+{
+    fn eval(&self, lambda: f64) -> f64
+    {
+        self.rsp.eval_at(lambda)
+    }
+}
+
+// Any non-negative RGB, as a sigmoid of half the scale times the scale
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RGBUnboundedSpectrum
+{
+    pub scale: f64,
+    pub rsp: RGBSigmoidPolynomial,
+}
+
+impl RGBUnboundedSpectrum
+{
+    pub fn new(table: &RGBToSpectrumTable, rgb: RGB) -> RGBUnboundedSpectrum
+    {
+        let scale = 2.*rgb.max_comp();
+        let rsp = if scale > 0. { table.lookup(rgb/scale) } else { table.lookup(RGB::black()) };
+        RGBUnboundedSpectrum{ scale, rsp }
+    }
+}
+
+impl Spectrum for RGBUnboundedSpectrum
+{
+    fn eval(&self, lambda: f64) -> f64
+    {
+        self.scale*self.rsp.eval_at(lambda)
+    }
+}
+
+// Emission with the given RGB, the unbounded spectrum times the table's
+// illuminant so that white lights are daylight rather than flat
+#[derive(Clone, Debug)]
+pub struct RGBIlluminantSpectrum
+{
+    pub color: RGBUnboundedSpectrum,
+    pub illuminant: ResponseCurve,
+}
+
+impl RGBIlluminantSpectrum
+{
+    pub fn new(table: &RGBToSpectrumTable, rgb: RGB) -> RGBIlluminantSpectrum
+    {
+        RGBIlluminantSpectrum{ color: RGBUnboundedSpectrum::new(table, rgb), illuminant: table.illuminant.clone() }
+    }
+}
+
+impl Spectrum for RGBIlluminantSpectrum
+{
+    fn eval(&self, lambda: f64) -> f64
+    {
+        self.color.eval(lambda)*self.illuminant.eval(lambda)
+    }
+}
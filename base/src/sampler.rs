@@ -0,0 +1,97 @@
+// PCG32 (XSH RR) generator with O'Neill's constants
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pcg32
+{
+    state: u64,
+    inc: u64,
+}
+
+const PCG_MULT: u64 = 0x5851_f42d_4c95_7f2d;
+
+impl Pcg32
+{
+    pub fn new(seed: u64, stream: u64) -> Pcg32
+    {
+        let mut rng = Pcg32{ state: 0, inc: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+    pub fn next_u32(&mut self) -> u32
+    {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG_MULT).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+    // Uniform in [0, 1)
+    pub fn uniform(&mut self) -> f64
+    {
+        self.next_u32() as f64/4294967296.
+    }
+    // Skips delta outputs in O(log delta)
+    pub fn advance(&mut self, delta: u64)
+    {
+        let (mut mult, mut plus) = (PCG_MULT, self.inc);
+        let (mut acc_mult, mut acc_plus) = (1u64, 0u64);
+        let mut delta = delta;
+        while delta > 0
+        {
+            if delta & 1 == 1
+            {
+                acc_mult = acc_mult.wrapping_mul(mult);
+                acc_plus = acc_plus.wrapping_mul(mult).wrapping_add(plus);
+            }
+            plus = mult.wrapping_add(1).wrapping_mul(plus);
+            mult = mult.wrapping_mul(mult);
+            delta >>= 1;
+        }
+        self.state = acc_mult.wrapping_mul(self.state).wrapping_add(acc_plus);
+    }
+}
+
+fn mix_bits(v: u64) -> u64
+{
+    let mut v = v;
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+// Independent uniform samples that only depend on the seed, the pixel and
+// the sample index, so they don't change with the order pixels are rendered
+// in or with a render being stopped and resumed. Each sample has its own
+// run of 65536 dimensions in its pixel's stream.
+#[derive(Clone, Copy, Debug)]
+pub struct IndependentSampler
+{
+    pub seed: u64,
+    rng: Pcg32,
+}
+
+impl IndependentSampler
+{
+    pub fn new(seed: u64) -> IndependentSampler
+    {
+        IndependentSampler{ seed, rng: Pcg32::new(seed, 0) }
+    }
+    pub fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64)
+    {
+        let stream = mix_bits(((x as u64) << 32 | y as u64) ^ mix_bits(self.seed));
+        self.rng = Pcg32::new(mix_bits(stream), stream);
+        self.rng.advance(index.wrapping_mul(65536));
+    }
+    pub fn get_1d(&mut self) -> f64
+    {
+        self.rng.uniform()
+    }
+    pub fn get_2d(&mut self) -> (f64, f64)
+    {
+        let u = self.rng.uniform();
+        (u, self.rng.uniform())
+    }
+}
@@ -0,0 +1,129 @@
+use crate::color::{RGB, mat_mul};
+use crate::spectrum::{ResponseCurve, Spectrum, LAMBDA_MAX, LAMBDA_MIN};
+
+// Camera channel responses to the radiance of a scene. Radiance and
+// illuminants are given in the same terms, scene RGB for an RGB response
+// and spectra otherwise, so a sensor can't mix them.
+pub trait CameraResponse
+{
+    type Radiance: ?Sized;
+    fn respond(&self, l: &Self::Radiance) -> RGB;
+}
+
+// Matrix from linear scene RGB to camera RGB
+#[derive(Clone, Debug)]
+pub struct RgbResponse
+{
+    pub matrix: [[f64; 3]; 3],
+}
+
+impl CameraResponse for RgbResponse
+{
+    type Radiance = RGB;
+    fn respond(&self, l: &RGB) -> RGB
+    {
+        mat_mul(&self.matrix, *l)
+    }
+}
+
+// Spectral sensitivities of the red, green and blue channels
+#[derive(Clone, Debug)]
+pub struct SpectralResponse
+{
+    pub r: ResponseCurve,
+    pub g: ResponseCurve,
+    pub b: ResponseCurve,
+}
+
+impl CameraResponse for SpectralResponse
+{
+    type Radiance = dyn Spectrum;
+    fn respond(&self, l: &dyn Spectrum) -> RGB
+    {
+        integrate(&self.r, &self.g, &self.b, l)
+    }
+}
+
+// Sensor stage turning radiance into the values a camera records, white
+// balanced for the illuminant it was made with
+#[derive(Clone, Debug)]
+pub struct Sensor<R: CameraResponse>
+{
+    pub iso: f64,
+    pub shutter_time: f64,
+    pub f_number: f64,
+    response: R,
+    gains: RGB,
+}
+
+impl Sensor<RgbResponse>
+{
+    // Neutral sensor: ISO 100, one second at f/1 and identity response
+    pub fn ideal() -> Sensor<RgbResponse>
+    {
+        Sensor::new(RgbResponse{ matrix: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]] }, None)
+    }
+}
+
+impl<R: CameraResponse> Sensor<R>
+{
+    // ISO 100 for one second at f/1, without white balance if there's no
+    // illuminant
+    pub fn new(response: R, white_balance: Option<&R::Radiance>) -> Sensor<R>
+    {
+        let gains = match white_balance
+        {
+            Some(w) => white_balance_gains(response.respond(w)),
+            None => RGB::white(),
+        };
+        Sensor{ iso: 100., shutter_time: 1., f_number: 1., response, gains }
+    }
+    pub fn response(&self) -> &R
+    {
+        &self.response
+    }
+    // Per channel gains that make the white balance illuminant neutral,
+    // normalized to keep the green channel unchanged
+    pub fn white_balance_gains(&self) -> RGB
+    {
+        self.gains
+    }
+    // Scale from radiance to sensor values, ISO 100 for one second at f/1
+    // leaves radiance unchanged
+    pub fn exposure(&self) -> f64
+    {
+        self.shutter_time*self.iso/100./(self.f_number*self.f_number)
+    }
+    pub fn expose(&self, l: &R::Radiance) -> RGB
+    {
+        self.response.respond(l)*self.gains*self.exposure()
+    }
+}
+
+// A channel that doesn't see the illuminant, or a green one that doesn't,
+// keeps a gain of one rather than an infinite or zero one
+fn white_balance_gains(w: RGB) -> RGB
+{
+    let gain = |c: f64| if w.g/c > 0. && (w.g/c).is_finite() { w.g/c } else { 1. };
+    RGB::new(gain(w.r), 1., gain(w.b))
+}
+
+// Integrates the channel responses against a spectrum at 1nm steps,
+// normalized so that a constant unit spectrum gives a green value of one.
+// A green channel that is zero over the visible range sees nothing.
+fn integrate(r: &ResponseCurve, g: &ResponseCurve, b: &ResponseCurve, l: &dyn Spectrum) -> RGB
+{
+    let (mut cr, mut cg, mut cb, mut norm) = (0., 0., 0., 0.);
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX
+    {
+        let v = l.eval(lambda);
+        let gl = g.eval(lambda);
+        cr += r.eval(lambda)*v;
+        cg += gl*v;
+        cb += b.eval(lambda)*v;
+        norm += gl;
+        lambda += 1.;
+    }
+    if norm > 0. { RGB::new(cr, cg, cb)/norm } else { RGB::black() }
+}
@@ -0,0 +1,302 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::spectrum::{self, BlackbodySpectrum, ResponseCurve, Spectrum};
+use crate::vector::Point2;
+
+// Index of refraction of a glass for wavelength in nm by the Sellmeier
+// equation, with the C terms in square micrometers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sellmeier
+{
+    pub b: [f64; 3],
+    pub c: [f64; 3],
+}
+
+impl Spectrum for Sellmeier
+{
+    fn eval(&self, lambda: f64) -> f64
+    {
+        let l2 = (lambda*1e-3).powi(2);
+        let sum: f64 = self.b.iter().zip(&self.c).map(|(b, c)| b*l2/(l2 - c)).sum();
+        (1. + sum).sqrt()
+    }
+}
+
+// Manufacturer coefficients, Schott for BK7, BAF10 and SF11 and Malitson
+// (1965) for fused silica
+pub const GLASS_BK7: Sellmeier = Sellmeier{ b: [1.039_612_12, 0.231_792_344, 1.010_469_45], c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653] };
+pub const GLASS_BAF10: Sellmeier = Sellmeier{ b: [1.585_149_5, 0.143_559_385, 1.085_212_69], c: [0.009_266_812_82, 0.042_448_980_5, 105.613_573] };
+pub const GLASS_SF11: Sellmeier = Sellmeier{ b: [1.737_596_95, 0.313_747_346, 1.898_781_01], c: [0.013_188_707, 0.062_306_814_2, 155.236_29] };
+pub const GLASS_FUSED_SILICA: Sellmeier = Sellmeier{ b: [0.696_166_3, 0.407_942_6, 0.897_479_4], c: [0.004_679_148_3, 0.013_512_063, 97.934_002_5] };
+
+// CIE standard illuminant A, a Planckian radiator at about 2856 K defined
+// with the older value of the second radiation constant, scaled to a
+// luminance of one
+pub fn illuminant_a() -> ResponseCurve
+{
+    let lambda: Vec<f64> = (0..=106).map(|i| 300. + 5.*i as f64).collect();
+    let c2: f64 = 1.435e7;
+    let value = lambda.iter().map(|&l| 100.*(560./l).powi(5)*((c2/(2848.*560.)).exp() - 1.)/((c2/(2848.*l)).exp() - 1.)).collect();
+    let a = ResponseCurve::new(lambda, value);
+    let y = spectrum::spectrum_to_xyz(&a).y;
+    ResponseCurve::new(a.lambda, a.value.iter().map(|v| v/y).collect())
+}
+
+pub fn illuminant_d50() -> ResponseCurve
+{
+    spectrum::daylight(Point2::new(0.34567, 0.35850))
+}
+
+pub fn illuminant_d65() -> ResponseCurve
+{
+    spectrum::daylight(Point2::new(0.31271, 0.32902))
+}
+
+// Complex index of refraction of a metal, eta + i k
+#[derive(Clone, Debug)]
+pub struct MetalIor
+{
+    pub eta: ResponseCurve,
+    pub k: ResponseCurve,
+}
+
+// Measured optical constants of Cu, Au and Ag from Palik, "Handbook of
+// Optical Constants of Solids" (1985), at photon energies from 4.15 down to
+// 1.40 eV in steps of 0.05 eV
+const CU_ETA: [f64; 56] = [1.400313, 1.38, 1.358438, 1.34, 1.329063, 1.325, 1.3325, 1.34, 1.334375, 1.325, 1.317812, 1.31, 1.300313, 1.29,
+                           1.281563, 1.27, 1.249062, 1.225, 1.2, 1.18, 1.174375, 1.175, 1.1775, 1.18, 1.178125, 1.175, 1.172812, 1.17,
+                           1.165312, 1.16, 1.155312, 1.15, 1.142812, 1.135, 1.131562, 1.12, 1.092437, 1.04, 0.950375, 0.826, 0.645875, 0.468,
+                           0.35125, 0.272, 0.230813, 0.214, 0.20925, 0.213, 0.21625, 0.223, 0.2365, 0.25, 0.254188, 0.26, 0.28, 0.3];
+const CU_K: [f64; 56] = [1.662125, 1.687, 1.703313, 1.72, 1.744563, 1.77, 1.791625, 1.81, 1.822125, 1.834, 1.85175, 1.872, 1.89425, 1.916,
+                         1.931688, 1.95, 1.972438, 2.015, 2.121562, 2.21, 2.177188, 2.13, 2.160063, 2.21, 2.249938, 2.289, 2.326, 2.362,
+                         2.397625, 2.433, 2.469187, 2.504, 2.535875, 2.564, 2.589625, 2.605, 2.595562, 2.583, 2.5765, 2.599, 2.678062, 2.809,
+                         3.01075, 3.24, 3.458187, 3.67, 3.863125, 4.05, 4.239563, 4.43, 4.619563, 4.817, 5.034125, 5.26, 5.485625, 5.717];
+const AU_ETA: [f64; 56] = [1.795, 1.812, 1.822625, 1.83, 1.837125, 1.84, 1.83425, 1.824, 1.812, 1.798, 1.782, 1.766, 1.7525, 1.74,
+                           1.727625, 1.716, 1.705875, 1.696, 1.68475, 1.674, 1.666, 1.658, 1.64725, 1.636, 1.628, 1.616, 1.59625, 1.562,
+                           1.502125, 1.426, 1.345875, 1.242, 1.08675, 0.916, 0.7545, 0.608, 0.49175, 0.402, 0.3455, 0.306, 0.267625, 0.236,
+                           0.212375, 0.194, 0.17775, 0.166, 0.161, 0.16, 0.160875, 0.164, 0.1695, 0.176, 0.181375, 0.188, 0.198125, 0.21];
+const AU_K: [f64; 56] = [1.920375, 1.92, 1.918875, 1.916, 1.911375, 1.904, 1.891375, 1.878, 1.86825, 1.86, 1.85175, 1.846, 1.84525, 1.848,
+                         1.852375, 1.862, 1.883, 1.906, 1.9225, 1.936, 1.94775, 1.956, 1.959375, 1.958, 1.951375, 1.94, 1.9175, 1.889,
+                         1.864, 1.846, 1.83925, 1.848, 1.88, 1.931, 2.0, 2.083, 2.193875, 2.328, 2.485, 2.645, 2.806875, 2.969,
+                         3.13225, 3.303, 3.48475, 3.668, 3.847625, 4.03, 4.225, 4.412, 4.606, 4.81, 5.01625, 5.243, 5.4875, 5.719];
+const AG_ETA: [f64; 56] = [1.519, 1.496, 1.4325, 1.323, 1.142062, 0.932, 0.719062, 0.526, 0.388125, 0.294, 0.253313, 0.238, 0.221438, 0.209,
+                           0.194813, 0.186, 0.192063, 0.2, 0.198063, 0.192, 0.182, 0.173, 0.172625, 0.173, 0.166688, 0.16, 0.1585, 0.157,
+                           0.151063, 0.144, 0.137313, 0.132, 0.13025, 0.13, 0.129938, 0.13, 0.130063, 0.129, 0.124375, 0.12, 0.119313, 0.121,
+                           0.1255, 0.131, 0.136125, 0.14, 0.140063, 0.14, 0.144313, 0.148, 0.145875, 0.143, 0.142563, 0.145, 0.151938, 0.163];
+const AG_K: [f64; 56] = [1.08, 0.882, 0.761063, 0.647, 0.550875, 0.504, 0.554375, 0.663, 0.818563, 0.986, 1.120687, 1.24, 1.34525, 1.44,
+                         1.53375, 1.61, 1.641875, 1.67, 1.735, 1.81, 1.87875, 1.95, 2.029375, 2.11, 2.18625, 2.26, 2.329375, 2.4,
+                         2.47875, 2.56, 2.64, 2.72, 2.798125, 2.88, 2.97375, 3.07, 3.159375, 3.25, 3.348125, 3.45, 3.55375, 3.66,
+                         3.76625, 3.88, 4.010625, 4.15, 4.293125, 4.44, 4.58625, 4.74, 4.908125, 5.09, 5.28875, 5.5, 5.720624, 5.95];
+
+// Aluminium from Rakić, "Algorithm for the determination of intrinsic
+// optical constants of metal films: application to aluminum" (1995)
+const AL_LAMBDA: [f64; 11] = [300.0, 350.0, 400.0, 450.0, 500.0, 550.0, 600.0, 650.0, 700.0, 750.0, 800.0];
+const AL_ETA: [f64; 11] = [0.276, 0.358, 0.49, 0.618, 0.769, 0.958, 1.2, 1.49, 1.83, 2.4, 2.8];
+const AL_K: [f64; 11] = [3.61, 4.23, 4.86, 5.47, 6.08, 6.69, 7.26, 7.82, 8.31, 8.62, 8.45];
+
+fn palik_lambda() -> Vec<f64>
+{
+    (0..56).map(|i| 1239.84/(4.15 - 0.05*i as f64)).collect()
+}
+
+// Tables that end inside the visible range hold their last value up to it
+fn tabulated(lambda: &[f64], value: &[f64]) -> ResponseCurve
+{
+    let (mut lambda, mut value) = (lambda.to_vec(), value.to_vec());
+    if lambda[lambda.len() - 1] < spectrum::LAMBDA_MAX
+    {
+        lambda.push(spectrum::LAMBDA_MAX);
+        value.push(value[value.len() - 1]);
+    }
+    ResponseCurve::new(lambda, value)
+}
+
+pub fn metal(name: &str) -> Option<MetalIor>
+{
+    let lambda = palik_lambda();
+    let (lambda, eta, k): (&[f64], &[f64], &[f64]) = match name
+    {
+        "Au" => (&lambda, &AU_ETA, &AU_K),
+        "Ag" => (&lambda, &AG_ETA, &AG_K),
+        "Cu" => (&lambda, &CU_ETA, &CU_K),
+        "Al" => (&AL_LAMBDA, &AL_ETA, &AL_K),
+        _ => return None,
+    };
+    Some(MetalIor{ eta: tabulated(lambda, eta), k: tabulated(lambda, k) })
+}
+
+// Relative power of the CIE F-series fluorescent illuminants F1 to F12 at
+// 5 nm from 380 to 780 nm, from CIE 15. Some of the measurements happen to
+// read like pi and tau.
+#[allow(clippy::approx_constant)]
+const FLUORESCENT: [[f64; 81]; 12] = [
+    [1.87, 2.36, 2.94, 3.47, 5.17, 19.49, 6.13, 6.24, 7.01, 7.79, 8.56, 43.67, 16.94, 10.72, 11.35, 11.89, 12.37,
+     12.75, 13.0, 13.15, 13.23, 13.17, 13.13, 12.85, 12.52, 12.2, 11.83, 11.5, 11.22, 11.05, 11.03, 11.18, 11.53, 27.74,
+     17.05, 13.55, 14.33, 15.01, 15.52, 18.29, 19.55, 15.48, 14.91, 14.15, 13.22, 12.19, 11.12, 10.03, 8.95, 7.96, 7.02,
+     6.2, 5.42, 4.73, 4.15, 3.64, 3.2, 2.81, 2.47, 2.18, 1.93, 1.72, 1.67, 1.43, 1.29, 1.19, 1.08, 0.96,
+     0.88, 0.81, 0.77, 0.75, 0.73, 0.68, 0.69, 0.64, 0.68, 0.69, 0.61, 0.52, 0.43],
+    [1.18, 1.48, 1.84, 2.15, 3.44, 15.69, 3.85, 3.74, 4.19, 4.62, 5.06, 34.98, 11.81, 6.27, 6.63, 6.93, 7.19,
+     7.4, 7.54, 7.62, 7.65, 7.62, 7.62, 7.45, 7.28, 7.15, 7.05, 7.04, 7.16, 7.47, 8.04, 8.88, 10.01, 24.88,
+     16.64, 14.59, 16.16, 17.56, 18.62, 21.47, 22.79, 19.29, 18.66, 17.73, 16.54, 15.21, 13.8, 12.36, 10.95, 9.65, 8.4,
+     7.32, 6.31, 5.43, 4.68, 4.02, 3.45, 2.96, 2.55, 2.19, 1.89, 1.64, 1.53, 1.27, 1.1, 0.99, 0.88, 0.76,
+     0.68, 0.61, 0.56, 0.54, 0.51, 0.47, 0.47, 0.43, 0.46, 0.47, 0.4, 0.33, 0.27],
+    [0.82, 1.02, 1.26, 1.44, 2.57, 14.36, 2.7, 2.45, 2.73, 3.0, 3.28, 31.85, 9.47, 4.02, 4.25, 4.44, 4.59,
+     4.72, 4.8, 4.86, 4.87, 4.85, 4.88, 4.77, 4.67, 4.62, 4.62, 4.73, 4.99, 5.48, 6.25, 7.34, 8.78, 23.82,
+     16.14, 14.59, 16.63, 18.49, 19.95, 23.11, 24.69, 21.41, 20.85, 19.93, 18.67, 17.22, 15.65, 14.04, 12.45, 10.95, 9.51,
+     8.27, 7.11, 6.09, 5.22, 4.45, 3.8, 3.23, 2.75, 2.33, 1.99, 1.7, 1.55, 1.27, 1.09, 0.96, 0.83, 0.71,
+     0.62, 0.54, 0.49, 0.46, 0.43, 0.39, 0.39, 0.35, 0.38, 0.39, 0.33, 0.28, 0.21],
+    [0.57, 0.7, 0.87, 0.98, 2.01, 13.75, 1.95, 1.59, 1.76, 1.93, 2.1, 30.28, 8.03, 2.55, 2.7, 2.82, 2.91,
+     2.99, 3.04, 3.08, 3.09, 3.09, 3.14, 3.06, 3.0, 2.98, 3.01, 3.14, 3.41, 3.9, 4.69, 5.81, 7.32, 22.59,
+     15.11, 13.88, 16.33, 18.68, 20.64, 24.28, 26.26, 23.28, 22.94, 22.14, 20.91, 19.43, 17.74, 16.0, 14.42, 12.56, 10.93,
+     9.52, 8.18, 7.01, 6.0, 5.11, 4.36, 3.69, 3.13, 2.64, 2.24, 1.91, 1.7, 1.39, 1.18, 1.03, 0.88, 0.74,
+     0.64, 0.54, 0.49, 0.46, 0.42, 0.37, 0.37, 0.33, 0.35, 0.36, 0.31, 0.26, 0.19],
+    [1.87, 2.35, 2.92, 3.45, 5.1, 18.91, 6.0, 6.11, 6.85, 7.58, 8.31, 40.76, 16.06, 10.32, 10.91, 11.4, 11.83,
+     12.17, 12.4, 12.54, 12.58, 12.52, 12.47, 12.2, 11.89, 11.61, 11.33, 11.1, 10.96, 10.97, 11.16, 11.54, 12.12, 27.78,
+     17.73, 14.47, 15.2, 15.77, 16.1, 18.54, 19.5, 15.39, 14.64, 13.72, 12.69, 11.57, 10.45, 9.35, 8.29, 7.32, 6.41,
+     5.63, 4.9, 4.26, 3.72, 3.25, 2.83, 2.49, 2.19, 1.93, 1.71, 1.52, 1.48, 1.26, 1.13, 1.05, 0.96, 0.85,
+     0.78, 0.72, 0.68, 0.67, 0.65, 0.61, 0.62, 0.59, 0.62, 0.64, 0.55, 0.47, 0.4],
+    [1.05, 1.31, 1.63, 1.9, 3.11, 14.8, 3.43, 3.3, 3.68, 4.07, 4.45, 32.61, 10.74, 5.48, 5.78, 6.03, 6.25,
+     6.41, 6.52, 6.58, 6.59, 6.56, 6.56, 6.42, 6.28, 6.2, 6.19, 6.3, 6.6, 7.12, 7.94, 9.07, 10.49, 25.22,
+     17.46, 15.63, 17.22, 18.53, 19.43, 21.97, 23.01, 19.41, 18.56, 17.42, 16.09, 14.64, 13.15, 11.68, 10.25, 8.96, 7.74,
+     6.69, 5.71, 4.87, 4.16, 3.55, 3.02, 2.57, 2.2, 1.87, 1.6, 1.37, 1.29, 1.05, 0.91, 0.81, 0.71, 0.61,
+     0.54, 0.48, 0.44, 0.43, 0.4, 0.37, 0.38, 0.35, 0.39, 0.41, 0.33, 0.26, 0.21],
+    [2.56, 3.18, 3.84, 4.53, 6.15, 19.37, 7.37, 7.05, 7.71, 8.41, 9.15, 44.14, 17.52, 11.35, 12.0, 12.58, 13.08,
+     13.45, 13.71, 13.88, 13.95, 13.93, 13.82, 13.64, 13.43, 13.25, 13.08, 12.93, 12.78, 12.6, 12.44, 12.33, 12.26, 29.52,
+     17.05, 12.44, 12.58, 12.72, 12.83, 15.46, 16.75, 12.83, 12.67, 12.45, 12.19, 11.89, 11.6, 11.35, 11.12, 10.95, 10.76,
+     10.42, 10.11, 10.04, 10.02, 10.11, 9.87, 8.65, 7.27, 6.44, 5.83, 5.41, 5.04, 4.57, 4.12, 3.77, 3.46, 3.08,
+     2.73, 2.47, 2.25, 2.06, 1.9, 1.75, 1.62, 1.54, 1.45, 1.32, 1.17, 0.99, 0.81],
+    [1.21, 1.5, 1.81, 2.13, 3.17, 13.08, 3.83, 3.45, 3.86, 4.42, 5.09, 34.1, 12.42, 7.68, 8.6, 9.46, 10.24,
+     10.84, 11.33, 11.71, 11.98, 12.17, 12.28, 12.32, 12.35, 12.44, 12.55, 12.68, 12.77, 12.72, 12.6, 12.43, 12.22, 28.96,
+     16.51, 11.79, 11.76, 11.77, 11.84, 14.61, 16.11, 12.34, 12.53, 12.72, 12.92, 13.12, 13.34, 13.61, 13.87, 14.07, 14.2,
+     14.16, 14.13, 14.34, 14.5, 14.46, 14.0, 12.58, 10.99, 9.98, 9.22, 8.62, 8.07, 7.39, 6.71, 6.16, 5.63, 5.03,
+     4.46, 4.02, 3.66, 3.36, 3.09, 2.85, 2.65, 2.51, 2.37, 2.15, 1.89, 1.61, 1.32],
+    [0.9, 1.12, 1.36, 1.6, 2.59, 12.8, 3.05, 2.56, 2.86, 3.3, 3.82, 32.62, 10.77, 5.84, 6.57, 7.25, 7.86,
+     8.35, 8.75, 9.06, 9.31, 9.48, 9.61, 9.68, 9.74, 9.88, 10.04, 10.26, 10.48, 10.63, 10.78, 10.96, 11.18, 27.71,
+     16.29, 12.28, 12.74, 13.21, 13.65, 16.57, 18.14, 14.55, 14.65, 14.66, 14.61, 14.5, 14.39, 14.4, 14.47, 14.62, 14.72,
+     14.55, 14.4, 14.58, 14.88, 15.51, 15.47, 13.2, 10.57, 9.18, 8.25, 7.57, 7.03, 6.35, 5.72, 5.25, 4.8, 4.29,
+     3.8, 3.43, 3.12, 2.86, 2.64, 2.43, 2.26, 2.14, 2.02, 1.83, 1.61, 1.38, 1.12],
+    [1.11, 0.63, 0.62, 0.57, 1.48, 12.16, 2.12, 2.7, 3.74, 5.14, 6.75, 34.39, 14.86, 10.4, 10.76, 10.67, 10.11,
+     9.27, 8.29, 7.29, 7.91, 16.64, 16.73, 10.44, 5.94, 3.34, 2.35, 1.88, 1.59, 1.47, 1.8, 5.71, 40.98, 73.69,
+     33.61, 8.24, 3.38, 2.47, 2.14, 4.86, 11.45, 14.79, 12.16, 8.97, 6.52, 8.31, 44.12, 34.55, 12.09, 12.15, 10.52,
+     4.43, 1.95, 2.19, 3.19, 2.77, 2.29, 2.0, 1.52, 1.35, 1.47, 1.79, 1.74, 1.02, 1.14, 3.32, 4.49, 2.05,
+     0.49, 0.24, 0.21, 0.21, 0.24, 0.24, 0.21, 0.17, 0.21, 0.22, 0.17, 0.12, 0.09],
+    [0.91, 0.63, 0.46, 0.37, 1.29, 12.68, 1.59, 1.79, 2.46, 3.33, 4.49, 33.94, 12.13, 6.95, 7.19, 7.12, 6.72,
+     6.13, 5.46, 4.79, 5.66, 14.29, 14.96, 8.97, 4.72, 2.33, 1.47, 1.1, 0.89, 0.83, 1.18, 4.9, 39.59, 72.84,
+     32.61, 7.52, 2.83, 1.96, 1.67, 4.43, 11.28, 14.76, 12.73, 9.74, 7.33, 9.72, 55.27, 42.58, 13.18, 13.16, 12.26,
+     5.11, 2.07, 2.34, 3.58, 3.01, 2.48, 2.14, 1.54, 1.33, 1.46, 1.94, 2.0, 1.2, 1.35, 4.1, 5.58, 2.51,
+     0.57, 0.27, 0.23, 0.21, 0.24, 0.24, 0.2, 0.24, 0.32, 0.26, 0.16, 0.12, 0.09],
+    [0.96, 0.64, 0.4, 0.33, 1.19, 12.48, 1.12, 0.94, 1.08, 1.37, 1.78, 29.05, 7.9, 2.65, 2.71, 2.65, 2.49,
+     2.33, 2.1, 1.91, 3.01, 10.83, 11.88, 6.88, 3.43, 1.49, 0.92, 0.71, 0.6, 0.63, 1.1, 4.56, 34.4, 65.4,
+     29.48, 7.16, 3.08, 2.47, 2.27, 5.09, 11.96, 15.32, 14.27, 11.86, 9.28, 12.31, 68.53, 53.02, 14.67, 14.38, 14.71,
+     6.46, 2.57, 2.75, 4.18, 3.44, 2.81, 2.42, 1.64, 1.36, 1.49, 2.14, 2.34, 1.42, 1.61, 5.04, 6.98, 3.19,
+     0.71, 0.3, 0.26, 0.23, 0.28, 0.28, 0.21, 0.17, 0.21, 0.19, 0.15, 0.1, 0.05],
+];
+
+// CIE fluorescent illuminant F1 to F12, scaled to a luminance of one
+pub fn illuminant_f(n: usize) -> Option<ResponseCurve>
+{
+    let table = FLUORESCENT.get(n.checked_sub(1)?)?;
+    let lambda = (0..81).map(|i| 380. + 5.*i as f64).collect();
+    let f = ResponseCurve::new(lambda, table.to_vec());
+    let y = spectrum::spectrum_to_xyz(&f).y;
+    Some(ResponseCurve::new(f.lambda, f.value.iter().map(|v| v/y).collect()))
+}
+
+fn invalid(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Measured data in the CSV layout of refractiveindex.info: a "wl,n" block
+// and an optional "wl,k" block, wavelengths in micrometers
+pub fn parse_ior_csv(text: &str) -> io::Result<MetalIor>
+{
+    let (mut n, mut k) = ((Vec::new(), Vec::new()), (Vec::new(), Vec::new()));
+    let mut block = None;
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty())
+    {
+        match line
+        {
+            "wl,n" => block = Some(0),
+            "wl,k" => block = Some(1),
+            _ =>
+            {
+                let mut parts = line.split(',').map(|v| v.trim().parse::<f64>());
+                let (wl, v) = match (parts.next(), parts.next())
+                {
+                    (Some(Ok(wl)), Some(Ok(v))) => (wl*1e3, v),
+                    _ => return Err(invalid(&format!("invalid IOR line {:?}", line))),
+                };
+                let target = match block
+                {
+                    Some(0) => &mut n,
+                    Some(_) => &mut k,
+                    None => return Err(invalid("IOR data before a wl,n header")),
+                };
+                if target.0.last().is_some_and(|&last| wl <= last)
+                {
+                    return Err(invalid("IOR wavelengths must increase"));
+                }
+                target.0.push(wl);
+                target.1.push(v);
+            }
+        }
+    }
+    if n.0.is_empty()
+    {
+        return Err(invalid("missing IOR data"));
+    }
+    if k.0.is_empty()
+    {
+        k = (n.0.clone(), vec![0.; n.0.len()]);
+    }
+    Ok(MetalIor{ eta: ResponseCurve::new(n.0, n.1), k: ResponseCurve::new(k.0, k.1) })
+}
+
+pub fn load_ior_csv(path: &Path) -> io::Result<MetalIor>
+{
+    parse_ior_csv(&fs::read_to_string(path)?)
+}
+
+// Spectra by the names scenes use:
+//   stdillum-A, stdillum-D50, stdillum-D65, stdillum-F1 to stdillum-F12:
+//     luminance of one
+//   blackbody-<kelvin>: peak of one, like blackbody-2700
+//   metal-<Au|Ag|Cu|Al>-<eta|k>: see metal
+//   glass-<BK7|BAF10|SF11|F-silica>: index of refraction
+pub fn named(name: &str) -> Option<Box<dyn Spectrum + Send + Sync>>
+{
+    if let Some(n) = name.strip_prefix("stdillum-F")
+    {
+        return Some(Box::new(illuminant_f(n.parse().ok()?)?));
+    }
+    if let Some(t) = name.strip_prefix("blackbody-")
+    {
+        let t = t.parse::<f64>().ok().filter(|&t| t > 0.)?;
+        return Some(Box::new(BlackbodySpectrum{ t, normalized: true }));
+    }
+    if let Some(rest) = name.strip_prefix("metal-")
+    {
+        let (metal_name, part) = rest.split_once('-')?;
+        let ior = metal(metal_name)?;
+        return match part
+        {
+            "eta" => Some(Box::new(ior.eta)),
+            "k" => Some(Box::new(ior.k)),
+            _ => None,
+        };
+    }
+    let s: Box<dyn Spectrum + Send + Sync> = match name
+    {
+        "stdillum-A" => Box::new(illuminant_a()),
+        "stdillum-D50" => Box::new(illuminant_d50()),
+        "stdillum-D65" => Box::new(illuminant_d65()),
+        "glass-BK7" => Box::new(GLASS_BK7),
+        "glass-BAF10" => Box::new(GLASS_BAF10),
+        "glass-SF11" => Box::new(GLASS_SF11),
+        "glass-F-silica" => Box::new(GLASS_FUSED_SILICA),
+        _ => return None,
+    };
+    Some(s)
+}
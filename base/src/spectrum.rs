@@ -0,0 +1,592 @@
+use std::ops;
+use crate::color::RGB;
+use crate::colorspace::RGBColorSpace;
+use crate::vector::{Point2, Vec3d};
+
+// Wavelengths carried by a path
+pub const N_SPECTRUM_SAMPLES: usize = 4;
+
+// Visible range in nm
+pub const LAMBDA_MIN: f64 = 360.;
+pub const LAMBDA_MAX: f64 = 830.;
+
+// Wavelengths of a path with the density each one was sampled with. The
+// first one is the hero wavelength, the others are evenly spaced after it
+// and wrap around the range, so together they cover it stratified.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledWavelengths
+{
+    pub lambda: [f64; N_SPECTRUM_SAMPLES],
+    pub pdf: [f64; N_SPECTRUM_SAMPLES],
+}
+
+impl SampledWavelengths
+{
+    pub fn sample_uniform(u: f64, min: f64, max: f64) -> SampledWavelengths
+    {
+        let mut lambda = [0.; N_SPECTRUM_SAMPLES];
+        lambda[0] = min + u*(max - min);
+        let delta = (max - min)/N_SPECTRUM_SAMPLES as f64;
+        for i in 1..N_SPECTRUM_SAMPLES
+        {
+            lambda[i] = lambda[i - 1] + delta;
+            if lambda[i] > max
+            {
+                lambda[i] = min + (lambda[i] - max);
+            }
+        }
+        SampledWavelengths{ lambda, pdf: [1./(max - min); N_SPECTRUM_SAMPLES] }
+    }
+    // Importance samples the visible range with a density close to the
+    // luminance response, each wavelength from u shifted by i/N
+    pub fn sample_visible(u: f64) -> SampledWavelengths
+    {
+        let mut w = SampledWavelengths{ lambda: [0.; N_SPECTRUM_SAMPLES], pdf: [0.; N_SPECTRUM_SAMPLES] };
+        for i in 0..N_SPECTRUM_SAMPLES
+        {
+            let up = (u + i as f64/N_SPECTRUM_SAMPLES as f64).fract();
+            w.lambda[i] = sample_visible_wavelength(up);
+            w.pdf[i] = visible_wavelength_pdf(w.lambda[i]);
+        }
+        w
+    }
+    // Keeps only the hero wavelength, for events like dispersion that send
+    // each wavelength its own way
+    pub fn terminate_secondary(&mut self)
+    {
+        if self.secondary_terminated()
+        {
+            return;
+        }
+        for p in self.pdf.iter_mut().skip(1)
+        {
+            *p = 0.;
+        }
+        self.pdf[0] /= N_SPECTRUM_SAMPLES as f64;
+    }
+    pub fn secondary_terminated(&self) -> bool
+    {
+        self.pdf[1..].iter().all(|&p| p == 0.)
+    }
+}
+
+// Normalized over [360, 830]
+pub fn visible_wavelength_pdf(lambda: f64) -> f64
+{
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda)
+    {
+        return 0.;
+    }
+    0.003_939_804_2/(0.0072*(lambda - 538.)).cosh().powi(2)
+}
+
+pub fn sample_visible_wavelength(u: f64) -> f64
+{
+    538. - 138.888_889*(0.856_910_62 - 1.827_501_97*u).atanh()
+}
+
+// Values of a spectrum at the sampled wavelengths
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledSpectrum
+{
+    pub values: [f64; N_SPECTRUM_SAMPLES],
+}
+
+impl SampledSpectrum
+{
+    pub fn new(values: [f64; N_SPECTRUM_SAMPLES]) -> SampledSpectrum
+    {
+        SampledSpectrum{ values }
+    }
+    pub fn constant(c: f64) -> SampledSpectrum
+    {
+        SampledSpectrum{ values: [c; N_SPECTRUM_SAMPLES] }
+    }
+    pub fn zero() -> SampledSpectrum
+    {
+        SampledSpectrum::constant(0.)
+    }
+    pub fn is_black(&self) -> bool
+    {
+        self.values.iter().all(|&v| v == 0.)
+    }
+    pub fn max_comp(&self) -> f64
+    {
+        self.values.iter().cloned().fold(f64::NEG_INFINITY, f64::max)
+    }
+    pub fn average(&self) -> f64
+    {
+        self.values.iter().sum::<f64>()/N_SPECTRUM_SAMPLES as f64
+    }
+    pub fn map<F: Fn(f64) -> f64>(&self, f: F) -> SampledSpectrum
+    {
+        let mut values = self.values;
+        for v in values.iter_mut()
+        {
+            *v = f(*v);
+        }
+        SampledSpectrum{ values }
+    }
+    // Zero where the divisor is
+    pub fn safe_div(&self, other: &SampledSpectrum) -> SampledSpectrum
+    {
+        let mut values = [0.; N_SPECTRUM_SAMPLES];
+        for (i, v) in values.iter_mut().enumerate()
+        {
+            if other.values[i] != 0.
+            {
+                *v = self.values[i]/other.values[i];
+            }
+        }
+        SampledSpectrum{ values }
+    }
+    // Monte Carlo estimate of the CIE XYZ of the spectrum, normalized so that
+    // a constant unit spectrum has a luminance of one. Wavelengths with zero
+    // density don't contribute.
+    pub fn to_xyz(&self, w: &SampledWavelengths) -> Vec3d
+    {
+        let mut xyz = Vec3d::zero();
+        for i in 0..N_SPECTRUM_SAMPLES
+        {
+            if w.pdf[i] != 0.
+            {
+                let (x, y, z) = cie_xyz(w.lambda[i]);
+                let v = self.values[i]/w.pdf[i];
+                xyz = xyz + Vec3d::new(x*v, y*v, z*v);
+            }
+        }
+        xyz/(N_SPECTRUM_SAMPLES as f64*cie_y_integral())
+    }
+    pub fn y(&self, w: &SampledWavelengths) -> f64
+    {
+        self.to_xyz(w).y
+    }
+    pub fn to_rgb(&self, w: &SampledWavelengths, space: &RGBColorSpace) -> RGB
+    {
+        space.from_xyz(self.to_xyz(w))
+    }
+}
+
+impl ops::Index<usize> for SampledSpectrum
+{
+    type Output = f64;
+    fn index(&self, i: usize) -> &f64
+    {
+        &self.values[i]
+    }
+}
+
+impl ops::IndexMut<usize> for SampledSpectrum
+{
+    fn index_mut(&mut self, i: usize) -> &mut f64
+    {
+        &mut self.values[i]
+    }
+}
+
+fn zip(a: SampledSpectrum, b: SampledSpectrum, f: fn(f64, f64) -> f64) -> SampledSpectrum
+{
+    let mut values = a.values;
+    for (v, &w) in values.iter_mut().zip(&b.values)
+    {
+        *v = f(*v, w);
+    }
+    SampledSpectrum{ values }
+}
+
+impl ops::Add for SampledSpectrum
+{
+    type Output = SampledSpectrum;
+    fn add(self, other: SampledSpectrum) -> SampledSpectrum
+    {
+        zip(self, other, |a, b| a + b)
+    }
+}
+
+impl ops::AddAssign for SampledSpectrum
+{
+    fn add_assign(&mut self, other: SampledSpectrum)
+    {
+        *self = *self + other;
+    }
+}
+
+impl ops::Sub for SampledSpectrum
+{
+    type Output = SampledSpectrum;
+    fn sub(self, other: SampledSpectrum) -> SampledSpectrum
+    {
+        zip(self, other, |a, b| a - b)
+    }
+}
+
+impl ops::Mul for SampledSpectrum
+{
+    type Output = SampledSpectrum;
+    fn mul(self, other: SampledSpectrum) -> SampledSpectrum
+    {
+        zip(self, other, |a, b| a*b)
+    }
+}
+
+impl ops::MulAssign for SampledSpectrum
+{
+    fn mul_assign(&mut self, other: SampledSpectrum)
+    {
+        *self = *self*other;
+    }
+}
+
+impl ops::Mul<f64> for SampledSpectrum
+{
+    type Output = SampledSpectrum;
+    fn mul(self, s: f64) -> SampledSpectrum
+    {
+        self.map(|v| v*s)
+    }
+}
+
+impl ops::MulAssign<f64> for SampledSpectrum
+{
+    fn mul_assign(&mut self, s: f64)
+    {
+        *self = *self*s;
+    }
+}
+
+impl ops::Div for SampledSpectrum
+{
+    type Output = SampledSpectrum;
+    fn div(self, other: SampledSpectrum) -> SampledSpectrum
+    {
+        zip(self, other, |a, b| a/b)
+    }
+}
+
+impl ops::Div<f64> for SampledSpectrum
+{
+    type Output = SampledSpectrum;
+    fn div(self, s: f64) -> SampledSpectrum
+    {
+        self.map(|v| v/s)
+    }
+}
+
+// Function of wavelength in nm
+pub trait Spectrum
+{
+    fn eval(&self, lambda: f64) -> f64;
+    fn sample(&self, w: &SampledWavelengths) -> SampledSpectrum
+    {
+        let mut s = SampledSpectrum::zero();
+        for (v, &l) in s.values.iter_mut().zip(&w.lambda)
+        {
+            *v = self.eval(l);
+        }
+        s
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConstantSpectrum
+{
+    pub c: f64,
+}
+
+impl Spectrum for ConstantSpectrum
+{
+    fn eval(&self, _: f64) -> f64
+    {
+        self.c
+    }
+}
+
+// Tabulated curve over wavelength in nm, linearly interpolated between the
+// samples and zero outside of them
+#[derive(Clone, Debug)]
+pub struct ResponseCurve
+{
+    pub lambda: Vec<f64>,
+    pub value: Vec<f64>,
+}
+
+impl ResponseCurve
+{
+    pub fn new(lambda: Vec<f64>, value: Vec<f64>) -> ResponseCurve
+    {
+        assert_eq!(lambda.len(), value.len(), "wavelengths and values differ in length");
+        ResponseCurve{ lambda, value }
+    }
+    pub fn eval(&self, l: f64) -> f64
+    {
+        let n = self.lambda.len();
+        if n == 0 || l < self.lambda[0] || l > self.lambda[n - 1]
+        {
+            return 0.;
+        }
+        let i = match self.lambda.iter().position(|&x| x > l)
+        {
+            Some(i) => i,
+            None => return self.value[n - 1],
+        };
+        let t = (l - self.lambda[i - 1])/(self.lambda[i] - self.lambda[i - 1]);
+        (1. - t)*self.value[i - 1] + t*self.value[i]
+    }
+}
+
+impl Spectrum for ResponseCurve
+{
+    fn eval(&self, lambda: f64) -> f64
+    {
+        ResponseCurve::eval(self, lambda)
+    }
+}
+
+// CIE 1931 2 degree color matching functions at 1 nm from 360 to 830 nm,
+// interpolated from the normative 5 nm table of CIE 15 with the Sprague
+// polynomials it recommends
+const CIE_X: [f64; 471] = [0.0001299, 0.0001486015, 0.0001669911, 0.0001859777, 0.0002072768, 0.0002321, 0.0002604965, 0.0002929255, 0.0003294901, 0.0003700579,
+                           0.0004149, 0.0004645842, 0.000519208, 0.0005813406, 0.0006546797, 0.0007416, 0.0008435541, 0.0009640134, 0.001097242, 0.001233421,
+                           0.001368, 0.001504767, 0.001644622, 0.001800854, 0.00199338, 0.002236, 0.002532329, 0.002891076, 0.00330465, 0.003757311,
+                           0.004243, 0.004768864, 0.005333869, 0.005971786, 0.006733262, 0.00765, 0.00873428, 0.01002195, 0.01144631, 0.01289396,
+                           0.01431, 0.01573362, 0.01717204, 0.01876476, 0.02072244, 0.02319, 0.02618582, 0.02976602, 0.03389645, 0.03848936,
+                           0.04351, 0.04901774, 0.05503813, 0.06170003, 0.06918842, 0.07763, 0.08697973, 0.09715795, 0.1083201, 0.120699,
+                           0.13438, 0.1492586, 0.1653095, 0.182035, 0.1986954, 0.21477, 0.2302191, 0.2449316, 0.2588084, 0.2718125,
+                           0.2839, 0.294966, 0.3049276, 0.3137996, 0.3216425, 0.3285, 0.3343584, 0.3392224, 0.3431298, 0.3461317,
+                           0.34828, 0.34961, 0.3501559, 0.3500073, 0.3492782, 0.34806, 0.3463798, 0.3442567, 0.341783, 0.3390737,
+                           0.3362, 0.3331712, 0.3300153, 0.3266437, 0.3229055, 0.3187, 0.3140353, 0.3088921, 0.3032836, 0.2972482,
+                           0.2908, 0.2839397, 0.2767146, 0.2689724, 0.2604743, 0.2511, 0.2409112, 0.2299058, 0.2183731, 0.2067569,
+                           0.19536, 0.1841858, 0.1732932, 0.1626825, 0.1522934, 0.1421, 0.1321725, 0.1225639, 0.1132777, 0.1043026,
+                           0.09564, 0.08730821, 0.07931047, 0.07170313, 0.06456691, 0.05795001, 0.05184697, 0.04626689, 0.04115568, 0.0364236,
+                           0.03201, 0.0279205, 0.0241492, 0.02068918, 0.01754021, 0.0147, 0.01216126, 0.009922144, 0.007972784, 0.006300304,
+                           0.0049, 0.003780608, 0.002950864, 0.00242824, 0.002236816, 0.0024, 0.002930672, 0.00383992, 0.005169968, 0.006976256,
+                           0.0093, 0.01214909, 0.01553101, 0.01946869, 0.02398709, 0.0291, 0.03480741, 0.04111133, 0.04798413, 0.05538173,
+                           0.06327, 0.07163701, 0.08046133, 0.08973365, 0.09945125, 0.1096, 0.1201562, 0.1311082, 0.1423805, 0.1538682,
+                           0.1655, 0.1772682, 0.1891526, 0.2011698, 0.2133605, 0.2257499, 0.2383217, 0.2510672, 0.2639911, 0.2771005,
+                           0.2904, 0.3038896, 0.3175715, 0.3314399, 0.3454846, 0.3597, 0.3740866, 0.388641, 0.403375, 0.4183077,
+                           0.4334499, 0.4487938, 0.4643326, 0.4800607, 0.4959699, 0.5120501, 0.5282922, 0.5446883, 0.5612113, 0.5778246,
+                           0.5945, 0.6112241, 0.6279776, 0.6447567, 0.6615658, 0.6784, 0.6952363, 0.7120551, 0.7288279, 0.7455199,
+                           0.7621, 0.7785445, 0.7948251, 0.8109226, 0.8268216, 0.8425, 0.8579272, 0.8730778, 0.8878987, 0.9023237,
+                           0.9163, 0.9298029, 0.9428027, 0.9552786, 0.9672168, 0.9786, 0.9893917, 0.9995498, 1.009077, 1.017996,
+                           1.0263, 1.033961, 1.040974, 1.047213, 1.052494, 1.0567, 1.059824, 1.061824, 1.06279, 1.062883,
+                           1.0622, 1.06071, 1.058427, 1.055247, 1.051005, 1.0456, 1.039061, 1.031384, 1.022657, 1.01303,
+                           1.0026, 0.9913603, 0.9793224, 0.9664897, 0.9528502, 0.9384, 0.9231789, 0.907242, 0.8905319, 0.8729474,
+                           0.8544499, 0.8351092, 0.8149759, 0.7941891, 0.7729434, 0.7514, 0.7296039, 0.7075919, 0.6855627, 0.6637739,
+                           0.6424, 0.6214773, 0.6010743, 0.581111, 0.5614204, 0.5419, 0.5226019, 0.5035522, 0.4847496, 0.4661966,
+                           0.4479, 0.4298669, 0.412104, 0.3946435, 0.3775302, 0.3608, 0.3444603, 0.3285163, 0.3130091, 0.2979923,
+                           0.2835, 0.2695352, 0.2561083, 0.243191, 0.230733, 0.2187, 0.2070976, 0.1959246, 0.1851725, 0.1748331,
+                           0.1649, 0.1553678, 0.1462317, 0.1374907, 0.1291466, 0.1212, 0.1136436, 0.1064657, 0.09968293, 0.09332362,
+                           0.0874, 0.08189533, 0.07679677, 0.07207477, 0.06768789, 0.0636, 0.05979973, 0.05627981, 0.05298237, 0.04982973,
+                           0.04677, 0.04379523, 0.04088669, 0.03807014, 0.03539736, 0.0329, 0.03056264, 0.02837806, 0.02634332, 0.0244525,
+                           0.0227, 0.02108317, 0.01959844, 0.01823697, 0.01698753, 0.01584, 0.01478916, 0.01383043, 0.01295001, 0.01213051,
+                           0.01135916, 0.01063146, 0.009941282, 0.009288794, 0.008678157, 0.008110916, 0.007582211, 0.007088691, 0.006627535, 0.006195621,
+                           0.005790346, 0.005410016, 0.005052868, 0.004717652, 0.004403503, 0.004109457, 0.003834046, 0.003575888, 0.003334323, 0.003108996,
+                           0.002899327, 0.00270428, 0.002523, 0.002354282, 0.002196726, 0.00204919, 0.001911026, 0.001781552, 0.001660188, 0.001546478,
+                           0.001439971, 0.001340106, 0.001246353, 0.001158482, 0.001076404, 0.000999949, 0.0009287361, 0.0008624445, 0.0008007718, 0.0007434102,
+                           0.000690079, 0.0006405333, 0.0005945236, 0.0005518679, 0.0005124223, 0.000476021, 0.0004424492, 0.0004115147, 0.0003829972, 0.0003566617,
+                           0.000332301, 0.0003097635, 0.0002889029, 0.0002695598, 0.0002515792, 0.000234826, 0.0002191858, 0.0002045465, 0.0001908488, 0.0001780637,
+                           0.000166151, 0.00015503, 0.0001446302, 0.0001349127, 0.0001258509, 0.000117413, 0.0001095519, 0.0001022269, 9.539806e-05, 8.902589e-05,
+                           8.3075e-05, 7.751531e-05, 7.231697e-05, 6.74596e-05, 6.292851e-05, 5.8707e-05, 5.477157e-05, 5.110119e-05, 4.76782e-05, 4.448636e-05,
+                           4.151e-05, 3.873371e-05, 3.614326e-05, 3.372487e-05, 3.146539e-05, 2.9353e-05, 2.737673e-05, 2.552576e-05, 2.379411e-05, 2.217842e-05,
+                           2.0674e-05, 1.927258e-05, 1.796702e-05, 1.675071e-05, 1.561704e-05, 1.456e-05, 1.357435e-05, 1.2655e-05, 1.179765e-05, 1.099855e-05,
+                           1.0254e-05, 9.559955e-06, 8.912646e-06, 8.309002e-06, 7.746333e-06, 7.222e-06, 6.73315e-06, 6.277122e-06, 5.851797e-06, 5.45536e-06,
+                           5.086e-06, 4.741698e-06, 4.420581e-06, 4.121136e-06, 3.842043e-06, 3.582e-06, 3.33959e-06, 3.113496e-06, 2.90265e-06, 2.706123e-06,
+                           2.523e-06, 2.351977e-06, 2.191521e-06, 2.041784e-06, 1.903624e-06, 1.777e-06, 1.660392e-06, 1.552721e-06, 1.451073e-06, 1.351516e-06,
+                           1.251e-06];
+const CIE_Y: [f64; 471] = [3.917e-06, 4.475326e-06, 5.027062e-06, 5.600115e-06, 6.235684e-06, 6.965e-06, 7.798145e-06, 8.751958e-06, 9.833416e-06, 1.104329e-05,
+                           1.239e-05, 1.389186e-05, 1.556044e-05, 1.743702e-05, 1.957707e-05, 2.202e-05, 2.481058e-05, 2.803532e-05, 3.158458e-05, 3.526545e-05,
+                           3.9e-05, 4.288784e-05, 4.696814e-05, 5.155845e-05, 5.712491e-05, 6.4e-05, 7.228346e-05, 8.218109e-05, 9.358256e-05, 0.000106216,
+                           0.00012, 0.000135112, 0.0001515664, 0.0001700688, 0.0001916592, 0.000217, 0.0002464864, 0.0002811008, 0.0003191872, 0.0003579216,
+                           0.000396, 0.0004345072, 0.0004736912, 0.0005174272, 0.0005715232, 0.00064, 0.00072376, 0.0008250512, 0.0009420624, 0.001070882,
+                           0.00121, 0.001362701, 0.001531654, 0.0017208, 0.001935306, 0.00218, 0.002456368, 0.002764432, 0.003115136, 0.00352384,
+                           0.004, 0.004543424, 0.005156656, 0.005830288, 0.00654824, 0.0073, 0.00808736, 0.008909728, 0.009767776, 0.01066406,
+                           0.0116, 0.01257354, 0.01358282, 0.01462906, 0.0157145, 0.01684, 0.0180057, 0.01921386, 0.02045642, 0.02172074,
+                           0.023, 0.02429722, 0.02561274, 0.0269577, 0.02834938, 0.0298, 0.03131027, 0.03288282, 0.03452064, 0.03622566,
+                           0.038, 0.03984656, 0.04176752, 0.04376528, 0.04584224, 0.048, 0.05024157, 0.05257232, 0.05498387, 0.05746198,
+                           0.06, 0.06260483, 0.06528083, 0.06804227, 0.07090979, 0.0739, 0.07701734, 0.08026659, 0.08366416, 0.08723037,
+                           0.09098, 0.09491648, 0.0990432, 0.1033645, 0.1078832, 0.1126, 0.1175236, 0.1226715, 0.128006, 0.1334659,
+                           0.13902, 0.1446894, 0.1504825, 0.1564591, 0.1627093, 0.1693, 0.1762422, 0.1835553, 0.19127, 0.1994161,
+                           0.20802, 0.2171124, 0.226731, 0.236867, 0.2474915, 0.2586, 0.2702092, 0.2823038, 0.2950155, 0.3085425,
+                           0.323, 0.3383639, 0.3546508, 0.3717144, 0.3893172, 0.4073, 0.4256557, 0.4443254, 0.463368, 0.482909,
+                           0.503, 0.5235483, 0.5444856, 0.5656845, 0.5869722, 0.6082, 0.62932, 0.6503013, 0.6709218, 0.6908862,
+                           0.71, 0.7282327, 0.7455102, 0.7619556, 0.7778038, 0.7932, 0.8080941, 0.8224824, 0.8363159, 0.8495058,
+                           0.862, 0.8738204, 0.8849716, 0.8954903, 0.9054363, 0.9148501, 0.9237315, 0.9320891, 0.9399235, 0.9472275,
+                           0.954, 0.9602554, 0.9660066, 0.9712602, 0.9760218, 0.9803, 0.9841033, 0.9874355, 0.9903277, 0.9928231,
+                           0.9949501, 0.9967091, 0.9981041, 0.9991279, 0.9997641, 1.0, 0.9998398, 0.999289, 0.9983219, 0.9969,
+                           0.995, 0.9926246, 0.9897695, 0.9864572, 0.9827257, 0.9786, 0.9740784, 0.9691664, 0.9638576, 0.9581376,
+                           0.952, 0.9454533, 0.9385016, 0.9311611, 0.923455, 0.9154, 0.9070026, 0.8982755, 0.8892101, 0.879787,
+                           0.87, 0.8598659, 0.8493973, 0.8386222, 0.8275792, 0.8163, 0.8047968, 0.7930822, 0.7811877, 0.7691507,
+                           0.757, 0.7447498, 0.7324181, 0.7200048, 0.7074995, 0.6949, 0.6822211, 0.6694728, 0.6566725, 0.6438426,
+                           0.631, 0.6181528, 0.6053125, 0.5924778, 0.5796406, 0.5668, 0.5539651, 0.5411394, 0.528348, 0.515627,
+                           0.503, 0.4904656, 0.4780256, 0.4656752, 0.4534032, 0.4412, 0.4290723, 0.4170336, 0.4050445, 0.3930442,
+                           0.381, 0.3689293, 0.3568397, 0.3447773, 0.3328125, 0.321, 0.309345, 0.2978509, 0.2865792, 0.2756115,
+                           0.265, 0.2547491, 0.2448752, 0.2353373, 0.2260618, 0.217, 0.2081629, 0.1995517, 0.1911581, 0.1829757,
+                           0.175, 0.1672262, 0.1596493, 0.1522771, 0.1451242, 0.1382, 0.1315011, 0.1250243, 0.1187763, 0.1127667,
+                           0.107, 0.1014732, 0.09618576, 0.09112387, 0.08626694, 0.0816, 0.07712115, 0.07282643, 0.06871075, 0.06476995,
+                           0.061, 0.05739651, 0.05395571, 0.05067443, 0.04754995, 0.04458, 0.04176051, 0.03908563, 0.03656115, 0.03419779,
+                           0.032, 0.02996054, 0.02807395, 0.02632864, 0.02470861, 0.0232, 0.02179829, 0.0205004, 0.01928523, 0.0181247,
+                           0.017, 0.01590791, 0.01484134, 0.01380972, 0.01283209, 0.01192, 0.01106769, 0.01027244, 0.009532808, 0.008846133,
+                           0.00821, 0.007623398, 0.00708492, 0.00659133, 0.006138579, 0.005723, 0.005342603, 0.00499565, 0.004677136, 0.00438079,
+                           0.004102, 0.003839094, 0.003589832, 0.003354242, 0.003133787, 0.002929, 0.002738112, 0.002559898, 0.002393347, 0.002237357,
+                           0.002091, 0.001953656, 0.001824683, 0.00170363, 0.001590186, 0.001484, 0.001384544, 0.001291318, 0.001204085, 0.001122715,
+                           0.001047, 0.000976565, 0.0009111016, 0.0008501742, 0.0007932781, 0.00074, 0.0006901066, 0.000643351, 0.0005995243, 0.0005584616,
+                           0.00052, 0.000483937, 0.0004500811, 0.0004183493, 0.0003887094, 0.0003611, 0.0003353837, 0.0003114445, 0.0002891733, 0.0002684589,
+                           0.0002492, 0.0002313082, 0.0002146933, 0.0001992896, 0.0001850451, 0.0001719, 0.0001597766, 0.0001486056, 0.0001383074, 0.0001287971,
+                           0.00012, 0.0001118613, 0.0001043282, 9.734304e-05, 9.084992e-05, 8.48e-05, 7.9152e-05, 7.386544e-05, 6.891888e-05, 6.430192e-05,
+                           6e-05, 5.5984e-05, 5.222848e-05, 4.871936e-05, 4.544704e-05, 4.24e-05, 3.956126e-05, 3.691608e-05, 3.44501e-05, 3.214899e-05,
+                           3e-05, 2.799226e-05, 2.611498e-05, 2.436082e-05, 2.27245e-05, 2.12e-05, 1.977884e-05, 1.845342e-05, 1.721735e-05, 1.606477e-05,
+                           1.499e-05, 1.398748e-05, 1.305209e-05, 1.217881e-05, 1.136288e-05, 1.06e-05, 9.886216e-06, 9.217613e-06, 8.592066e-06, 8.008414e-06,
+                           7.465e-06, 6.958829e-06, 6.48733e-06, 6.048094e-06, 5.638715e-06, 5.257e-06, 4.901064e-06, 4.56907e-06, 4.259461e-06, 3.970883e-06,
+                           3.702e-06, 3.451346e-06, 3.217557e-06, 2.999536e-06, 2.796331e-06, 2.607e-06, 2.43051e-06, 2.265904e-06, 2.112402e-06, 1.969323e-06,
+                           1.836e-06, 1.711704e-06, 1.595754e-06, 1.487619e-06, 1.386853e-06, 1.293e-06, 1.205546e-06, 1.124019e-06, 1.048005e-06, 9.771184e-07,
+                           9.11e-07, 8.491869e-07, 7.911234e-07, 7.368919e-07, 6.868458e-07, 6.41e-07, 5.988088e-07, 5.598972e-07, 5.232001e-07, 4.872713e-07,
+                           4.51e-07];
+const CIE_Z: [f64; 471] = [0.0006061, 0.0006938883, 0.000780192, 0.0008692872, 0.0009693108, 0.001086, 0.001219542, 0.001372087, 0.001544112, 0.001734986,
+                           0.001946, 0.002179825, 0.002436875, 0.002729476, 0.003075389, 0.003486, 0.00396817, 0.004538404, 0.005169247, 0.00581372,
+                           0.006450001, 0.00709613, 0.007756408, 0.008493934, 0.00940327, 0.01054999, 0.01195132, 0.01364866, 0.01560619, 0.01774949,
+                           0.02005001, 0.02254199, 0.02522103, 0.02824711, 0.03185999, 0.03621, 0.04135654, 0.04747111, 0.05423727, 0.06111687,
+                           0.06785001, 0.07462511, 0.08147895, 0.08907615, 0.09841943, 0.1102, 0.1245104, 0.1416242, 0.1613799, 0.1833592,
+                           0.2074, 0.2337974, 0.2626878, 0.2946907, 0.33069, 0.3713, 0.416321, 0.4653803, 0.5192753, 0.5791946,
+                           0.6456, 0.7180002, 0.7962938, 0.878095, 0.9598469, 1.03905, 1.115527, 1.188746, 1.258263, 1.323943,
+                           1.3856, 1.442729, 1.494943, 1.542259, 1.58488, 1.62296, 1.656447, 1.685365, 1.709917, 1.730389,
+                           1.74706, 1.760098, 1.769666, 1.77623, 1.780384, 1.7826, 1.783, 1.781666, 1.779059, 1.775759,
+                           1.77211, 1.768125, 1.7639, 1.758968, 1.75255, 1.7441, 1.733596, 1.720882, 1.705904, 1.688697,
+                           1.6692, 1.647363, 1.623379, 1.596328, 1.564814, 1.5281, 1.48646, 1.439827, 1.389707, 1.338447,
+                           1.28764, 1.237276, 1.187651, 1.138745, 1.090211, 1.0419, 0.9941854, 0.9473313, 0.9014487, 0.8566225,
+                           0.8129501, 0.7705349, 0.7294404, 0.6898654, 0.652064, 0.6162, 0.5822878, 0.5503679, 0.5203328, 0.4919845,
+                           0.46518, 0.4399208, 0.4161788, 0.3938812, 0.3729472, 0.3533, 0.3348644, 0.3175511, 0.3013207, 0.2861541,
+                           0.272, 0.2587829, 0.2464669, 0.234815, 0.2234992, 0.2123, 0.2012039, 0.1901628, 0.1792337, 0.168549,
+                           0.1582, 0.1481521, 0.1383841, 0.1289797, 0.1200585, 0.1117, 0.1038983, 0.09665295, 0.08996846, 0.08383908,
+                           0.07824999, 0.07319268, 0.0686639, 0.064576, 0.06080194, 0.05725001, 0.05390825, 0.05075481, 0.04776033, 0.04490168,
+                           0.04216, 0.03951416, 0.0369432, 0.034458, 0.03208504, 0.02984, 0.02771176, 0.02569408, 0.02378656, 0.02198888,
+                           0.0203, 0.01871774, 0.01723976, 0.01586314, 0.01458443, 0.0134, 0.01230667, 0.01130138, 0.01037816, 0.009529743,
+                           0.008749999, 0.008035359, 0.007381839, 0.006785519, 0.006242799, 0.005749999, 0.005303439, 0.004899919, 0.0045348, 0.00420288,
+                           0.0039, 0.00362368, 0.0033712, 0.003141519, 0.002934639, 0.002749999, 0.002585359, 0.002438719, 0.00230928, 0.00219664,
+                           0.0021, 0.00201768, 0.00194808, 0.00188968, 0.00184088, 0.0018, 0.00176584, 0.00173768, 0.001711921, 0.001683761,
+                           0.001650001, 0.001610561, 0.001565121, 0.00151408, 0.00145864, 0.0014, 0.00133768, 0.00127048, 0.00120368, 0.00114528,
+                           0.0011, 0.0010672, 0.00104808, 0.00103656, 0.00102264, 0.001, 0.000969664, 0.00093088, 0.000886496, 0.000841792,
+                           0.0008, 0.00076032, 0.000723296, 0.000686592, 0.000646208, 0.0006, 0.000548816, 0.000492272, 0.000434608, 0.000382464,
+                           0.00034, 0.000306528, 0.000282368, 0.000265488, 0.000252208, 0.00024, 0.000229408, 0.000220688, 0.000212368, 0.000202528,
+                           0.00019, 0.000174672, 0.0001560481, 0.0001357441, 0.0001164801, 0.0001, 8.595183e-05, 7.438356e-05, 6.497527e-05, 5.700707e-05,
+                           4.9999e-05, 4.412707e-05, 3.945527e-05, 3.574356e-05, 3.267183e-05, 3e-05, 2.76641e-05, 2.558411e-05, 2.366407e-05, 2.182403e-05,
+                           2e-05, 1.814399e-05, 1.622399e-05, 1.422399e-05, 1.2144e-05, 1e-05, 7.776e-06, 5.424e-06, 3.152e-06, 1.28e-06,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.28e-07, 1.44e-07, 8e-08, 1.6e-08,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                           0.0];
+
+// Color matching functions linearly interpolated between the 1 nm entries,
+// zero outside of the visible range
+pub fn cie_xyz(lambda: f64) -> (f64, f64, f64)
+{
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda)
+    {
+        return (0., 0., 0.);
+    }
+    let x = lambda - LAMBDA_MIN;
+    let i = (x as usize).min(CIE_Y.len() - 2);
+    let t = x - i as f64;
+    let lerp = |a: &[f64]| a[i] + t*(a[i + 1] - a[i]);
+    (lerp(&CIE_X), lerp(&CIE_Y), lerp(&CIE_Z))
+}
+
+// Integral of y over the visible range at the 1 nm spacing of the table
+pub fn cie_y_integral() -> f64
+{
+    CIE_Y.iter().sum()
+}
+
+// CIE XYZ of a spectrum integrated at 1 nm steps over the visible range, with
+// the same normalization as SampledSpectrum::to_xyz
+pub fn spectrum_to_xyz(s: &dyn Spectrum) -> Vec3d
+{
+    let mut xyz = Vec3d::zero();
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX
+    {
+        let (x, y, z) = cie_xyz(lambda);
+        let v = s.eval(lambda);
+        xyz = xyz + Vec3d::new(x*v, y*v, z*v);
+        lambda += 1.;
+    }
+    xyz/cie_y_integral()
+}
+
+// Mean and first two characteristic vectors of daylight from CIE 15, at 10 nm
+// from 300 to 830 nm
+const DAYLIGHT_S0: [f64; 54] = [0.04, 6.0, 29.6, 55.3, 57.3, 61.8, 61.5, 68.8, 63.4, 65.8, 94.8, 104.8, 105.9, 96.8, 113.9, 125.6, 125.5, 121.3,
+                                121.3, 113.5, 113.1, 110.8, 106.5, 108.8, 105.3, 104.4, 100.0, 96.0, 95.1, 89.1, 90.5, 90.3, 88.4, 84.0, 85.1, 81.9,
+                                82.6, 84.9, 81.3, 71.9, 74.3, 76.4, 63.3, 71.7, 77.0, 65.2, 47.7, 68.6, 65.0, 66.0, 61.0, 53.3, 58.9, 61.9];
+const DAYLIGHT_S1: [f64; 54] = [0.02, 4.5, 22.4, 42.0, 40.6, 41.6, 38.0, 42.4, 38.5, 35.0, 43.4, 46.3, 43.9, 37.1, 36.7, 35.9, 32.6, 27.9,
+                                24.3, 20.1, 16.2, 13.2, 8.6, 6.1, 4.2, 1.9, 0.0, -1.6, -3.5, -3.5, -5.8, -7.2, -8.6, -9.5, -10.9, -10.7,
+                                -12.0, -14.0, -13.6, -12.0, -13.3, -12.9, -10.6, -11.6, -12.2, -10.2, -7.8, -11.2, -10.4, -10.6, -9.7, -8.3, -9.3, -9.8];
+const DAYLIGHT_S2: [f64; 54] = [0.0, 2.0, 4.0, 8.5, 7.8, 6.7, 5.3, 6.1, 3.0, 1.2, -1.1, -0.5, -0.7, -1.2, -2.6, -2.9, -2.8, -2.6,
+                                -2.6, -1.8, -1.5, -1.3, -1.2, -1.0, -0.5, -0.3, 0.0, 0.2, 0.5, 2.1, 3.2, 4.1, 4.7, 5.1, 6.7, 7.3,
+                                8.6, 9.8, 10.2, 8.3, 9.6, 8.5, 7.0, 7.6, 8.0, 6.7, 5.2, 7.4, 6.8, 7.0, 6.4, 5.5, 6.1, 6.5];
+
+// CIE daylight with the given chromaticity, which should be close to the
+// daylight locus, scaled to a luminance of one
+pub fn daylight(white: Point2) -> ResponseCurve
+{
+    let (x, y) = (white.x, white.y);
+    let d = 0.0241 + 0.2562*x - 0.7341*y;
+    let m1 = (-1.3515 - 1.7703*x + 5.9114*y)/d;
+    let m2 = (0.0300 - 31.4424*x + 30.0717*y)/d;
+    let lambda = (0..54).map(|i| 300. + 10.*i as f64).collect();
+    let value = (0..54).map(|i| DAYLIGHT_S0[i] + m1*DAYLIGHT_S1[i] + m2*DAYLIGHT_S2[i]).collect();
+    let s = ResponseCurve::new(lambda, value);
+    let y = spectrum_to_xyz(&s).y;
+    ResponseCurve::new(s.lambda, s.value.iter().map(|v| v/y).collect())
+}
+
+// Planck's law for wavelength in nm and temperature in kelvin, as spectral
+// radiance in W/(sr m^2 nm)
+pub fn planck(lambda: f64, t: f64) -> f64
+{
+    if t <= 0.
+    {
+        return 0.;
+    }
+    let (c, h, kb): (f64, f64, f64) = (299_792_458., 6.626_070_15e-34, 1.380_649e-23);
+    let l = lambda*1e-9;
+    2.*h*c*c/(l.powi(5)*((h*c/(l*kb*t)).exp() - 1.))*1e-9
+}
+
+// Blackbody emission at temperature t in kelvin, either absolute radiance
+// or scaled to a peak of one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlackbodySpectrum
+{
+    pub t: f64,
+    pub normalized: bool,
+}
+
+impl Spectrum for BlackbodySpectrum
+{
+    fn eval(&self, lambda: f64) -> f64
+    {
+        let v = planck(lambda, self.t);
+        if !self.normalized
+        {
+            return v;
+        }
+        // Wien's displacement law
+        let peak = 2.897_771_955e6/self.t;
+        v/planck(peak, self.t)
+    }
+}
@@ -0,0 +1,355 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::color::{RGB, linear_to_srgb, mat_mul};
+use crate::colorspace::apply_matrix;
+use crate::image::Image;
+use crate::transformation::Matrix3;
+
+// Curves from scene-linear values to display-linear ones in [0, 1]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneCurve
+{
+    // Clamp only
+    Linear,
+    // Extended Reinhard on luminance, with the value mapped to white or
+    // the plain L/(1 + L) curve without one
+    Reinhard{ white: Option<f64> },
+    // Stephen Hill's fit of the ACES reference and output transforms
+    Aces,
+    // Minimal AgX base look, with the polynomial contrast approximation
+    AgX,
+}
+
+impl ToneCurve
+{
+    pub fn eval(&self, c: RGB) -> RGB
+    {
+        match *self
+        {
+            ToneCurve::Linear => c.clamp(0., 1.),
+            ToneCurve::Reinhard{ white } =>
+            {
+                let l = c.luminance();
+                if l <= 0.
+                {
+                    return RGB::black();
+                }
+                let ld = match white
+                {
+                    Some(w) => l*(1. + l/(w*w))/(1. + l),
+                    None => l/(1. + l),
+                };
+                (c*(ld/l)).clamp(0., 1.)
+            }
+            ToneCurve::Aces => aces(c),
+            ToneCurve::AgX => agx(c),
+        }
+    }
+}
+
+fn aces(c: RGB) -> RGB
+{
+    // sRGB to RRT input space and ODT output back to sRGB
+    const INPUT: [[f64; 3]; 3] = [[0.59719, 0.35458, 0.04823], [0.07600, 0.90834, 0.01566], [0.02840, 0.13383, 0.83777]];
+    const OUTPUT: [[f64; 3]; 3] = [[1.60475, -0.53108, -0.07367], [-0.10208, 1.10813, -0.00605], [-0.00327, -0.07276, 1.07602]];
+    let v = mat_mul(&INPUT, c).map(|v|
+    {
+        let a = v*(v + 0.0245786) - 0.000090537;
+        let b = v*(0.983729*v + 0.4329510) + 0.238081;
+        a/b
+    });
+    mat_mul(&OUTPUT, v).clamp(0., 1.)
+}
+
+fn agx(c: RGB) -> RGB
+{
+    const INSET: [[f64; 3]; 3] = [[0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+                                  [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+                                  [0.0423756549057051, 0.0784336, 0.879142973793104]];
+    const OUTSET: [[f64; 3]; 3] = [[1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+                                   [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+                                   [-0.0529716355144438, -0.0980434501171241, 1.15107367264116]];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+    let v = mat_mul(&INSET, c).map(|v|
+    {
+        let x = (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV)/(MAX_EV - MIN_EV);
+        let (x2, x4) = (x*x, x*x*x*x);
+        15.5*x4*x2 - 40.14*x4*x + 31.96*x4 - 6.868*x2*x + 0.4298*x2 + 0.1191*x - 0.00232
+    });
+    // The curve output is display encoded with a 2.2 gamma
+    mat_mul(&OUTSET, v).map(|v| v.max(0.).powf(2.2)).clamp(0., 1.)
+}
+
+// Display encodings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Oetf
+{
+    Linear,
+    SRGB,
+    Gamma(f64),
+}
+
+impl Oetf
+{
+    pub fn encode(&self, v: f64) -> f64
+    {
+        match *self
+        {
+            Oetf::Linear => v,
+            Oetf::SRGB => linear_to_srgb(v),
+            Oetf::Gamma(g) => v.max(0.).powf(1./g),
+        }
+    }
+}
+
+// Exposure that brings the average log luminance of a histogram range to
+// the key value, ignoring the darkest and brightest pixels
+#[derive(Clone, Copy, Debug)]
+pub struct AutoExposure
+{
+    pub min_ev: f64,
+    pub max_ev: f64,
+    pub bins: usize,
+    // Fractions of the pixels, sorted by luminance, that are averaged
+    pub low_percent: f64,
+    pub high_percent: f64,
+    pub key: f64,
+}
+
+impl Default for AutoExposure
+{
+    fn default() -> AutoExposure
+    {
+        AutoExposure{ min_ev: -12., max_ev: 12., bins: 128, low_percent: 0.5, high_percent: 0.95, key: 0.18 }
+    }
+}
+
+impl AutoExposure
+{
+    // Exposure compensation in EV for the image
+    pub fn exposure(&self, img: &Image) -> f64
+    {
+        let mut histogram = vec![0usize; self.bins];
+        let scale = self.bins as f64/(self.max_ev - self.min_ev);
+        for y in 0..img.height
+        {
+            for x in 0..img.width
+            {
+                let l = img.get_rgb(x, y).luminance();
+                if l <= 0. || !l.is_finite()
+                {
+                    continue;
+                }
+                let bin = ((l.log2() - self.min_ev)*scale).clamp(0., self.bins as f64 - 1.);
+                histogram[bin as usize] += 1;
+            }
+        }
+        let total: usize = histogram.iter().sum();
+        if total == 0
+        {
+            return 0.;
+        }
+        let (low, high) = (self.low_percent*total as f64, self.high_percent*total as f64);
+        let (mut seen, mut sum, mut weight) = (0., 0., 0.);
+        for (i, &n) in histogram.iter().enumerate()
+        {
+            // Part of the bin inside [low, high]
+            let n = n as f64;
+            let inside = (seen + n).min(high) - seen.max(low);
+            seen += n;
+            if inside > 0.
+            {
+                sum += inside*(self.min_ev + (i as f64 + 0.5)/scale);
+                weight += inside;
+            }
+        }
+        if weight == 0.
+        {
+            return 0.;
+        }
+        self.key.log2() - sum/weight
+    }
+}
+
+// 3D lookup table from an Adobe/Resolve .cube file, red varying fastest
+#[derive(Clone, Debug)]
+pub struct Lut3D
+{
+    pub size: usize,
+    pub domain_min: [f64; 3],
+    pub domain_max: [f64; 3],
+    pub data: Vec<RGB>,
+}
+
+fn invalid(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn parse_floats(fields: &[&str]) -> io::Result<[f64; 3]>
+{
+    if fields.len() != 3
+    {
+        return Err(invalid("expected three values in .cube file"));
+    }
+    Ok([parse_float(fields[0])?, parse_float(fields[1])?, parse_float(fields[2])?])
+}
+
+fn parse_float(field: &str) -> io::Result<f64>
+{
+    field.parse().map_err(|_| invalid("invalid number in .cube file"))
+}
+
+impl Lut3D
+{
+    pub fn identity(size: usize) -> Lut3D
+    {
+        assert!(size >= 2, "a LUT needs at least two entries per axis");
+        let s = (size - 1) as f64;
+        let mut data = Vec::with_capacity(size*size*size);
+        for b in 0..size
+        {
+            for g in 0..size
+            {
+                for r in 0..size
+                {
+                    data.push(RGB::new(r as f64/s, g as f64/s, b as f64/s));
+                }
+            }
+        }
+        Lut3D{ size, domain_min: [0.; 3], domain_max: [1.; 3], data }
+    }
+    pub fn parse_cube(text: &str) -> io::Result<Lut3D>
+    {
+        let (mut size, mut domain_min, mut domain_max) = (0, [0.; 3], [1.; 3]);
+        let mut data = Vec::new();
+        for line in text.lines()
+        {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.first()
+            {
+                None => {}
+                Some(f) if f.starts_with('#') => {}
+                Some(&"LUT_3D_SIZE") if fields.len() == 2 => size = fields[1].parse().map_err(|_| invalid("invalid .cube size"))?,
+                Some(&"LUT_1D_SIZE") => return Err(io::Error::new(io::ErrorKind::Unsupported, "1D .cube LUTs are not supported")),
+                Some(&"DOMAIN_MIN") => domain_min = parse_floats(&fields[1..])?,
+                Some(&"DOMAIN_MAX") => domain_max = parse_floats(&fields[1..])?,
+                Some(&"LUT_3D_INPUT_RANGE") if fields.len() == 3 =>
+                {
+                    domain_min = [parse_float(fields[1])?; 3];
+                    domain_max = [parse_float(fields[2])?; 3];
+                }
+                // TITLE and any other keyword, values start with a digit,
+                // a sign or a dot
+                Some(f) if f.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+                Some(_) =>
+                {
+                    let v = parse_floats(&fields)?;
+                    data.push(RGB::new(v[0], v[1], v[2]));
+                }
+            }
+        }
+        if size < 2 || data.len() != size*size*size
+        {
+            return Err(invalid("wrong number of .cube entries"));
+        }
+        Ok(Lut3D{ size, domain_min, domain_max, data })
+    }
+    pub fn read(path: &Path) -> io::Result<Lut3D>
+    {
+        Lut3D::parse_cube(&fs::read_to_string(path)?)
+    }
+    // Trilinear lookup, values outside of the domain are clamped to it
+    pub fn apply(&self, c: RGB) -> RGB
+    {
+        let n = self.size;
+        let s = (n - 1) as f64;
+        let mut i = [0; 3];
+        let mut t = [0.; 3];
+        for (k, &v) in [c.r, c.g, c.b].iter().enumerate()
+        {
+            let x = (v - self.domain_min[k])/(self.domain_max[k] - self.domain_min[k]);
+            let x = if x.is_nan() { 0. } else { x.clamp(0., 1.)*s };
+            i[k] = usize::min(x as usize, n - 2);
+            t[k] = x - i[k] as f64;
+        }
+        let at = |r: usize, g: usize, b: usize| self.data[(b*n + g)*n + r];
+        let mut out = RGB::black();
+        for corner in 0..8
+        {
+            let (dr, dg, db) = (corner & 1, (corner >> 1) & 1, corner >> 2);
+            let w = (if dr == 1 { t[0] } else { 1. - t[0] })*(if dg == 1 { t[1] } else { 1. - t[1] })*(if db == 1 { t[2] } else { 1. - t[2] });
+            out += at(i[0] + dr, i[1] + dg, i[2] + db)*w;
+        }
+        out
+    }
+}
+
+// Display transform applied to a resolved film before it's quantized:
+// exposure, conversion to the display gamut, tone curve, OETF and finally
+// the LUT on the encoded values
+#[derive(Clone, Debug)]
+pub struct DisplayTransform
+{
+    // Exposure compensation in EV, added to the automatic one
+    pub exposure: f64,
+    pub auto_exposure: Option<AutoExposure>,
+    // From the rendering space to the display one, see
+    // RGBColorSpace::convert_matrix
+    pub gamut: Option<Matrix3>,
+    pub curve: ToneCurve,
+    pub oetf: Oetf,
+    pub lut: Option<Lut3D>,
+}
+
+impl Default for DisplayTransform
+{
+    fn default() -> DisplayTransform
+    {
+        DisplayTransform{ exposure: 0., auto_exposure: None, gamut: None, curve: ToneCurve::Linear, oetf: Oetf::SRGB, lut: None }
+    }
+}
+
+impl DisplayTransform
+{
+    pub fn eval(&self, c: RGB, scale: f64) -> RGB
+    {
+        let c = match self.gamut
+        {
+            Some(ref m) => apply_matrix(m, c*scale),
+            None => c*scale,
+        };
+        let c = self.curve.eval(c).map(|v| self.oetf.encode(v));
+        match self.lut
+        {
+            Some(ref lut) => lut.apply(c),
+            None => c,
+        }
+    }
+    // Color channels are transformed and any other channels, like alpha,
+    // are kept. Single channel images are treated as gray.
+    pub fn apply(&self, img: &Image) -> Image
+    {
+        let ev = self.exposure + self.auto_exposure.map_or(0., |a| a.exposure(img));
+        let scale = 2f64.powf(ev);
+        let mut out = img.clone();
+        for y in 0..img.height
+        {
+            for x in 0..img.width
+            {
+                let c = self.eval(img.get_rgb(x, y), scale);
+                if img.channel_count() >= 3
+                {
+                    out.set_rgb(x, y, c);
+                }
+                else if img.channel_count() > 0
+                {
+                    out.set(x, y, 0, c.luminance());
+                }
+            }
+        }
+        out
+    }
+}
@@ -124,6 +124,108 @@ impl cmp::PartialEq for Matrix4
     }
 }
 
+// 3x3 matrix for linear maps between color spaces
+#[derive(Clone, Copy, Debug)]
+pub struct Matrix3
+{
+    pub mat: [[f64; 3]; 3],
+}
+
+impl Matrix3
+{
+    pub fn i() -> Matrix3
+    {
+        Matrix3::diag(1., 1., 1.)
+    }
+    pub fn new(m: [[f64; 3]; 3]) -> Matrix3
+    {
+        Matrix3 {mat: m}
+    }
+    pub fn diag(a: f64, b: f64, c: f64) -> Matrix3
+    {
+        Matrix3::new([[a, 0., 0.], [0., b, 0.], [0., 0., c]])
+    }
+    // Matrix with the given vectors as columns
+    pub fn from_columns(c0: Vec3d, c1: Vec3d, c2: Vec3d) -> Matrix3
+    {
+        Matrix3::new([[c0.x, c1.x, c2.x], [c0.y, c1.y, c2.y], [c0.z, c1.z, c2.z]])
+    }
+    pub fn mul(m1: &Matrix3, m2: &Matrix3) -> Matrix3
+    {
+        let mut mat = [[0.; 3]; 3];
+        for i in 0..3
+        {
+            for j in 0..3
+            {
+                mat[i][j] = m1[(i,0)]*m2[(0,j)] + m1[(i,1)]*m2[(1,j)] + m1[(i,2)]*m2[(2,j)];
+            }
+        }
+        Matrix3::new(mat)
+    }
+    pub fn act(&self, v: Vec3d) -> Vec3d
+    {
+        let m = &self.mat;
+        Vec3d::new(m[0][0]*v.x + m[0][1]*v.y + m[0][2]*v.z,
+                   m[1][0]*v.x + m[1][1]*v.y + m[1][2]*v.z,
+                   m[2][0]*v.x + m[2][1]*v.y + m[2][2]*v.z)
+    }
+    pub fn det(&self) -> f64
+    {
+        let m = &self.mat;
+        m[0][0]*(m[1][1]*m[2][2] - m[1][2]*m[2][1])
+            - m[0][1]*(m[1][0]*m[2][2] - m[1][2]*m[2][0])
+            + m[0][2]*(m[1][0]*m[2][1] - m[1][1]*m[2][0])
+    }
+    pub fn inv(&self) -> Matrix3
+    {
+        let det = self.det();
+        if det == 0.
+        {
+            panic!("Singular matrix!");
+        }
+        // Adjugate over determinant
+        let m = &self.mat;
+        let mut v = [[0.; 3]; 3];
+        for (i, row) in v.iter_mut().enumerate()
+        {
+            for (j, e) in row.iter_mut().enumerate()
+            {
+                let (a, b) = ((j + 1) % 3, (j + 2) % 3);
+                let (c, d) = ((i + 1) % 3, (i + 2) % 3);
+                *e = (m[a][c]*m[b][d] - m[a][d]*m[b][c])/det;
+            }
+        }
+        Matrix3::new(v)
+    }
+}
+
+impl ops::Index<(usize, usize)> for Matrix3
+{
+    type Output = f64;
+    fn index(&self, (a, b): (usize, usize)) -> &f64
+    {
+        &self.mat[a][b]
+    }
+}
+
+impl cmp::PartialEq for Matrix3
+{
+    fn eq(&self, other: &Matrix3) -> bool
+    {
+        for i in 0..3
+        {
+            for j in 0..3
+            {
+                if f64::abs(self[(i,j)]-other[(i,j)]) > 1e-4
+                {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Transform
 {
@@ -95,6 +95,21 @@ impl Vec3d
     }
 }
 
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Point2
+{
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Point2
+{
+    pub fn new(x: f64, y: f64) -> Point2
+    {
+        Point2{ x, y }
+    }
+}
+
 impl ops::Add for Vec3d
 {
     type Output = Vec3d;
@@ -2,6 +2,7 @@ use base::transformation::Transform;
 use base::bounding::AABB;
 use crate::shape::Shape;
 use crate::shape::Interaction;
+use base::vector::Point2;
 
 pub struct Cylinder
 {
@@ -65,6 +66,8 @@ impl Shape for Cylinder
             n_hit = t.act_normal(n_hit);
         }
         n_hit = n_hit.norm();
-        Interaction { hit:true, t_hit, n_hit}
+        let u = phi/self.phi_max;
+        let v = (p_hit.z - self.z_min)/(self.z_max - self.z_min);
+        Interaction { hit:true, t_hit, n_hit, uv:Point2::new(u, v), prim_id:0 }
     }
 }
\ No newline at end of file
@@ -9,3 +9,26 @@ mod tests {
         assert_eq!(2 + 2, 4);
     }
 }
+
+#[cfg(test)]
+mod interaction_tests {
+    use base::color::RGB;
+    use base::ray::Ray;
+    use base::vector::Vec3d;
+    use crate::mesh::Triangle;
+    use crate::shape::{Shape, Interaction};
+    #[test]
+    fn aov_test_0() {
+        let tri = Triangle{ p0: Vec3d::new(0., 0., 1.), p1: Vec3d::new(1., 0., 1.), p2: Vec3d::new(0., 1., 1.) };
+        let ray = Ray::new(Vec3d::new(0.25, 0.5, 0.), Vec3d::new(0., 0., 2.));
+        let inter = tri.intersect(&ray);
+        assert!(inter.hit);
+        assert!((inter.uv.x - 0.25).abs() < 1e-12 && (inter.uv.y - 0.5).abs() < 1e-12);
+        let s = inter.aov_sample(&ray, RGB::gray(0.5), 7);
+        assert!((s.depth - 1.).abs() < 1e-12);
+        assert!((s.position.z - 1.).abs() < 1e-12);
+        assert_eq!(s.normal.z, -1.);
+        assert_eq!(s.shape_id, 7);
+        assert_eq!(Interaction::miss().aov_sample(&ray, RGB::white(), 7).depth, f64::INFINITY);
+    }
+}
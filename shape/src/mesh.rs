@@ -1,6 +1,6 @@
 use base::transformation::Transform;
 use base::bounding::AABB;
-use base::vector::Vec3d;
+use base::vector::{Point2, Vec3d};
 use base::ray::Ray;
 use crate::shape::Shape;
 use crate::shape::Interaction;
@@ -49,6 +49,8 @@ impl Shape for Mesh
         let num_tri = self.vertex_indices.len() / 3;
         let mut t_hit = f64::INFINITY;
         let mut n_hit = Vec3d::zero();
+        let mut uv = Point2::new(0., 0.);
+        let mut prim_id = 0;
         for i in 0..num_tri
         {
             let p0 = v_world[self.vertex_indices[3*i]];
@@ -60,6 +62,8 @@ impl Shape for Mesh
             {
                 t_hit = inter.t_hit;
                 n_hit = inter.n_hit;
+                uv = inter.uv;
+                prim_id = i;
             }
         }
         if t_hit == f64::INFINITY
@@ -68,7 +72,7 @@ impl Shape for Mesh
         }
         else
         {
-            return Interaction{ hit: true, t_hit, n_hit };
+            return Interaction{ hit: true, t_hit, n_hit, uv, prim_id };
         }
     }
 }
@@ -124,6 +128,6 @@ impl Shape for Triangle
         {
             n_hit = -n_hit;
         }     
-        Interaction{ hit: true, t_hit, n_hit } 
+        Interaction{ hit: true, t_hit, n_hit, uv: Point2::new(u, v), prim_id: 0 }
     }
 }
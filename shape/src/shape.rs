@@ -1,5 +1,8 @@
+use base::aov::AovSample;
 use base::bounding::AABB;
-use base::vector::Vec3d;
+use base::color::RGB;
+use base::ray::Ray;
+use base::vector::{Point2, Vec3d};
 pub trait Shape
 {
     fn bound(&self) -> AABB;
@@ -12,12 +15,34 @@ pub struct Interaction
     pub hit: bool,
     pub t_hit: f64,
     pub n_hit: Vec3d,
+    // Surface parameterization of the hit, barycentrics for triangles
+    pub uv: Point2,
+    // Index of the triangle hit inside of a mesh
+    pub prim_id: usize,
 }
 
 impl Interaction
 {
     pub fn miss() -> Interaction
     {
-        Interaction{ hit:false, t_hit:0., n_hit:Vec3d::zero() }
+        Interaction{ hit:false, t_hit:0., n_hit:Vec3d::zero(), uv:Point2::new(0., 0.), prim_id:0 }
+    }
+    // AOVs of the hit of a camera ray, the motion vector is left for the
+    // caller to fill in with Film::motion_vector
+    pub fn aov_sample(&self, ray: &Ray, albedo: RGB, shape_id: u32) -> AovSample
+    {
+        if !self.hit
+        {
+            return AovSample::miss();
+        }
+        let mut s = AovSample::miss();
+        s.depth = self.t_hit*ray.d.len();
+        s.position = ray.pos(self.t_hit);
+        s.normal = self.n_hit;
+        s.albedo = albedo;
+        s.uv = self.uv;
+        s.prim_id = self.prim_id as u32;
+        s.shape_id = shape_id;
+        s
     }
 }
\ No newline at end of file
@@ -2,6 +2,7 @@ use base::transformation::Transform;
 use base::bounding::AABB;
 use crate::shape::Shape;
 use crate::shape::Interaction;
+use base::vector::Point2;
 
 pub struct Sphere
 {
@@ -74,6 +75,9 @@ impl Shape for Sphere
             n_hit = t.act_normal(n_hit);
         }
         n_hit = n_hit.norm();
-        Interaction { hit:true, t_hit, n_hit}
+        let theta = (p_hit.z/self.r).clamp(-1., 1.).acos();
+        let u = phi/self.phi_max;
+        let v = (theta - self.theta_min)/(self.theta_max - self.theta_min);
+        Interaction { hit:true, t_hit, n_hit, uv:Point2::new(u, v), prim_id:0 }
     }
 }
\ No newline at end of file